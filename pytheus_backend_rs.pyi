@@ -0,0 +1,605 @@
+from typing import Any, Callable, Iterable, Iterator, Literal, TypedDict
+
+class RedisEndpoint(TypedDict):
+    host: str
+    port: int
+
+class RedisRoute(RedisEndpoint, total=False):
+    metrics: Iterable[str]
+    prefixes: list[str]
+
+class GaugeSource(TypedDict, total=False):
+    file: str
+    proc: Literal["open_fds", "threads"]
+
+class RawObservations(TypedDict, total=False):
+    maxlen: int
+    sample_rate: float
+
+class TopKLabel(TypedDict, total=False):
+    label: str
+    k: int
+    other: str
+    server: bool
+
+class RelabelRule(TypedDict, total=False):
+    action: Literal["drop", "rename", "set", "replace"]
+    label: str
+    target: str
+    value: str
+    regex: str
+    replacement: str
+    metrics: Iterable[str] | None
+
+class MetricRename(TypedDict, total=False):
+    name: str
+    keep_original: bool
+
+class ArchiveStream(TypedDict, total=False):
+    stream: str
+    maxlen: int | None
+
+class ArchivedSeries(TypedDict):
+    metric: str
+    key: str
+    labels: dict[str, str] | None
+    value: float | None
+    last_write: float
+    archived_at: float
+
+class RedisBackendConfig(TypedDict, total=False):
+    url: str
+    host: str
+    port: int
+    expire_at: dict[str, int]
+    expire_jitter: int
+    expire_key_seconds: int
+    expire_refresh_fraction: float
+    max_key_length: int | None
+    max_labels_length: int | None
+    registry_namespaces: dict[str, Any]
+    routes: list[RedisRoute]
+    shards: list[RedisEndpoint]
+    confirmed_writes: Iterable[str]
+    dead_letter_path: str
+    track_last_update: Iterable[str]
+    last_updated_samples: bool
+    track_created: Iterable[str]
+    created_samples: bool
+    units: dict[str, str]
+    serializer: Literal["float", "float_timestamp"]
+    panic_policy: Literal["raise", "warn", "abort"]
+    drop_warning_interval: float
+    slow_operation_threshold: float | None
+    render_cache_ttl: float | None
+    render_lease_timeout: float | None
+    idle_series_timeout: float | None
+    delete_idle_series: bool
+    summary_quantiles: dict[str, list[float]]
+    timeseries: Iterable[str]
+    timeseries_retention: float | None
+    documents: Iterable[str]
+    failover: RedisEndpoint | None
+    failover_threshold: int
+    failover_probe_interval: float
+    key_layout: Literal["native", "pytheus"]
+    username: str | None
+    password: str | None
+    credential_provider: Callable[[], str | tuple[str | None, str]] | None
+    tcp_keepalive: bool
+    tcp_keepalive_idle: float | None
+    tcp_keepalive_interval: float | None
+    tcp_keepalive_count: int | None
+    tcp_nodelay: bool
+    connect_timeout_ms: int | None
+    command_timeout_ms: int | None
+    ssl: bool
+    ca_cert: str | None
+    client_cert: str | None
+    client_key: str | None
+    ssl_check_hostname: bool
+    sentinels: list[tuple[str, int]] | None
+    service_name: str
+    track_pending_writes: bool
+    gauge_callbacks: dict[str, Callable[[], Any] | GaugeSource]
+    archive_sink: Callable[[list[ArchivedSeries]], Any] | dict[str, str] | ArchiveStream | None
+    raw_observations: dict[str, int | RawObservations]
+    top_k_labels: dict[str, TopKLabel]
+    max_commands_per_second: int | None
+    backpressure: Literal["coalesce", "shed"]
+    relabel: list[RelabelRule]
+    metric_renames: dict[str, str | MetricRename]
+    omit_zero_series: Iterable[str]
+    namespace: str | None
+    subsystem: str | None
+    synchronous: bool
+    worker_threads: int
+    db: int
+    key_prefix: str
+
+class OutSample:
+    suffix: str
+    labels: dict[str, str] | None
+    quantile: float | None
+    value: float
+    def __init__(
+        self, suffix: str, labels: dict[str, str] | None = None, value: float = 0.0
+    ) -> None: ...
+    def __eq__(self, other: object) -> bool: ...
+    def __ne__(self, other: object) -> bool: ...
+    def __hash__(self) -> int: ...
+    def to_dict(self) -> dict[str, Any]: ...
+
+class SampleFamily:
+    name: str
+    type_: str
+    help: str
+    unit: str | None
+    samples: list[OutSample]
+    aliases: list[str]
+
+class CollectorError(TypedDict):
+    collector: str | None
+    error: str
+
+class SampleSet:
+    errors: list[CollectorError]
+    def __len__(self) -> int: ...
+    def __iter__(self) -> Iterator[Any]: ...
+    def __contains__(self, key: Any) -> bool: ...
+    def __getitem__(self, key: Any) -> list[OutSample]: ...
+    def get(self, key: Any, default: Any = None) -> list[OutSample] | Any: ...
+    def keys(self) -> list[Any]: ...
+    def values(self) -> list[list[OutSample]]: ...
+    def items(self) -> list[tuple[Any, list[OutSample]]]: ...
+    def families(self) -> list[SampleFamily]: ...
+    def family(self, name: str) -> SampleFamily | None: ...
+    def render(
+        self, format: Literal["prometheus", "text", "openmetrics"] = "prometheus"
+    ) -> str: ...
+
+class SampleIter:
+    errors: list[CollectorError]
+    def __iter__(self) -> Iterator[tuple[Any, OutSample]]: ...
+    def __next__(self) -> tuple[Any, OutSample]: ...
+
+class IdleSeries(TypedDict):
+    metric: str
+    labels: dict[str, str] | None
+    last_updated: float
+    idle_seconds: float
+
+class MemoryUsage(TypedDict):
+    backends: int
+    queued_jobs: int
+    cached_hashes: int
+    estimated_bytes: int
+
+class WorkerState(TypedDict):
+    name: str
+    state: Literal["idle", "processing", "reconnecting", "dead"]
+    since: float
+
+class PendingWrites(TypedDict):
+    jobs: int
+    delta: float
+    set: float | None
+
+class SeriesSnapshot(TypedDict, total=False):
+    backend: str
+    key: str
+    labels: dict[str, str] | None
+    value: float | None
+    pending: dict[str, PendingWrites]
+
+class ExportSink(TypedDict, total=False):
+    pushgateway: str
+    job: str
+    grouping: dict[str, str]
+    remote_write: str
+    file: str
+    format: Literal["prometheus", "text", "openmetrics"]
+    graphite: str
+    template: str
+
+class PreflightStep(TypedDict):
+    name: str
+    ok: bool
+    detail: str
+
+class PreflightReport(TypedDict):
+    ok: bool
+    checks: list[PreflightStep]
+
+class RedisBackend:
+    config: dict[str, Any]
+    metric: Any
+    histogram_bucket: str | None
+    resolved_prefix: str
+    key_name: str
+    labels_hash: str | None
+    expire_at: int | None
+    confirmed_writes: bool
+    route: int
+    def __init__(
+        self, config: RedisBackendConfig, metric: Any, histogram_bucket: str | None = None
+    ) -> None: ...
+    @classmethod
+    def _initialize(cls, config: RedisBackendConfig) -> None: ...
+    @classmethod
+    def _check(cls, config: RedisBackendConfig) -> PreflightReport: ...
+    @classmethod
+    def _generate_samples(cls, registry: Any) -> SampleSet: ...
+    @classmethod
+    def _generate_samples_iter(cls, registry: Any, chunk_size: int = 64) -> SampleIter: ...
+    @classmethod
+    def render_metrics(
+        cls, registry: Any, format: Literal["prometheus", "text", "openmetrics"] = "prometheus"
+    ) -> str: ...
+    @classmethod
+    def evict_idle_series(cls, registry: Any = None) -> int: ...
+    @classmethod
+    def idle_series(cls, window: float, registry: Any = None) -> list[IdleSeries]: ...
+    @classmethod
+    def series_count(cls, registry: Any) -> dict[str, int]: ...
+    @classmethod
+    def warm_start(cls, registry: Any) -> int: ...
+    @classmethod
+    def redis_memory_usage(
+        cls, registry: Any, samples: int = 5, batch_size: int = 100, pause: float = 0.01
+    ) -> dict[str, int]: ...
+    @classmethod
+    def snapshot(cls, registry: Any) -> dict[str, list[SeriesSnapshot]]: ...
+    def _snapshot(self) -> SeriesSnapshot: ...
+    @classmethod
+    def start_exporter(cls, registry: Any, sink: ExportSink, interval: float) -> None: ...
+    @classmethod
+    def stop_exporter(cls) -> bool: ...
+    @classmethod
+    def worker_states(cls) -> list[WorkerState]: ...
+    @classmethod
+    def dropped_jobs(cls) -> int: ...
+    @classmethod
+    def memory_usage(cls) -> MemoryUsage: ...
+    @classmethod
+    def replay_dead_letters(cls) -> int: ...
+    @classmethod
+    def _flush(cls, timeout: float | None = None) -> bool: ...
+    @classmethod
+    def handle_post_fork(cls) -> None: ...
+    def _initialize_key(self) -> None: ...
+    def inc(
+        self,
+        value: float,
+        labels: dict[str, str] | None = None,
+        expire: float | None = None,
+        return_value: bool = False,
+    ) -> float | None: ...
+    def dec(
+        self, value: float, labels: dict[str, str] | None = None, expire: float | None = None
+    ) -> None: ...
+    def update_batch(self, updates: list[tuple[dict[str, str], float]]) -> None: ...
+    def set(
+        self, value: float, labels: dict[str, str] | None = None, expire: float | None = None
+    ) -> None: ...
+    def observe(self, value: float, labels: dict[str, str] | None = None) -> None: ...
+    def observe_buckets(
+        self, bucket_counts: list[int], sum: float, labels: dict[str, str] | None = None
+    ) -> None: ...
+    def get(self) -> float: ...
+    def last_updated(self) -> float | None: ...
+    def staleness(self) -> float | None: ...
+    def created(self) -> float | None: ...
+    def add_unique(self, items: list[str], labels: dict[str, str] | None = None) -> None: ...
+    def raw_observations(self, labels: dict[str, str] | None = None) -> list[float]: ...
+    @classmethod
+    def get_many(cls, backends: Iterable[Any]) -> list[float]: ...
+    @classmethod
+    def created_timestamps(cls, name: str, registry: Any = None) -> dict[str, float]: ...
+
+class FakeRedisBackend(RedisBackend):
+    @classmethod
+    def _initialize(cls, config: RedisBackendConfig) -> None: ...
+    @classmethod
+    def execute_command(cls, *args: Any) -> Any: ...
+
+class SingleProcessBackend:
+    config: dict[str, Any]
+    metric: Any
+    histogram_bucket: str | None
+    def __init__(
+        self, config: dict[str, Any], metric: Any, histogram_bucket: str | None = None
+    ) -> None: ...
+    @classmethod
+    def series_count(cls, registry: Any) -> dict[str, int]: ...
+    @classmethod
+    def snapshot(cls, registry: Any) -> dict[str, list[SeriesSnapshot]]: ...
+    def _snapshot(self) -> SeriesSnapshot: ...
+    def inc(self, value: float) -> None: ...
+    def dec(self, value: float) -> None: ...
+    def set(self, value: float) -> None: ...
+    def get(self) -> float: ...
+    def add_unique(self, items: list[str]) -> None: ...
+
+class SingleProcessAtomicBackend:
+    config: dict[str, Any]
+    metric: Any
+    histogram_bucket: str | None
+    def __init__(
+        self, config: dict[str, Any], metric: Any, histogram_bucket: str | None = None
+    ) -> None: ...
+    @classmethod
+    def series_count(cls, registry: Any) -> dict[str, int]: ...
+    @classmethod
+    def snapshot(cls, registry: Any) -> dict[str, list[SeriesSnapshot]]: ...
+    def _snapshot(self) -> SeriesSnapshot: ...
+    def inc(self, value: float) -> None: ...
+    def dec(self, value: float) -> None: ...
+    def set(self, value: float) -> None: ...
+    def get(self) -> float: ...
+    def add_unique(self, items: list[str]) -> None: ...
+
+class ParityBackendConfig(TypedDict, total=False):
+    primary: type | str
+    secondary: type | str
+    primary_config: dict[str, Any]
+    secondary_config: dict[str, Any]
+    tolerance: float
+
+class Divergence(TypedDict):
+    metric: str
+    suffix: str
+    labels: dict[str, str]
+    primary: float | None
+    secondary: float | None
+
+class ParityBackend:
+    primary: Any
+    secondary: Any
+    def __init__(
+        self, config: ParityBackendConfig, metric: Any, histogram_bucket: str | None = None
+    ) -> None: ...
+    @classmethod
+    def _initialize(cls, config: ParityBackendConfig) -> None: ...
+    @classmethod
+    def _generate_samples(cls, registry: Any) -> dict[Any, list[Any]]: ...
+    @classmethod
+    def divergences(cls) -> list[Divergence]: ...
+    @classmethod
+    def clear_divergences(cls) -> None: ...
+    def inc(self, value: float) -> None: ...
+    def dec(self, value: float) -> None: ...
+    def set(self, value: float) -> None: ...
+    def get(self) -> float: ...
+
+class FanOutChild(TypedDict, total=False):
+    backend: type | str
+    config: dict[str, Any]
+
+class FanOutBackendConfig(TypedDict):
+    backends: list[FanOutChild]
+
+class FanOutBackend:
+    children: list[Any]
+    def __init__(
+        self, config: FanOutBackendConfig, metric: Any, histogram_bucket: str | None = None
+    ) -> None: ...
+    @classmethod
+    def _initialize(cls, config: FanOutBackendConfig) -> None: ...
+    @classmethod
+    def _generate_samples(cls, registry: Any) -> dict[Any, list[Any]]: ...
+    @classmethod
+    def child_errors(cls) -> list[int]: ...
+    @classmethod
+    def clear_child_errors(cls) -> None: ...
+    def inc(self, value: float) -> None: ...
+    def dec(self, value: float) -> None: ...
+    def set(self, value: float) -> None: ...
+    def get(self) -> float: ...
+
+class CollectorRegistry:
+    prefix: str | None
+    def __init__(self, prefix: str | None = None) -> None: ...
+    def register(self, collector: Any) -> None: ...
+    def unregister(self, collector: Any) -> None: ...
+    def collect(self) -> list[Any]: ...
+    def __len__(self) -> int: ...
+
+class MetricCollector:
+    name: str
+    description: str
+    type_: str
+    _required_labels: list[str] | None
+    _default_labels: dict[str, str]
+    _default_labels_count: int
+    _labeled_metrics: dict[tuple[str, ...], Metric]
+    _metric: Metric | None
+
+class Metric:
+    _collector: MetricCollector
+    _labels: dict[str, str] | None
+    _metric_value_backend: Any
+    _upper_bounds: list[float] | None
+    def labels(self, labels: dict[str, str] | None = None, **kwargs: str) -> Metric: ...
+
+class Counter(Metric):
+    def __init__(
+        self,
+        name: str,
+        description: str,
+        required_labels: list[str] | None = None,
+        default_labels: dict[str, str] | None = None,
+        registry: Any = None,
+    ) -> None: ...
+    def inc(self, value: float = 1.0) -> None: ...
+
+class Gauge(Metric):
+    def __init__(
+        self,
+        name: str,
+        description: str,
+        required_labels: list[str] | None = None,
+        default_labels: dict[str, str] | None = None,
+        registry: Any = None,
+    ) -> None: ...
+    def inc(self, value: float = 1.0) -> None: ...
+    def dec(self, value: float = 1.0) -> None: ...
+    def set(self, value: float) -> None: ...
+
+class Histogram(Metric):
+    def __init__(
+        self,
+        name: str,
+        description: str,
+        required_labels: list[str] | None = None,
+        default_labels: dict[str, str] | None = None,
+        registry: Any = None,
+        buckets: list[float] | None = None,
+    ) -> None: ...
+    def observe(self, value: float) -> None: ...
+    def observe_buckets(self, bucket_counts: list[int], sum: float) -> None: ...
+
+class Summary(Metric):
+    def __init__(
+        self,
+        name: str,
+        description: str,
+        required_labels: list[str] | None = None,
+        default_labels: dict[str, str] | None = None,
+        registry: Any = None,
+    ) -> None: ...
+    def observe(self, value: float) -> None: ...
+
+class UniqueCounter(Metric):
+    def __init__(
+        self,
+        name: str,
+        description: str,
+        required_labels: list[str] | None = None,
+        default_labels: dict[str, str] | None = None,
+        registry: Any = None,
+    ) -> None: ...
+    def add(self, *items: Any) -> None: ...
+
+class Ewma:
+    half_life: float
+    rate: bool
+    def __init__(self, half_life: float, rate: bool = False) -> None: ...
+    def observe(self, value: float = 1.0) -> None: ...
+    def get(self) -> float: ...
+    def __call__(self) -> float: ...
+
+class OtelExporter:
+    _preferred_temporality: dict[type, Any]
+    _preferred_aggregation: dict[type, Any]
+    def __init__(self, registry: Any = None) -> None: ...
+    def export(self, metrics_data: Any, **kwargs: Any) -> Any: ...
+    def force_flush(self, timeout_millis: float = 30000.0) -> bool: ...
+    def shutdown(self, timeout_millis: float = 30000.0, **kwargs: Any) -> None: ...
+    def reader(self, export_interval_millis: float | None = None) -> Any: ...
+
+class PrometheusClientValue:
+    def __init__(
+        self,
+        typ: str,
+        metric_name: str,
+        name: str,
+        labelnames: Iterable[str],
+        labelvalues: Iterable[str],
+        help_text: str,
+        **kwargs: Any,
+    ) -> None: ...
+    def inc(self, amount: float) -> None: ...
+    def set(self, value: float, _timestamp: float | None = None) -> None: ...
+    def set_exemplar(self, _exemplar: Any) -> None: ...
+    def get_exemplar(self) -> None: ...
+    def get(self) -> float: ...
+
+class PrometheusClientCollector:
+    def __init__(self) -> None: ...
+    def describe(self) -> list[Any]: ...
+    def collect(self) -> list[Any]: ...
+
+def install_prometheus_client() -> None: ...
+def labels_fast(metric: Any, labels: dict[str, Any] | None = None, **kwargs: Any) -> Any: ...
+
+class TestClock:
+    def __init__(self, timestamp: float | None = None) -> None: ...
+    def time(self) -> float: ...
+    def set(self, timestamp: float) -> None: ...
+    def advance(self, seconds: float) -> None: ...
+
+def set_clock(clock: TestClock | None) -> None: ...
+
+class BenchmarkReport(TypedDict):
+    operations: int
+    duration_seconds: float
+    throughput: float
+    latency_p50_us: float
+    latency_p90_us: float
+    latency_p99_us: float
+    latency_max_us: float
+
+def benchmark(
+    backend: Any,
+    threads: int = 4,
+    iterations: int = 10000,
+    operation: str = "inc",
+    value: float = 1.0,
+) -> BenchmarkReport: ...
+
+class DoctorFinding(TypedDict):
+    check: str
+    severity: Literal["ok", "warning", "error"]
+    message: str
+
+def doctor(config: RedisBackendConfig) -> list[DoctorFinding]: ...
+
+class BuildInfo(TypedDict):
+    version: str
+    features: list[str]
+    redis_rs_version: str | None
+    pyo3_version: str | None
+
+class ServerFeatures(TypedDict):
+    multi_field_hset: bool
+    scripting: bool
+    getex: bool
+    hexpire: bool
+    functions: bool
+    resp3: bool
+    tdigest: bool
+    topk: bool
+    timeseries: bool
+    json: bool
+
+class ServerInfo(TypedDict):
+    redis_version: str | None
+    mode: str | None
+    cluster_enabled: bool
+    modules: list[str]
+    features: ServerFeatures
+
+def build_info() -> BuildInfo: ...
+def server_info(config: RedisBackendConfig | None = None) -> ServerInfo: ...
+
+FaultKind = Literal["connection_drop", "command_error", "latency", "queue_overflow"]
+
+def inject_fault(kind: FaultKind, times: int = 1, latency_ms: int = 0) -> None: ...
+def clear_faults() -> None: ...
+
+class BackendCapabilities(TypedDict):
+    supports_multiprocess: bool
+    supports_observe: bool
+
+def register_backend(
+    name: str,
+    backend: type,
+    supports_multiprocess: bool = False,
+    supports_observe: bool = False,
+    replace: bool = False,
+) -> None: ...
+def backend_class(name: str) -> type: ...
+def backend_capabilities(name: str) -> BackendCapabilities: ...
+def registered_backends() -> list[str]: ...
+def load_backend(name: str, config: dict[str, Any] | None = None) -> None: ...
@@ -0,0 +1,167 @@
+use crate::execute_pipeline;
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Final state of a series field about to be evicted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub metric: String,
+    pub key_name: String,
+    pub labels: Option<BTreeMap<String, String>>,
+    /// Last value stored, `None` when the field is gone already or isn't stored in a hash.
+    pub value: Option<f64>,
+    /// Unix timestamp of the last write of the process to the series.
+    pub last_write: f64,
+    pub route: usize,
+}
+
+impl Record {
+    fn json(&self, archived_at: f64) -> serde_json::Value {
+        json!({
+            "metric": self.metric,
+            "key": self.key_name,
+            "labels": self.labels,
+            "value": self.value,
+            "last_write": self.last_write,
+            "archived_at": archived_at,
+        })
+    }
+}
+
+/// Where the series evicted by `evict_idle_series` are archived before they expire or are
+/// deleted, so that long-term analysis keeps their last values.
+#[derive(Debug)]
+pub enum Sink {
+    /// Python callable called with the records of every eviction, a list of dicts.
+    Python(PyObject),
+    /// File the records are appended to, one JSON object per line.
+    File(PathBuf),
+    /// Redis Stream the records are added to, on the endpoint of their series, trimmed to about
+    /// `maxlen` entries.
+    Stream { key: String, maxlen: Option<usize> },
+}
+
+impl Sink {
+    /// Sink from the config: a callable, `{"file": path}` or `{"stream": key, "maxlen": n}`.
+    pub fn from_py(sink: &PyAny) -> PyResult<Self> {
+        if sink.is_callable() {
+            return Ok(Self::Python(sink.into()));
+        }
+        let sink: &PyDict = sink.downcast()?;
+        if let Some(path) = sink.get_item("file") {
+            return Ok(Self::File(path.extract()?));
+        }
+        match sink.get_item("stream") {
+            Some(key) => Ok(Self::Stream {
+                key: key.extract()?,
+                maxlen: match sink.get_item("maxlen") {
+                    Some(maxlen) if !maxlen.is_none() => Some(maxlen.extract()?),
+                    _ => None,
+                },
+            }),
+            None => Err(PyValueError::new_err(
+                "an archive sink is a callable, a file or a stream",
+            )),
+        }
+    }
+
+    /// Archive the records, failing when they couldn't all be written.
+    pub fn write(&self, py: Python, records: &[Record], archived_at: f64) -> PyResult<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        match self {
+            Sink::Python(callback) => {
+                let records = records
+                    .iter()
+                    .map(|record| {
+                        let dict = PyDict::new(py);
+                        dict.set_item("metric", &record.metric)?;
+                        dict.set_item("key", &record.key_name)?;
+                        dict.set_item("labels", &record.labels)?;
+                        dict.set_item("value", record.value)?;
+                        dict.set_item("last_write", record.last_write)?;
+                        dict.set_item("archived_at", archived_at)?;
+                        Ok(dict)
+                    })
+                    .collect::<PyResult<Vec<_>>>()?;
+                callback.call1(py, (records,))?;
+                Ok(())
+            }
+            Sink::File(path) => {
+                let lines: String = records
+                    .iter()
+                    .map(|record| format!("{}\n", record.json(archived_at)))
+                    .collect();
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| file.write_all(lines.as_bytes()))
+                    .map_err(|e| PyOSError::new_err(format!("{}: {e}", path.display())))
+            }
+            Sink::Stream { key, maxlen } => {
+                let mut pipes: BTreeMap<usize, redis::Pipeline> = BTreeMap::new();
+                for record in records {
+                    let pipe = pipes.entry(record.route).or_default();
+                    let cmd = pipe.cmd("XADD").arg(key);
+                    if let Some(maxlen) = maxlen {
+                        cmd.arg("MAXLEN").arg("~").arg(maxlen);
+                    }
+                    cmd.arg("*")
+                        .arg("metric")
+                        .arg(&record.metric)
+                        .arg("key")
+                        .arg(&record.key_name)
+                        .arg("labels")
+                        .arg(json!(record.labels).to_string())
+                        .arg("value")
+                        .arg(json!(record.value).to_string())
+                        .arg("last_write")
+                        .arg(record.last_write)
+                        .arg("archived_at")
+                        .arg(archived_at)
+                        .ignore();
+                }
+                for (route, pipe) in pipes {
+                    execute_pipeline(py, route, pipe)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn json_records() {
+        let record = Record {
+            metric: "requests".to_string(),
+            key_name: "requests".to_string(),
+            labels: Some(BTreeMap::from([("path".to_string(), "/".to_string())])),
+            value: Some(3.0),
+            last_write: 1_700_000_000.0,
+            route: 0,
+        };
+        assert_eq!(
+            record.json(1_700_000_060.0).to_string(),
+            r#"{"archived_at":1700000060.0,"key":"requests","labels":{"path":"/"},"last_write":1700000000.0,"metric":"requests","value":3.0}"#
+        );
+        let gone = Record {
+            value: None,
+            labels: None,
+            ..record
+        };
+        assert!(gone.json(0.0).to_string().contains(r#""value":null"#));
+    }
+}
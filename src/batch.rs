@@ -0,0 +1,218 @@
+use crate::config::Storage;
+use crate::features::ServerFeatures;
+use crate::{add_expire_to_pipeline, BackendAction, RedisJob};
+use redis::ToRedisArgs;
+
+/// Increments several fields of a hash in a single command:
+/// `EVAL <script> 1 key field increment [field increment ...]`.
+pub const HINCRBYFLOAT_FIELDS_SCRIPT: &str = "for i = 1, #ARGV, 2 do \
+     redis.call('HINCRBYFLOAT', KEYS[1], ARGV[i], ARGV[i + 1]) \
+     end";
+
+/// Outcome of the jobs of a batch on one series.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeriesWrite {
+    Incr(f64),
+    Set(f64),
+}
+
+impl SeriesWrite {
+    fn apply(self, action: BackendAction, value: f64) -> Self {
+        match (action, self) {
+            (BackendAction::Inc | BackendAction::Dec, SeriesWrite::Incr(current)) => {
+                SeriesWrite::Incr(current + value)
+            }
+            (BackendAction::Inc | BackendAction::Dec, SeriesWrite::Set(current)) => {
+                SeriesWrite::Set(current + value)
+            }
+            (BackendAction::Set, _) => SeriesWrite::Set(value),
+        }
+    }
+}
+
+/// Writes of a batch on one key, by hash field (`None` for unlabeled keys).
+#[derive(Debug)]
+pub struct KeyWrites<'a> {
+    pub key_name: &'a str,
+    pub expire_at: Option<usize>,
+    /// Where the series of the key are configured to be stored.
+    pub storage: Storage,
+    pub fields: Vec<(Option<&'a str>, SeriesWrite)>,
+}
+
+/// Fold the jobs of a batch into one write per series, grouped by key in order of first
+/// appearance.
+pub fn fold(jobs: &[RedisJob]) -> Vec<KeyWrites<'_>> {
+    let mut keys: Vec<KeyWrites> = vec![];
+    for job in jobs {
+        let index = match keys.iter().position(|key| key.key_name == job.key_name) {
+            Some(index) => index,
+            None => {
+                keys.push(KeyWrites {
+                    key_name: &job.key_name,
+                    expire_at: job.expire_at,
+                    storage: job.storage,
+                    fields: vec![],
+                });
+                keys.len() - 1
+            }
+        };
+        let key = &mut keys[index];
+        key.expire_at = job.expire_at;
+
+        let field = job.labels_hash.as_deref();
+        match key.fields.iter_mut().find(|(f, _)| *f == field) {
+            Some((_, write)) => *write = write.apply(job.action, job.value),
+            None => key
+                .fields
+                .push((field, SeriesWrite::Incr(0.0).apply(job.action, job.value))),
+        }
+    }
+    keys
+}
+
+/// Set several fields of a hash in one command, with HMSET on servers without multi-field HSET.
+pub fn add_hash_fields_to_pipeline<T: ToRedisArgs>(
+    key_name: &str,
+    fields: &[(&str, T)],
+    features: &ServerFeatures,
+    pipe: &mut redis::Pipeline,
+) {
+    let command = match features.multi_field_hset {
+        true => "HSET",
+        false => "HMSET",
+    };
+    pipe.cmd(command).arg(key_name).arg(fields).ignore();
+}
+
+/// Add the writes of a key with as few commands as possible: the fields that are set in one
+/// HSET and the incremented ones in one HINCRBYFLOAT, or a script when there are several and the
+/// server supports scripting.
+pub fn add_key_writes_to_pipeline(
+    key: &KeyWrites,
+    features: &ServerFeatures,
+    pipe: &mut redis::Pipeline,
+) {
+    let mut sets = vec![];
+    let mut increments = vec![];
+    for (field, write) in &key.fields {
+        match (field, write) {
+            (None, SeriesWrite::Incr(value)) => {
+                pipe.incr(key.key_name, *value).ignore();
+            }
+            (None, SeriesWrite::Set(value)) => {
+                pipe.set(key.key_name, *value).ignore();
+            }
+            (Some(field), SeriesWrite::Incr(value)) => increments.push((*field, *value)),
+            (Some(field), SeriesWrite::Set(value)) => sets.push((*field, *value)),
+        }
+    }
+
+    if !sets.is_empty() {
+        add_hash_fields_to_pipeline(key.key_name, &sets, features, pipe);
+    }
+    match increments.as_slice() {
+        [] => (),
+        increments if increments.len() == 1 || !features.scripting => {
+            for (field, value) in increments {
+                pipe.hincr(key.key_name, *field, *value).ignore();
+            }
+        }
+        increments => {
+            pipe.cmd("EVAL")
+                .arg(HINCRBYFLOAT_FIELDS_SCRIPT)
+                .arg(1)
+                .arg(key.key_name);
+            for (field, value) in increments {
+                pipe.arg(*field).arg(*value);
+            }
+            pipe.ignore();
+        }
+    }
+    add_expire_to_pipeline(key.key_name, key.expire_at, pipe);
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn job(action: BackendAction, key_name: &str, field: Option<&str>, value: f64) -> RedisJob {
+        RedisJob {
+            action,
+            key_name: key_name.to_string(),
+            labels_hash: field.map(str::to_string),
+            value,
+            expire_at: None,
+            lease_at: None,
+            last_updated_key: None,
+            created_key: None,
+            digest_key: None,
+            observations: None,
+            unique_items: None,
+            storage: Storage::Keys,
+            route: 0,
+            ack_tx: None,
+            reply_tx: None,
+        }
+    }
+
+    fn commands(key: &KeyWrites, features: &ServerFeatures) -> Vec<String> {
+        let mut pipe = redis::pipe();
+        add_key_writes_to_pipeline(key, features, &mut pipe);
+        pipe.cmd_iter()
+            .map(|cmd| match cmd.args_iter().next() {
+                Some(redis::Arg::Simple(name)) => String::from_utf8_lossy(name).into_owned(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fold_by_series() {
+        let jobs = [
+            job(BackendAction::Inc, "a", Some("x"), 1.0),
+            job(BackendAction::Inc, "b", None, 2.0),
+            job(BackendAction::Inc, "a", Some("y"), 1.0),
+            job(BackendAction::Dec, "a", Some("x"), -0.5),
+            job(BackendAction::Set, "b", None, 5.0),
+            job(BackendAction::Inc, "b", None, 1.0),
+        ];
+        let keys = fold(&jobs);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].key_name, "a");
+        assert_eq!(
+            keys[0].fields,
+            [
+                (Some("x"), SeriesWrite::Incr(0.5)),
+                (Some("y"), SeriesWrite::Incr(1.0))
+            ]
+        );
+        assert_eq!(keys[1].fields, [(None, SeriesWrite::Set(6.0))]);
+    }
+
+    #[test]
+    fn single_command_per_key() {
+        let jobs = [
+            job(BackendAction::Inc, "a", Some("x"), 1.0),
+            job(BackendAction::Inc, "a", Some("y"), 2.0),
+            job(BackendAction::Set, "a", Some("z"), 3.0),
+            job(BackendAction::Set, "a", Some("w"), 4.0),
+        ];
+        let keys = fold(&jobs);
+        assert_eq!(
+            commands(&keys[0], &ServerFeatures::default()),
+            ["HSET", "EVAL", "EXPIRE"]
+        );
+
+        let old_server = ServerFeatures {
+            multi_field_hset: false,
+            scripting: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            commands(&keys[0], &old_server),
+            ["HMSET", "HINCRBYFLOAT", "HINCRBYFLOAT", "EXPIRE"]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Value at the given percentile (0-100) of sorted samples, using the nearest-rank method.
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((percentile / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Spawn `threads` threads each calling `operation(value)` on the backend `iterations` times and
+/// report throughput and latency percentiles (in microseconds).
+#[pyfunction]
+#[pyo3(signature = (backend, threads=4, iterations=10_000, operation="inc", value=1.0))]
+pub fn benchmark(
+    py: Python,
+    backend: PyObject,
+    threads: usize,
+    iterations: usize,
+    operation: &str,
+    value: f64,
+) -> PyResult<PyObject> {
+    if threads == 0 {
+        return Err(PyValueError::new_err("threads must be at least 1"));
+    }
+    if !backend.as_ref(py).hasattr(operation)? {
+        return Err(PyValueError::new_err(format!(
+            "backend has no `{operation}` operation"
+        )));
+    }
+
+    let start = Instant::now();
+    let results: Vec<PyResult<Vec<Duration>>> = py.allow_threads(|| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let backend = Python::with_gil(|py| backend.clone_ref(py));
+                let operation = operation.to_string();
+                thread::spawn(move || {
+                    let mut latencies = Vec::with_capacity(iterations);
+                    for _ in 0..iterations {
+                        let call_start = Instant::now();
+                        Python::with_gil(|py| {
+                            backend.call_method1(py, operation.as_str(), (value,))
+                        })?;
+                        latencies.push(call_start.elapsed());
+                    }
+                    Ok(latencies)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("benchmark thread panicked"))
+            .collect()
+    });
+    let elapsed = start.elapsed();
+
+    let mut latencies = vec![];
+    for result in results {
+        latencies.extend(result?);
+    }
+    latencies.sort_unstable();
+
+    let operations = latencies.len();
+    let micros = |duration: Duration| duration.as_secs_f64() * 1_000_000.0;
+
+    let report = PyDict::new(py);
+    report.set_item("operations", operations)?;
+    report.set_item("duration_seconds", elapsed.as_secs_f64())?;
+    report.set_item("throughput", operations as f64 / elapsed.as_secs_f64())?;
+    report.set_item("latency_p50_us", micros(percentile(&latencies, 50.0)))?;
+    report.set_item("latency_p90_us", micros(percentile(&latencies, 90.0)))?;
+    report.set_item("latency_p99_us", micros(percentile(&latencies, 99.0)))?;
+    report.set_item(
+        "latency_max_us",
+        micros(latencies.last().copied().unwrap_or_default()),
+    )?;
+    Ok(report.into())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_micros).collect();
+        assert_eq!(percentile(&samples, 50.0), Duration::from_micros(50));
+        assert_eq!(percentile(&samples, 99.0), Duration::from_micros(99));
+        assert_eq!(percentile(&samples, 100.0), Duration::from_micros(100));
+        assert_eq!(percentile(&samples, 0.0), Duration::from_micros(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+}
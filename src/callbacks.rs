@@ -0,0 +1,121 @@
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+pub type Labels = BTreeMap<String, String>;
+
+/// Where the value of a callback gauge comes from, read by every scrape instead of being stored.
+#[derive(Debug)]
+pub enum Source {
+    /// Python callable returning the value of the gauge, or `(labels, value)` pairs (a dict of
+    /// values by labels also works) for a labeled gauge.
+    Python(PyObject),
+    /// First number in a file, e.g. a cgroup or sysfs counter.
+    File(PathBuf),
+    /// File descriptors open in the process.
+    OpenFds,
+    /// Threads of the process.
+    Threads,
+}
+
+impl Source {
+    /// Source from the config: a callable, `{"file": path}` or `{"proc": "open_fds" | "threads"}`.
+    pub fn from_py(source: &PyAny) -> PyResult<Self> {
+        if source.is_callable() {
+            return Ok(Self::Python(source.into()));
+        }
+        let source: &PyDict = source.downcast()?;
+        if let Some(path) = source.get_item("file") {
+            return Ok(Self::File(path.extract()?));
+        }
+        match source.get_item("proc") {
+            Some(stat) => match stat.extract()? {
+                "open_fds" => Ok(Self::OpenFds),
+                "threads" => Ok(Self::Threads),
+                stat => Err(PyValueError::new_err(format!("unknown proc stat: {stat}"))),
+            },
+            None => Err(PyValueError::new_err(
+                "a gauge callback is a callable, a file or a proc stat",
+            )),
+        }
+    }
+
+    /// Current values, with the labels of their series when the source returned some.
+    pub fn read(&self, py: Python) -> PyResult<Vec<(Option<Labels>, f64)>> {
+        match self {
+            Source::Python(callback) => {
+                let result = callback.call0(py)?;
+                let result = result.as_ref(py);
+                if let Ok(value) = result.extract() {
+                    return Ok(vec![(None, value)]);
+                }
+                let pairs = match result.downcast::<PyDict>() {
+                    Ok(values) => values.items().as_ref(),
+                    Err(_) => result,
+                };
+                pairs
+                    .iter()?
+                    .map(|pair| {
+                        let (labels, value) = pair?.extract()?;
+                        Ok((Some(labels), value))
+                    })
+                    .collect()
+            }
+            source => Ok(vec![(None, source.stat().map_err(PyOSError::new_err)?)]),
+        }
+    }
+
+    /// Value of a source read by Rust.
+    fn stat(&self) -> Result<f64, String> {
+        match self {
+            Source::Python(_) => Err("not a Rust source".to_string()),
+            Source::File(path) => {
+                let content =
+                    fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+                first_number(&content)
+            }
+            Source::OpenFds => {
+                let fds = fs::read_dir("/proc/self/fd").map_err(|e| e.to_string())?;
+                Ok(fds.count() as f64)
+            }
+            Source::Threads => {
+                let status = fs::read_to_string("/proc/self/status").map_err(|e| e.to_string())?;
+                let threads = status
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Threads:"))
+                    .ok_or("no thread count in /proc/self/status")?;
+                first_number(threads)
+            }
+        }
+    }
+}
+
+fn first_number(content: &str) -> Result<f64, String> {
+    let number = content.split_whitespace().next().unwrap_or_default();
+    number
+        .parse()
+        .map_err(|_| format!("not a number: {number:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn rust_sources() {
+        assert!(Source::OpenFds.stat().unwrap() >= 3.0);
+        assert!(Source::Threads.stat().unwrap() >= 1.0);
+
+        let path = env::temp_dir().join("pytheus_backend_rs_callback_source");
+        fs::write(&path, "  42 7\n").unwrap();
+        assert_eq!(Source::File(path.clone()).stat().unwrap(), 42.0);
+        fs::write(&path, "max\n").unwrap();
+        assert!(Source::File(path.clone()).stat().is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
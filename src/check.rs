@@ -0,0 +1,200 @@
+use crate::config::RedisConfig;
+use crate::connection;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::HashMap;
+use std::net::ToSocketAddrs;
+use std::time::{Duration, Instant};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const PROBE_EXPIRE_SECONDS: usize = 10;
+
+/// Outcome of one step of a preflight check.
+#[derive(Debug)]
+pub struct Step {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Step {
+    pub fn new(name: &'static str, result: Result<String, String>) -> Self {
+        let (ok, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        Self { name, ok, detail }
+    }
+}
+
+fn options(config: &RedisConfig) -> connection::SocketOptions {
+    connection::SocketOptions {
+        connect_timeout: config
+            .socket_options
+            .connect_timeout
+            .or(Some(CONNECT_TIMEOUT)),
+        ..config.socket_options
+    }
+}
+
+/// Host and port of the configured server, the current master with Sentinel.
+fn server(config: &RedisConfig) -> Result<(String, u16), String> {
+    match &config.sentinel {
+        Some(sentinel) => sentinel
+            .master(&options(config), config.tls.as_ref())
+            .map_err(|e| e.to_string()),
+        None => Ok((config.host.clone(), config.port)),
+    }
+}
+
+/// Open a connection to the configured server, failing fast when it's not reachable.
+pub fn connect(config: &RedisConfig) -> Result<connection::Connection, String> {
+    let (host, port) = server(config)?;
+    let mut connection =
+        connection::Connection::open(&host, port, &options(config), config.tls.as_ref())
+            .map_err(|e| e.to_string())?;
+    if let Some(credentials) = &config.credentials {
+        credentials
+            .authenticate(&mut connection)
+            .map_err(|e| e.to_string())?;
+    }
+    if config.db != 0 {
+        connection.select(config.db).map_err(|e| e.to_string())?;
+    }
+    Ok(connection)
+}
+
+/// Fields of an `INFO` reply by name.
+pub fn parse_info(info: &str) -> HashMap<String, String> {
+    info.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// `major.minor.patch` of a server version, missing parts being 0.
+pub fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.split('.').map(|part| part.parse::<u32>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+    Some((major, minor, patch))
+}
+
+fn resolve(host: &str, port: u16) -> Result<String, String> {
+    let addresses: Vec<String> = (connection::host(host), port)
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .map(|address| address.to_string())
+        .collect();
+    Ok(addresses.join(", "))
+}
+
+fn ping(connection: &mut connection::Connection) -> Result<String, String> {
+    let started = Instant::now();
+    redis::cmd("PING")
+        .query::<String>(connection)
+        .map_err(|e| e.to_string())?;
+    Ok(format!(
+        "{:.2} ms",
+        started.elapsed().as_secs_f64() * 1000.0
+    ))
+}
+
+fn write_probe(connection: &mut connection::Connection) -> Result<String, String> {
+    let key = format!("pytheus:probe:{}", std::process::id());
+    redis::pipe()
+        .set_ex(&key, 1, PROBE_EXPIRE_SECONDS)
+        .ignore()
+        .del(&key)
+        .ignore()
+        .query::<()>(connection)
+        .map_err(|e| e.to_string())?;
+    Ok(format!("wrote and deleted {key}"))
+}
+
+/// Run the preflight steps in order, stopping at the first failure since the next ones depend
+/// on it.
+pub fn run(config: &RedisConfig) -> Vec<Step> {
+    let mut steps = vec![];
+    let (host, port) = match server(config) {
+        Ok(server) => server,
+        Err(e) => return vec![Step::new("sentinel", Err(e))],
+    };
+    if config.sentinel.is_some() {
+        steps.push(Step::new(
+            "sentinel",
+            Ok(format!("master at {host}:{port}")),
+        ));
+    }
+    steps.push(Step::new("dns", resolve(&host, port)));
+    if !steps.iter().all(|step| step.ok) {
+        return steps;
+    }
+
+    let mut connection = match connect(config) {
+        Ok(connection) => {
+            steps.push(Step::new("connect", Ok(format!("{host}:{port}"))));
+            connection
+        }
+        Err(e) => {
+            steps.push(Step::new("connect", Err(e)));
+            return steps;
+        }
+    };
+
+    steps.push(Step::new("ping", ping(&mut connection)));
+    if steps.iter().all(|step| step.ok) {
+        steps.push(Step::new("write", write_probe(&mut connection)));
+    }
+    steps
+}
+
+/// Report as returned to Python: `{"ok": bool, "checks": [{"name", "ok", "detail"}, ...]}`.
+pub fn report(py: Python, steps: &[Step]) -> PyResult<PyObject> {
+    let checks = steps
+        .iter()
+        .map(|step| {
+            let check = PyDict::new(py);
+            check.set_item("name", step.name)?;
+            check.set_item("ok", step.ok)?;
+            check.set_item("detail", &step.detail)?;
+            Ok(check)
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+
+    let report = PyDict::new(py);
+    report.set_item("ok", steps.iter().all(|step| step.ok))?;
+    report.set_item("checks", checks)?;
+    Ok(report.into())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn info_and_version() {
+        let info = parse_info("# Server\r\nredis_version:7.2.4\r\nredis_mode:standalone\r\n");
+        assert_eq!(info["redis_version"], "7.2.4");
+        assert_eq!(info["redis_mode"], "standalone");
+        assert_eq!(parse_version("7.2.4"), Some((7, 2, 4)));
+        assert_eq!(parse_version("6"), Some((6, 0, 0)));
+        assert_eq!(parse_version("bob"), None);
+    }
+
+    #[test]
+    fn stops_at_dns_failure() {
+        let config = RedisConfig {
+            host: "invalid host name".to_string(),
+            port: 6379,
+            ..Default::default()
+        };
+        let steps = run(&config);
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].name, "dns");
+        assert!(!steps[0].ok);
+    }
+}
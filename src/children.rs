@@ -0,0 +1,115 @@
+use crate::metrics::Metric;
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{IntoPyDict, PyDict};
+use pyo3::{PyTraverseError, PyVisit};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+// attribute of a pytheus collector holding its cache
+const CACHE_ATTRIBUTE: &str = "_labels_fast_cache";
+
+/// Labels of a child sorted by name, values formatted with `str` like pytheus does.
+type CanonicalLabels = Vec<(String, String)>;
+
+/// Children of a pytheus collector by their canonical labels, stored on the collector so that
+/// it lives and dies with it.
+#[pyclass]
+struct ChildCache {
+    children: Mutex<HashMap<CanonicalLabels, PyObject>>,
+}
+
+#[pymethods]
+impl ChildCache {
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        for child in self.children.lock().unwrap().values() {
+            visit.call(child)?;
+        }
+        Ok(())
+    }
+
+    fn __clear__(&mut self) {
+        self.children.lock().unwrap().clear();
+    }
+}
+
+/// Labels of the dicts, later ones overriding earlier ones.
+fn canonical_labels(dicts: &[Option<&PyDict>]) -> PyResult<CanonicalLabels> {
+    let mut labels = BTreeMap::new();
+    for dict in dicts.iter().flatten() {
+        for (name, value) in dict.iter() {
+            labels.insert(name.str()?.to_string(), value.str()?.to_string());
+        }
+    }
+    Ok(labels.into_iter().collect())
+}
+
+fn cache(collector: &PyAny) -> PyResult<&PyCell<ChildCache>> {
+    let py = collector.py();
+    if let Ok(cache) = collector.getattr(intern!(py, CACHE_ATTRIBUTE)) {
+        return Ok(cache.downcast()?);
+    }
+    let cache = PyCell::new(
+        py,
+        ChildCache {
+            children: Mutex::default(),
+        },
+    )?;
+    collector.setattr(intern!(py, CACHE_ATTRIBUTE), cache)?;
+    Ok(cache)
+}
+
+/// Child of a pytheus metric with the given labels, like `metric.labels(...)` but looked up in a
+/// cache of the collector keyed by the canonical labels, skipping the Python code of `labels`
+/// once the child was resolved. The children of the Rust metrics are already cached in Rust.
+#[pyfunction]
+#[pyo3(signature = (metric, labels=None, **kwargs))]
+pub fn labels_fast(
+    py: Python,
+    metric: &PyAny,
+    labels: Option<&PyDict>,
+    kwargs: Option<&PyDict>,
+) -> PyResult<PyObject> {
+    let given = canonical_labels(&[labels, kwargs])?.into_py_dict(py);
+    if metric.is_instance_of::<Metric>() {
+        return Ok(metric
+            .call_method(intern!(py, "labels"), (given,), None)?
+            .into());
+    }
+
+    let own_labels = metric.getattr(intern!(py, "_labels"))?;
+    let own_labels = match own_labels.is_none() {
+        true => None,
+        false => Some(own_labels.downcast::<PyDict>()?),
+    };
+    let key = canonical_labels(&[own_labels, Some(given)])?;
+    let cache = cache(metric.getattr(intern!(py, "_collector"))?)?.borrow();
+    if let Some(child) = cache.children.lock().unwrap().get(&key) {
+        return Ok(child.clone_ref(py));
+    }
+
+    let child: PyObject = metric
+        .call_method(intern!(py, "labels"), (given,), None)?
+        .into();
+    cache
+        .children
+        .lock()
+        .unwrap()
+        .insert(key, child.clone_ref(py));
+    Ok(child)
+}
+
+/// Drop a child from the cache of its collector, when it's evicted from the collector.
+pub fn forget(collector: &PyAny, child: &PyAny) -> PyResult<()> {
+    let Ok(cache) = collector.getattr(intern!(collector.py(), CACHE_ATTRIBUTE)) else {
+        return Ok(());
+    };
+    let cache: &PyCell<ChildCache> = cache.downcast()?;
+    cache
+        .borrow()
+        .children
+        .lock()
+        .unwrap()
+        .retain(|_, cached| !cached.is(child));
+    Ok(())
+}
@@ -0,0 +1,367 @@
+use crate::metrics::Gauge;
+use crate::registry::CollectorRegistry;
+use crate::snapshot;
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyType};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+type Labels = BTreeMap<String, String>;
+// values of a sample name by labels
+type Values = Vec<(Labels, f64)>;
+
+// series of the prometheus_client metrics created in the process
+static SERIES: Mutex<Option<Series>> = Mutex::new(None);
+
+/// Metric of prometheus_client: its type, help and the names of its samples, e.g.
+/// `requests_total` for the counter `requests`.
+#[derive(Clone)]
+struct Family {
+    type_: String,
+    help: String,
+    sample_names: Vec<String>,
+}
+
+/// Every sample name of the prometheus_client metrics stored as a Rust gauge, written and read
+/// by the backend loaded in pytheus like any of its metrics. Python is never called with the
+/// lock held, a call releasing the GIL could let a thread waiting for the lock take it.
+struct Series {
+    registry: PyObject,
+    gauges: HashMap<String, PyObject>,
+    families: BTreeMap<String, Family>,
+    // class of the backends of the gauges, which reads them in scrapes
+    backend_class: Option<Py<PyType>>,
+}
+
+impl Series {
+    fn copy(&self, py: Python) -> Self {
+        Self {
+            registry: self.registry.clone_ref(py),
+            gauges: self
+                .gauges
+                .iter()
+                .map(|(name, gauge)| (name.clone(), gauge.clone_ref(py)))
+                .collect(),
+            families: self.families.clone(),
+            backend_class: self.backend_class.as_ref().map(|class| class.clone_ref(py)),
+        }
+    }
+
+    /// Samples of every gauge: from `_generate_samples` of the backend class when it has one,
+    /// like pytheus' exposition, otherwise from the backend of every series.
+    fn samples(&self, py: Python) -> PyResult<HashMap<String, Values>> {
+        let mut samples = HashMap::new();
+        let generated = match &self.backend_class {
+            Some(class) if class.as_ref(py).hasattr(intern!(py, "_generate_samples"))? => Some(
+                class
+                    .as_ref(py)
+                    .call_method1(intern!(py, "_generate_samples"), (&self.registry,))?,
+            ),
+            _ => None,
+        };
+        for (name, gauge) in &self.gauges {
+            let collector = gauge.getattr(py, intern!(py, "_collector"))?;
+            let mut series = vec![];
+            match generated {
+                Some(generated) => {
+                    let Ok(out_samples) = generated.get_item(&collector) else {
+                        continue;
+                    };
+                    for sample in out_samples.iter()? {
+                        let sample = sample?;
+                        series.push((
+                            sample
+                                .getattr(intern!(py, "labels"))?
+                                .extract::<Option<Labels>>()?
+                                .unwrap_or_default(),
+                            sample.getattr(intern!(py, "value"))?.extract()?,
+                        ));
+                    }
+                }
+                None => {
+                    for child in snapshot::series(collector.as_ref(py))? {
+                        let backend = child.getattr(intern!(py, "_metric_value_backend"))?;
+                        series.push((
+                            child
+                                .getattr(intern!(py, "_labels"))?
+                                .extract::<Option<Labels>>()?
+                                .unwrap_or_default(),
+                            backend.call_method0(intern!(py, "get"))?.extract()?,
+                        ));
+                    }
+                }
+            }
+            samples.insert(name.clone(), series);
+        }
+        Ok(samples)
+    }
+}
+
+fn bound(labels: &Labels) -> f64 {
+    labels
+        .get("le")
+        .and_then(|le| le.parse().ok())
+        .unwrap_or(f64::INFINITY)
+}
+
+/// Cumulative buckets of histograms from the observations of each bucket as prometheus_client
+/// stores them, and the count of each histogram, by labels.
+fn accumulate_buckets(buckets: Values) -> (Values, Values) {
+    let mut histograms: BTreeMap<Labels, Values> = BTreeMap::new();
+    for (labels, value) in buckets {
+        let mut histogram_labels = labels.clone();
+        histogram_labels.remove("le");
+        histograms
+            .entry(histogram_labels)
+            .or_default()
+            .push((labels, value));
+    }
+    let mut cumulative = vec![];
+    let mut counts = vec![];
+    for (histogram_labels, mut buckets) in histograms {
+        buckets.sort_by(|(a, _), (b, _)| bound(a).total_cmp(&bound(b)));
+        let mut count = 0.0;
+        for (labels, value) in buckets {
+            count += value;
+            cumulative.push((labels, count));
+        }
+        counts.push((histogram_labels, count));
+    }
+    (cumulative, counts)
+}
+
+/// Registry of the gauges, and the gauge of a sample name when it was created already.
+fn registered_gauge(py: Python, name: &str) -> PyResult<(PyObject, Option<PyObject>)> {
+    if SERIES.lock().unwrap().is_none() {
+        let registry: PyObject = py.get_type::<CollectorRegistry>().call0()?.into();
+        SERIES.lock().unwrap().get_or_insert(Series {
+            registry,
+            gauges: HashMap::new(),
+            families: BTreeMap::new(),
+            backend_class: None,
+        });
+    }
+    let series = SERIES.lock().unwrap();
+    let series = series.as_ref().unwrap();
+    Ok((
+        series.registry.clone_ref(py),
+        series.gauges.get(name).map(|gauge| gauge.clone_ref(py)),
+    ))
+}
+
+/// Gauge storing a sample name, created with its labels on first use.
+fn gauge(py: Python, name: &str, labelnames: &[String]) -> PyResult<PyObject> {
+    let (registry, gauge) = registered_gauge(py, name)?;
+    if let Some(gauge) = gauge {
+        return Ok(gauge);
+    }
+    let kwargs = PyDict::new(py);
+    if !labelnames.is_empty() {
+        kwargs.set_item("required_labels", labelnames)?;
+    }
+    kwargs.set_item("registry", registry)?;
+    let created: PyObject = py
+        .get_type::<Gauge>()
+        .call((name, ""), Some(kwargs))?
+        .into();
+    let mut series = SERIES.lock().unwrap();
+    let gauges = &mut series.as_mut().unwrap().gauges;
+    // a gauge created concurrently by another thread was registered first
+    Ok(gauges
+        .entry(name.to_string())
+        .or_insert(created)
+        .clone_ref(py))
+}
+
+/// Value of a `prometheus_client` metric stored by the backend loaded in pytheus, e.g.
+/// `RedisBackend`: set `prometheus_client.values.ValueClass` to it, see
+/// `install_prometheus_client`, and expose the values with `PrometheusClientCollector` like in
+/// prometheus_client's multiprocess mode. Gauges are summed across processes unless set, the
+/// last value set wins, whatever their `multiprocess_mode`.
+#[pyclass]
+pub struct PrometheusClientValue {
+    metric: PyObject,
+}
+
+#[pymethods]
+impl PrometheusClientValue {
+    // the arguments prometheus_client creates its values with
+    #[allow(clippy::too_many_arguments)]
+    #[new]
+    #[pyo3(signature = (typ, metric_name, name, labelnames, labelvalues, help_text, **_kwargs))]
+    fn new(
+        py: Python,
+        typ: String,
+        metric_name: String,
+        name: &str,
+        labelnames: Vec<String>,
+        labelvalues: Vec<String>,
+        help_text: String,
+        _kwargs: Option<&PyDict>,
+    ) -> PyResult<Self> {
+        let gauge = gauge(py, name, &labelnames)?;
+        let metric = match labelnames.is_empty() {
+            true => gauge,
+            false => {
+                let labels: BTreeMap<String, String> =
+                    labelnames.into_iter().zip(labelvalues).collect();
+                gauge.call_method1(py, intern!(py, "labels"), (labels,))?
+            }
+        };
+        let backend = metric.getattr(py, intern!(py, "_metric_value_backend"))?;
+        let backend_class: Option<Py<PyType>> =
+            (!backend.is_none(py)).then(|| backend.as_ref(py).get_type().into());
+
+        let mut series = SERIES.lock().unwrap();
+        let series = series.as_mut().unwrap();
+        if series.backend_class.is_none() {
+            series.backend_class = backend_class;
+        }
+        let family = series.families.entry(metric_name).or_insert(Family {
+            type_: typ,
+            help: help_text,
+            sample_names: vec![],
+        });
+        if !family
+            .sample_names
+            .iter()
+            .any(|sample_name| sample_name == name)
+        {
+            family.sample_names.push(name.to_string());
+        }
+        Ok(Self { metric })
+    }
+
+    fn inc(&self, py: Python, amount: f64) -> PyResult<()> {
+        self.metric
+            .call_method1(py, intern!(py, "inc"), (amount,))?;
+        Ok(())
+    }
+
+    /// Set the value, the timestamp isn't stored.
+    #[pyo3(signature = (value, _timestamp=None))]
+    fn set(&self, py: Python, value: f64, _timestamp: Option<f64>) -> PyResult<()> {
+        self.metric.call_method1(py, intern!(py, "set"), (value,))?;
+        Ok(())
+    }
+
+    /// Exemplars aren't stored.
+    fn set_exemplar(&self, _exemplar: &PyAny) {}
+
+    fn get_exemplar(&self) -> Option<PyObject> {
+        None
+    }
+
+    /// Value as known to the backend, `RedisBackend` doesn't read Redis for it.
+    fn get(&self, py: Python) -> PyResult<f64> {
+        self.metric
+            .getattr(py, intern!(py, "_metric_value_backend"))?
+            .call_method0(py, intern!(py, "get"))?
+            .extract(py)
+    }
+}
+
+/// Collector of `prometheus_client` exposing the metrics stored with `PrometheusClientValue` by
+/// every process, to register to the registry of the exposition, e.g.
+/// `CollectorRegistry().register(PrometheusClientCollector())`. The buckets of histograms are
+/// made cumulative and counted like prometheus_client's `MultiProcessCollector` does.
+#[pyclass]
+pub struct PrometheusClientCollector {}
+
+#[pymethods]
+impl PrometheusClientCollector {
+    #[new]
+    fn new() -> Self {
+        Self {}
+    }
+
+    /// No metric is described, so that registering doesn't read the backend.
+    fn describe(&self) -> Vec<PyObject> {
+        vec![]
+    }
+
+    fn collect(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        let series = match &*SERIES.lock().unwrap() {
+            Some(series) => series.copy(py),
+            None => return Ok(vec![]),
+        };
+        let metric_class = py
+            .import(intern!(py, "prometheus_client.metrics_core"))?
+            .getattr(intern!(py, "Metric"))?;
+        let mut samples = series.samples(py)?;
+
+        let mut metrics = vec![];
+        for (metric_name, family) in &series.families {
+            let metric = metric_class.call1((metric_name, &family.help, &family.type_))?;
+            let mut family_samples = vec![];
+            for sample_name in &family.sample_names {
+                let values = samples.remove(sample_name).unwrap_or_default();
+                let counted = match family.type_.as_str() {
+                    "histogram" => "_count",
+                    "gaugehistogram" => "_gcount",
+                    _ => "",
+                };
+                if counted.is_empty() || !sample_name.ends_with("_bucket") {
+                    family_samples.push((sample_name.clone(), values));
+                    continue;
+                }
+                let (buckets, counts) = accumulate_buckets(values);
+                family_samples.push((sample_name.clone(), buckets));
+                family_samples.push((format!("{metric_name}{counted}"), counts));
+            }
+            for (sample_name, values) in family_samples {
+                for (labels, value) in values {
+                    metric.call_method1(
+                        intern!(py, "add_sample"),
+                        (sample_name.as_str(), labels, value),
+                    )?;
+                }
+            }
+            metrics.push(metric.into());
+        }
+        Ok(metrics)
+    }
+}
+
+/// Store the values of the `prometheus_client` metrics created from now on with the backend
+/// loaded in pytheus, by setting `prometheus_client.values.ValueClass`.
+#[pyfunction]
+pub fn install_prometheus_client(py: Python) -> PyResult<()> {
+    py.import(intern!(py, "prometheus_client.values"))?.setattr(
+        intern!(py, "ValueClass"),
+        py.get_type::<PrometheusClientValue>(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn cumulative_buckets() {
+        let bucket = |method: &str, le: &str, value| {
+            let labels = [("method", method), ("le", le)];
+            let labels = labels.map(|(name, value)| (name.to_string(), value.to_string()));
+            (Labels::from(labels), value)
+        };
+        let (buckets, counts) = accumulate_buckets(vec![
+            bucket("GET", "+Inf", 1.0),
+            bucket("GET", "5.0", 2.0),
+            bucket("POST", "1.0", 4.0),
+            bucket("GET", "1.0", 0.0),
+        ]);
+        assert_eq!(
+            buckets,
+            [
+                bucket("GET", "1.0", 0.0),
+                bucket("GET", "5.0", 2.0),
+                bucket("GET", "+Inf", 3.0),
+                bucket("POST", "1.0", 4.0),
+            ]
+        );
+        let method = |method: &str| Labels::from([("method".to_string(), method.to_string())]);
+        assert_eq!(counts, [(method("GET"), 3.0), (method("POST"), 4.0)]);
+    }
+}
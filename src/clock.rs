@@ -0,0 +1,141 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Source of the current time for every time-based decision in the crate (expiry, timestamps),
+/// so that tests can control it instead of sleeping.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock that only moves when told to.
+pub struct ManualClock {
+    now: Mutex<SystemTime>,
+}
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+static CLOCK: OnceLock<RwLock<Arc<dyn Clock>>> = OnceLock::new();
+
+fn clock() -> &'static RwLock<Arc<dyn Clock>> {
+    CLOCK.get_or_init(|| RwLock::new(Arc::new(SystemClock)))
+}
+
+/// Current time according to the installed clock.
+pub fn now() -> SystemTime {
+    clock().read().unwrap().now()
+}
+
+/// Current unix timestamp in seconds according to the installed clock.
+pub fn unix_timestamp() -> f64 {
+    now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn system_time_from_timestamp(timestamp: f64) -> PyResult<SystemTime> {
+    if !timestamp.is_finite() || timestamp < 0.0 {
+        return Err(PyValueError::new_err(format!(
+            "invalid timestamp: {timestamp}"
+        )));
+    }
+    Ok(UNIX_EPOCH + Duration::from_secs_f64(timestamp))
+}
+
+/// Python-settable clock, install it with `set_clock` to drive time in tests.
+#[pyclass]
+pub struct TestClock {
+    inner: Arc<ManualClock>,
+}
+
+#[pymethods]
+impl TestClock {
+    #[new]
+    #[pyo3(signature = (timestamp=None))]
+    fn new(timestamp: Option<f64>) -> PyResult<Self> {
+        let now = match timestamp {
+            Some(timestamp) => system_time_from_timestamp(timestamp)?,
+            None => SystemTime::now(),
+        };
+        Ok(Self {
+            inner: Arc::new(ManualClock::new(now)),
+        })
+    }
+
+    fn time(&self) -> f64 {
+        self.inner
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+    }
+
+    fn set(&self, timestamp: f64) -> PyResult<()> {
+        self.inner.set(system_time_from_timestamp(timestamp)?);
+        Ok(())
+    }
+
+    fn advance(&self, seconds: f64) -> PyResult<()> {
+        if !seconds.is_finite() || seconds < 0.0 {
+            return Err(PyValueError::new_err("the clock can only move forward"));
+        }
+        self.inner.advance(Duration::from_secs_f64(seconds));
+        Ok(())
+    }
+}
+
+/// Install a `TestClock`, or restore the system clock when called with `None`.
+#[pyfunction]
+pub fn set_clock(clock: Option<PyRef<TestClock>>) {
+    let new_clock: Arc<dyn Clock> = match clock {
+        Some(clock) => clock.inner.clone(),
+        None => Arc::new(SystemClock),
+    };
+    *self::clock().write().unwrap() = new_clock;
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::new(UNIX_EPOCH);
+        assert_eq!(clock.now(), UNIX_EPOCH);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(10));
+        clock.set(UNIX_EPOCH + Duration::from_secs(3));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(3));
+    }
+}
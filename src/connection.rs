@@ -0,0 +1,566 @@
+use crate::credentials::Credentials;
+use crate::sentinel::{self, Sentinel};
+use redis::{ConnectionLike, ErrorKind, RedisError, RedisResult, Value};
+use rustls::{ClientConfig, ClientConnection, ServerName, StreamOwned};
+use socket2::{SockRef, TcpKeepalive};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Keepalive probes of the connections, the system defaults for the unset settings.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Keepalive {
+    /// Idle time before the first probe.
+    pub idle: Option<Duration>,
+    /// Time between the probes.
+    pub interval: Option<Duration>,
+    /// Unanswered probes before the connection is dropped, always 10 on Windows.
+    pub count: Option<u32>,
+}
+
+/// Options of the sockets of the connections to Redis.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SocketOptions {
+    /// Probe idle connections, so that the ones silently dropped on the way, e.g. by a NAT, are
+    /// noticed before a write is sent to them.
+    pub keepalive: Option<Keepalive>,
+    /// Disable Nagle's algorithm, sending the small commands without waiting.
+    pub nodelay: bool,
+    /// How long connecting to an address may take, the system timeout when unset.
+    pub connect_timeout: Option<Duration>,
+    /// How long sending a command or waiting for its reply may take, so that a stalled server
+    /// fails the command and the connection is replaced instead of blocking the worker.
+    pub command_timeout: Option<Duration>,
+}
+
+impl SocketOptions {
+    fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        stream.set_read_timeout(self.command_timeout)?;
+        stream.set_write_timeout(self.command_timeout)?;
+        let Some(keepalive) = self.keepalive else {
+            return Ok(());
+        };
+        let mut params = TcpKeepalive::new();
+        if let Some(idle) = keepalive.idle {
+            params = params.with_time(idle);
+        }
+        if let Some(interval) = keepalive.interval {
+            params = params.with_interval(interval);
+        }
+        // Windows doesn't let the number of probes be set
+        #[cfg(not(windows))]
+        if let Some(count) = keepalive.count {
+            params = params.with_retries(count);
+        }
+        SockRef::from(stream).set_tcp_keepalive(&params)
+    }
+}
+
+/// Host of an endpoint as given to the resolver, IPv6 addresses may be in brackets like in URLs.
+pub fn host(host: &str) -> &str {
+    match host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+    {
+        Some(address) => address,
+        None => host,
+    }
+}
+
+/// Addresses of an endpoint, IPv4 or IPv6, resolved again on every call so that the endpoints
+/// moved by changing their DNS records are followed.
+fn resolve(endpoint_host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    Ok((host(endpoint_host), port).to_socket_addrs()?.collect())
+}
+
+/// Whether the server refused a write as a replica, e.g. the former primary of a managed
+/// service after a failover switching the DNS record of the endpoint to the new primary.
+pub fn is_read_only_error(error: &redis::RedisError) -> bool {
+    error.code() == Some("READONLY")
+}
+
+/// Socket of a connection, encrypted when the server requires TLS.
+enum Stream {
+    Tcp(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    /// Wrap a socket in TLS, completing the handshake so that a rejected certificate fails the
+    /// connection rather than its first command.
+    fn tls(
+        mut socket: TcpStream,
+        host_name: &str,
+        config: &Arc<ClientConfig>,
+    ) -> RedisResult<Self> {
+        let name = ServerName::try_from(host(host_name)).map_err(|_| {
+            RedisError::from((
+                ErrorKind::InvalidClientConfig,
+                "invalid TLS server name",
+                host_name.to_string(),
+            ))
+        })?;
+        let mut tls = ClientConnection::new(config.clone(), name).map_err(|e| {
+            RedisError::from((ErrorKind::InvalidClientConfig, "TLS failed", e.to_string()))
+        })?;
+        while tls.is_handshaking() {
+            tls.complete_io(&mut socket)?;
+        }
+        Ok(Stream::Tls(Box::new(StreamOwned::new(tls, socket))))
+    }
+
+    #[cfg(test)]
+    fn socket(&self) -> &TcpStream {
+        match self {
+            Stream::Tcp(socket) => socket,
+            Stream::Tls(stream) => &stream.sock,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(socket) => socket.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(socket) => socket.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(socket) => socket.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Reply of every command of a transaction, `None` when a watched key changed.
+pub type TransactionReplies = Option<Vec<RedisResult<Value>>>;
+
+/// Connection to a Redis server over a socket set up with the `SocketOptions`, the ones of
+/// redis-rs can't be tuned.
+pub struct Connection {
+    stream: BufReader<Stream>,
+    peer: SocketAddr,
+    open: bool,
+    db: i64,
+}
+
+impl Connection {
+    /// Connect to the first address of the endpoint accepting the connection, over TLS when
+    /// there's a TLS config.
+    pub fn open(
+        host: &str,
+        port: u16,
+        options: &SocketOptions,
+        tls: Option<&Arc<ClientConfig>>,
+    ) -> RedisResult<Self> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no address for the host");
+        for peer in resolve(host, port)? {
+            let socket = match options.connect_timeout {
+                Some(timeout) => TcpStream::connect_timeout(&peer, timeout),
+                None => TcpStream::connect(peer),
+            };
+            match socket {
+                Ok(socket) => {
+                    options.apply(&socket)?;
+                    let stream = match tls {
+                        Some(config) => Stream::tls(socket, host, config)?,
+                        None => Stream::Tcp(socket),
+                    };
+                    return Ok(Self {
+                        stream: BufReader::new(stream),
+                        peer,
+                        open: true,
+                        db: 0,
+                    });
+                }
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err.into())
+    }
+
+    /// Switch to a logical database, the commands sent afterwards use its keys.
+    pub fn select(&mut self, db: i64) -> RedisResult<()> {
+        redis::cmd("SELECT").arg(db).query::<()>(self)?;
+        self.db = db;
+        Ok(())
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> RedisResult<()> {
+        self.stream.get_mut().write_all(bytes).map_err(|e| {
+            self.open = false;
+            e.into()
+        })
+    }
+
+    /// Read the bytes of the next reply, with where each of its elements starts when it's an
+    /// array. The IO errors leave the connection unusable.
+    fn read_raw_reply(&mut self) -> RedisResult<(Vec<u8>, Vec<usize>)> {
+        let mut raw = vec![];
+        match read_reply(&mut self.stream, &mut raw) {
+            Ok(elements) => Ok((raw, elements)),
+            Err(e) => {
+                self.open = false;
+                Err(e.into())
+            }
+        }
+    }
+
+    // the server errors are replies like any other
+    fn read_reply(&mut self) -> RedisResult<Value> {
+        let (raw, _) = self.read_raw_reply()?;
+        redis::parse_redis_value(&raw)
+    }
+
+    /// Execute the commands of an atomic pipeline, returning the reply of each of them: Redis
+    /// applies the others when one of them fails. Fails as a whole when the transaction was
+    /// aborted, none of them being applied, and is `None` when a watched key changed.
+    pub fn transaction(&mut self, pipe: &redis::Pipeline) -> RedisResult<TransactionReplies> {
+        self.send(&pipe.get_packed_pipeline())?;
+        // MULTI and the commands queued, a command rejected when queued aborts the EXEC
+        let mut first_err = None;
+        for _ in 0..=pipe.cmd_iter().count() {
+            if let Err(e) = self.read_reply() {
+                if e.is_io_error() {
+                    return Err(e);
+                }
+                first_err.get_or_insert(e);
+            }
+        }
+        let (raw, elements) = self.read_raw_reply()?;
+        if let Some(e) = first_err {
+            return Err(e);
+        }
+        if raw.first() != Some(&b'*') {
+            return match redis::parse_redis_value(&raw)? {
+                Value::Nil => Ok(None),
+                _ => Err(RedisError::from((
+                    ErrorKind::ResponseError,
+                    "Invalid response when parsing multi response",
+                ))),
+            };
+        }
+        let ends = elements.iter().skip(1).copied().chain([raw.len()]);
+        Ok(Some(
+            elements
+                .iter()
+                .zip(ends)
+                .map(|(start, end)| redis::parse_redis_value(&raw[*start..end]))
+                .collect(),
+        ))
+    }
+}
+
+/// Read the line of a reply up to its CRLF into `raw`, returning where it starts.
+fn read_line(reader: &mut impl BufRead, raw: &mut Vec<u8>) -> io::Result<usize> {
+    let start = raw.len();
+    reader.read_until(b'\n', raw)?;
+    match raw[start..].strip_suffix(b"\r\n") {
+        Some(line) if !line.is_empty() => Ok(start),
+        _ => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+/// Read the bytes of one RESP2 reply into `raw`, returning where the elements of an array
+/// start.
+fn read_reply(reader: &mut impl BufRead, raw: &mut Vec<u8>) -> io::Result<Vec<usize>> {
+    let start = read_line(reader, raw)?;
+    let length: Option<i64> = std::str::from_utf8(&raw[start + 1..raw.len() - 2])
+        .ok()
+        .and_then(|length| length.parse().ok());
+    match (raw[start], length) {
+        (b'+' | b'-' | b':', _) => Ok(vec![]),
+        (b'$', Some(length)) => {
+            if length >= 0 {
+                let data = raw.len();
+                raw.resize(data + length as usize + 2, 0);
+                reader.read_exact(&mut raw[data..])?;
+            }
+            Ok(vec![])
+        }
+        (b'*', Some(length)) => {
+            let mut elements = vec![];
+            for _ in 0..length.max(0) {
+                elements.push(raw.len());
+                read_reply(reader, raw)?;
+            }
+            Ok(elements)
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid reply")),
+    }
+}
+
+impl ConnectionLike for Connection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        self.send(cmd)?;
+        self.read_reply()
+    }
+
+    /// Replies of a pipeline from the `offset` one, failing with the first error once every
+    /// reply was read.
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        self.send(cmd)?;
+        let mut replies = vec![];
+        let mut first_err = None;
+        for index in 0..offset + count {
+            match self.read_reply() {
+                Ok(reply) if index >= offset => replies.push(reply),
+                Ok(_) => {}
+                Err(e) => {
+                    let broken = e.is_io_error();
+                    first_err.get_or_insert(e);
+                    if broken {
+                        break;
+                    }
+                }
+            }
+        }
+        first_err.map_or(Ok(replies), Err)
+    }
+
+    fn get_db(&self) -> i64 {
+        self.db
+    }
+
+    fn check_connection(&mut self) -> bool {
+        redis::cmd("PING").query::<String>(self).is_ok()
+    }
+
+    fn is_open(&self) -> bool {
+        self.open
+    }
+}
+
+/// Connections of the pools, authenticated when there are credentials. A credential provider is
+/// called for every new connection, so that it can hand out short-lived tokens. With Sentinel the
+/// connections go to the current master instead of the host and port. The connections then
+/// select the logical database of the keys.
+pub struct Manager {
+    host: String,
+    port: u16,
+    options: SocketOptions,
+    tls: Option<Arc<ClientConfig>>,
+    credentials: Option<Credentials>,
+    sentinel: Option<Sentinel>,
+    db: i64,
+}
+
+impl Manager {
+    pub fn new(
+        host: &str,
+        port: u16,
+        options: SocketOptions,
+        tls: Option<Arc<ClientConfig>>,
+        credentials: Option<Credentials>,
+    ) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            options,
+            tls,
+            credentials,
+            sentinel: None,
+            db: 0,
+        }
+    }
+
+    pub fn with_sentinel(self, sentinel: Option<Sentinel>) -> Self {
+        Self { sentinel, ..self }
+    }
+
+    pub fn with_db(self, db: i64) -> Self {
+        Self { db, ..self }
+    }
+}
+
+impl r2d2::ManageConnection for Manager {
+    type Connection = Connection;
+    type Error = redis::RedisError;
+
+    fn connect(&self) -> RedisResult<Connection> {
+        let mut connection = match &self.sentinel {
+            Some(sentinel) => {
+                let (host, port) = sentinel.master(&self.options, self.tls.as_ref())?;
+                Connection::open(&host, port, &self.options, self.tls.as_ref())?
+            }
+            None => Connection::open(&self.host, self.port, &self.options, self.tls.as_ref())?,
+        };
+        if let Some(credentials) = &self.credentials {
+            credentials.authenticate(&mut connection)?;
+        }
+        if self.db != 0 {
+            connection.select(self.db)?;
+        }
+        Ok(connection)
+    }
+
+    /// Alive and still connected to an address of the endpoint, the connections to the former
+    /// addresses are replaced. Kept when the host can't be resolved, like the connections of
+    /// redis-rs. With Sentinel, still connected to a master.
+    fn is_valid(&self, connection: &mut Connection) -> RedisResult<()> {
+        if self.sentinel.is_some() {
+            return match sentinel::is_master(connection)? {
+                true => Ok(()),
+                false => Err(redis::RedisError::from((
+                    redis::ErrorKind::IoError,
+                    "master demoted to a replica",
+                ))),
+            };
+        }
+        redis::cmd("PING").query::<()>(connection)?;
+        match resolve(&self.host, self.port) {
+            Ok(peers) if !peers.contains(&connection.peer) => Err(redis::RedisError::from((
+                redis::ErrorKind::IoError,
+                "endpoint moved to another address",
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn has_broken(&self, connection: &mut Connection) -> bool {
+        !connection.is_open()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn pipeline_replies() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream.write_all(b"+OK\r\n:2\r\n-ERR wrong\r\n").unwrap();
+        });
+
+        let options = SocketOptions {
+            keepalive: Some(Keepalive {
+                idle: Some(Duration::from_secs(30)),
+                interval: Some(Duration::from_secs(5)),
+                count: Some(3),
+            }),
+            nodelay: true,
+            connect_timeout: Some(Duration::from_secs(1)),
+            command_timeout: None,
+        };
+        let mut connection = Connection::open("127.0.0.1", port, &options, None).unwrap();
+        let socket = SockRef::from(connection.stream.get_ref().socket());
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+        assert!(connection.stream.get_ref().socket().nodelay().unwrap());
+
+        let mut pipe = redis::pipe();
+        pipe.cmd("SET").arg("a").arg(1).ignore();
+        pipe.cmd("INCR").arg("a");
+        pipe.cmd("FOO");
+        let result: RedisResult<Value> = pipe.query(&mut connection);
+        assert_eq!(result.unwrap_err().code(), Some("ERR"));
+        assert!(connection.is_open());
+        server.join().unwrap();
+
+        // the server closed the connection
+        assert!(!connection.check_connection());
+        assert!(!connection.is_open());
+    }
+
+    #[test]
+    fn transaction_replies() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"+OK\r\n+QUEUED\r\n+QUEUED\r\n+QUEUED\r\n")
+                .unwrap();
+            stream
+                .write_all(b"*3\r\n$3\r\n4\r\n\r\n-WRONGTYPE not a hash\r\n*1\r\n:1\r\n")
+                .unwrap();
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"+OK\r\n-ERR unknown command\r\n-EXECABORT discarded\r\n")
+                .unwrap();
+        });
+
+        let options = SocketOptions::default();
+        let mut connection = Connection::open("127.0.0.1", port, &options, None).unwrap();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.cmd("INCRBYFLOAT").arg("a").arg(4);
+        pipe.cmd("HINCRBYFLOAT").arg("b").arg("c").arg(1);
+        pipe.cmd("LRANGE").arg("d").arg(0).arg(-1);
+        let replies = connection.transaction(&pipe).unwrap().unwrap();
+        assert_eq!(replies[0], Ok(Value::Data(b"4\r\n".to_vec())));
+        assert_eq!(replies[1].as_ref().unwrap_err().code(), Some("WRONGTYPE"));
+        assert_eq!(replies[2], Ok(Value::Bulk(vec![Value::Int(1)])));
+
+        // rejected when queued, nothing applied
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.cmd("FOO");
+        let result = connection.transaction(&pipe);
+        assert_eq!(result.unwrap_err().code(), Some("ERR"));
+        assert!(connection.is_open());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn command_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let options = SocketOptions {
+            command_timeout: Some(Duration::from_millis(50)),
+            ..SocketOptions::default()
+        };
+        let mut connection = Connection::open("127.0.0.1", port, &options, None).unwrap();
+        // accepted, never answered
+        let (_stalled, _) = listener.accept().unwrap();
+        let started = std::time::Instant::now();
+        let result: RedisResult<String> = redis::cmd("PING").query(&mut connection);
+        assert!(result.unwrap_err().is_io_error());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!connection.is_open());
+    }
+
+    #[test]
+    fn ipv6_endpoints() {
+        assert_eq!(host("[::1]"), "::1");
+        assert_eq!(host("::1"), "::1");
+        assert_eq!(host("redis.internal"), "redis.internal");
+
+        // not every sandbox has an IPv6 loopback
+        let Ok(listener) = TcpListener::bind("[::1]:0") else {
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        let connection = Connection::open("[::1]", port, &SocketOptions::default(), None).unwrap();
+        assert!(connection.peer.is_ipv6());
+    }
+}
@@ -0,0 +1,81 @@
+use pyo3::exceptions::PyPermissionError;
+use pyo3::prelude::*;
+use redis::{ConnectionLike, ErrorKind, RedisError, RedisResult};
+
+/// How the connections authenticate.
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    /// The `username` and `password` of the config, the default user when there's no username.
+    Static {
+        username: Option<String>,
+        password: String,
+    },
+    /// Python callable returning a password or a `(username, password)` tuple.
+    Provider(PyObject),
+}
+
+impl Credentials {
+    /// Authenticate a new connection, with fresh credentials from the provider when there's one.
+    pub fn authenticate(&self, connection: &mut dyn ConnectionLike) -> RedisResult<()> {
+        let (username, password) = match self {
+            Credentials::Static { username, password } => (username.clone(), password.clone()),
+            Credentials::Provider(provider) => fetch(provider)?,
+        };
+        let mut auth = redis::cmd("AUTH");
+        if let Some(username) = username {
+            auth.arg(username);
+        }
+        auth.arg(password).query(connection)
+    }
+}
+
+/// Credentials returned by the provider, a password or a `(username, password)` tuple.
+fn fetch(provider: &PyObject) -> RedisResult<(Option<String>, String)> {
+    Python::with_gil(|py| {
+        let credentials = provider.call0(py)?;
+        let credentials = credentials.as_ref(py);
+        match credentials.extract() {
+            Ok(credentials) => Ok(credentials),
+            Err(_) => Ok((None, credentials.extract()?)),
+        }
+    })
+    .map_err(|e: PyErr| {
+        RedisError::from((
+            ErrorKind::AuthenticationFailed,
+            "credential provider failed",
+            e.to_string(),
+        ))
+    })
+}
+
+/// Python exception of credentials rejected by the server.
+pub fn auth_failed(error: &RedisError) -> PyErr {
+    PyPermissionError::new_err(format!("Redis authentication failed: {error}"))
+}
+
+/// Whether the server rejected the credentials of the connection, expired tokens included.
+pub fn is_auth_error(error: &RedisError) -> bool {
+    error.kind() == ErrorKind::AuthenticationFailed
+        || matches!(error.code(), Some("NOAUTH" | "WRONGPASS"))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn reply_error(reply: &str) -> RedisError {
+        redis::parse_redis_value(reply.as_bytes()).unwrap_err()
+    }
+
+    #[test]
+    fn auth_errors() {
+        assert!(is_auth_error(&reply_error(
+            "-NOAUTH Authentication required.\r\n"
+        )));
+        assert!(is_auth_error(&reply_error(
+            "-WRONGPASS invalid username-password pair\r\n"
+        )));
+        assert!(!is_auth_error(&reply_error("-ERR unknown command\r\n")));
+    }
+}
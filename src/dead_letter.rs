@@ -0,0 +1,232 @@
+use crate::config::Storage;
+use crate::observations::Target;
+use crate::unique::Items;
+use crate::{BackendAction, RedisJob};
+use serde_json::{json, Value};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// guards the file so that taking the jobs can't lose the ones appended meanwhile
+static DEAD_LETTER_LOCK: Mutex<()> = Mutex::new(());
+
+fn job_to_line(job: &RedisJob) -> String {
+    let action = match job.action {
+        BackendAction::Inc => "inc",
+        BackendAction::Dec => "dec",
+        BackendAction::Set => "set",
+    };
+    json!({
+        "action": action,
+        "key_name": job.key_name,
+        "labels_hash": job.labels_hash,
+        "value": job.value,
+        "expire_at": job.expire_at,
+        "lease_at": job.lease_at,
+        "last_updated_key": job.last_updated_key,
+        "created_key": job.created_key,
+        "digest_key": job.digest_key,
+        "observations": job.observations.as_ref().map(|target| json!({
+            "key_name": target.key_name,
+            "maxlen": target.maxlen,
+        })),
+        "unique_items": job.unique_items.as_ref().map(|items| json!({
+            "hll_key": items.hll_key,
+            "items": items.items,
+        })),
+        "storage": job.storage.name(),
+        "route": job.route,
+    })
+    .to_string()
+}
+
+fn job_from_line(line: &str) -> Result<RedisJob, Box<dyn std::error::Error>> {
+    let value: Value = serde_json::from_str(line)?;
+    let action = match value["action"].as_str() {
+        Some("inc") => BackendAction::Inc,
+        Some("dec") => BackendAction::Dec,
+        Some("set") => BackendAction::Set,
+        _ => return Err(format!("invalid dead letter action: {line}").into()),
+    };
+    let key_name = value["key_name"]
+        .as_str()
+        .ok_or_else(|| format!("invalid dead letter key_name: {line}"))?
+        .to_string();
+    let job_value = value["value"]
+        .as_f64()
+        .ok_or_else(|| format!("invalid dead letter value: {line}"))?;
+
+    Ok(RedisJob {
+        action,
+        key_name,
+        labels_hash: value["labels_hash"].as_str().map(str::to_string),
+        value: job_value,
+        expire_at: value["expire_at"].as_u64().map(|ts| ts as usize),
+        lease_at: value["lease_at"].as_u64().map(|ts| ts as usize),
+        last_updated_key: value["last_updated_key"].as_str().map(str::to_string),
+        created_key: value["created_key"].as_str().map(str::to_string),
+        digest_key: value["digest_key"].as_str().map(str::to_string),
+        observations: match (
+            value["observations"]["key_name"].as_str(),
+            value["observations"]["maxlen"].as_u64(),
+        ) {
+            (Some(key_name), Some(maxlen)) => Some(Target {
+                key_name: key_name.to_string(),
+                maxlen: maxlen as usize,
+            }),
+            _ => None,
+        },
+        unique_items: match (
+            value["unique_items"]["hll_key"].as_str(),
+            value["unique_items"]["items"].as_array(),
+        ) {
+            (Some(hll_key), Some(items)) => Some(Items {
+                hll_key: hll_key.to_string(),
+                items: items
+                    .iter()
+                    .filter_map(|item| item.as_str().map(str::to_string))
+                    .collect(),
+            }),
+            _ => None,
+        },
+        storage: value["storage"]
+            .as_str()
+            .and_then(Storage::parse)
+            .unwrap_or_default(),
+        // written before routes existed
+        route: value["route"].as_u64().unwrap_or_default() as usize,
+        ack_tx: None,
+        reply_tx: None,
+    })
+}
+
+/// Append the jobs to the dead letter file, creating it if needed.
+pub fn append(path: &Path, jobs: &[&RedisJob]) -> io::Result<()> {
+    let _guard = DEAD_LETTER_LOCK.lock().unwrap();
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for job in jobs {
+        writeln!(file, "{}", job_to_line(job))?;
+    }
+    Ok(())
+}
+
+/// Remove and return every job stored in the dead letter file.
+pub fn take(path: &Path) -> Result<Vec<RedisJob>, Box<dyn std::error::Error>> {
+    let _guard = DEAD_LETTER_LOCK.lock().unwrap();
+    let jobs = read(path)?;
+    clear(path)?;
+    Ok(jobs)
+}
+
+// a missing file has no jobs
+fn read(path: &Path) -> Result<Vec<RedisJob>, Box<dyn std::error::Error>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut jobs = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            jobs.push(job_from_line(&line)?);
+        }
+    }
+    Ok(jobs)
+}
+
+fn clear(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn line_roundtrip() {
+        let job = RedisJob {
+            action: BackendAction::Dec,
+            key_name: "name".to_string(),
+            labels_hash: Some(r#"{"bob":"cat"}"#.to_string()),
+            value: -2.5,
+            expire_at: Some(1700000000),
+            lease_at: Some(1700000300),
+            last_updated_key: Some("name:last_updated".to_string()),
+            created_key: None,
+            digest_key: Some("name:tdigest:".to_string()),
+            observations: Some(Target {
+                key_name: "name:observations:".to_string(),
+                maxlen: 100,
+            }),
+            unique_items: Some(Items {
+                hll_key: "name:hll:".to_string(),
+                items: vec!["alice".to_string(), "bob".to_string()],
+            }),
+            storage: Storage::TimeSeries,
+            route: 1,
+            ack_tx: None,
+            reply_tx: None,
+        };
+        let parsed = job_from_line(&job_to_line(&job)).unwrap();
+        assert!(matches!(parsed.action, BackendAction::Dec));
+        assert_eq!(parsed.key_name, "name");
+        assert_eq!(parsed.labels_hash.as_deref(), Some(r#"{"bob":"cat"}"#));
+        assert_eq!(parsed.value, -2.5);
+        assert_eq!(parsed.expire_at, Some(1700000000));
+        assert_eq!(parsed.lease_at, Some(1700000300));
+        assert_eq!(parsed.route, 1);
+        assert_eq!(
+            parsed.last_updated_key.as_deref(),
+            Some("name:last_updated")
+        );
+        assert_eq!(parsed.digest_key.as_deref(), Some("name:tdigest:"));
+        let observations = parsed.observations.unwrap();
+        assert_eq!(observations.key_name, "name:observations:");
+        assert_eq!(observations.maxlen, 100);
+        let unique_items = parsed.unique_items.unwrap();
+        assert_eq!(unique_items.hll_key, "name:hll:");
+        assert_eq!(unique_items.items, ["alice", "bob"]);
+        assert_eq!(parsed.storage, Storage::TimeSeries);
+    }
+
+    #[test]
+    fn invalid_line() {
+        assert!(job_from_line(r#"{"action": "observe"}"#).is_err());
+        assert!(job_from_line("not json").is_err());
+    }
+
+    #[test]
+    fn append_and_take() {
+        let path = std::env::temp_dir().join("pytheus_dead_letter_test.jsonl");
+        clear(&path).unwrap();
+        let job = RedisJob {
+            action: BackendAction::Inc,
+            key_name: "name".to_string(),
+            labels_hash: None,
+            value: 1.0,
+            expire_at: None,
+            lease_at: None,
+            last_updated_key: None,
+            created_key: Some("name:created".to_string()),
+            digest_key: None,
+            observations: None,
+            unique_items: None,
+            storage: Storage::Keys,
+            route: 0,
+            ack_tx: None,
+            reply_tx: None,
+        };
+        append(&path, &[&job]).unwrap();
+        let jobs = take(&path).unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].labels_hash, None);
+        assert!(take(&path).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,340 @@
+use crate::check;
+use crate::clock;
+use crate::config::RedisConfig;
+use crate::connection;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+const LATENCY_SAMPLES: usize = 10;
+const LATENCY_WARNING: Duration = Duration::from_millis(10);
+const CLOCK_SKEW_WARNING_SECONDS: f64 = 1.0;
+// enough to spot collisions without walking a huge keyspace
+const MAX_SCANNED_KEYS: usize = 10_000;
+// multi-field HSET
+const MIN_SERVER_VERSION: (u32, u32, u32) = (4, 0, 0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Ok => "ok",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Finding {
+    check: &'static str,
+    severity: Severity,
+    message: String,
+}
+
+impl Finding {
+    fn new(check: &'static str, severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            check,
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+fn latency(connection: &mut connection::Connection) -> Finding {
+    let mut samples = Vec::with_capacity(LATENCY_SAMPLES);
+    for _ in 0..LATENCY_SAMPLES {
+        let started = Instant::now();
+        if let Err(e) = redis::cmd("PING").query::<String>(connection) {
+            return Finding::new("latency", Severity::Error, format!("PING failed: {e}"));
+        }
+        samples.push(started.elapsed());
+    }
+    samples.sort_unstable();
+
+    let median = samples[LATENCY_SAMPLES / 2];
+    let max = samples[LATENCY_SAMPLES - 1];
+    let severity = match median > LATENCY_WARNING {
+        true => Severity::Warning,
+        false => Severity::Ok,
+    };
+    Finding::new(
+        "latency",
+        severity,
+        format!(
+            "median round trip {:.2} ms, max {:.2} ms over {LATENCY_SAMPLES} pings",
+            median.as_secs_f64() * 1000.0,
+            max.as_secs_f64() * 1000.0
+        ),
+    )
+}
+
+fn eviction_policy(connection: &mut connection::Connection) -> Finding {
+    let reply: redis::RedisResult<Vec<String>> = redis::cmd("CONFIG")
+        .arg("GET")
+        .arg("maxmemory-policy")
+        .query(connection);
+    match reply.as_deref() {
+        Ok([_, policy]) if policy == "noeviction" => Finding::new(
+            "eviction_policy",
+            Severity::Ok,
+            "maxmemory-policy is noeviction",
+        ),
+        Ok([_, policy]) => Finding::new(
+            "eviction_policy",
+            Severity::Warning,
+            format!("maxmemory-policy is {policy}, metrics can be evicted when Redis is full"),
+        ),
+        Ok(_) => Finding::new(
+            "eviction_policy",
+            Severity::Warning,
+            "maxmemory-policy not reported by the server",
+        ),
+        Err(e) => Finding::new(
+            "eviction_policy",
+            Severity::Warning,
+            format!("could not read maxmemory-policy: {e}"),
+        ),
+    }
+}
+
+fn server_version(connection: &mut connection::Connection) -> Finding {
+    let info = match redis::cmd("INFO").arg("server").query::<String>(connection) {
+        Ok(info) => check::parse_info(&info),
+        Err(e) => {
+            return Finding::new(
+                "version",
+                Severity::Warning,
+                format!("could not read the server info: {e}"),
+            )
+        }
+    };
+    let Some(version) = info.get("redis_version") else {
+        return Finding::new("version", Severity::Warning, "server version not reported");
+    };
+
+    match check::parse_version(version) {
+        Some(parsed) if parsed < MIN_SERVER_VERSION => Finding::new(
+            "version",
+            Severity::Error,
+            format!("Redis {version} is not supported, multi-field HSET needs Redis 4.0"),
+        ),
+        Some(_) => Finding::new("version", Severity::Ok, format!("Redis {version}")),
+        None => Finding::new(
+            "version",
+            Severity::Warning,
+            format!("unrecognized server version {version}"),
+        ),
+    }
+}
+
+fn clock_skew(connection: &mut connection::Connection) -> Finding {
+    let (seconds, micros): (u64, u64) = match redis::cmd("TIME").query(connection) {
+        Ok(time) => time,
+        Err(e) => {
+            return Finding::new(
+                "clock_skew",
+                Severity::Warning,
+                format!("could not read the server time: {e}"),
+            )
+        }
+    };
+    let server_time = seconds as f64 + micros as f64 / 1_000_000.0;
+    let skew = clock::unix_timestamp() - server_time;
+    let severity = match skew.abs() > CLOCK_SKEW_WARNING_SECONDS {
+        true => Severity::Warning,
+        false => Severity::Ok,
+    };
+    Finding::new(
+        "clock_skew",
+        severity,
+        format!("local clock is {skew:+.3} s from the server, expiry timestamps use the local one"),
+    )
+}
+
+/// Metric names stored both as plain keys and as hashes, meaning that processes disagree on
+/// whether the metric is labeled and scrapes will fail on the wrong type.
+fn collisions(key_types: &[(String, String)], key_prefix: &str) -> Vec<String> {
+    let mut types_by_metric: BTreeMap<&str, (bool, bool)> = BTreeMap::new();
+    for (key, key_type) in key_types {
+        // the keys of the other services sharing the server
+        let Some(key) = key.strip_prefix(key_prefix) else {
+            continue;
+        };
+        // the per-series timestamps are hashes whether or not the metric is labeled, and the
+        // backend keeps its own keys under `pytheus:`
+        if key.ends_with(":created")
+            || key.ends_with(":last_updated")
+            || key.starts_with("pytheus:")
+        {
+            continue;
+        }
+        let metric = key.split(':').next().unwrap_or_default();
+        let types = types_by_metric.entry(metric).or_default();
+        match key_type.as_str() {
+            "string" => types.0 = true,
+            "hash" => types.1 = true,
+            _ => (),
+        }
+    }
+    types_by_metric
+        .into_iter()
+        .filter(|(_, (string, hash))| *string && *hash)
+        .map(|(metric, _)| metric.to_string())
+        .collect()
+}
+
+fn prefix_collisions(connection: &mut connection::Connection, key_prefix: &str) -> Finding {
+    let mut keys: Vec<String> = vec![];
+    let mut cursor = 0u64;
+    loop {
+        let reply: redis::RedisResult<(u64, Vec<String>)> = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("COUNT")
+            .arg(1000)
+            .query(connection);
+        match reply {
+            Ok((next, batch)) => {
+                keys.extend(batch);
+                cursor = next;
+            }
+            Err(e) => {
+                return Finding::new(
+                    "prefix_collisions",
+                    Severity::Warning,
+                    format!("could not scan the keys: {e}"),
+                )
+            }
+        }
+        if cursor == 0 || keys.len() >= MAX_SCANNED_KEYS {
+            break;
+        }
+    }
+
+    let mut pipe = redis::pipe();
+    for key in &keys {
+        pipe.cmd("TYPE").arg(key);
+    }
+    let types: Vec<String> = match pipe.query(connection) {
+        Ok(types) => types,
+        Err(e) => {
+            return Finding::new(
+                "prefix_collisions",
+                Severity::Warning,
+                format!("could not read the key types: {e}"),
+            )
+        }
+    };
+
+    let key_types: Vec<(String, String)> = keys.into_iter().zip(types).collect();
+    let colliding = collisions(&key_types, key_prefix);
+    match colliding.is_empty() {
+        true => Finding::new(
+            "prefix_collisions",
+            Severity::Ok,
+            format!("no collisions in {} keys", key_types.len()),
+        ),
+        false => Finding::new(
+            "prefix_collisions",
+            Severity::Error,
+            format!(
+                "stored both labeled and unlabeled: {}",
+                colliding.join(", ")
+            ),
+        ),
+    }
+}
+
+fn run(config: &RedisConfig) -> Vec<Finding> {
+    let steps = check::run(config);
+    let mut findings: Vec<Finding> = steps
+        .iter()
+        .map(|step| {
+            let severity = match step.ok {
+                true => Severity::Ok,
+                false => Severity::Error,
+            };
+            Finding::new(
+                "connectivity",
+                severity,
+                format!("{}: {}", step.name, step.detail),
+            )
+        })
+        .collect();
+    if !steps.iter().all(|step| step.ok) {
+        return findings;
+    }
+
+    let mut connection = match check::connect(config) {
+        Ok(connection) => connection,
+        Err(e) => {
+            findings.push(Finding::new("connectivity", Severity::Error, e));
+            return findings;
+        }
+    };
+    findings.push(latency(&mut connection));
+    findings.push(eviction_policy(&mut connection));
+    findings.push(server_version(&mut connection));
+    findings.push(clock_skew(&mut connection));
+    findings.push(prefix_collisions(&mut connection, &config.key_prefix));
+    findings
+}
+
+/// Run diagnostics against the Redis server of a configuration and return the findings as
+/// `{"check", "severity", "message"}` dicts, severity being `ok`, `warning` or `error`.
+#[pyfunction]
+pub fn doctor(py: Python, config: &PyDict) -> PyResult<Vec<PyObject>> {
+    let findings = match RedisConfig::from_pydict(config) {
+        Ok(config) => py.allow_threads(|| run(&config)),
+        Err(e) => vec![Finding::new("config", Severity::Error, e.to_string())],
+    };
+
+    findings
+        .iter()
+        .map(|finding| {
+            let dict = PyDict::new(py);
+            dict.set_item("check", finding.check)?;
+            dict.set_item("severity", finding.severity.as_str())?;
+            dict.set_item("message", &finding.message)?;
+            Ok(dict.into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn labeled_and_unlabeled_collision() {
+        let key_types: Vec<(String, String)> = [
+            ("requests", "string"),
+            ("requests:created", "hash"),
+            ("latency:1.0", "string"),
+            ("latency:count", "hash"),
+            ("other", "list"),
+            ("pytheus:key_names", "hash"),
+            ("pytheus:render:default:prometheus", "string"),
+        ]
+        .iter()
+        .map(|(key, key_type)| (key.to_string(), key_type.to_string()))
+        .collect();
+        assert_eq!(collisions(&key_types, ""), ["latency"]);
+
+        let prefixed: Vec<(String, String)> = key_types
+            .iter()
+            .map(|(key, key_type)| (format!("myapp:{key}"), key_type.clone()))
+            .chain([("requests:count".to_string(), "hash".to_string())])
+            .collect();
+        assert_eq!(collisions(&prefixed, "myapp:"), ["latency"]);
+    }
+}
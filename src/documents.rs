@@ -0,0 +1,141 @@
+use crate::batch::{KeyWrites, SeriesWrite};
+use crate::keys::fnv1a;
+use crate::{add_expire_to_pipeline, clock};
+use redis::Value;
+use serde_json::json;
+
+/// Path of the record of a series in the document of its key, by hash field (`None` for
+/// unlabeled keys). Labels hashes are JSON themselves, a hash of them keeps the path plain.
+fn record_path(field: Option<&str>) -> String {
+    format!("$.s{:016x}", fnv1a(field.unwrap_or_default().as_bytes()))
+}
+
+/// Add the writes of a key to the records of its series, creating the document and the records
+/// on their first write.
+pub fn add_key_writes_to_pipeline(key: &KeyWrites, pipe: &mut redis::Pipeline) {
+    let now = clock::unix_timestamp();
+    pipe.cmd("JSON.SET")
+        .arg(key.key_name)
+        .arg("$")
+        .arg("{}")
+        .arg("NX")
+        .ignore();
+    for (field, write) in &key.fields {
+        let path = record_path(*field);
+        let record = json!({
+            "labels": field.unwrap_or_default(),
+            "value": 0.0,
+            "created": now,
+            "last_updated": now,
+            "exemplar": null,
+        });
+        pipe.cmd("JSON.SET")
+            .arg(key.key_name)
+            .arg(&path)
+            .arg(record.to_string())
+            .arg("NX")
+            .ignore();
+        let value_path = format!("{path}.value");
+        match write {
+            SeriesWrite::Incr(value) => pipe
+                .cmd("JSON.NUMINCRBY")
+                .arg(key.key_name)
+                .arg(value_path)
+                .arg(*value),
+            SeriesWrite::Set(value) => pipe
+                .cmd("JSON.SET")
+                .arg(key.key_name)
+                .arg(value_path)
+                .arg(json!(value).to_string()),
+        };
+        pipe.ignore();
+        pipe.cmd("JSON.SET")
+            .arg(key.key_name)
+            .arg(format!("{path}.last_updated"))
+            .arg(json!(now).to_string())
+            .ignore();
+    }
+    add_expire_to_pipeline(key.key_name, key.expire_at, pipe);
+}
+
+/// Read the document of a key, see `stored_reply`.
+pub fn add_read_to_pipeline(key_name: &str, expire_at: Option<usize>, pipe: &mut redis::Pipeline) {
+    add_expire_to_pipeline(key_name, expire_at, pipe);
+    pipe.cmd("JSON.GET").arg(key_name);
+}
+
+/// Reply of `JSON.GET` shaped like the read of the key stored as usual: the value of an
+/// unlabeled key, or the flat `HGETALL` reply of the values by labels hash.
+pub fn stored_reply(reply: &Value, has_labels: bool) -> Value {
+    let document = match reply {
+        Value::Data(document) => serde_json::from_slice(document).ok(),
+        _ => None,
+    };
+    let records = match &document {
+        Some(serde_json::Value::Object(records)) => records.values().collect(),
+        _ => vec![],
+    };
+    let mut fields = vec![];
+    for record in records {
+        let (Some(labels), Some(value)) = (record["labels"].as_str(), record["value"].as_f64())
+        else {
+            continue;
+        };
+        let value = Value::Data(value.to_string().into_bytes());
+        if !has_labels {
+            return value;
+        }
+        fields.extend([Value::Data(labels.as_bytes().to_vec()), value]);
+    }
+    match has_labels {
+        true => Value::Bulk(fields),
+        false => Value::Nil,
+    }
+}
+
+/// Number of series in a reply of `JSON.GET`.
+pub fn series_count(reply: &Value) -> usize {
+    match reply {
+        Value::Data(document) => serde_json::from_slice::<serde_json::Value>(document)
+            .ok()
+            .and_then(|document| document.as_object().map(|records| records.len()))
+            .unwrap_or_default(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn data(value: &str) -> Value {
+        Value::Data(value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn reply_as_stored() {
+        let a = r#"{"path":"a"}"#;
+        let b = r#"{"path":"b"}"#;
+        let document = json!({
+            record_path(Some(a)).trim_start_matches("$."): {"labels": a, "value": 2.5},
+            record_path(Some(b)).trim_start_matches("$."): {"labels": b, "value": 1.0},
+        });
+        let reply = data(&document.to_string());
+        let Value::Bulk(fields) = stored_reply(&reply, true) else {
+            panic!("expected the fields of a hash");
+        };
+        let mut pairs: Vec<_> = fields.chunks(2).map(<[Value]>::to_vec).collect();
+        pairs.sort_by_key(|pair| format!("{pair:?}"));
+        assert_eq!(pairs, [[data(a), data("2.5")], [data(b), data("1")]]);
+        assert_eq!(series_count(&reply), 2);
+
+        let unlabeled = json!({"s0": {"labels": "", "value": 3.0}});
+        assert_eq!(
+            stored_reply(&data(&unlabeled.to_string()), false),
+            data("3")
+        );
+        assert_eq!(stored_reply(&Value::Nil, false), Value::Nil);
+        assert_eq!(stored_reply(&Value::Nil, true), Value::Bulk(vec![]));
+    }
+}
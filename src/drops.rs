@@ -0,0 +1,76 @@
+use crate::clock;
+use pyo3::exceptions::PyRuntimeWarning;
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+// every job lost since startup, exposed as a self-metric
+static DROPPED_JOBS: AtomicU64 = AtomicU64::new(0);
+// jobs lost since the last warning
+static UNREPORTED_JOBS: AtomicU64 = AtomicU64::new(0);
+static LAST_WARNING: Mutex<Option<SystemTime>> = Mutex::new(None);
+
+/// Record jobs that were lost: rejected by the queue or failed without being dead lettered.
+pub fn record(count: usize) {
+    if count == 0 {
+        return;
+    }
+    DROPPED_JOBS.fetch_add(count as u64, Ordering::Relaxed);
+    UNREPORTED_JOBS.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+pub fn dropped_jobs() -> u64 {
+    DROPPED_JOBS.load(Ordering::Relaxed)
+}
+
+/// Whether a warning is due, at most one per interval, taking the count of jobs to report.
+fn take_due(interval: Duration) -> Option<u64> {
+    if UNREPORTED_JOBS.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+
+    let now = clock::now();
+    let mut last_warning = LAST_WARNING.lock().unwrap();
+    if let Some(last_warning) = *last_warning {
+        if now.duration_since(last_warning).unwrap_or_default() < interval {
+            return None;
+        }
+    }
+    *last_warning = Some(now);
+    Some(UNREPORTED_JOBS.swap(0, Ordering::Relaxed))
+}
+
+/// Emit a `RuntimeWarning` for the jobs lost since the last one, rate limited to one per interval.
+/// Called from the Python side since the workers don't hold the GIL.
+pub fn warn(py: Python, interval: Duration) -> PyResult<()> {
+    match take_due(interval) {
+        Some(count) => PyErr::warn(
+            py,
+            py.get_type::<PyRuntimeWarning>(),
+            &format!("{count} metric updates were dropped, see the logs for the cause"),
+            1,
+        ),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn rate_limited() {
+        record(0);
+        assert_eq!(take_due(Duration::ZERO), None);
+
+        record(2);
+        record(1);
+        assert!(dropped_jobs() >= 3);
+        assert_eq!(take_due(Duration::ZERO), Some(3));
+        record(1);
+        assert_eq!(take_due(Duration::from_secs(3600)), None);
+        assert_eq!(take_due(Duration::ZERO), Some(1));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::clock;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::f64::consts::LN_2;
+use std::sync::Mutex;
+
+/// Observations decayed by their age, halved every `half_life` seconds.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Decaying {
+    // decayed sum of the observed values
+    sum: f64,
+    // decayed number of observations
+    weight: f64,
+    // unix timestamp the sums were decayed to
+    updated_at: f64,
+}
+
+impl Decaying {
+    fn decayed(&self, now: f64, half_life: f64) -> Self {
+        // the clock may go backwards, observations never grow with age
+        let factor = 0.5_f64.powf((now - self.updated_at).max(0.0) / half_life);
+        Self {
+            sum: self.sum * factor,
+            weight: self.weight * factor,
+            updated_at: now.max(self.updated_at),
+        }
+    }
+
+    fn observe(&mut self, value: f64, now: f64, half_life: f64) {
+        *self = self.decayed(now, half_life);
+        self.sum += value;
+        self.weight += 1.0;
+    }
+
+    /// Average of the observations weighted by their age, NaN before the first one.
+    fn average(&self) -> f64 {
+        match self.weight > 0.0 {
+            true => self.sum / self.weight,
+            false => f64::NAN,
+        }
+    }
+
+    /// Observed amount per second: a steady rate `r` decays into a sum of `r * half_life / ln 2`.
+    fn rate(&self, now: f64, half_life: f64) -> f64 {
+        self.decayed(now, half_life).sum * LN_2 / half_life
+    }
+}
+
+/// Exponentially weighted moving average maintained in Rust, for smoothed values where the
+/// consumer can't compute them from raw counters with PromQL. Calling it returns its current
+/// value, so that it's exposed as a gauge through `gauge_callbacks`: the average of the
+/// observations, or with `rate` the observed amount per second, both weighting the observations
+/// by their age with the `half_life` in seconds.
+#[pyclass]
+pub struct Ewma {
+    #[pyo3(get)]
+    half_life: f64,
+    #[pyo3(get)]
+    rate: bool,
+    state: Mutex<Decaying>,
+}
+
+#[pymethods]
+impl Ewma {
+    #[new]
+    #[pyo3(signature = (half_life, rate=false))]
+    fn new(half_life: f64, rate: bool) -> PyResult<Self> {
+        if !(half_life.is_finite() && half_life > 0.0) {
+            return Err(PyValueError::new_err(format!(
+                "invalid half_life: {half_life}"
+            )));
+        }
+        Ok(Self {
+            half_life,
+            rate,
+            state: Mutex::new(Decaying {
+                updated_at: clock::unix_timestamp(),
+                ..Decaying::default()
+            }),
+        })
+    }
+
+    /// Record an observation, e.g. a latency, or an amount like a number of requests or bytes
+    /// with `rate`.
+    #[pyo3(signature = (value=1.0))]
+    fn observe(&self, value: f64) -> PyResult<()> {
+        if !value.is_finite() {
+            return Err(PyValueError::new_err(format!("cannot observe {value}")));
+        }
+        let now = clock::unix_timestamp();
+        self.state
+            .lock()
+            .unwrap()
+            .observe(value, now, self.half_life);
+        Ok(())
+    }
+
+    /// Current value, decayed to now.
+    fn get(&self) -> f64 {
+        let state = *self.state.lock().unwrap();
+        match self.rate {
+            true => state.rate(clock::unix_timestamp(), self.half_life),
+            false => state.average(),
+        }
+    }
+
+    fn __call__(&self) -> f64 {
+        self.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn decaying_average_and_rate() {
+        let mut state = Decaying::default();
+        assert!(state.average().is_nan());
+        state.observe(10.0, 0.0, 60.0);
+        state.observe(20.0, 0.0, 60.0);
+        assert_eq!(state.average(), 15.0);
+        // the older observations weigh half as much after a half life
+        state.observe(30.0, 60.0, 60.0);
+        assert_eq!(state.average(), 22.5);
+
+        let mut state = Decaying::default();
+        for second in 0..600 {
+            state.observe(5.0, second as f64, 10.0);
+        }
+        let rate = state.rate(599.0, 10.0);
+        assert!((rate - 5.0).abs() < 0.2, "{rate}");
+        assert!((state.rate(609.0, 10.0) - rate / 2.0).abs() < 1e-9);
+        // going back in time doesn't grow it
+        assert_eq!(state.rate(0.0, 10.0), state.rate(599.0, 10.0));
+    }
+}
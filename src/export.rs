@@ -0,0 +1,476 @@
+use crate::clock;
+use crate::flush;
+use crate::samples::{Format, SampleSet};
+use log::warn;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, UNIX_EPOCH};
+use url::Url;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Samples of the registry exported, generated with the GIL held.
+pub type Collect = Box<dyn Fn(Python) -> PyResult<SampleSet> + Send>;
+
+/// Where the exporter sends the samples.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sink {
+    /// Pushgateway group of the job, replaced by every push.
+    Pushgateway { url: Url },
+    /// Prometheus remote write endpoint.
+    RemoteWrite { url: Url },
+    /// File rewritten atomically, e.g. for the textfile collector of the node exporter.
+    File { path: PathBuf, format: Format },
+    /// Carbon server receiving the plaintext protocol, each series on the path of the template.
+    Graphite {
+        host: String,
+        port: u16,
+        template: String,
+    },
+}
+
+fn http_url(url: &str) -> PyResult<Url> {
+    let url = Url::parse(url).map_err(|e| PyValueError::new_err(format!("invalid url: {e}")))?;
+    if url.scheme() != "http" || url.host_str().is_none() {
+        return Err(PyValueError::new_err(format!(
+            "only http urls are supported: {url}"
+        )));
+    }
+    Ok(url)
+}
+
+impl Sink {
+    /// Sink from a dict: `{"pushgateway": url, "job": name, "grouping": {label: value}}`,
+    /// `{"remote_write": url}` or `{"file": path, "format": "prometheus" | "openmetrics"}`.
+    pub fn from_pydict(sink: &PyDict) -> PyResult<Self> {
+        if let Some(url) = sink.get_item("pushgateway") {
+            let mut url = http_url(url.extract()?)?;
+            let job: String = PyAny::get_item(sink, "job")?.extract()?;
+            let grouping: BTreeMap<String, String> = match sink.get_item("grouping") {
+                Some(grouping) => grouping.extract()?,
+                None => BTreeMap::new(),
+            };
+            url.path_segments_mut()
+                .map_err(|_| PyValueError::new_err("invalid pushgateway url"))?
+                .pop_if_empty()
+                .extend(["metrics", "job", &job]);
+            for (name, value) in &grouping {
+                url.path_segments_mut()
+                    .unwrap()
+                    .extend([name.as_str(), value.as_str()]);
+            }
+            return Ok(Sink::Pushgateway { url });
+        }
+        if let Some(url) = sink.get_item("remote_write") {
+            return Ok(Sink::RemoteWrite {
+                url: http_url(url.extract()?)?,
+            });
+        }
+        if let Some(path) = sink.get_item("file") {
+            let format = match sink.get_item("format") {
+                Some(format) => {
+                    let name: &str = format.extract()?;
+                    Format::parse(name)
+                        .ok_or_else(|| PyValueError::new_err(format!("unknown format: {name}")))?
+                }
+                None => Format::Prometheus,
+            };
+            return Ok(Sink::File {
+                path: path.extract()?,
+                format,
+            });
+        }
+        if let Some(address) = sink.get_item("graphite") {
+            let address: &str = address.extract()?;
+            let (host, port) = address
+                .rsplit_once(':')
+                .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+                .ok_or_else(|| {
+                    PyValueError::new_err(format!("invalid graphite address: {address}"))
+                })?;
+            let template = match sink.get_item("template") {
+                Some(template) => template.extract()?,
+                None => GRAPHITE_TEMPLATE.to_string(),
+            };
+            return Ok(Sink::Graphite {
+                host: host.to_string(),
+                port,
+                template,
+            });
+        }
+        Err(PyValueError::new_err(
+            "an export sink is a pushgateway, a remote_write url, a file or a graphite server",
+        ))
+    }
+
+    fn send(&self, samples: &SampleSet) -> Result<(), String> {
+        match self {
+            Sink::Pushgateway { url } => http_request(
+                "PUT",
+                url,
+                "text/plain; version=0.0.4",
+                &[],
+                samples.render(Format::Prometheus).as_bytes(),
+            ),
+            Sink::RemoteWrite { url } => {
+                let timestamp = now().as_millis() as i64;
+                let request = write_request(&samples.timeseries(), timestamp);
+                http_request(
+                    "POST",
+                    url,
+                    "application/x-protobuf",
+                    &[
+                        ("Content-Encoding", "snappy"),
+                        ("X-Prometheus-Remote-Write-Version", "0.1.0"),
+                    ],
+                    &snappy_literals(&request),
+                )
+            }
+            Sink::File { path, format } => {
+                // renamed over the previous export so that readers never see half of it
+                let mut partial = path.clone().into_os_string();
+                partial.push(".tmp");
+                fs::write(&partial, samples.render(*format)).map_err(|e| e.to_string())?;
+                fs::rename(&partial, path).map_err(|e| e.to_string())
+            }
+            Sink::Graphite {
+                host,
+                port,
+                template,
+            } => {
+                let lines = graphite_lines(&samples.timeseries(), template, now().as_secs());
+                let address = (host.trim_start_matches('[').trim_end_matches(']'), *port)
+                    .to_socket_addrs()
+                    .map_err(|e| e.to_string())?
+                    .next()
+                    .ok_or_else(|| format!("no address for {host}"))?;
+                TcpStream::connect_timeout(&address, HTTP_TIMEOUT)
+                    .and_then(|mut stream| {
+                        stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+                        stream.write_all(lines.as_bytes())
+                    })
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+// time since the epoch per the installed clock
+fn now() -> Duration {
+    clock::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// Default template of the Graphite paths: the metric name followed by the labels.
+const GRAPHITE_TEMPLATE: &str = "{name}.{labels}";
+
+/// Graphite path component, the dots separating the path components and the whitespace
+/// separating the fields of a line are replaced.
+fn graphite_component(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | ':' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Path of a series from a template: `{name}` is the metric name, `{labels}` every other label
+/// sorted by name as `name.value` and `{label}` the value of that label, e.g.
+/// `app.{service}.{name}.{labels}`. Components left empty are dropped.
+fn graphite_path(labels: &BTreeMap<String, String>, template: &str) -> String {
+    let mut path = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        path.push_str(&rest[..start]);
+        let placeholder = &rest[start + 1..start + end];
+        let expanded = match placeholder {
+            "name" => graphite_component(labels.get("__name__").map_or("", String::as_str)),
+            "labels" => labels
+                .iter()
+                .filter(|(name, _)| name.as_str() != "__name__")
+                .map(|(name, value)| {
+                    format!("{}.{}", graphite_component(name), graphite_component(value))
+                })
+                .collect::<Vec<_>>()
+                .join("."),
+            label => labels
+                .get(label)
+                .map(|value| graphite_component(value))
+                .unwrap_or_default(),
+        };
+        path.push_str(&expanded);
+        rest = &rest[start + end + 1..];
+    }
+    path.push_str(rest);
+    path.split('.')
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Lines of the plaintext protocol, leaving out the values Graphite can't store.
+fn graphite_lines(
+    timeseries: &[(BTreeMap<String, String>, f64)],
+    template: &str,
+    timestamp: u64,
+) -> String {
+    timeseries
+        .iter()
+        .filter(|(_, value)| value.is_finite())
+        .map(|(labels, value)| format!("{} {value} {timestamp}\n", graphite_path(labels, template)))
+        .collect()
+}
+
+/// Send a request over plain HTTP/1.1, failing unless the reply has a 2xx status.
+fn http_request(
+    method: &str,
+    url: &Url,
+    content_type: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<(), String> {
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or(80);
+    let address = (host.trim_start_matches('[').trim_end_matches(']'), port)
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("no address for {host}"))?;
+    let mut stream =
+        TcpStream::connect_timeout(&address, HTTP_TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(HTTP_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(HTTP_TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+
+    let mut path = url.path().to_string();
+    if let Some(query) = url.query() {
+        path = format!("{path}?{query}");
+    }
+    let mut request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {host}:{port}\r\nContent-Type: {content_type}\r\n\
+         Content-Length: {}\r\nConnection: close\r\n",
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+    request.push_str("\r\n");
+    let mut request = request.into_bytes();
+    request.extend_from_slice(body);
+    stream.write_all(&request).map_err(|e| e.to_string())?;
+
+    let mut response = vec![];
+    stream
+        .read_to_end(&mut response)
+        .map_err(|e| e.to_string())?;
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(format!("{method} {url} failed: {status_line}")),
+    }
+}
+
+fn put_varint(mut value: u64, buffer: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn put_bytes(field: u64, bytes: &[u8], buffer: &mut Vec<u8>) {
+    put_varint(field << 3 | 2, buffer);
+    put_varint(bytes.len() as u64, buffer);
+    buffer.extend_from_slice(bytes);
+}
+
+/// Protobuf `WriteRequest` of the remote write protocol, one sample per series.
+fn write_request(timeseries: &[(BTreeMap<String, String>, f64)], timestamp: i64) -> Vec<u8> {
+    let mut request = vec![];
+    for (labels, value) in timeseries {
+        let mut series = vec![];
+        for (name, label_value) in labels {
+            let mut label = vec![];
+            put_bytes(1, name.as_bytes(), &mut label);
+            put_bytes(2, label_value.as_bytes(), &mut label);
+            put_bytes(1, &label, &mut series);
+        }
+        let mut sample = vec![1 << 3 | 1];
+        sample.extend_from_slice(&value.to_le_bytes());
+        put_varint(2 << 3, &mut sample);
+        put_varint(timestamp as u64, &mut sample);
+        put_bytes(2, &sample, &mut series);
+        put_bytes(1, &series, &mut request);
+    }
+    request
+}
+
+/// Snappy block made of literals only, valid for any decoder: the series are small and sent
+/// once per interval, compressing them isn't worth a dependency.
+fn snappy_literals(data: &[u8]) -> Vec<u8> {
+    let mut block = vec![];
+    put_varint(data.len() as u64, &mut block);
+    for chunk in data.chunks(1 << 16) {
+        let len = chunk.len() - 1;
+        match len {
+            0..=59 => block.push((len as u8) << 2),
+            60..=255 => block.extend([60 << 2, len as u8]),
+            _ => {
+                block.push(61 << 2);
+                block.extend_from_slice(&(len as u16).to_le_bytes());
+            }
+        }
+        block.extend_from_slice(chunk);
+    }
+    block
+}
+
+struct Exporter {
+    stop_tx: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+    // process the thread runs in, it's gone in the children forked since
+    pid: u32,
+}
+
+static EXPORTER: Mutex<Option<Exporter>> = Mutex::new(None);
+
+fn export(collect: &Collect, sink: &Sink) {
+    let samples = Python::with_gil(collect);
+    let result = samples
+        .map_err(|e| e.to_string())
+        .and_then(|samples| sink.send(&samples));
+    if let Err(e) = result {
+        warn!("periodic export failed: {e}");
+    }
+}
+
+/// Export the samples to the sink every interval from a thread of its own, replacing the
+/// exporter already running. The writes queued when it's stopped are exported a last time.
+pub fn start(py: Python, collect: Collect, sink: Sink, interval: Duration) {
+    stop(py);
+    let (stop_tx, stop_rx) = mpsc::channel();
+    let thread = thread::spawn(move || loop {
+        match stop_rx.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => export(&collect, &sink),
+            _ => {
+                flush::wait(Some(interval));
+                export(&collect, &sink);
+                break;
+            }
+        }
+    });
+    *EXPORTER.lock().unwrap() = Some(Exporter {
+        stop_tx,
+        thread,
+        pid: process::id(),
+    });
+}
+
+/// Stop the exporter after its last export, `false` when none was running.
+pub fn stop(py: Python) -> bool {
+    let Some(exporter) = EXPORTER.lock().unwrap().take() else {
+        return false;
+    };
+    if exporter.pid != process::id() {
+        return false;
+    }
+    let _ = exporter.stop_tx.send(());
+    // the last export needs the GIL
+    py.allow_threads(|| exporter.thread.join().is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn remote_write_encoding() {
+        let labels = BTreeMap::from([("__name__".to_string(), "up".to_string())]);
+        let request = write_request(&[(labels, 1.0)], 1);
+        let label = [&[0x0a, 8][..], b"__name__", &[0x12, 2], b"up"].concat();
+        let sample = [&[0x09][..], &1.0f64.to_le_bytes(), &[0x10, 1]].concat();
+        let series = [&[0x0a, label.len() as u8][..], &label, &[0x12, 11], &sample].concat();
+        assert_eq!(request, [&[0x0a, series.len() as u8][..], &series].concat());
+
+        assert_eq!(snappy_literals(b"abc"), [3, 2 << 2, b'a', b'b', b'c']);
+        let long = vec![7; 300];
+        let block = snappy_literals(&long);
+        assert_eq!(block[..5], [0xac, 0x02, 61 << 2, 0x2b, 0x01]);
+        assert_eq!(block.len(), 5 + 300);
+    }
+
+    #[test]
+    fn graphite_plaintext() {
+        let labels = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+        let timeseries = [
+            (
+                labels(&[
+                    ("__name__", "requests"),
+                    ("path", "/a b"),
+                    ("method", "GET"),
+                ]),
+                2.5,
+            ),
+            (labels(&[("__name__", "up")]), 1.0),
+            (
+                labels(&[("__name__", "latency_bucket"), ("le", "+Inf")]),
+                f64::INFINITY,
+            ),
+        ];
+        assert_eq!(
+            graphite_lines(&timeseries, GRAPHITE_TEMPLATE, 10),
+            "requests.method.GET.path._a_b 2.5 10\nup 1 10\n"
+        );
+        assert_eq!(
+            graphite_path(&timeseries[0].0, "app.{method}.{name}.{missing}"),
+            "app.GET.requests"
+        );
+    }
+
+    #[test]
+    fn http_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/push", listener.local_addr().unwrap())).unwrap();
+        let server = thread::spawn(move || {
+            let mut received = vec![];
+            for status in ["200 OK", "400 Bad Request"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let len = stream.read(&mut request).unwrap();
+                received.push(String::from_utf8_lossy(&request[..len]).into_owned());
+                write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").unwrap();
+            }
+            received
+        });
+
+        assert!(http_request("PUT", &url, "text/plain", &[], b"up 1\n").is_ok());
+        let err = http_request("PUT", &url, "text/plain", &[], b"").unwrap_err();
+        assert!(err.contains("400 Bad Request"));
+        let received = server.join().unwrap();
+        assert!(received[0].starts_with("PUT /push HTTP/1.1\r\n"));
+        assert!(received[0].ends_with("\r\n\r\nup 1\n"));
+    }
+}
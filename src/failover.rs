@@ -0,0 +1,131 @@
+use crate::clock;
+use crate::config::{RedisConfig, DEFAULT_ROUTE};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+// writes kept for the reconciliation while the circuit is open, later ones are lost
+const MAX_JOURNALED_JOBS: usize = 1_000_000;
+
+// mirrors the circuit of the write worker for the read workers
+static OPEN: AtomicBool = AtomicBool::new(false);
+
+/// Endpoint serving a route: the failover endpoint for the default one while its circuit is open.
+pub fn endpoint(config: &RedisConfig, route: usize) -> usize {
+    match config.failover_route() {
+        Some(failover) if route == DEFAULT_ROUTE && OPEN.load(Ordering::Relaxed) => failover,
+        _ => route,
+    }
+}
+
+/// Circuit of the default endpoint, owned by the write worker. It opens after consecutive failed
+/// writes, the writes then go to the failover endpoint and are journaled until the default
+/// endpoint accepts the journal, which closes it.
+#[derive(Debug)]
+pub struct Circuit<J> {
+    failures: u32,
+    last_probe: Option<SystemTime>,
+    journal: Vec<J>,
+    open: bool,
+}
+
+impl<J> Circuit<J> {
+    /// Closed circuit of a new write worker, the one it replaces is gone with its journal.
+    pub fn new() -> Self {
+        OPEN.store(false, Ordering::Relaxed);
+        Self {
+            failures: 0,
+            last_probe: None,
+            journal: vec![],
+            open: false,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn write_succeeded(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Record a failed write to the default endpoint, `true` when it opened the circuit.
+    pub fn write_failed(&mut self, threshold: u32) -> bool {
+        self.failures += 1;
+        if self.failures < threshold {
+            return false;
+        }
+        self.open = true;
+        self.last_probe = Some(clock::now());
+        OPEN.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Whether the default endpoint should be tried again, at most once per interval.
+    pub fn probe_due(&mut self, interval: Duration) -> bool {
+        let now = clock::now();
+        if let Some(last_probe) = self.last_probe {
+            if now.duration_since(last_probe).unwrap_or_default() < interval {
+                return false;
+            }
+        }
+        self.last_probe = Some(now);
+        true
+    }
+
+    /// Keep writes sent to the failover endpoint, returning how many didn't fit.
+    pub fn journal(&mut self, jobs: impl IntoIterator<Item = J>) -> usize {
+        let mut overflow = 0;
+        for job in jobs {
+            match self.journal.len() < MAX_JOURNALED_JOBS {
+                true => self.journal.push(job),
+                false => overflow += 1,
+            }
+        }
+        overflow
+    }
+
+    /// Writes to replay into the default endpoint, journaled again when it's still failing.
+    pub fn take_journal(&mut self) -> Vec<J> {
+        mem::take(&mut self.journal)
+    }
+
+    /// Switch back to the default endpoint once it accepted the journal.
+    pub fn close(&mut self) {
+        self.failures = 0;
+        self.open = false;
+        OPEN.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn open_probe_and_close() {
+        let config = RedisConfig {
+            failover: Some(("failover".to_string(), 6379)),
+            ..Default::default()
+        };
+        let mut circuit = Circuit::new();
+        assert!(!circuit.write_failed(2));
+        circuit.write_succeeded();
+        assert!(!circuit.write_failed(2));
+        assert!(circuit.write_failed(2));
+        assert!(circuit.is_open());
+        assert_eq!(endpoint(&config, DEFAULT_ROUTE), 1);
+
+        // probed at most once per interval, the first one after an interval since opening
+        assert!(!circuit.probe_due(Duration::from_secs(3600)));
+        assert!(circuit.probe_due(Duration::ZERO));
+
+        assert_eq!(circuit.journal([1, 2]), 0);
+        assert_eq!(circuit.take_journal(), [1, 2]);
+        assert!(circuit.take_journal().is_empty());
+        circuit.close();
+        assert!(!circuit.is_open());
+        assert_eq!(endpoint(&config, DEFAULT_ROUTE), DEFAULT_ROUTE);
+    }
+}
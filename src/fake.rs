@@ -0,0 +1,1013 @@
+use crate::batch::HINCRBYFLOAT_FIELDS_SCRIPT;
+use crate::clock::now;
+use crate::unique::UNIQUE_ADD_SCRIPT;
+use crate::RELEASE_LEASE_SCRIPT;
+use pyo3::prelude::*;
+use pyo3::types::PyList;
+use redis::{ConnectionLike, ErrorKind, RedisError, RedisResult, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// every command the fake implements
+const COMMANDS: &[&str] = &[
+    "PING",
+    "ECHO",
+    "WATCH",
+    "UNWATCH",
+    "FLUSHALL",
+    "FLUSHDB",
+    "GET",
+    "SET",
+    "INCRBYFLOAT",
+    "HGET",
+    "HMGET",
+    "HSET",
+    "HSETNX",
+    "HINCRBYFLOAT",
+    "EVAL",
+    "HGETALL",
+    "HLEN",
+    "HKEYS",
+    "HDEL",
+    "DEL",
+    "EXISTS",
+    "EXPIRE",
+    "EXPIREAT",
+    "TTL",
+    "MEMORY",
+    "INFO",
+    "RPUSH",
+    "LTRIM",
+    "LRANGE",
+    "PFADD",
+    "PFCOUNT",
+];
+
+// bytes of a key not counting its name and content, the order of magnitude of a real server
+const KEY_OVERHEAD: usize = 48;
+
+#[derive(Debug, Clone)]
+enum Entry {
+    String(String),
+    Hash(BTreeMap<String, String>),
+    List(Vec<String>),
+    // exact in the fake, the estimate of a real HyperLogLog is only close
+    HyperLogLog(BTreeSet<String>),
+}
+
+#[derive(Debug)]
+struct Stored {
+    entry: Entry,
+    expire_at: Option<SystemTime>,
+}
+
+/// In-memory emulation of the subset of Redis used by `RedisBackend`, keeping the same
+/// key/field layout and expiry semantics.
+#[derive(Debug, Default)]
+pub struct FakeRedis {
+    keys: HashMap<String, Stored>,
+}
+
+fn response_error(detail: &str) -> RedisError {
+    RedisError::from((
+        ErrorKind::ResponseError,
+        "An error was signalled by the server",
+        detail.to_string(),
+    ))
+}
+
+/// Minimal `COMMAND INFO` entry, nil for the commands the fake doesn't implement.
+fn command_info(name: &str) -> Value {
+    match COMMANDS.contains(&name.to_uppercase().as_str()) {
+        true => Value::Bulk(vec![Value::Data(name.to_lowercase().into_bytes())]),
+        false => Value::Nil,
+    }
+}
+
+fn wrong_type() -> RedisError {
+    response_error("WRONGTYPE Operation against a key holding the wrong kind of value")
+}
+
+fn wrong_arguments(command: &str) -> RedisError {
+    response_error(&format!(
+        "wrong number of arguments for '{}' command",
+        command.to_lowercase()
+    ))
+}
+
+fn parse_float(value: &str) -> RedisResult<f64> {
+    match value.parse::<f64>() {
+        Ok(float) if float.is_finite() => Ok(float),
+        _ => Err(response_error("value is not a valid float")),
+    }
+}
+
+fn parse_int(value: &str) -> RedisResult<i64> {
+    value
+        .parse::<i64>()
+        .map_err(|_| response_error("value is not an integer or out of range"))
+}
+
+fn increment(current: Option<&String>, increment: &str) -> RedisResult<String> {
+    let current = match current {
+        Some(current) => parse_float(current)?,
+        None => 0.0,
+    };
+    let new_value = current + parse_float(increment)?;
+    if !new_value.is_finite() {
+        return Err(response_error("increment would produce NaN or Infinity"));
+    }
+    Ok(new_value.to_string())
+}
+
+/// Elements of a list of `len` elements between the inclusive `start` and `stop` indexes,
+/// negative ones counting from the end.
+fn list_range(len: usize, start: &str, stop: &str) -> RedisResult<Range<usize>> {
+    let len = len as i64;
+    let resolve = |index: i64| if index < 0 { len + index } else { index };
+    let start = resolve(parse_int(start)?).max(0);
+    let stop = resolve(parse_int(stop)?).min(len - 1);
+    Ok(match start <= stop {
+        true => start as usize..stop as usize + 1,
+        false => 0..0,
+    })
+}
+
+impl FakeRedis {
+    fn remove_if_expired(&mut self, key: &str) {
+        let expired = match self.keys.get(key) {
+            Some(Stored {
+                expire_at: Some(expire_at),
+                ..
+            }) => *expire_at <= now(),
+            _ => false,
+        };
+        if expired {
+            self.keys.remove(key);
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<&mut Stored> {
+        self.remove_if_expired(key);
+        self.keys.get_mut(key)
+    }
+
+    fn get_string(&mut self, key: &str) -> RedisResult<Option<&mut String>> {
+        match self.get(key) {
+            Some(Stored {
+                entry: Entry::String(value),
+                ..
+            }) => Ok(Some(value)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    fn get_hash(&mut self, key: &str) -> RedisResult<Option<&mut BTreeMap<String, String>>> {
+        match self.get(key) {
+            Some(Stored {
+                entry: Entry::Hash(hash),
+                ..
+            }) => Ok(Some(hash)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    fn get_list(&mut self, key: &str) -> RedisResult<Option<&mut Vec<String>>> {
+        match self.get(key) {
+            Some(Stored {
+                entry: Entry::List(list),
+                ..
+            }) => Ok(Some(list)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    fn get_hll(&mut self, key: &str) -> RedisResult<Option<&mut BTreeSet<String>>> {
+        match self.get(key) {
+            Some(Stored {
+                entry: Entry::HyperLogLog(hll),
+                ..
+            }) => Ok(Some(hll)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    fn get_or_create_hash(&mut self, key: &str) -> RedisResult<&mut BTreeMap<String, String>> {
+        if self.get_hash(key)?.is_none() {
+            self.keys.insert(
+                key.to_string(),
+                Stored {
+                    entry: Entry::Hash(BTreeMap::new()),
+                    expire_at: None,
+                },
+            );
+        }
+        Ok(self.get_hash(key)?.unwrap())
+    }
+
+    fn set_expire_at(&mut self, key: &str, expire_at: SystemTime) -> Value {
+        match self.get(key) {
+            Some(stored) => {
+                stored.expire_at = Some(expire_at);
+                self.remove_if_expired(key);
+                Value::Int(1)
+            }
+            None => Value::Int(0),
+        }
+    }
+
+    /// Execute a single command, the arguments include the command name.
+    pub fn execute(&mut self, args: &[String]) -> RedisResult<Value> {
+        let Some(command) = args.first() else {
+            return Err(response_error("empty command"));
+        };
+        let command = command.to_uppercase();
+        let args = &args[1..];
+
+        match (command.as_str(), args) {
+            ("PING", []) => Ok(Value::Status("PONG".to_string())),
+            ("ECHO", [message]) => Ok(Value::Data(message.as_bytes().to_vec())),
+            // every request runs under the store lock and the worker is the only writer, so the
+            // watched keys can't change behind a transaction
+            ("WATCH", [_, ..]) | ("UNWATCH", []) => Ok(Value::Okay),
+            ("FLUSHALL" | "FLUSHDB", _) => {
+                self.keys.clear();
+                Ok(Value::Okay)
+            }
+            ("GET", [key]) => Ok(match self.get_string(key)? {
+                Some(value) => Value::Data(value.as_bytes().to_vec()),
+                None => Value::Nil,
+            }),
+            ("SET", [key, value, options @ ..]) => {
+                let mut only_new = false;
+                let mut expire_at = None;
+                let mut options = options.iter();
+                while let Some(option) = options.next() {
+                    match option.to_uppercase().as_str() {
+                        "NX" => only_new = true,
+                        "PX" => {
+                            let millis = options.next().ok_or_else(|| wrong_arguments("SET"))?;
+                            let millis = parse_int(millis)?.max(0) as u64;
+                            expire_at = Some(now() + Duration::from_millis(millis));
+                        }
+                        _ => return Err(response_error("syntax error")),
+                    }
+                }
+                if only_new && self.get(key).is_some() {
+                    return Ok(Value::Nil);
+                }
+                // like Redis, SET discards any previous expiry
+                self.keys.insert(
+                    key.clone(),
+                    Stored {
+                        entry: Entry::String(value.clone()),
+                        expire_at,
+                    },
+                );
+                Ok(Value::Okay)
+            }
+            ("INCRBYFLOAT", [key, value]) => {
+                let new_value = match self.get_string(key)? {
+                    Some(current) => {
+                        *current = increment(Some(current), value)?;
+                        current.clone()
+                    }
+                    None => {
+                        let new_value = increment(None, value)?;
+                        self.keys.insert(
+                            key.clone(),
+                            Stored {
+                                entry: Entry::String(new_value.clone()),
+                                expire_at: None,
+                            },
+                        );
+                        new_value
+                    }
+                };
+                Ok(Value::Data(new_value.into_bytes()))
+            }
+            ("HGET", [key, field]) => Ok(match self.get_hash(key)? {
+                Some(hash) => match hash.get(field) {
+                    Some(value) => Value::Data(value.as_bytes().to_vec()),
+                    None => Value::Nil,
+                },
+                None => Value::Nil,
+            }),
+            ("HMGET", [key, fields @ ..]) if !fields.is_empty() => {
+                let hash = self.get_hash(key)?;
+                Ok(Value::Bulk(
+                    fields
+                        .iter()
+                        .map(
+                            |field| match hash.as_ref().and_then(|hash| hash.get(field)) {
+                                Some(value) => Value::Data(value.as_bytes().to_vec()),
+                                None => Value::Nil,
+                            },
+                        )
+                        .collect(),
+                ))
+            }
+            ("HSET", [key, fields @ ..]) if !fields.is_empty() && fields.len() % 2 == 0 => {
+                let hash = self.get_or_create_hash(key)?;
+                let mut created = 0;
+                for pair in fields.chunks(2) {
+                    if hash.insert(pair[0].clone(), pair[1].clone()).is_none() {
+                        created += 1;
+                    }
+                }
+                Ok(Value::Int(created))
+            }
+            ("HSETNX", [key, field, value]) => {
+                let hash = self.get_or_create_hash(key)?;
+                if hash.contains_key(field) {
+                    return Ok(Value::Int(0));
+                }
+                hash.insert(field.clone(), value.clone());
+                Ok(Value::Int(1))
+            }
+            ("HINCRBYFLOAT", [key, field, value]) => {
+                // validate before creating the hash so that errors don't leave empty keys around
+                let new_value =
+                    increment(self.get_hash(key)?.and_then(|hash| hash.get(field)), value)?;
+                self.get_or_create_hash(key)?
+                    .insert(field.clone(), new_value.clone());
+                Ok(Value::Data(new_value.into_bytes()))
+            }
+            ("RPUSH", [key, values @ ..]) if !values.is_empty() => {
+                if self.get_list(key)?.is_none() {
+                    self.keys.insert(
+                        key.to_string(),
+                        Stored {
+                            entry: Entry::List(vec![]),
+                            expire_at: None,
+                        },
+                    );
+                }
+                let list = self.get_list(key)?.unwrap();
+                list.extend(values.iter().cloned());
+                Ok(Value::Int(list.len() as i64))
+            }
+            ("LTRIM", [key, start, stop]) => {
+                let Some(list) = self.get_list(key)? else {
+                    return Ok(Value::Okay);
+                };
+                let range = list_range(list.len(), start, stop)?;
+                *list = list.drain(range).collect();
+                if list.is_empty() {
+                    self.keys.remove(key);
+                }
+                Ok(Value::Okay)
+            }
+            ("LRANGE", [key, start, stop]) => Ok(Value::Bulk(match self.get_list(key)? {
+                Some(list) => list[list_range(list.len(), start, stop)?]
+                    .iter()
+                    .map(|value| Value::Data(value.as_bytes().to_vec()))
+                    .collect(),
+                None => vec![],
+            })),
+            ("PFADD", [key, items @ ..]) => {
+                let created = self.get_hll(key)?.is_none();
+                if created {
+                    self.keys.insert(
+                        key.to_string(),
+                        Stored {
+                            entry: Entry::HyperLogLog(BTreeSet::new()),
+                            expire_at: None,
+                        },
+                    );
+                }
+                let hll = self.get_hll(key)?.unwrap();
+                let mut changed = created;
+                for item in items {
+                    changed |= hll.insert(item.clone());
+                }
+                Ok(Value::Int(changed as i64))
+            }
+            ("PFCOUNT", keys) if !keys.is_empty() => {
+                let mut union = BTreeSet::new();
+                for key in keys {
+                    if let Some(hll) = self.get_hll(key)? {
+                        union.extend(hll.iter().cloned());
+                    }
+                }
+                Ok(Value::Int(union.len() as i64))
+            }
+            // the scripts run by the backend, see `batch::HINCRBYFLOAT_FIELDS_SCRIPT`
+            ("EVAL", [script, numkeys, key, fields @ ..])
+                if script == HINCRBYFLOAT_FIELDS_SCRIPT
+                    && numkeys == "1"
+                    && !fields.is_empty()
+                    && fields.len() % 2 == 0 =>
+            {
+                for pair in fields.chunks(2) {
+                    self.execute(&[
+                        "HINCRBYFLOAT".to_string(),
+                        key.clone(),
+                        pair[0].clone(),
+                        pair[1].clone(),
+                    ])?;
+                }
+                Ok(Value::Nil)
+            }
+            ("EVAL", [script, numkeys, key, hll_key, field, items @ ..])
+                if script == UNIQUE_ADD_SCRIPT && numkeys == "2" && !items.is_empty() =>
+            {
+                self.execute(&[&["PFADD".to_string(), hll_key.clone()], items].concat())?;
+                let count = self.get_hll(hll_key)?.map_or(0, |hll| hll.len());
+                let command = match field.is_empty() {
+                    true => vec!["SET".to_string(), key.clone(), count.to_string()],
+                    false => vec![
+                        "HSET".to_string(),
+                        key.clone(),
+                        field.clone(),
+                        count.to_string(),
+                    ],
+                };
+                self.execute(&command)?;
+                Ok(Value::Int(count as i64))
+            }
+            ("EVAL", [script, numkeys, key, lease])
+                if script == RELEASE_LEASE_SCRIPT && numkeys == "1" =>
+            {
+                let held = matches!(self.get_string(key), Ok(Some(value)) if value == lease);
+                if !held {
+                    return Ok(Value::Int(0));
+                }
+                self.keys.remove(key);
+                Ok(Value::Int(1))
+            }
+            ("EVAL", [_, ..]) => Err(response_error("NOSCRIPT script not supported by the fake")),
+            ("HGETALL", [key]) => Ok(match self.get_hash(key)? {
+                Some(hash) => Value::Bulk(
+                    hash.iter()
+                        .flat_map(|(field, value)| {
+                            [
+                                Value::Data(field.as_bytes().to_vec()),
+                                Value::Data(value.as_bytes().to_vec()),
+                            ]
+                        })
+                        .collect(),
+                ),
+                None => Value::Bulk(vec![]),
+            }),
+            ("HDEL", [key, fields @ ..]) if !fields.is_empty() => {
+                let Some(hash) = self.get_hash(key)? else {
+                    return Ok(Value::Int(0));
+                };
+                let removed = fields
+                    .iter()
+                    .filter(|field| hash.remove(*field).is_some())
+                    .count();
+                if hash.is_empty() {
+                    self.keys.remove(key);
+                }
+                Ok(Value::Int(removed as i64))
+            }
+            ("HLEN", [key]) => Ok(Value::Int(
+                self.get_hash(key)?.map_or(0, |hash| hash.len() as i64),
+            )),
+            ("HKEYS", [key]) => Ok(Value::Bulk(match self.get_hash(key)? {
+                Some(hash) => hash
+                    .keys()
+                    .map(|field| Value::Data(field.as_bytes().to_vec()))
+                    .collect(),
+                None => vec![],
+            })),
+            ("EXISTS", keys) if !keys.is_empty() => Ok(Value::Int(
+                keys.iter().filter(|key| self.get(key).is_some()).count() as i64,
+            )),
+            ("DEL", keys) if !keys.is_empty() => {
+                let mut removed = 0;
+                for key in keys {
+                    if self.get(key).is_some() {
+                        self.keys.remove(key);
+                        removed += 1;
+                    }
+                }
+                Ok(Value::Int(removed))
+            }
+            ("EXPIRE", [key, seconds, option]) if option.eq_ignore_ascii_case("NX") => {
+                let seconds = parse_int(seconds)?.max(0) as u64;
+                let persistent = matches!(
+                    self.get(key),
+                    Some(Stored {
+                        expire_at: None,
+                        ..
+                    })
+                );
+                Ok(match persistent {
+                    true => self.set_expire_at(key, now() + Duration::from_secs(seconds)),
+                    false => Value::Int(0),
+                })
+            }
+            ("EXPIRE", [key, seconds]) => {
+                let seconds = parse_int(seconds)?;
+                let expire_at = if seconds > 0 {
+                    now() + Duration::from_secs(seconds as u64)
+                } else {
+                    UNIX_EPOCH
+                };
+                Ok(self.set_expire_at(key, expire_at))
+            }
+            ("EXPIREAT", [key, timestamp]) => {
+                let timestamp = parse_int(timestamp)?.max(0) as u64;
+                Ok(self.set_expire_at(key, UNIX_EPOCH + Duration::from_secs(timestamp)))
+            }
+            ("TTL", [key]) => Ok(match self.get(key) {
+                Some(Stored {
+                    expire_at: Some(expire_at),
+                    ..
+                }) => {
+                    let remaining = expire_at.duration_since(now()).unwrap_or_default();
+                    Value::Int(((remaining.as_millis() + 500) / 1000) as i64)
+                }
+                Some(_) => Value::Int(-1),
+                None => Value::Int(-2),
+            }),
+            // an estimate from the size of the content, the samples don't matter
+            ("MEMORY", [subcommand, key, ..]) if subcommand.eq_ignore_ascii_case("USAGE") => {
+                Ok(match self.get(key) {
+                    Some(stored) => {
+                        let content = match &stored.entry {
+                            Entry::String(value) => value.len(),
+                            Entry::Hash(hash) => hash
+                                .iter()
+                                .map(|(field, value)| field.len() + value.len())
+                                .sum(),
+                            Entry::List(list) => list.iter().map(String::len).sum(),
+                            // the dense encoding of Redis
+                            Entry::HyperLogLog(_) => 12304,
+                        };
+                        Value::Int((KEY_OVERHEAD + key.len() + content) as i64)
+                    }
+                    None => Value::Nil,
+                })
+            }
+            // the options of the commands the fake emulates are the ones of Redis 7.0
+            ("INFO", _) => Ok(Value::Data(b"# Server\r\nredis_version:7.0.0\r\n".to_vec())),
+            // lets the backend detect which features the fake emulates
+            ("COMMAND", [subcommand, names @ ..]) if subcommand.eq_ignore_ascii_case("INFO") => Ok(
+                Value::Bulk(names.iter().map(|name| command_info(name)).collect()),
+            ),
+            (command, _) if COMMANDS.contains(&command) => Err(wrong_arguments(command)),
+            _ => Err(response_error(&format!("unknown command '{command}'"))),
+        }
+    }
+}
+
+/// Parse the RESP arrays produced by redis-rs when packing commands.
+fn parse_packed_commands(mut packed: &[u8]) -> RedisResult<Vec<Vec<String>>> {
+    fn read_line<'a>(packed: &mut &'a [u8]) -> RedisResult<&'a str> {
+        let end = packed
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or_else(|| response_error("invalid packed command"))?;
+        let line = std::str::from_utf8(&packed[..end])
+            .map_err(|_| response_error("invalid packed command"))?;
+        *packed = &packed[end + 2..];
+        Ok(line)
+    }
+
+    fn read_length(line: &str, prefix: char) -> RedisResult<usize> {
+        line.strip_prefix(prefix)
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| response_error("invalid packed command"))
+    }
+
+    let mut commands = vec![];
+    while !packed.is_empty() {
+        let args_count = read_length(read_line(&mut packed)?, '*')?;
+        let mut args = Vec::with_capacity(args_count);
+        for _ in 0..args_count {
+            let len = read_length(read_line(&mut packed)?, '$')?;
+            if packed.len() < len + 2 {
+                return Err(response_error("invalid packed command"));
+            }
+            args.push(String::from_utf8_lossy(&packed[..len]).into_owned());
+            packed = &packed[len + 2..];
+        }
+        commands.push(args);
+    }
+    Ok(commands)
+}
+
+/// A connection to the process-wide `FakeRedis` store, usable wherever a Redis connection is.
+pub struct FakeConnection {
+    store: Arc<Mutex<FakeRedis>>,
+}
+
+impl FakeConnection {
+    pub fn new(store: Arc<Mutex<FakeRedis>>) -> Self {
+        Self { store }
+    }
+
+    fn execute_all(&mut self, commands: Vec<Vec<String>>) -> Vec<RedisResult<Value>> {
+        let mut store = self.store.lock().unwrap();
+        let mut responses = vec![];
+        let mut transaction: Option<Vec<Vec<String>>> = None;
+
+        for args in commands {
+            let command = args.first().map(|c| c.to_uppercase()).unwrap_or_default();
+            match (command.as_str(), transaction.as_mut()) {
+                ("MULTI", None) => {
+                    transaction = Some(vec![]);
+                    responses.push(Ok(Value::Okay));
+                }
+                ("EXEC", Some(_)) => {
+                    // like Redis every command is applied, the first error failing the reply
+                    let results: Vec<RedisResult<Value>> = transaction
+                        .take()
+                        .unwrap()
+                        .iter()
+                        .map(|args| store.execute(args))
+                        .collect();
+                    responses.push(
+                        results
+                            .into_iter()
+                            .collect::<RedisResult<_>>()
+                            .map(Value::Bulk),
+                    );
+                }
+                (_, Some(queued)) => {
+                    queued.push(args);
+                    responses.push(Ok(Value::Status("QUEUED".to_string())));
+                }
+                (_, None) => responses.push(store.execute(&args)),
+            }
+        }
+        responses
+    }
+
+    /// Execute the commands of an atomic pipeline, returning the reply of each of them, see
+    /// `Connection::transaction`.
+    pub fn transaction(
+        &mut self,
+        pipe: &redis::Pipeline,
+    ) -> RedisResult<crate::connection::TransactionReplies> {
+        let mut store = self.store.lock().unwrap();
+        let commands: Vec<RedisResult<Value>> = pipe
+            .cmd_iter()
+            .map(|cmd| parse_packed_commands(&cmd.get_packed_command()))
+            .map(|args| store.execute(&args?.pop().unwrap_or_default()))
+            .collect();
+        Ok(Some(commands))
+    }
+}
+
+impl ConnectionLike for FakeConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        let commands = parse_packed_commands(cmd)?;
+        self.execute_all(commands)
+            .pop()
+            .unwrap_or_else(|| Err(response_error("empty command")))
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        let commands = parse_packed_commands(cmd)?;
+        // like the real connection, every response is consumed and the first error returned
+        let mut first_err = None;
+        let mut values = vec![];
+        for (idx, response) in self.execute_all(commands).into_iter().enumerate() {
+            match response {
+                Ok(value) if idx >= offset && idx < offset + count => values.push(value),
+                Ok(_) => (),
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        first_err.map_or(Ok(values), Err)
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+
+    fn check_connection(&mut self) -> bool {
+        true
+    }
+
+    fn is_open(&self) -> bool {
+        true
+    }
+}
+
+/// Convert a reply to the Python types redis-py returns with `decode_responses=True`.
+pub fn value_to_py(py: Python, value: &Value) -> PyObject {
+    match value {
+        Value::Nil => py.None(),
+        Value::Int(int) => int.into_py(py),
+        Value::Data(data) => String::from_utf8_lossy(data).into_py(py),
+        Value::Bulk(values) => {
+            PyList::new(py, values.iter().map(|value| value_to_py(py, value))).into()
+        }
+        Value::Status(status) => status.into_py(py),
+        Value::Okay => "OK".into_py(py),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn execute(redis: &mut FakeRedis, args: &[&str]) -> RedisResult<Value> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        redis.execute(&args)
+    }
+
+    #[test]
+    fn incrbyfloat() {
+        let mut redis = FakeRedis::default();
+        execute(&mut redis, &["INCRBYFLOAT", "key", "1.5"]).unwrap();
+        let value = execute(&mut redis, &["INCRBYFLOAT", "key", "1"]).unwrap();
+        assert_eq!(value, Value::Data(b"2.5".to_vec()));
+    }
+
+    #[test]
+    fn hash_layout() {
+        let mut redis = FakeRedis::default();
+        execute(
+            &mut redis,
+            &["HINCRBYFLOAT", "key", r#"{"bob":"cat"}"#, "2"],
+        )
+        .unwrap();
+        execute(&mut redis, &["HSET", "key", r#"{"bob":"dog"}"#, "1.0"]).unwrap();
+        let value = execute(&mut redis, &["HGETALL", "key"]).unwrap();
+        assert_eq!(
+            value,
+            Value::Bulk(vec![
+                Value::Data(br#"{"bob":"cat"}"#.to_vec()),
+                Value::Data(b"2".to_vec()),
+                Value::Data(br#"{"bob":"dog"}"#.to_vec()),
+                Value::Data(b"1.0".to_vec()),
+            ])
+        );
+        assert_eq!(execute(&mut redis, &["HLEN", "key"]), Ok(Value::Int(2)));
+        assert_eq!(execute(&mut redis, &["HLEN", "missing"]), Ok(Value::Int(0)));
+        assert_eq!(
+            execute(&mut redis, &["HKEYS", "key"]),
+            Ok(Value::Bulk(vec![
+                Value::Data(br#"{"bob":"cat"}"#.to_vec()),
+                Value::Data(br#"{"bob":"dog"}"#.to_vec()),
+            ]))
+        );
+        assert_eq!(
+            execute(&mut redis, &["EXISTS", "key", "missing"]),
+            Ok(Value::Int(1))
+        );
+    }
+
+    #[test]
+    fn memory_usage() {
+        let mut redis = FakeRedis::default();
+        execute(&mut redis, &["HSET", "key", "field", "1.0"]).unwrap();
+        assert_eq!(
+            execute(&mut redis, &["MEMORY", "USAGE", "key", "SAMPLES", "5"]),
+            Ok(Value::Int((KEY_OVERHEAD + 11) as i64))
+        );
+        assert_eq!(
+            execute(&mut redis, &["MEMORY", "USAGE", "missing"]),
+            Ok(Value::Nil)
+        );
+    }
+
+    #[test]
+    fn unique_add() {
+        let mut redis = FakeRedis::default();
+        for items in [&["a", "b"][..], &["b", "c"]] {
+            let args = [
+                &["EVAL", UNIQUE_ADD_SCRIPT, "2", "key", "key:hll:", ""],
+                items,
+            ]
+            .concat();
+            execute(&mut redis, &args).unwrap();
+        }
+        assert_eq!(
+            execute(&mut redis, &["GET", "key"]),
+            Ok(Value::Data("3".into()))
+        );
+        assert_eq!(
+            execute(&mut redis, &["PFCOUNT", "key:hll:", "missing"]),
+            Ok(Value::Int(3))
+        );
+        assert_eq!(
+            execute(&mut redis, &["PFADD", "key:hll:", "a"]),
+            Ok(Value::Int(0))
+        );
+        assert_eq!(
+            execute(&mut redis, &["PFADD", "key", "a"]),
+            Err(wrong_type())
+        );
+    }
+
+    #[test]
+    fn capped_list() {
+        let mut redis = FakeRedis::default();
+        for value in ["1", "2", "3", "4"] {
+            execute(&mut redis, &["RPUSH", "list", value]).unwrap();
+            execute(&mut redis, &["LTRIM", "list", "-3", "-1"]).unwrap();
+        }
+        let range = |redis: &mut FakeRedis, start, stop| {
+            execute(redis, &["LRANGE", "list", start, stop]).unwrap()
+        };
+        assert_eq!(
+            range(&mut redis, "0", "-1"),
+            Value::Bulk(["2", "3", "4"].map(|v| Value::Data(v.into())).to_vec())
+        );
+        assert_eq!(
+            range(&mut redis, "-1", "10"),
+            Value::Bulk(vec![Value::Data("4".into())])
+        );
+        assert_eq!(range(&mut redis, "2", "1"), Value::Bulk(vec![]));
+        assert_eq!(
+            execute(&mut redis, &["HGET", "list", "field"]),
+            Err(wrong_type())
+        );
+
+        execute(&mut redis, &["LTRIM", "list", "5", "-1"]).unwrap();
+        assert_eq!(execute(&mut redis, &["EXISTS", "list"]), Ok(Value::Int(0)));
+    }
+
+    #[test]
+    fn eval_multiple_increments() {
+        let mut redis = FakeRedis::default();
+        execute(
+            &mut redis,
+            &[
+                "EVAL",
+                HINCRBYFLOAT_FIELDS_SCRIPT,
+                "1",
+                "key",
+                "a",
+                "1",
+                "b",
+                "2.5",
+            ],
+        )
+        .unwrap();
+        execute(
+            &mut redis,
+            &[
+                "EVAL",
+                HINCRBYFLOAT_FIELDS_SCRIPT,
+                "1",
+                "key",
+                "a",
+                "1",
+                "b",
+                "2.5",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            execute(&mut redis, &["HGET", "key", "b"]),
+            Ok(Value::Data(b"5".to_vec()))
+        );
+        assert!(execute(&mut redis, &["EVAL", "return 1", "0"]).is_err());
+    }
+
+    #[test]
+    fn transaction_applies_every_command() {
+        let store = Arc::new(Mutex::new(FakeRedis::default()));
+        let mut connection = FakeConnection::new(store);
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.cmd("SET").arg("broken").arg("text");
+        pipe.cmd("INCRBYFLOAT").arg("broken").arg(1);
+        pipe.cmd("INCRBYFLOAT").arg("healthy").arg(2);
+        assert!(pipe.query::<Value>(&mut connection).is_err());
+        let value: String = redis::cmd("GET")
+            .arg("healthy")
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(value, "2");
+
+        let replies = connection.transaction(&pipe).unwrap().unwrap();
+        assert!(replies[1].is_err());
+        assert_eq!(replies[2], Ok(Value::Data(b"4".to_vec())));
+    }
+
+    #[test]
+    fn detected_features() {
+        let store = Arc::new(Mutex::new(FakeRedis::default()));
+        let features = crate::features::detect(&mut FakeConnection::new(store));
+        assert_eq!(
+            features,
+            crate::features::ServerFeatures {
+                expire_options: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn hsetnx() {
+        let mut redis = FakeRedis::default();
+        assert_eq!(
+            execute(&mut redis, &["HSETNX", "key", "field", "1"]),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            execute(&mut redis, &["HSETNX", "key", "field", "2"]),
+            Ok(Value::Int(0))
+        );
+        assert_eq!(
+            execute(&mut redis, &["HGET", "key", "field"]),
+            Ok(Value::Data(b"1".to_vec()))
+        );
+    }
+
+    #[test]
+    fn wrong_type_and_invalid_float() {
+        let mut redis = FakeRedis::default();
+        execute(&mut redis, &["SET", "key", "bob"]).unwrap();
+        assert!(execute(&mut redis, &["HGETALL", "key"]).is_err());
+        assert!(execute(&mut redis, &["INCRBYFLOAT", "key", "1"]).is_err());
+        assert!(execute(&mut redis, &["HINCRBYFLOAT", "other", "field", "bob"]).is_err());
+        assert_eq!(
+            execute(&mut redis, &["TTL", "other"]).unwrap(),
+            Value::Int(-2)
+        );
+    }
+
+    #[test]
+    fn expiry() {
+        let mut redis = FakeRedis::default();
+        execute(&mut redis, &["SET", "key", "1"]).unwrap();
+        assert_eq!(
+            execute(&mut redis, &["TTL", "key"]).unwrap(),
+            Value::Int(-1)
+        );
+        execute(&mut redis, &["EXPIRE", "key", "3600"]).unwrap();
+        assert_eq!(
+            execute(&mut redis, &["TTL", "key"]).unwrap(),
+            Value::Int(3600)
+        );
+        execute(&mut redis, &["EXPIREAT", "key", "1"]).unwrap();
+        assert_eq!(execute(&mut redis, &["GET", "key"]).unwrap(), Value::Nil);
+        assert_eq!(
+            execute(&mut redis, &["EXPIRE", "key", "10"]).unwrap(),
+            Value::Int(0)
+        );
+
+        execute(&mut redis, &["SET", "key", "1"]).unwrap();
+        execute(&mut redis, &["EXPIRE", "key", "20", "NX"]).unwrap();
+        assert_eq!(
+            execute(&mut redis, &["EXPIRE", "key", "3600", "NX"]).unwrap(),
+            Value::Int(0)
+        );
+        assert_eq!(
+            execute(&mut redis, &["TTL", "key"]).unwrap(),
+            Value::Int(20)
+        );
+
+        execute(&mut redis, &["SET", "key", "1", "PX", "2000"]).unwrap();
+        assert_eq!(
+            execute(&mut redis, &["SET", "key", "2", "NX", "PX", "10"]).unwrap(),
+            Value::Nil
+        );
+        assert_eq!(execute(&mut redis, &["TTL", "key"]).unwrap(), Value::Int(2));
+    }
+
+    #[test]
+    fn pipeline_through_connection() {
+        let store = Arc::new(Mutex::new(FakeRedis::default()));
+        let mut connection = FakeConnection::new(store);
+        let (value, hash): (f64, BTreeMap<String, String>) = redis::pipe()
+            .incr("key", 2.7)
+            .ignore()
+            .expire("key", 3600)
+            .ignore()
+            .get("key")
+            .hgetall("missing")
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(value, 2.7);
+        assert!(hash.is_empty());
+    }
+
+    #[test]
+    fn atomic_pipeline_through_connection() {
+        let store = Arc::new(Mutex::new(FakeRedis::default()));
+        let mut connection = FakeConnection::new(store);
+        let (first, second): (f64, f64) = redis::pipe()
+            .atomic()
+            .incr("key", 1.0)
+            .incr("key", 1.0)
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!((first, second), (1.0, 2.0));
+    }
+}
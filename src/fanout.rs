@@ -0,0 +1,252 @@
+use crate::plugins;
+use log::warn;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyType};
+use std::cell::Cell;
+use std::sync::Mutex;
+
+thread_local! {
+    // first child `get` reads from, moved past the children that failed to collect the samples
+    static READ_FROM: Cell<usize> = const { Cell::new(0) };
+}
+
+static CHILD_CLASSES: Mutex<Vec<Py<PyType>>> = Mutex::new(Vec::new());
+static CHILD_ERRORS: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+/// Backend classes of the children with their configs, in order.
+fn children(config: &PyDict) -> PyResult<Vec<(&PyType, &PyDict)>> {
+    let py = config.py();
+    // using the PyAny::get_item so that it will raise a KeyError on missing key
+    let entries = PyAny::get_item(config, "backends")?;
+    let children = entries
+        .iter()?
+        .map(|entry| {
+            let entry: &PyDict = entry?.downcast()?;
+            let class = plugins::resolve(PyAny::get_item(entry, "backend")?)?;
+            let child_config = match entry.get_item("config") {
+                Some(child_config) => child_config.downcast()?,
+                None => PyDict::new(py),
+            };
+            Ok((class, child_config))
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    if children.is_empty() {
+        return Err(PyValueError::new_err(
+            "FanOutBackend needs at least one backend",
+        ));
+    }
+    Ok(children)
+}
+
+fn child_failed(index: usize, operation: &str, err: &PyErr) {
+    warn!("fan-out backend {index} failed to {operation}: {err}");
+    let mut errors = CHILD_ERRORS.lock().unwrap();
+    if errors.len() <= index {
+        errors.resize(index + 1, 0);
+    }
+    errors[index] += 1;
+}
+
+/// Run an operation on every child, isolating their errors: a failing child is logged and
+/// counted, and the error is only raised when every child failed.
+fn forward<C, T>(
+    children: impl IntoIterator<Item = C>,
+    operation: &str,
+    mut call: impl FnMut(C) -> PyResult<T>,
+) -> PyResult<()> {
+    let mut first_err = None;
+    let mut succeeded = false;
+    for (index, child) in children.into_iter().enumerate() {
+        match call(child) {
+            Ok(_) => succeeded = true,
+            Err(err) => {
+                child_failed(index, operation, &err);
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+    match (succeeded, first_err) {
+        (false, Some(err)) => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Samples of every collector of the registry read through the children `get`.
+fn collected_samples(registry: &PyAny) -> PyResult<PyObject> {
+    let py = registry.py();
+    let result = PyDict::new(py);
+    for collector in registry.call_method0(intern!(py, "collect"))?.iter()? {
+        let collector = collector?;
+        let samples: Vec<&PyAny> = collector
+            .call_method0(intern!(py, "collect"))?
+            .iter()?
+            .collect::<PyResult<_>>()?;
+        result.set_item(collector, PyList::new(py, samples))?;
+    }
+    Ok(result.into())
+}
+
+/// Backend forwarding every operation to an ordered list of backends, e.g. a single process
+/// backend for fast local reads and Redis for the aggregation, or Redis and another backend
+/// during a migration. Reads come from the first child able to serve them, and a failing child
+/// never keeps the operation from the others.
+///
+/// Config: `backends`, a list of `{"backend": class, "config": dict}` with an optional config,
+/// the class can be the name of a registered backend.
+#[pyclass]
+pub struct FanOutBackend {
+    #[pyo3(get)]
+    children: Vec<PyObject>,
+}
+
+#[pymethods]
+impl FanOutBackend {
+    #[new]
+    #[pyo3(signature = (config, metric, histogram_bucket=None))]
+    fn new(config: &PyDict, metric: &PyAny, histogram_bucket: Option<String>) -> PyResult<Self> {
+        let py = config.py();
+        let children = children(config)?
+            .into_iter()
+            .map(|(class, child_config)| {
+                Ok(class
+                    .call1((child_config, metric, histogram_bucket.clone()))?
+                    .into_py(py))
+            })
+            .collect::<PyResult<_>>()?;
+        Ok(Self { children })
+    }
+
+    #[classmethod]
+    fn _initialize(_cls: &PyType, config: &PyDict) -> PyResult<()> {
+        let py = config.py();
+        let children = children(config)?;
+        forward(&children, "initialize", |&(class, child_config)| {
+            if class.hasattr(intern!(py, "_initialize"))? {
+                class.call_method1(intern!(py, "_initialize"), (child_config,))?;
+            }
+            Ok(())
+        })?;
+
+        *CHILD_CLASSES.lock().unwrap() = children
+            .into_iter()
+            .map(|(class, _)| class.into())
+            .collect();
+        CHILD_ERRORS.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// Samples of the first child able to produce them, with its `_generate_samples` when it has
+    /// one and through `get` otherwise.
+    #[classmethod]
+    fn _generate_samples(cls: &PyType, registry: &PyAny) -> PyResult<PyObject> {
+        let py = cls.py();
+        let classes: Vec<Py<PyType>> = CHILD_CLASSES
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|class| class.clone_ref(py))
+            .collect();
+        if classes.is_empty() {
+            return Err(PyException::new_err("FanOutBackend is not initialized"));
+        }
+
+        let mut last_err = None;
+        for (index, class) in classes.iter().enumerate() {
+            let class = class.as_ref(py);
+            let samples = match class.hasattr(intern!(py, "_generate_samples"))? {
+                true => class
+                    .call_method1(intern!(py, "_generate_samples"), (registry,))
+                    .map(Into::into),
+                false => {
+                    READ_FROM.with(|read_from| read_from.set(index));
+                    let samples = collected_samples(registry);
+                    READ_FROM.with(|read_from| read_from.set(0));
+                    samples
+                }
+            };
+            match samples {
+                Ok(samples) => return Ok(samples),
+                Err(err) => {
+                    child_failed(index, "generate samples", &err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.unwrap())
+    }
+
+    /// Errors of every child so far, by position in `backends`.
+    #[classmethod]
+    fn child_errors(_cls: &PyType) -> Vec<u64> {
+        let mut errors = CHILD_ERRORS.lock().unwrap().clone();
+        errors.resize(errors.len().max(CHILD_CLASSES.lock().unwrap().len()), 0);
+        errors
+    }
+
+    #[classmethod]
+    fn clear_child_errors(_cls: &PyType) {
+        CHILD_ERRORS.lock().unwrap().clear();
+    }
+
+    fn inc(&self, py: Python, value: f64) -> PyResult<()> {
+        forward(&self.children, "inc", |child| {
+            child.call_method1(py, intern!(py, "inc"), (value,))
+        })
+    }
+
+    fn dec(&self, py: Python, value: f64) -> PyResult<()> {
+        forward(&self.children, "dec", |child| {
+            child.call_method1(py, intern!(py, "dec"), (value,))
+        })
+    }
+
+    fn set(&self, py: Python, value: f64) -> PyResult<()> {
+        forward(&self.children, "set", |child| {
+            child.call_method1(py, intern!(py, "set"), (value,))
+        })
+    }
+
+    fn get(&self, py: Python) -> PyResult<PyObject> {
+        let read_from = READ_FROM.with(Cell::get);
+        let mut last_err = None;
+        for (index, child) in self.children.iter().enumerate().skip(read_from) {
+            match child.call_method0(py, intern!(py, "get")) {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    child_failed(index, "get", &err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| PyException::new_err("no backend to read from")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn errors_are_isolated() {
+        let fail = || PyErr::new::<PyValueError, _>("fail");
+        let mut called = vec![];
+        CHILD_ERRORS.lock().unwrap().clear();
+
+        let result = forward([true, false, true], "inc", |ok| {
+            called.push(ok);
+            ok.then_some(()).ok_or_else(fail)
+        });
+        assert!(result.is_ok());
+        assert_eq!(called, [true, false, true]);
+        assert_eq!(*CHILD_ERRORS.lock().unwrap(), [0, 1]);
+
+        let result = forward([false, false], "inc", |ok| {
+            ok.then_some(()).ok_or_else(fail)
+        });
+        assert!(result.is_err());
+        assert_eq!(*CHILD_ERRORS.lock().unwrap(), [1, 2]);
+    }
+}
@@ -0,0 +1,158 @@
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// Fault injection is only available when this environment variable is set, so that it can't be
+/// triggered by accident in production.
+pub const FAULT_INJECTION_ENV: &str = "PYTHEUS_FAULT_INJECTION";
+
+// fast path for the worker, avoids taking the lock when nothing was injected
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static FAULTS: OnceLock<Mutex<Faults>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultKind {
+    ConnectionDrop,
+    CommandError,
+    Latency,
+    QueueOverflow,
+}
+
+impl FaultKind {
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "connection_drop" => Some(FaultKind::ConnectionDrop),
+            "command_error" => Some(FaultKind::CommandError),
+            "latency" => Some(FaultKind::Latency),
+            "queue_overflow" => Some(FaultKind::QueueOverflow),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Faults {
+    remaining: HashMap<FaultKind, usize>,
+    latency: Duration,
+}
+
+fn faults() -> &'static Mutex<Faults> {
+    FAULTS.get_or_init(Default::default)
+}
+
+fn inject(kind: FaultKind, times: usize, latency: Duration) {
+    let mut faults = faults().lock().unwrap();
+    *faults.remaining.entry(kind).or_insert(0) += times;
+    if kind == FaultKind::Latency {
+        faults.latency = latency;
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+fn clear() {
+    let mut faults = faults().lock().unwrap();
+    faults.remaining.clear();
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Consume one occurrence of the fault, returning whether it was pending.
+fn take(kind: FaultKind) -> bool {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    let mut faults = faults().lock().unwrap();
+    match faults.remaining.get_mut(&kind) {
+        Some(remaining) if *remaining > 0 => {
+            *remaining -= 1;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Called by the worker threads before executing a pipeline against Redis.
+pub fn before_command() -> Result<(), Box<dyn std::error::Error>> {
+    if take(FaultKind::Latency) {
+        let latency = faults().lock().unwrap().latency;
+        thread::sleep(latency);
+    }
+    if take(FaultKind::ConnectionDrop) {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionReset,
+            "injected fault: connection dropped",
+        )
+        .into());
+    }
+    if take(FaultKind::CommandError) {
+        return Err("injected fault: command error".into());
+    }
+    Ok(())
+}
+
+/// Called when a job is enqueued, simulates the job being rejected by a full queue.
+pub fn queue_overflow() -> bool {
+    take(FaultKind::QueueOverflow)
+}
+
+fn check_enabled() -> PyResult<()> {
+    if std::env::var_os(FAULT_INJECTION_ENV).is_none() {
+        return Err(PyRuntimeError::new_err(format!(
+            "fault injection is disabled, set the {FAULT_INJECTION_ENV} environment variable"
+        )));
+    }
+    Ok(())
+}
+
+/// Make the next `times` worker operations fail with the given fault.
+/// Supported kinds: `connection_drop`, `command_error`, `latency` and `queue_overflow`.
+#[pyfunction]
+#[pyo3(signature = (kind, times=1, latency_ms=0))]
+pub fn inject_fault(kind: &str, times: usize, latency_ms: u64) -> PyResult<()> {
+    check_enabled()?;
+    let Some(kind) = FaultKind::parse(kind) else {
+        return Err(PyValueError::new_err(format!("unknown fault kind: {kind}")));
+    };
+    inject(kind, times, Duration::from_millis(latency_ms));
+    Ok(())
+}
+
+/// Remove every pending injected fault.
+#[pyfunction]
+pub fn clear_faults() -> PyResult<()> {
+    check_enabled()?;
+    clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            FaultKind::parse("connection_drop"),
+            Some(FaultKind::ConnectionDrop)
+        );
+        assert_eq!(FaultKind::parse("bob"), None);
+    }
+
+    #[test]
+    fn inject_take_and_clear() {
+        inject(FaultKind::QueueOverflow, 2, Duration::ZERO);
+        assert!(queue_overflow());
+        assert!(queue_overflow());
+        assert!(!queue_overflow());
+
+        inject(FaultKind::QueueOverflow, 1, Duration::ZERO);
+        clear();
+        assert!(!queue_overflow());
+    }
+}
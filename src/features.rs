@@ -0,0 +1,186 @@
+use crate::check;
+use log::{info, warn};
+use redis::ConnectionLike;
+use std::sync::RwLock;
+
+/// Server features the backend can take advantage of, falling back to compatible command
+/// sequences when they are missing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServerFeatures {
+    /// `HSET` with several fields (Redis 4.0), `HMSET` otherwise.
+    pub multi_field_hset: bool,
+    /// `EVAL` to increment several hash fields at once, one `HINCRBYFLOAT` per field otherwise.
+    pub scripting: bool,
+    /// `GETEX` (Redis 6.2) to read and refresh the expiry of a key with one command.
+    pub getex: bool,
+    /// The `NX` option of `EXPIRE` (Redis 7.0), to set the expiry only of a key without one.
+    pub expire_options: bool,
+    /// Per-field expiry of hashes (Redis 7.4).
+    pub hexpire: bool,
+    /// Redis Functions (Redis 7.0).
+    pub functions: bool,
+    /// The RESP3 protocol, negotiated with `HELLO` (Redis 6.0).
+    pub resp3: bool,
+    /// The `TDIGEST` commands of RedisBloom (Redis Stack), for quantiles of summaries computed by
+    /// the server.
+    pub tdigest: bool,
+    /// The `TOPK` commands of RedisBloom (Redis Stack), for heavy hitters counted by the server.
+    pub topk: bool,
+    /// The RedisTimeSeries module (Redis Stack), for metrics stored as time series.
+    pub timeseries: bool,
+    /// The RedisJSON module (Redis Stack), for metrics stored as documents.
+    pub json: bool,
+}
+
+impl Default for ServerFeatures {
+    /// What the backend used before detection existed, for servers that can't tell.
+    fn default() -> Self {
+        Self {
+            multi_field_hset: true,
+            scripting: true,
+            getex: false,
+            expire_options: false,
+            hexpire: false,
+            functions: false,
+            resp3: false,
+            tdigest: false,
+            topk: false,
+            timeseries: false,
+            json: false,
+        }
+    }
+}
+
+// commands looked up with COMMAND INFO, in this order
+const COMMANDS: [&str; 9] = [
+    "eval",
+    "getex",
+    "hexpire",
+    "function",
+    "hello",
+    "tdigest.add",
+    "topk.add",
+    "ts.add",
+    "json.numincrby",
+];
+
+static SERVER_FEATURES: RwLock<Option<ServerFeatures>> = RwLock::new(None);
+
+impl ServerFeatures {
+    fn from_version(version: (u32, u32, u32)) -> Self {
+        Self {
+            multi_field_hset: version >= (4, 0, 0),
+            scripting: true,
+            getex: version >= (6, 2, 0),
+            expire_options: version >= (7, 0, 0),
+            hexpire: version >= (7, 4, 0),
+            functions: version >= (7, 0, 0),
+            resp3: version >= (6, 0, 0),
+            // modules, never implied by the version
+            tdigest: false,
+            topk: false,
+            timeseries: false,
+            json: false,
+        }
+    }
+
+    /// Features as listed by `COMMAND INFO`, more reliable than the version on forks like Valkey
+    /// or Dragonfly that report a Redis version they don't fully implement.
+    fn from_commands(version: Option<(u32, u32, u32)>, commands: &[bool]) -> Self {
+        let [eval, getex, hexpire, functions, hello, tdigest, topk, timeseries, json] = commands
+        else {
+            return version.map(Self::from_version).unwrap_or_default();
+        };
+        Self {
+            // HSET is everywhere, only the arity changed
+            multi_field_hset: !matches!(version, Some(version) if version < (4, 0, 0)),
+            scripting: *eval,
+            getex: *getex,
+            // the options of EXPIRE don't show in COMMAND INFO
+            expire_options: matches!(version, Some(version) if version >= (7, 0, 0)),
+            hexpire: *hexpire,
+            functions: *functions,
+            resp3: *hello,
+            tdigest: *tdigest,
+            topk: *topk,
+            timeseries: *timeseries,
+            json: *json,
+        }
+    }
+}
+
+fn server_version(connection: &mut dyn ConnectionLike) -> Option<(u32, u32, u32)> {
+    let info: String = redis::cmd("INFO").arg("server").query(connection).ok()?;
+    check::parse_version(check::parse_info(&info).get("redis_version")?)
+}
+
+/// Detect the features of the server behind a connection.
+pub fn detect(connection: &mut dyn ConnectionLike) -> ServerFeatures {
+    let version = server_version(connection);
+    // unknown commands are nil in the reply
+    let reply: redis::RedisResult<Vec<redis::Value>> = redis::cmd("COMMAND")
+        .arg("INFO")
+        .arg(&COMMANDS)
+        .query(connection);
+    match reply {
+        Ok(commands) => {
+            let available: Vec<bool> = commands
+                .iter()
+                .map(|command| *command != redis::Value::Nil)
+                .collect();
+            ServerFeatures::from_commands(version, &available)
+        }
+        Err(e) => {
+            warn!("could not list the server commands, guessing its features: {e}");
+            version
+                .map(ServerFeatures::from_version)
+                .unwrap_or_default()
+        }
+    }
+}
+
+/// Detect and remember the features of the server the workers talk to.
+pub fn init(connection: &mut dyn ConnectionLike) {
+    let features = detect(connection);
+    info!("server features: {features:?}");
+    *SERVER_FEATURES.write().unwrap() = Some(features);
+}
+
+/// Features of the server the workers talk to, the defaults before detection.
+pub fn current() -> ServerFeatures {
+    SERVER_FEATURES.read().unwrap().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn degrade_by_version_and_commands() {
+        let old = ServerFeatures::from_version((3, 2, 12));
+        assert!(!old.multi_field_hset && !old.getex && !old.resp3);
+        assert!(ServerFeatures::from_version((7, 4, 0)).hexpire);
+        assert!(!ServerFeatures::from_version((6, 2, 14)).expire_options);
+
+        // a fork claiming 7.2 without scripting nor GETEX
+        let fork = ServerFeatures::from_commands(
+            Some((7, 2, 0)),
+            &[false, false, false, true, true, false, false, false, false],
+        );
+        assert!(fork.multi_field_hset && !fork.scripting && !fork.getex && fork.functions);
+        assert!(fork.expire_options);
+        assert!(!fork.tdigest && !fork.topk);
+
+        let stack = ServerFeatures::from_commands(
+            Some((7, 2, 0)),
+            &[true, true, false, true, true, true, true, true, true],
+        );
+        assert!(stack.tdigest && stack.topk && stack.timeseries && stack.json);
+
+        assert_eq!(
+            ServerFeatures::from_commands(None, &[]),
+            ServerFeatures::default()
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// Write workers the batches are counted for.
+pub const MAX_WRITE_WORKERS: usize = 64;
+
+// batches of jobs sent to each write worker and batches it executed, in the order they were sent
+static QUEUED: [AtomicU64; MAX_WRITE_WORKERS] = [const { AtomicU64::new(0) }; MAX_WRITE_WORKERS];
+static EXECUTED: Mutex<[u64; MAX_WRITE_WORKERS]> = Mutex::new([0; MAX_WRITE_WORKERS]);
+static BATCH_EXECUTED: Condvar = Condvar::new();
+
+/// Count a batch about to be sent to a worker, before sending so that a flush never misses it.
+pub fn batch_queued(worker: usize) {
+    QUEUED[worker].fetch_add(1, Ordering::SeqCst);
+}
+
+/// Batches executed by a worker, or that failed to be sent to it.
+pub fn batches_executed(worker: usize, count: u64) {
+    EXECUTED.lock().unwrap()[worker] += count;
+    BATCH_EXECUTED.notify_all();
+}
+
+/// Marks batches taken by a worker as executed when dropped, even when executing them panicked.
+pub struct Executed {
+    pub worker: usize,
+    pub batches: u64,
+}
+
+impl Drop for Executed {
+    fn drop(&mut self) {
+        batches_executed(self.worker, self.batches);
+    }
+}
+
+/// Forget the batches of the workers of the parent after a fork, the child never executes them.
+pub fn reset() {
+    let mut executed = EXECUTED.lock().unwrap();
+    for (executed, queued) in executed.iter_mut().zip(&QUEUED) {
+        *executed = queued.load(Ordering::SeqCst);
+    }
+}
+
+/// Wait until every batch sent before the call was executed, `false` when the timeout elapsed
+/// first. Each worker executes its batches in order, so once it executed as many batches as were
+/// sent to it before the call, they all are.
+pub fn wait(timeout: Option<Duration>) -> bool {
+    let executed = EXECUTED.lock().unwrap();
+    let targets: Vec<u64> = QUEUED
+        .iter()
+        .map(|queued| queued.load(Ordering::SeqCst))
+        .collect();
+    let pending = |executed: &mut [u64; MAX_WRITE_WORKERS]| {
+        executed
+            .iter()
+            .zip(&targets)
+            .any(|(executed, target)| executed < target)
+    };
+    match timeout {
+        Some(timeout) => {
+            let (_executed, result) = BATCH_EXECUTED
+                .wait_timeout_while(executed, timeout, pending)
+                .unwrap();
+            !result.timed_out()
+        }
+        None => {
+            let _executed = BATCH_EXECUTED.wait_while(executed, pending).unwrap();
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn wait_for_the_batches_sent_before() {
+        reset();
+        batch_queued(0);
+        batch_queued(1);
+        batch_queued(1);
+        assert!(!wait(Some(Duration::from_millis(10))));
+
+        // one worker being done isn't enough
+        batches_executed(1, 2);
+        assert!(!wait(Some(Duration::from_millis(10))));
+        let worker = thread::spawn(|| {
+            let _executed = Executed {
+                worker: 0,
+                batches: 1,
+            };
+        });
+        assert!(wait(Some(Duration::from_secs(5))));
+        worker.join().unwrap();
+        assert!(wait(None));
+    }
+}
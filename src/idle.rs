@@ -0,0 +1,137 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// A labeled series of a metric, identified like the children of its collector.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Series {
+    pub resolved_prefix: String,
+    pub labels_hash: String,
+}
+
+/// What eviction needs to know about a series.
+#[derive(Debug, Clone)]
+pub struct Written {
+    pub last_write: SystemTime,
+    /// Keys holding a field of the series, several for a histogram.
+    pub key_names: BTreeSet<String>,
+    pub route: usize,
+}
+
+/// Last write of every labeled series of the process.
+#[derive(Debug, Default)]
+pub struct IdleTracker {
+    series: HashMap<Series, Written>,
+}
+
+impl IdleTracker {
+    pub fn touch(
+        &mut self,
+        series: Series,
+        key_names: impl IntoIterator<Item = String>,
+        route: usize,
+        now: SystemTime,
+    ) {
+        let written = self.series.entry(series).or_insert_with(|| Written {
+            last_write: now,
+            key_names: BTreeSet::new(),
+            route,
+        });
+        written.last_write = now;
+        written.key_names.extend(key_names);
+    }
+
+    /// Forget and return the series not written since `since`.
+    pub fn take_idle(&mut self, since: SystemTime) -> Vec<(Series, Written)> {
+        let idle: Vec<Series> = self
+            .series
+            .iter()
+            .filter(|(_, written)| written.last_write < since)
+            .map(|(series, _)| series.clone())
+            .collect();
+        idle.into_iter()
+            .filter_map(|series| self.series.remove_entry(&series))
+            .collect()
+    }
+
+    /// Series tracked and the bytes of their names.
+    pub fn tracked(&self) -> (usize, usize) {
+        let bytes = self
+            .series
+            .iter()
+            .map(|(series, written)| {
+                series.resolved_prefix.len()
+                    + series.labels_hash.len()
+                    + written.key_names.iter().map(String::len).sum::<usize>()
+            })
+            .sum();
+        (self.series.len(), bytes)
+    }
+}
+
+static TRACKER: Mutex<Option<IdleTracker>> = Mutex::new(None);
+
+pub fn touch(
+    series: Series,
+    key_names: impl IntoIterator<Item = String>,
+    route: usize,
+    now: SystemTime,
+) {
+    TRACKER
+        .lock()
+        .unwrap()
+        .get_or_insert_with(Default::default)
+        .touch(series, key_names, route, now);
+}
+
+pub fn take_idle(since: SystemTime) -> Vec<(Series, Written)> {
+    TRACKER
+        .lock()
+        .unwrap()
+        .get_or_insert_with(Default::default)
+        .take_idle(since)
+}
+
+pub fn tracked() -> (usize, usize) {
+    TRACKER
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or((0, 0), IdleTracker::tracked)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn series(labels_hash: &str) -> Series {
+        Series {
+            resolved_prefix: "latency".to_string(),
+            labels_hash: labels_hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn take_series_idle_since() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let later = start + Duration::from_secs(60);
+        let mut tracker = IdleTracker::default();
+        tracker.touch(series("cat"), ["latency:1".to_string()], 0, start);
+        tracker.touch(series("dog"), ["latency:1".to_string()], 0, start);
+        tracker.touch(series("cat"), ["latency:sum".to_string()], 0, later);
+
+        let idle = tracker.take_idle(later);
+        assert_eq!(idle.len(), 1);
+        assert_eq!(idle[0].0, series("dog"));
+        assert!(tracker.take_idle(later).is_empty());
+
+        assert_eq!(tracker.tracked().0, 1);
+        let idle = tracker.take_idle(later + Duration::from_secs(1));
+        assert_eq!(
+            Vec::from_iter(&idle[0].1.key_names),
+            ["latency:1", "latency:sum"]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use crate::check;
+use crate::config::RedisConfig;
+use crate::connection;
+use crate::features::{self, ServerFeatures};
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// cargo writes the lockfile before compiling, so it's always there at build time
+const CARGO_LOCK: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.lock"));
+// cargo features of this crate, none are declared yet
+const FEATURES: &[&str] = &[];
+
+/// Version of a package as pinned in a `Cargo.lock`.
+fn locked_version<'a>(lock: &'a str, package: &str) -> Option<&'a str> {
+    let name = format!("name = \"{package}\"");
+    let mut lines = lock.lines();
+    lines.find(|line| *line == name)?;
+    lines
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+}
+
+/// Versions this extension was built with.
+#[pyfunction]
+pub fn build_info(py: Python) -> PyResult<PyObject> {
+    let info = PyDict::new(py);
+    info.set_item("version", env!("CARGO_PKG_VERSION"))?;
+    info.set_item("features", FEATURES)?;
+    info.set_item("redis_rs_version", locked_version(CARGO_LOCK, "redis"))?;
+    info.set_item("pyo3_version", locked_version(CARGO_LOCK, "pyo3"))?;
+    Ok(info.into())
+}
+
+/// What a Redis server reports about itself.
+#[derive(Debug, Default)]
+pub struct ServerInfo {
+    pub version: Option<String>,
+    pub mode: Option<String>,
+    pub cluster_enabled: bool,
+    pub modules: Vec<String>,
+    pub features: ServerFeatures,
+}
+
+fn module_names(connection: &mut connection::Connection) -> Vec<String> {
+    // MODULE LIST is missing before Redis 4 and can be denied by ACLs, either way no modules
+    // are usable
+    let modules: Vec<HashMap<String, redis::Value>> =
+        match redis::cmd("MODULE").arg("LIST").query(connection) {
+            Ok(modules) => modules,
+            Err(_) => return vec![],
+        };
+    modules
+        .iter()
+        .filter_map(|module| module.get("name"))
+        .filter_map(|name| redis::from_redis_value(name).ok())
+        .collect()
+}
+
+pub fn server_info(connection: &mut connection::Connection) -> redis::RedisResult<ServerInfo> {
+    // the default sections include both server and cluster
+    let info = check::parse_info(&redis::cmd("INFO").query::<String>(connection)?);
+    Ok(ServerInfo {
+        version: info.get("redis_version").cloned(),
+        mode: info.get("redis_mode").cloned(),
+        cluster_enabled: info.get("cluster_enabled").map(String::as_str) == Some("1"),
+        modules: module_names(connection),
+        features: features::detect(connection),
+    })
+}
+
+/// Version, mode, loaded modules and detected features of the Redis server of a configuration, or of the one the
+/// backend was initialized with.
+#[pyfunction]
+#[pyo3(name = "server_info")]
+pub fn py_server_info(py: Python, config: Option<&PyDict>) -> PyResult<PyObject> {
+    let config = match config {
+        Some(config) => Arc::new(RedisConfig::from_pydict(config)?),
+        None => crate::current_config(),
+    };
+    let server = py
+        .allow_threads(|| {
+            let mut connection = check::connect(&config)?;
+            server_info(&mut connection).map_err(|e| e.to_string())
+        })
+        .map_err(PyException::new_err)?;
+
+    let info = PyDict::new(py);
+    info.set_item("redis_version", server.version)?;
+    info.set_item("mode", server.mode)?;
+    info.set_item("cluster_enabled", server.cluster_enabled)?;
+    info.set_item("modules", server.modules)?;
+    let features = PyDict::new(py);
+    features.set_item("multi_field_hset", server.features.multi_field_hset)?;
+    features.set_item("scripting", server.features.scripting)?;
+    features.set_item("getex", server.features.getex)?;
+    features.set_item("hexpire", server.features.hexpire)?;
+    features.set_item("functions", server.features.functions)?;
+    features.set_item("resp3", server.features.resp3)?;
+    features.set_item("tdigest", server.features.tdigest)?;
+    features.set_item("topk", server.features.topk)?;
+    features.set_item("timeseries", server.features.timeseries)?;
+    features.set_item("json", server.features.json)?;
+    info.set_item("features", features)?;
+    Ok(info.into())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn lockfile_versions() {
+        let lock = "[[package]]\nname = \"redis\"\nversion = \"0.23.3\"\n\n[[package]]\nname = \"redis-test\"\nversion = \"0.1.0\"\n";
+        assert_eq!(locked_version(lock, "redis"), Some("0.23.3"));
+        assert_eq!(locked_version(lock, "pyo3"), None);
+        assert!(locked_version(CARGO_LOCK, "redis").is_some());
+    }
+}
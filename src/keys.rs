@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Hash mapping hashed keys to the readable name they replace.
+pub const KEY_NAMES_KEY: &str = "pytheus:key_names";
+/// Prefix of the rendered expositions cached by registry namespace and format.
+pub const RENDER_CACHE_KEY: &str = "pytheus:render";
+
+// hashed keys seen by this process and the `(hashed, readable)` pairs the worker has yet to store
+static RECORDED: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+static PENDING: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// 64-bit FNV-1a, stable across processes and Rust versions unlike the std hashers.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Redis key of a readable key name, hashed when longer than `max_length`. Hashed keys have no
+/// `:` so that they are never mistaken for the key of another metric, their readable name is
+/// queued to be stored in `KEY_NAMES_KEY` the first time the process sees them.
+pub fn redis_key(name: String, max_length: Option<usize>) -> String {
+    if max_length.is_none_or(|max_length| name.len() <= max_length) {
+        return name;
+    }
+    let hashed = format!("pytheus-{:016x}", fnv1a(name.as_bytes()));
+
+    let mut recorded = RECORDED.lock().unwrap();
+    if recorded
+        .get_or_insert_with(HashSet::new)
+        .insert(hashed.clone())
+    {
+        PENDING.lock().unwrap().push((hashed.clone(), name));
+    }
+    hashed
+}
+
+/// Readable names of hashed keys not stored yet, best effort: they are not queued again if the
+/// pipeline storing them fails.
+pub fn take_pending() -> Vec<(String, String)> {
+    std::mem::take(&mut *PENDING.lock().unwrap())
+}
+
+/// Hashed keys recorded by this process and the bytes they hold.
+pub fn recorded() -> (usize, usize) {
+    let recorded = RECORDED.lock().unwrap();
+    let recorded = recorded.iter().flatten();
+    (recorded.clone().count(), recorded.map(String::len).sum())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn hash_long_keys() {
+        assert_eq!(redis_key("short".to_string(), Some(10)), "short");
+        assert_eq!(redis_key("a".repeat(20), None), "a".repeat(20));
+
+        let hashed = redis_key("a".repeat(20), Some(10));
+        assert_eq!(hashed, redis_key("a".repeat(20), Some(10)));
+        assert!(hashed.starts_with("pytheus-") && !hashed.contains(':'));
+        // the readable name is stored once
+        assert_eq!(take_pending(), [(hashed.clone(), "a".repeat(20))]);
+        assert!(take_pending().is_empty());
+
+        assert_ne!(hashed, redis_key("b".repeat(20), Some(10)));
+        // reference value of FNV-1a
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+}
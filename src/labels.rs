@@ -0,0 +1,182 @@
+use crate::config::KeyLayout;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// entries of every cache of the process and the bytes of their labels and hashes
+static CACHED_HASHES: AtomicUsize = AtomicUsize::new(0);
+static CACHED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+fn entry_bytes(labels: &BTreeMap<String, String>, hash: &str) -> usize {
+    labels
+        .iter()
+        .map(|(name, value)| name.len() + value.len())
+        .sum::<usize>()
+        + hash.len()
+}
+
+/// Entries of the caches of the process and the bytes they hold.
+pub fn cached() -> (usize, usize) {
+    (
+        CACHED_HASHES.load(Ordering::Relaxed),
+        CACHED_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+fn forget(labels: &BTreeMap<String, String>, hash: &str) {
+    CACHED_HASHES.fetch_sub(1, Ordering::Relaxed);
+    CACHED_BYTES.fetch_sub(entry_bytes(labels, hash), Ordering::Relaxed);
+}
+
+// a JSON string like Python's json.dumps with ensure_ascii
+fn python_json_string(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            ' '..='~' => out.push(c),
+            _ => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    write!(out, "\\u{unit:04x}").unwrap();
+                }
+            }
+        }
+    }
+    out.push('"');
+}
+
+/// Field of a series in the hash of its key: its labels as JSON sorted by name, formatted the
+/// way the key layout wants.
+pub fn labels_hash<'a>(
+    labels: impl IntoIterator<Item = (&'a str, &'a str)>,
+    layout: KeyLayout,
+) -> String {
+    let labels: BTreeMap<&str, &str> = labels.into_iter().collect();
+    match layout {
+        // a map of strings always serializes
+        KeyLayout::Native => serde_json::to_string(&labels).unwrap(),
+        KeyLayout::Pytheus => {
+            let mut hash = String::from("{");
+            for (index, (name, value)) in labels.into_iter().enumerate() {
+                if index > 0 {
+                    hash.push_str(", ");
+                }
+                python_json_string(name, &mut hash);
+                hash.push_str(": ");
+                python_json_string(value, &mut hash);
+            }
+            hash.push('}');
+            hash
+        }
+    }
+}
+
+/// Labels hashes of the series written with call-time labels, keeping the most recently used.
+#[derive(Debug)]
+pub struct LabelsCache {
+    capacity: usize,
+    // hash of the labels and when it was last used
+    entries: HashMap<BTreeMap<String, String>, (String, u64)>,
+    tick: u64,
+}
+
+impl LabelsCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Cached hash of the labels, computed and cached when missing, evicting the least recently
+    /// used entry when full.
+    pub fn get_or_insert_with<E>(
+        &mut self,
+        labels: BTreeMap<String, String>,
+        hash: impl FnOnce(&BTreeMap<String, String>) -> Result<String, E>,
+    ) -> Result<String, E> {
+        self.tick += 1;
+        if let Some((hash, used)) = self.entries.get_mut(&labels) {
+            *used = self.tick;
+            return Ok(hash.clone());
+        }
+
+        let hash = hash(&labels)?;
+        if self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(labels, _)| labels.clone());
+            if let Some((labels, (hash, _))) =
+                oldest.and_then(|oldest| self.entries.remove_entry(&oldest))
+            {
+                forget(&labels, &hash);
+            }
+        }
+        CACHED_HASHES.fetch_add(1, Ordering::Relaxed);
+        CACHED_BYTES.fetch_add(entry_bytes(&labels, &hash), Ordering::Relaxed);
+        self.entries.insert(labels, (hash.clone(), self.tick));
+        Ok(hash)
+    }
+}
+
+impl Drop for LabelsCache {
+    fn drop(&mut self) {
+        for (labels, (hash, _)) in &self.entries {
+            forget(labels, hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn hash(cache: &mut LabelsCache, computed: &mut usize, value: &str) -> String {
+        let labels = BTreeMap::from([("bob".to_string(), value.to_string())]);
+        cache
+            .get_or_insert_with(labels, |labels| {
+                *computed += 1;
+                Ok::<_, ()>(labels["bob"].clone())
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn hash_layouts() {
+        let labels = [("zone", "b\"1"), ("city", "Zürich 😀\n")];
+        assert_eq!(
+            labels_hash(labels, KeyLayout::Native),
+            r#"{"city":"Zürich 😀\n","zone":"b\"1"}"#
+        );
+        assert_eq!(
+            labels_hash(labels, KeyLayout::Pytheus),
+            r#"{"city": "Z\u00fcrich \ud83d\ude00\n", "zone": "b\"1"}"#
+        );
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LabelsCache::new(2);
+        let mut computed = 0;
+        for value in ["cat", "dog", "cat", "bird", "cat"] {
+            assert_eq!(hash(&mut cache, &mut computed, value), value);
+        }
+        assert_eq!(computed, 3);
+        // dog was the least recently used when bird came in
+        hash(&mut cache, &mut computed, "dog");
+        assert_eq!(computed, 4);
+
+        // other tests run concurrently, the two entries are at least counted until the drop
+        assert!(cached().0 >= 2);
+    }
+}
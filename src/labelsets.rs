@@ -0,0 +1,93 @@
+use crate::keys::fnv1a;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Hash mapping compact fields to the labels hash they replace.
+pub const LABEL_SETS_KEY: &str = "pytheus:label_sets";
+
+// starts no labels hash, they are JSON objects
+const COMPACT_PREFIX: char = '#';
+
+// labels hashes of the compact fields known to this process and the `(compact, readable)` pairs
+// the worker has yet to store
+static READABLE: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+static PENDING: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Field of a series with the labels hash, replaced by a fixed-size hash of it when longer than
+/// `max_length`. The labels hash is stored once in `LABEL_SETS_KEY` instead of in every key of
+/// the metric, queued the first time the process sees it.
+pub fn compact(labels_hash: String, max_length: Option<usize>) -> String {
+    if max_length.is_none_or(|max_length| labels_hash.len() <= max_length) {
+        return labels_hash;
+    }
+    let compact = format!("{COMPACT_PREFIX}{:016x}", fnv1a(labels_hash.as_bytes()));
+
+    let mut readable = READABLE.lock().unwrap();
+    let readable = readable.get_or_insert_with(HashMap::new);
+    if !readable.contains_key(&compact) {
+        readable.insert(compact.clone(), labels_hash.clone());
+        PENDING.lock().unwrap().push((compact.clone(), labels_hash));
+    }
+    compact
+}
+
+pub fn is_compact(field: &str) -> bool {
+    field.starts_with(COMPACT_PREFIX)
+}
+
+/// Labels hash of a field, `None` for a compact field this process doesn't know yet.
+pub fn readable(field: &str) -> Option<String> {
+    if !is_compact(field) {
+        return Some(field.to_string());
+    }
+    READABLE.lock().unwrap().as_ref()?.get(field).cloned()
+}
+
+/// Labels hash of a field, a compact field this process doesn't know yet is left as it is.
+pub fn resolve(field: String) -> String {
+    match is_compact(&field) {
+        true => readable(&field).unwrap_or(field),
+        false => field,
+    }
+}
+
+/// Learn the labels hashes of compact fields read from `LABEL_SETS_KEY`.
+pub fn learn(pairs: impl IntoIterator<Item = (String, String)>) {
+    READABLE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .extend(pairs);
+}
+
+/// Labels hashes of compact fields not stored yet, best effort: they are not queued again if
+/// the pipeline storing them fails.
+pub fn take_pending() -> Vec<(String, String)> {
+    std::mem::take(&mut *PENDING.lock().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn compact_long_labels() {
+        let long = format!(r#"{{"query":"{}"}}"#, "a".repeat(100));
+        assert_eq!(compact("{}".to_string(), Some(10)), "{}");
+        assert_eq!(compact(long.clone(), None), long);
+
+        let field = compact(long.clone(), Some(10));
+        assert_eq!(field.len(), 17);
+        assert!(is_compact(&field) && !is_compact(&long));
+        assert_eq!(readable(&field), Some(long.clone()));
+        assert!(take_pending().contains(&(field.clone(), long.clone())));
+        // queued once
+        compact(long.clone(), Some(10));
+        assert!(!take_pending().iter().any(|(compact, _)| *compact == field));
+
+        assert_eq!(readable("#0000000000000000"), None);
+        learn([("#0000000000000000".to_string(), "{}".to_string())]);
+        assert_eq!(readable("#0000000000000000"), Some("{}".to_string()));
+    }
+}
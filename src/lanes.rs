@@ -0,0 +1,46 @@
+use crossbeam::channel::{self, Receiver};
+
+/// Queue of a pipeline job: exposition reads are taken before anything else waiting, so that a
+/// scrape isn't stuck behind a burst of other pipelines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lane {
+    Exposition,
+    Default,
+}
+
+/// Next job for a pipeline worker, from the exposition lane whenever it has one. `None` once
+/// the lanes are disconnected, when the workers are replaced.
+pub fn next_job<T>(exposition: &Receiver<T>, default: &Receiver<T>) -> Option<T> {
+    if let Ok(job) = exposition.try_recv() {
+        return Some(job);
+    }
+    // a disconnected lane is always ready, the other one is drained before giving up
+    channel::select! {
+        recv(exposition) -> job => job.or_else(|_| default.recv()).ok(),
+        recv(default) -> job => job.or_else(|_| exposition.recv()).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn exposition_first() {
+        let (exposition_tx, exposition_rx) = channel::unbounded();
+        let (default_tx, default_rx) = channel::unbounded();
+        for job in 0..3 {
+            default_tx.send(job).unwrap();
+        }
+        exposition_tx.send(10).unwrap();
+
+        assert_eq!(next_job(&exposition_rx, &default_rx), Some(10));
+        assert_eq!(next_job(&exposition_rx, &default_rx), Some(0));
+
+        drop((exposition_tx, default_tx));
+        assert_eq!(next_job(&exposition_rx, &default_rx), Some(1));
+        assert_eq!(next_job(&exposition_rx, &default_rx), Some(2));
+        assert_eq!(next_job(&exposition_rx, &default_rx), None);
+    }
+}
@@ -1,23 +1,123 @@
+mod archive;
 mod atomic;
+mod batch;
+mod bench;
+mod callbacks;
+mod check;
+mod children;
+mod client;
+mod clock;
+mod config;
+mod connection;
+mod credentials;
+mod dead_letter;
+mod doctor;
+mod documents;
+mod drops;
+mod ewma;
+mod export;
+mod failover;
+mod fake;
+mod fanout;
+mod fault;
+mod features;
+mod flush;
+mod idle;
+mod info;
+mod keys;
+mod labels;
+mod labelsets;
+mod lanes;
+mod memory;
+mod metrics;
+mod observations;
+mod otel;
+mod panics;
+mod parity;
+mod pending;
+mod plugins;
+mod ratelimit;
+mod refresh;
+mod registry;
+mod relabel;
+mod samples;
+mod sentinel;
+mod serializer;
+mod sharding;
+mod snapshot;
+mod timeseries;
+mod tls;
+mod topk;
+mod unique;
+mod workers;
 
+use config::{KeyLayout, RedisConfig, Storage, DEFAULT_ROUTE};
 use crossbeam::channel;
-use log::{error, info};
-use pyo3::exceptions::PyException;
+use lanes::Lane;
+use log::{error, info, warn};
+use pyo3::basic::CompareOp;
+use pyo3::exceptions::{PyException, PyTypeError, PyValueError};
 use pyo3::intern;
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyType};
-use redis::{from_redis_value, ConnectionLike, FromRedisValue, RedisResult, Value};
-use std::collections::BTreeMap;
-use std::sync::atomic::Ordering;
-use std::sync::{mpsc, Mutex, OnceLock};
+use pyo3::types::{PyCFunction, PyDict, PyType};
+use pyo3::AsPyPointer;
+use ratelimit::Backpressure;
+use redis::{
+    from_redis_value, ConnectionLike, ErrorKind, FromRedisValue, RedisError, RedisResult, Value,
+};
+use samples::SampleSet;
+use sentinel::Sentinel;
+use serializer::ValueSerializer;
+use std::collections::hash_map::{DefaultHasher, Entry, RandomState};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, OnceLock, TryLockError};
 use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+// series hashes kept per backend for call-time labels
+const LABELS_CACHE_CAPACITY: usize = 256;
+// threads executing the scrape and read pipelines
+const PIPELINE_THREADS: usize = 4;
 
 // This could be completely wrong, not sure if it would break the channel, let's try 🤞
-static REDIS_JOB_TX: OnceLock<Mutex<mpsc::Sender<RedisJob>>> = OnceLock::new();
+// jobs sent together are always applied in the same pipeline
+static REDIS_JOB_TX: OnceLock<Mutex<JobQueues>> = OnceLock::new();
 static REDIS_PIPELINE_JOB_TX: OnceLock<Mutex<channel::Sender<RedisPipelineJob>>> = OnceLock::new();
-const EXPIRE_KEY_SECONDS: usize = 3600;
+static REDIS_EXPOSITION_JOB_TX: OnceLock<Mutex<channel::Sender<RedisPipelineJob>>> =
+    OnceLock::new();
+// replaced on every `_initialize` call, same as the config pytheus hands to new backends
+static REDIS_CONFIG: OnceLock<Mutex<Arc<RedisConfig>>> = OnceLock::new();
+// process that started the workers and where they write: the threads don't survive a fork and
+// don't exist at all in a child started with spawn
+static WORKERS: Mutex<Option<(u32, Store)>> = Mutex::new(None);
+// namespace of the collectors by address, with a weak reference to the collector removing its
+// entry once it's garbage collected, resolved again with the registries of every `_initialize`
+static COLLECTOR_NAMESPACES: Mutex<Option<CollectorNamespaces>> = Mutex::new(None);
+type CollectorNamespaces = HashMap<usize, (PyObject, Option<String>)>;
+const QUANTILE_LABEL: &str = "quantile";
+// attempts at applying a batch when other clients keep modifying the watched keys
+const MAX_TRANSACTION_ATTEMPTS: usize = 16;
+// how often a process waiting for another one to render the exposition checks the cache
+const RENDER_CACHE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+// releases the lease on the shared exposition only if it wasn't taken over after expiring
+const RELEASE_LEASE_SCRIPT: &str = "if redis.call('GET', KEYS[1]) == ARGV[1] then \
+    return redis.call('DEL', KEYS[1]) end return 0";
+// TDIGEST.ADD fails on a missing key and TDIGEST.CREATE on an existing one
+const DIGEST_ADD_SCRIPT: &str = "if redis.call('EXISTS', KEYS[1]) == 0 then \
+    redis.call('TDIGEST.CREATE', KEYS[1]) end \
+    return redis.call('TDIGEST.ADD', KEYS[1], ARGV[1])";
+// no quantiles for a series whose t-digest expired or predates the quantiles
+const DIGEST_QUANTILE_SCRIPT: &str = "if redis.call('EXISTS', KEYS[1]) == 0 then return {} end \
+    return redis.call('TDIGEST.QUANTILE', KEYS[1], unpack(ARGV))";
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum BackendAction {
     Inc,
     Dec,
@@ -26,125 +126,943 @@ enum BackendAction {
 
 #[derive(Debug)]
 struct RedisPipelineJobResult {
-    values: Result<Vec<PipelineResult>, PyErr>,
+    values: Result<Vec<Value>, PyErr>,
 }
 
-#[derive(Debug)]
+// used by confirmed writes to wait for the outcome of the pipeline that executed the job
+type JobAck = mpsc::Sender<Result<(), String>>;
+// used by writes returning the value they leave in the series
+type JobReply = mpsc::Sender<Result<f64, String>>;
+
+#[derive(Debug, Clone)]
 struct RedisJob {
     action: BackendAction,
     key_name: String,
     labels_hash: Option<String>,
     value: f64,
+    expire_at: Option<usize>,
+    // expiry of the series set by the call, overriding the one of the metric
+    lease_at: Option<usize>,
+    // hash recording the time of the write for the series, when tracked
+    last_updated_key: Option<String>,
+    // hash recording when the series first appeared, when tracked
+    created_key: Option<String>,
+    // t-digest of the series the value is also observed into, for summaries with quantiles
+    digest_key: Option<String>,
+    // capped list the value is also appended to, for histograms and summaries recording their
+    // raw observations
+    observations: Option<observations::Target>,
+    // items added to the HyperLogLog of the series, whose estimate then replaces the value of
+    // the series, for unique counters
+    unique_items: Option<unique::Items>,
+    // where the series is configured to be stored, as usual when the server lacks the module
+    storage: Storage,
+    // endpoint the metric is routed to
+    route: usize,
+    ack_tx: Option<JobAck>,
+    reply_tx: Option<JobReply>,
+}
+
+/// What a scrape reads for a collector.
+struct CollectorReads {
+    name: String,
+    route: usize,
+    has_labels: bool,
+    expire_at: Option<usize>,
+    keys: Vec<String>,
+    // where the series are configured to be stored
+    storage: Storage,
+    // hashes of the per-series timestamps exposed as samples
+    companions: Vec<String>,
 }
 
 struct RedisPipelineJob {
     pipeline: redis::Pipeline,
+    route: usize,
     result_tx: mpsc::Sender<RedisPipelineJobResult>,
 }
 
+/// Weak reference to the metric of a backend: the metric holds its backend, a strong reference
+/// back would make a cycle keeping dynamically created metrics alive until the cyclic GC runs.
 #[derive(Debug)]
-#[pyclass]
+struct MetricRef(PyObject);
+
+impl MetricRef {
+    fn new(metric: &PyAny) -> PyResult<Self> {
+        let py = metric.py();
+        let weakref = py
+            .import(intern!(py, "weakref"))?
+            .getattr(intern!(py, "ref"))?;
+        Ok(Self(weakref.call1((metric,))?.into()))
+    }
+
+    /// The metric, `None` once it has been garbage collected.
+    fn get(&self, py: Python) -> PyResult<PyObject> {
+        self.0.call0(py)
+    }
+}
+
+#[derive(Debug)]
+#[pyclass(subclass)]
 struct RedisBackend {
     #[pyo3(get)]
     config: Py<PyDict>,
-    #[pyo3(get)]
-    metric: Py<PyAny>,
+    metric: MetricRef,
     #[pyo3(get)]
     histogram_bucket: Option<String>,
-    redis_job_tx: mpsc::Sender<RedisJob>,
+    redis_job_tx: JobQueues,
+    // process the backend was created in, after a fork the workers of the parent are gone
+    pid: u32,
+    /// Key shared by every child of the collector, histogram buckets and sum/count keys are
+    /// suffixed onto it.
+    #[pyo3(get)]
+    resolved_prefix: String,
+    /// Redis key this child writes to.
     #[pyo3(get)]
     key_name: String,
+    /// Hash field this child writes to, `None` for unlabeled metrics stored as plain keys.
     #[pyo3(get)]
     labels_hash: Option<String>,
+    #[pyo3(get)]
+    expire_at: Option<usize>,
+    #[pyo3(get)]
+    confirmed_writes: bool,
+    last_updated_key: Option<String>,
+    created_key: Option<String>,
+    // observations are also added to the t-digest of their series, for the sum of a summary
+    // with quantiles
+    observes_quantiles: bool,
+    // observations are also recorded raw, by the sum of a histogram or summary or by a backend
+    // covering a whole histogram
+    raw_observations: Option<observations::Recording>,
+    // observations made so far, for the sampling of the recorded ones
+    observed: AtomicU64,
+    // where the series are configured to be stored
+    storage: Storage,
+    /// Upper bounds of the buckets, `+Inf` included, when the backend was created for a whole
+    /// histogram rather than for one of its buckets, empty for a whole summary.
+    histogram_bounds: Option<Vec<f64>>,
+    // default and metric labels, completed by the labels passed to each call
+    base_labels: BTreeMap<String, String>,
+    required_labels: BTreeSet<String>,
+    labels_cache: Mutex<labels::LabelsCache>,
+    // label whose values other than the heavy hitters are aggregated, with their tracker
+    top_k: Option<(String, Arc<Mutex<topk::Tracker>>)>,
+    /// Endpoint the keys of the metric live on, `0` for the one of `host` and `port`.
+    #[pyo3(get)]
+    route: usize,
 }
 
-#[derive(Debug)]
-#[pyclass]
+#[derive(Debug, Clone, PartialEq)]
+// the module is needed for pickle to find the class
+#[pyclass(module = "pytheus_backend_rs")]
 struct OutSample {
     #[pyo3(get)]
     suffix: String,
-    #[pyo3(get)]
+    // identity labels of the series, the quantile of summary samples is kept apart
     labels: Option<BTreeMap<String, String>>,
     #[pyo3(get)]
+    quantile: Option<f64>,
+    #[pyo3(get)]
     value: f64,
 }
 
 impl OutSample {
-    fn new(suffix: String, labels: Option<BTreeMap<String, String>>, value: f64) -> Self {
+    fn new(suffix: String, mut labels: Option<BTreeMap<String, String>>, value: f64) -> Self {
+        let quantile = labels.as_mut().and_then(|labels| {
+            let quantile = labels.get(QUANTILE_LABEL)?.parse::<f64>().ok()?;
+            labels.remove(QUANTILE_LABEL);
+            Some(quantile)
+        });
         Self {
             suffix,
             labels,
+            quantile,
             value,
         }
     }
+
+    /// Labels as exposed, with the quantile in its canonical format.
+    fn exposed_labels(&self) -> Option<BTreeMap<String, String>> {
+        let Some(quantile) = self.quantile else {
+            return self.labels.clone();
+        };
+        let mut labels = self.labels.clone().unwrap_or_default();
+        labels.insert(
+            QUANTILE_LABEL.to_string(),
+            samples::float_to_go_string(quantile),
+        );
+        Some(labels)
+    }
 }
 
-#[derive(Debug)]
-struct SamplesResultDict {
-    collectors: Vec<Py<PyAny>>,
-    samples_vec: Vec<Vec<OutSample>>,
+#[pymethods]
+impl OutSample {
+    #[new]
+    #[pyo3(signature = (suffix, labels=None, value=0.0))]
+    fn py_new(suffix: String, labels: Option<BTreeMap<String, String>>, value: f64) -> Self {
+        Self::new(suffix, labels, value)
+    }
+
+    #[getter]
+    fn labels(&self) -> Option<BTreeMap<String, String>> {
+        self.exposed_labels()
+    }
+
+    fn __richcmp__(&self, other: &PyAny, op: CompareOp, py: Python) -> PyObject {
+        let Ok(other) = other.extract::<PyRef<OutSample>>() else {
+            return py.NotImplemented();
+        };
+        match op {
+            CompareOp::Eq => (*self == *other).into_py(py),
+            CompareOp::Ne => (*self != *other).into_py(py),
+            _ => py.NotImplemented(),
+        }
+    }
+
+    fn __hash__(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.suffix.hash(&mut hasher);
+        self.labels.hash(&mut hasher);
+        self.quantile.map(f64::to_bits).hash(&mut hasher);
+        // 0.0 and -0.0 are equal so they must hash the same
+        let value = if self.value == 0.0 { 0.0 } else { self.value };
+        value.to_bits().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        Ok(format!(
+            "OutSample(suffix={}, labels={}, value={})",
+            self.suffix.to_object(py).as_ref(py).repr()?,
+            self.exposed_labels().to_object(py).as_ref(py).repr()?,
+            self.value.to_object(py).as_ref(py).repr()?,
+        ))
+    }
+
+    fn __reduce__(slf: &PyCell<Self>) -> PyResult<(PyObject, PyObject)> {
+        let py = slf.py();
+        let sample = slf.borrow();
+        let args = (sample.suffix.clone(), sample.exposed_labels(), sample.value);
+        Ok((slf.get_type().into_py(py), args.into_py(py)))
+    }
+
+    fn to_dict(&self, py: Python) -> PyResult<PyObject> {
+        let dict = PyDict::new(py);
+        dict.set_item(intern!(py, "suffix"), &self.suffix)?;
+        dict.set_item(intern!(py, "labels"), self.exposed_labels())?;
+        dict.set_item(intern!(py, "value"), self.value)?;
+        Ok(dict.into())
+    }
 }
 
-impl SamplesResultDict {
-    fn new() -> Self {
-        Self {
-            collectors: vec![],
-            samples_vec: vec![],
+fn current_config() -> Arc<RedisConfig> {
+    REDIS_CONFIG
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .clone()
+}
+
+/// Sliding expiry of a key, with the configured jitter.
+fn sliding_expire_seconds() -> usize {
+    let config = current_config();
+    if config.expire_jitter == 0 {
+        return config.expire_key_seconds;
+    }
+    // every RandomState is seeded differently, good enough to spread expiries
+    let random = RandomState::new().build_hasher().finish() as usize;
+    config.expire_key_seconds + random % (config.expire_jitter + 1)
+}
+
+/// Set the expiry of a key, the sliding one at most once per `expire_refresh_interval` by thread
+/// in the batches of writes. In between only a key without an expiry gets one.
+fn add_expire_to_pipeline(key_name: &str, expire_at: Option<usize>, pipe: &mut redis::Pipeline) {
+    match expire_at {
+        Some(timestamp) => pipe.expire_at(key_name, timestamp).ignore(),
+        None if refresh::due(key_name, clock::now()) => {
+            pipe.expire(key_name, sliding_expire_seconds()).ignore()
         }
+        // the key may have been deleted and recreated since its last refresh
+        None => pipe
+            .cmd("EXPIRE")
+            .arg(key_name)
+            .arg(sliding_expire_seconds())
+            .arg("NX")
+            .ignore(),
+    };
+}
+
+/// Read a metric key for a scrape, refreshing its expiry. With GETEX an unlabeled key is read
+/// and refreshed with a single command.
+fn add_read_to_pipeline(
+    key_name: &str,
+    has_labels: bool,
+    expire_at: Option<usize>,
+    features: &features::ServerFeatures,
+    pipe: &mut redis::Pipeline,
+) {
+    if !has_labels && features.getex {
+        match expire_at {
+            Some(timestamp) => pipe.cmd("GETEX").arg(key_name).arg("EXAT").arg(timestamp),
+            None => pipe
+                .cmd("GETEX")
+                .arg(key_name)
+                .arg("EX")
+                .arg(sliding_expire_seconds()),
+        };
+        return;
     }
+
+    add_expire_to_pipeline(key_name, expire_at, pipe);
+    match has_labels {
+        true => pipe.hgetall(key_name),
+        false => pipe.get(key_name),
+    };
 }
 
-impl IntoPy<PyResult<PyObject>> for SamplesResultDict {
-    fn into_py(self, py: Python<'_>) -> PyResult<PyObject> {
-        let pydict = PyDict::new(py);
-        for (collector, samples) in self
-            .collectors
-            .into_iter()
-            .zip(self.samples_vec.into_iter())
+/// Read a metric key for a scrape from where its series are stored, see `stored_reply`.
+fn add_stored_read_to_pipeline(
+    storage: Storage,
+    key_name: &str,
+    has_labels: bool,
+    expire_at: Option<usize>,
+    features: &features::ServerFeatures,
+    pipe: &mut redis::Pipeline,
+) {
+    match storage {
+        Storage::Keys => add_read_to_pipeline(key_name, has_labels, expire_at, features, pipe),
+        // time series are refreshed by their writes only
+        Storage::TimeSeries => timeseries::add_read_to_pipeline(key_name, pipe),
+        Storage::Documents => documents::add_read_to_pipeline(key_name, expire_at, pipe),
+    }
+}
+
+/// Reply of a read of a metric key shaped like the read of a key stored as usual.
+fn stored_reply(storage: Storage, reply: Value, has_labels: bool) -> Value {
+    match storage {
+        Storage::Keys => reply,
+        Storage::TimeSeries => timeseries::stored_reply(&reply, has_labels),
+        Storage::Documents => documents::stored_reply(&reply, has_labels),
+    }
+}
+
+/// Hash storing the last update time of every series of a metric, by labels hash.
+fn last_updated_key(resolved_prefix: &str) -> String {
+    redis_key(format!("{resolved_prefix}:last_updated"))
+}
+
+/// Hash storing the creation time of every series of a metric, by labels hash.
+fn created_key(resolved_prefix: &str) -> String {
+    redis_key(format!("{resolved_prefix}:created"))
+}
+
+/// T-digest of the observations of a series of a summary with quantiles.
+fn digest_key(resolved_prefix: &str, labels_hash: &Option<String>) -> String {
+    redis_key(format!(
+        "{resolved_prefix}:tdigest:{}",
+        series_field(labels_hash)
+    ))
+}
+
+/// Key in Redis for a readable key name, hashed when longer than `max_key_length`.
+fn redis_key(name: String) -> String {
+    keys::redis_key(name, current_config().max_key_length)
+}
+
+/// Prefix of the keys of a metric, the metric name within the namespace of its registry, after
+/// the configured key prefix.
+fn namespaced(namespace: Option<&str>, name: &str) -> String {
+    let key_prefix = &current_config().key_prefix;
+    match namespace {
+        Some(namespace) => format!("{key_prefix}{namespace}/{name}"),
+        None => format!("{key_prefix}{name}"),
+    }
+}
+
+/// Namespace of the registry a collector is registered to, if any. The registries are only
+/// scanned for a collector seen for the first time since `_initialize`, recording the namespace
+/// of every collector they hold, so that creating the children of a metric doesn't scan them.
+fn collector_namespace(config: &RedisConfig, collector: &PyAny) -> PyResult<Option<String>> {
+    let py = collector.py();
+    let id = collector.as_ptr() as usize;
+    if let Some((_, namespace)) = COLLECTOR_NAMESPACES
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .get(&id)
+    {
+        return Ok(namespace.clone());
+    }
+
+    // resolved without the lock, iterating a registry and taking weak references run Python code
+    let mut resolved: HashMap<usize, (Option<PyObject>, Option<String>)> = HashMap::new();
+    for (namespace, registry) in &config.registry_namespaces {
+        for registered in registry
+            .as_ref(py)
+            .call_method0(intern!(py, "collect"))?
+            .iter()?
         {
-            pydict.set_item(collector, samples.into_py(py))?;
+            let registered = registered?;
+            // the first registry holding a collector wins
+            if let Entry::Vacant(entry) = resolved.entry(registered.as_ptr() as usize) {
+                entry.insert((collector_ref(registered)?, Some(namespace.clone())));
+            }
+        }
+    }
+    if let Entry::Vacant(entry) = resolved.entry(id) {
+        entry.insert((collector_ref(collector)?, None));
+    }
+    let namespace = resolved[&id].1.clone();
+
+    let mut namespaces = COLLECTOR_NAMESPACES.lock().unwrap();
+    let namespaces = namespaces.get_or_insert_with(HashMap::new);
+    // the collectors without weak references are resolved again every time
+    for (id, (weakref, namespace)) in resolved {
+        if let Some(weakref) = weakref {
+            namespaces.entry(id).or_insert((weakref, namespace));
+        }
+    }
+    Ok(namespace)
+}
+
+/// Weak reference to a collector removing its entry from `COLLECTOR_NAMESPACES` once it's
+/// garbage collected, `None` when the collector doesn't support weak references.
+fn collector_ref(collector: &PyAny) -> PyResult<Option<PyObject>> {
+    let py = collector.py();
+    let id = collector.as_ptr() as usize;
+    let forget = PyCFunction::new_closure(py, None, None, move |args, _| -> PyResult<()> {
+        let weakref = args.get_item(0)?;
+        if let Some(namespaces) = COLLECTOR_NAMESPACES.lock().unwrap().as_mut() {
+            // replaced by another reference when resolved again after `_initialize`
+            if namespaces
+                .get(&id)
+                .is_some_and(|(entry, _)| entry.is(weakref))
+            {
+                namespaces.remove(&id);
+            }
+        }
+        Ok(())
+    })?;
+    let weakref = py
+        .import(intern!(py, "weakref"))?
+        .getattr(intern!(py, "ref"))?;
+    match weakref.call1((collector, forget)) {
+        Ok(weakref) => Ok(Some(weakref.into())),
+        Err(e) if e.is_instance_of::<PyTypeError>(py) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Namespace configured for a registry, if any.
+fn registry_namespace<'a>(config: &'a RedisConfig, registry: &PyAny) -> Option<&'a str> {
+    config
+        .registry_namespaces
+        .iter()
+        .find(|(_, namespaced)| namespaced.is(registry))
+        .map(|(namespace, _)| namespace.as_str())
+}
+
+/// Hashes of per-series timestamps exposed as extra samples of a metric, with their suffix.
+fn companion_samples(
+    config: &RedisConfig,
+    name: &str,
+    prefix: &str,
+) -> Vec<(&'static str, String)> {
+    let mut companions = vec![];
+    if config.created_samples && config.track_created.contains(name) {
+        companions.push(("_created", created_key(prefix)));
+    }
+    if config.last_updated_samples && config.track_last_update.contains(name) {
+        companions.push(("_last_updated", last_updated_key(prefix)));
+    }
+    companions
+}
+
+// unlabeled series use the empty field
+fn series_field(labels_hash: &Option<String>) -> &str {
+    labels_hash.as_deref().unwrap_or_default()
+}
+
+fn series_labels(field: &str) -> PyResult<Option<BTreeMap<String, String>>> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    let Some(field) = labelsets::readable(field) else {
+        return Err(PyException::new_err(format!(
+            "unknown compact labels: {field}"
+        )));
+    };
+    match serde_json::from_str(&field) {
+        Ok(labels) => Ok(Some(labels)),
+        Err(e) => Err(PyException::new_err(e.to_string())),
+    }
+}
+
+/// Last values of the fields of idle series read from Redis, for the archive sink. The series
+/// stored as time series or documents are archived without their value.
+fn archive_records(
+    py: Python,
+    config: &RedisConfig,
+    idle: &[(idle::Series, idle::Written)],
+) -> PyResult<Vec<archive::Record>> {
+    let mut records = vec![];
+    let mut pipes: BTreeMap<usize, (redis::Pipeline, Vec<usize>)> = BTreeMap::new();
+    for (series, written) in idle {
+        let metric_name = series
+            .resolved_prefix
+            .strip_prefix(&config.key_prefix)
+            .unwrap_or_default()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        let stored_in_hash = config.storage(metric_name) == Storage::Keys;
+        for key_name in &written.key_names {
+            if stored_in_hash {
+                let (pipe, read) = pipes.entry(written.route).or_default();
+                pipe.hget(key_name, &series.labels_hash);
+                read.push(records.len());
+            }
+            records.push(archive::Record {
+                metric: series.resolved_prefix.clone(),
+                key_name: key_name.clone(),
+                labels: series_labels(&series.labels_hash)?,
+                value: None,
+                last_write: written
+                    .last_write
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64(),
+                route: written.route,
+            });
+        }
+    }
+    for (route, (pipe, read)) in pipes {
+        for (index, reply) in read.into_iter().zip(execute_pipeline(py, route, pipe)?) {
+            let raw: Option<String> = from_redis_value(&reply).unwrap_or_default();
+            records[index].value = raw.and_then(|raw| decode_value(&raw).ok());
         }
-        Ok(pydict.into())
+    }
+    Ok(records)
+}
+
+/// Bucket bounds of a histogram collector, always ending with the `+Inf` bucket.
+fn histogram_bounds(collector: &PyAny) -> PyResult<Vec<f64>> {
+    let py = collector.py();
+    let upper_bounds: Vec<f64> = collector
+        .getattr(intern!(py, "_metric"))?
+        .getattr(intern!(py, "_upper_bounds"))?
+        .extract()?;
+    Ok(samples::bucket_bounds(&upper_bounds))
+}
+
+/// Suffix of the key of a histogram bucket, its bound formatted as the key layout wants. Read back
+/// as the `le` of the samples with `samples::normalize_bound`.
+fn bucket_suffix(bound: f64) -> String {
+    match current_config().key_layout {
+        KeyLayout::Native => samples::format_bound(bound),
+        KeyLayout::Pytheus => samples::python_str(bound),
+    }
+}
+
+/// Key suffixes of a histogram: its buckets in order followed by `count` and `sum`.
+fn histogram_suffixes(collector: &PyAny) -> PyResult<Vec<String>> {
+    let mut suffixes: Vec<String> = histogram_bounds(collector)?
+        .into_iter()
+        .map(bucket_suffix)
+        .collect();
+    suffixes.extend(["count".to_string(), "sum".to_string()]);
+    Ok(suffixes)
+}
+
+fn add_last_updated_to_pipeline(job: &RedisJob, pipe: &mut redis::Pipeline) {
+    if let Some(key_name) = &job.last_updated_key {
+        pipe.hset(
+            key_name,
+            series_field(&job.labels_hash),
+            clock::unix_timestamp(),
+        )
+        .ignore();
+        add_expire_to_pipeline(key_name, job.expire_at, pipe);
+    }
+}
+
+/// Apply the expiry set by the call after the one of the metric: on the field of a labeled series,
+/// when the server supports field expiry, or on the key of an unlabeled one.
+fn add_lease_to_pipeline(
+    job: &RedisJob,
+    features: &features::ServerFeatures,
+    pipe: &mut redis::Pipeline,
+) {
+    let Some(lease_at) = job.lease_at else {
+        return;
+    };
+    match &job.labels_hash {
+        Some(labels_hash) if features.hexpire => pipe
+            .cmd("HEXPIREAT")
+            .arg(&job.key_name)
+            .arg(lease_at)
+            .arg("FIELDS")
+            .arg(1)
+            .arg(labels_hash)
+            .ignore(),
+        // refused by the call, expiring the key would delete the other series of the metric
+        Some(_) => return,
+        None => pipe.expire_at(&job.key_name, lease_at).ignore(),
+    };
+}
+
+fn add_created_to_pipeline(job: &RedisJob, pipe: &mut redis::Pipeline) {
+    if let Some(key_name) = &job.created_key {
+        // only the first write of the series sets it, also recreating it after expiry
+        pipe.hset_nx(
+            key_name,
+            series_field(&job.labels_hash),
+            clock::unix_timestamp(),
+        )
+        .ignore();
+        add_expire_to_pipeline(key_name, job.expire_at, pipe);
+    }
+}
+
+fn add_digest_to_pipeline(
+    job: &RedisJob,
+    features: &features::ServerFeatures,
+    pipe: &mut redis::Pipeline,
+) {
+    let Some(key_name) = &job.digest_key else {
+        return;
+    };
+    if !features.tdigest || !features.scripting {
+        return;
+    }
+    pipe.cmd("EVAL")
+        .arg(DIGEST_ADD_SCRIPT)
+        .arg(1)
+        .arg(key_name)
+        .arg(job.value)
+        .ignore();
+    add_expire_to_pipeline(key_name, job.expire_at, pipe);
+}
+
+fn add_observation_to_pipeline(job: &RedisJob, pipe: &mut redis::Pipeline) {
+    if let Some(target) = &job.observations {
+        observations::add_to_pipeline(target, job.value, job.expire_at, pipe);
     }
 }
 
 fn create_redis_pool(
     host: &str,
     port: u16,
-) -> Result<r2d2::Pool<redis::Client>, Box<dyn std::error::Error>> {
-    let url = format!("redis://{host}:{port}");
-    let client = redis::Client::open(url)?;
-    let pool = r2d2::Pool::builder().build(client)?;
-    Ok(pool)
-}
-
-fn add_job_to_pipeline(received: RedisJob, pipe: &mut redis::Pipeline) {
-    match received.action {
-        BackendAction::Inc | BackendAction::Dec => {
-            match received.labels_hash {
-                Some(labels_hash) => pipe
-                    .hincr(&received.key_name, &labels_hash, received.value)
-                    .ignore(),
-                None => pipe.incr(&received.key_name, received.value).ignore(),
-            };
-            pipe.expire(&received.key_name, EXPIRE_KEY_SECONDS).ignore();
+    sentinel: Option<Sentinel>,
+    config: &RedisConfig,
+    max_size: u32,
+) -> PyResult<r2d2::Pool<connection::Manager>> {
+    let credentials = config.credentials.clone();
+    let manager = connection::Manager::new(
+        host,
+        port,
+        config.socket_options,
+        config.tls.clone(),
+        credentials.clone(),
+    )
+    .with_sentinel(sentinel)
+    .with_db(config.db);
+    // rejected credentials or database would only surface as the timeout of the pool, the
+    // server errors of a new connection can only come from `AUTH` and `SELECT`
+    if credentials.is_some() || config.db != 0 {
+        match r2d2::ManageConnection::connect(&manager) {
+            Err(e) if e.is_io_error() => {}
+            Err(e) if credentials::is_auth_error(&e) || config.db == 0 => {
+                return Err(credentials::auth_failed(&e))
+            }
+            Err(e) => {
+                return Err(PyValueError::new_err(format!(
+                    "cannot select db {}: {e}",
+                    config.db
+                )))
+            }
+            Ok(_) => {}
+        }
+    }
+    r2d2::Pool::builder()
+        .max_size(max_size)
+        .build(manager)
+        .map_err(|e| PyException::new_err(e.to_string()))
+}
+
+/// Pools of every endpoint, each worker thread holding one connection per endpoint. The extra
+/// connection lets a worker replace a broken connection while still holding it. Connecting may
+/// call the credential provider from the threads of the pools, the GIL must be released.
+fn create_redis_pools(
+    config: &RedisConfig,
+    threads: usize,
+) -> PyResult<Vec<r2d2::Pool<connection::Manager>>> {
+    config
+        .endpoints()
+        .into_iter()
+        .enumerate()
+        .map(|(route, (host, port))| {
+            // only the default endpoint has sentinels
+            let sentinel = config.sentinel.clone().filter(|_| route == DEFAULT_ROUTE);
+            create_redis_pool(host, port, sentinel, config, threads as u32 + 1)
+        })
+        .collect()
+}
+
+/// Compact fields in the replies whose labels this process doesn't know.
+fn unknown_label_sets<'a>(value: &'a Value, unknown: &mut BTreeSet<&'a str>) {
+    match value {
+        Value::Bulk(values) => {
+            for value in values {
+                unknown_label_sets(value, unknown);
+            }
+        }
+        Value::Data(data) => {
+            if let Ok(field) = std::str::from_utf8(data) {
+                if labelsets::is_compact(field) && labelsets::readable(field).is_none() {
+                    unknown.insert(field);
+                }
+            }
         }
+        _ => {}
+    }
+}
+
+/// Read the labels of the compact fields written by other processes from the default endpoint,
+/// before the replies are decoded.
+fn learn_label_sets(py: Python, replies: &BTreeMap<usize, Vec<Value>>) -> PyResult<()> {
+    let mut unknown = BTreeSet::new();
+    for value in replies.values().flatten() {
+        unknown_label_sets(value, &mut unknown);
+    }
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    let mut pipe = redis::pipe();
+    pipe.cmd("HMGET")
+        .arg(labelsets::LABEL_SETS_KEY)
+        .arg(Vec::from_iter(&unknown));
+    let values = execute_pipeline_in(py, Lane::Exposition, DEFAULT_ROUTE, pipe)?;
+    let readable: Vec<Option<String>> = match values.first() {
+        Some(value) => from_redis_value(value).map_err(|e| PyException::new_err(e.to_string()))?,
+        None => vec![],
+    };
+    labelsets::learn(
+        unknown
+            .into_iter()
+            .zip(readable)
+            .filter_map(|(compact, readable)| Some((compact.to_string(), readable?))),
+    );
+    Ok(())
+}
+
+/// Store the labels hashes of the fields compacted since the last write.
+fn add_label_sets_to_pipeline(features: &features::ServerFeatures, pipe: &mut redis::Pipeline) {
+    let label_sets = labelsets::take_pending();
+    if label_sets.is_empty() {
+        return;
+    }
+    let fields: Vec<(&str, &str)> = label_sets
+        .iter()
+        .map(|(compact, readable)| (compact.as_str(), readable.as_str()))
+        .collect();
+    batch::add_hash_fields_to_pipeline(labelsets::LABEL_SETS_KEY, &fields, features, pipe);
+}
+
+/// Store the readable names of the keys hashed since the last write.
+fn add_key_names_to_pipeline(features: &features::ServerFeatures, pipe: &mut redis::Pipeline) {
+    let key_names = keys::take_pending();
+    if key_names.is_empty() {
+        return;
+    }
+    let fields: Vec<(&str, &str)> = key_names
+        .iter()
+        .map(|(hashed, readable)| (hashed.as_str(), readable.as_str()))
+        .collect();
+    batch::add_hash_fields_to_pipeline(keys::KEY_NAMES_KEY, &fields, features, pipe);
+}
+
+/// Write of a job awaiting the value it leaves in the series, the only reply of the pipeline
+/// that isn't ignored. Returns the commands of the write.
+fn add_replied_write_to_pipeline(job: &RedisJob, pipe: &mut redis::Pipeline) -> JobCommands {
+    let start = pipe.cmd_iter().count();
+    let value = match job.action {
+        // decrements are queued negated
+        BackendAction::Inc | BackendAction::Dec => job.value,
         BackendAction::Set => {
-            match received.labels_hash {
-                Some(labels_hash) => pipe
-                    .hset(&received.key_name, &labels_hash, received.value)
-                    .ignore(),
-                None => pipe.set(&received.key_name, received.value).ignore(),
+            // the reply of the SET is replaced by the value it sets
+            match &job.labels_hash {
+                Some(labels_hash) => pipe.hset(&job.key_name, labels_hash, job.value).ignore(),
+                None => pipe.set(&job.key_name, job.value).ignore(),
+            };
+            pipe.cmd("ECHO").arg(job.value);
+            add_expire_to_pipeline(&job.key_name, job.expire_at, pipe);
+            return JobCommands {
+                writes: start..start + 2,
+                reply: Some(start + 1),
             };
-            pipe.expire(&received.key_name, EXPIRE_KEY_SECONDS).ignore();
+        }
+    };
+    match &job.labels_hash {
+        Some(labels_hash) => pipe.hincr(&job.key_name, labels_hash, value),
+        None => pipe.incr(&job.key_name, value),
+    };
+    add_expire_to_pipeline(&job.key_name, job.expire_at, pipe);
+    JobCommands {
+        writes: start..start + 1,
+        reply: Some(start),
+    }
+}
+
+/// Add the items of a unique counter job to the HyperLogLog of its series, written on their own.
+fn add_unique_to_pipeline(
+    job: &RedisJob,
+    items: &unique::Items,
+    pipe: &mut redis::Pipeline,
+) -> JobCommands {
+    let start = pipe.cmd_iter().count();
+    unique::add_to_pipeline(items, &job.key_name, &job.labels_hash, job.expire_at, pipe);
+    JobCommands {
+        writes: start..start + 1,
+        reply: None,
+    }
+}
+
+/// Commands of a transaction writing the value of a job, and the one replying to it when it
+/// awaits a reply.
+#[derive(Debug, Clone)]
+struct JobCommands {
+    writes: Range<usize>,
+    reply: Option<usize>,
+}
+
+/// Outcome of the jobs of a transaction Redis applied, a failed command not undoing the others:
+/// for every job the reply it awaits or the error of its own write, and the errors of the other
+/// commands, e.g. of the created timestamps.
+#[derive(Debug)]
+struct Written {
+    outcomes: Vec<Result<Option<f64>, String>>,
+    errors: Vec<String>,
+}
+
+impl Written {
+    fn new(commands: &[JobCommands], replies: &[RedisResult<Value>]) -> Self {
+        let outcomes = commands
+            .iter()
+            .map(|job| {
+                let writes = replies.get(job.writes.clone()).unwrap_or_default();
+                if let Some(Err(e)) = writes.iter().find(|reply| reply.is_err()) {
+                    return Err(e.to_string());
+                }
+                match job.reply.map(|index| replies.get(index)) {
+                    Some(Some(Ok(value))) => {
+                        from_redis_value(value).map(Some).map_err(|e| e.to_string())
+                    }
+                    Some(_) => Err("missing reply".to_string()),
+                    None => Ok(None),
+                }
+            })
+            .collect();
+        let errors = replies
+            .iter()
+            .enumerate()
+            .filter_map(|(index, reply)| Some((index, reply.as_ref().err()?)))
+            .filter(|(index, _)| !commands.iter().any(|job| job.writes.contains(index)))
+            .map(|(_, e)| e.to_string())
+            .collect();
+        Self { outcomes, errors }
+    }
+
+    /// Every job failed, none of the commands reached Redis or the transaction was aborted.
+    fn failed(jobs: usize, e: &str) -> Self {
+        Self {
+            outcomes: vec![Err(e.to_string()); jobs],
+            errors: vec![],
+        }
+    }
+
+    /// The outcome of the jobs from the `start` one.
+    fn split_off(&mut self, start: usize) -> Self {
+        Self {
+            outcomes: self.outcomes.split_off(start),
+            errors: mem::take(&mut self.errors),
+        }
+    }
+}
+
+/// Add a batch of jobs folded into one write per series, with a single command per key. Jobs
+/// awaiting a reply are written on their own between the folded writes of the jobs sent before
+/// and after them, so that their reply is the value right after their write, and so are the
+/// items of unique counters, which can't be folded.
+fn add_jobs_to_pipeline(
+    jobs: &[RedisJob],
+    route: usize,
+    pipe: &mut redis::Pipeline,
+) -> Vec<JobCommands> {
+    let features = features::current();
+    // the readable names and labels are all kept on the default endpoint
+    if route == DEFAULT_ROUTE {
+        add_key_names_to_pipeline(&features, pipe);
+        add_label_sets_to_pipeline(&features, pipe);
+    }
+    let mut commands = vec![];
+    let written_alone = |job: &RedisJob| job.reply_tx.is_some() || job.unique_items.is_some();
+    for segment in jobs.split_inclusive(written_alone) {
+        let (folded, alone) = match segment.split_last() {
+            Some((last, folded)) if written_alone(last) => (folded, Some(last)),
+            _ => (segment, None),
+        };
+        // the folded jobs of a key share its writes
+        let mut key_writes: HashMap<&str, Range<usize>> = HashMap::new();
+        for key in batch::fold(folded) {
+            let start = pipe.cmd_iter().count();
+            match key.storage.on(&features) {
+                Storage::Keys => batch::add_key_writes_to_pipeline(&key, &features, pipe),
+                Storage::TimeSeries => timeseries::add_key_writes_to_pipeline(
+                    &key,
+                    current_config().timeseries_retention,
+                    pipe,
+                ),
+                Storage::Documents => documents::add_key_writes_to_pipeline(&key, pipe),
+            }
+            key_writes.insert(key.key_name, start..pipe.cmd_iter().count());
+        }
+        commands.extend(folded.iter().map(|job| JobCommands {
+            writes: key_writes[job.key_name.as_str()].clone(),
+            reply: None,
+        }));
+        if let Some(job) = alone {
+            commands.push(match &job.unique_items {
+                Some(items) => add_unique_to_pipeline(job, items, pipe),
+                None => add_replied_write_to_pipeline(job, pipe),
+            });
         }
     }
+    for job in jobs {
+        add_lease_to_pipeline(job, &features, pipe);
+        add_created_to_pipeline(job, pipe);
+        add_last_updated_to_pipeline(job, pipe);
+        add_digest_to_pipeline(job, &features, pipe);
+        add_observation_to_pipeline(job, pipe);
+    }
+    commands
 }
 
 #[derive(Debug)]
 enum PipelineResult {
     Float(f64),
-    Hash(BTreeMap<String, String>),
+    Hash(BTreeMap<String, f64>),
+}
+
+fn decode_value(raw: &str) -> RedisResult<f64> {
+    serializer::decode(raw).ok_or_else(|| {
+        RedisError::from((
+            ErrorKind::TypeError,
+            "Stored value is not a valid metric value",
+            raw.to_string(),
+        ))
+    })
 }
 
 impl FromRedisValue for PipelineResult {
@@ -152,11 +1070,18 @@ impl FromRedisValue for PipelineResult {
         let result = match v {
             Value::Bulk(_) => {
                 let map: BTreeMap<String, String> = from_redis_value(v)?;
+                let map = map
+                    .into_iter()
+                    .map(|(field, raw)| Ok((labelsets::resolve(field), decode_value(&raw)?)))
+                    .collect::<RedisResult<_>>()?;
                 PipelineResult::Hash(map)
             }
             _ => {
-                let float: Option<f64> = from_redis_value(v)?;
-                PipelineResult::Float(float.unwrap_or(0f64))
+                let raw: Option<String> = from_redis_value(v)?;
+                match raw {
+                    Some(raw) => PipelineResult::Float(decode_value(&raw)?),
+                    None => PipelineResult::Float(0f64),
+                }
             }
         };
 
@@ -164,74 +1089,929 @@ impl FromRedisValue for PipelineResult {
     }
 }
 
-fn handle_generate_metrics_job(
-    pipeline: redis::Pipeline,
-    connection: &mut r2d2::PooledConnection<redis::Client>,
-    pool: &r2d2::Pool<redis::Client>,
-) -> Result<Vec<PipelineResult>, Box<dyn std::error::Error>> {
-    if !connection.is_open() {
-        *connection = pool.get()?
-    }
+/// Where the worker threads send their commands: real Redis servers, by route, or the in-memory
+/// fake shared by every route.
+#[derive(Clone)]
+enum Connector {
+    Redis(Vec<r2d2::Pool<connection::Manager>>),
+    Fake(Arc<Mutex<fake::FakeRedis>>),
+}
 
-    let values: Vec<PipelineResult> = pipeline.query(connection)?;
+impl Connector {
+    fn connect(&self) -> WorkerConnection {
+        match self {
+            // the first connection happens at startup so we let it panic, the routes connect on
+            // first use
+            Connector::Redis(pools) => WorkerConnection::Redis {
+                connections: pools
+                    .iter()
+                    .enumerate()
+                    .map(|(route, pool)| (route == DEFAULT_ROUTE).then(|| pool.get().unwrap()))
+                    .collect(),
+                pools: pools.clone(),
+            },
+            Connector::Fake(store) => {
+                WorkerConnection::Fake(fake::FakeConnection::new(store.clone()))
+            }
+        }
+    }
+}
 
-    Ok(values)
+enum WorkerConnection {
+    Redis {
+        pools: Vec<r2d2::Pool<connection::Manager>>,
+        connections: Vec<Option<r2d2::PooledConnection<connection::Manager>>>,
+    },
+    Fake(fake::FakeConnection),
 }
 
-fn handle_backend_action_job(
-    received: RedisJob,
-    connection: &mut r2d2::PooledConnection<redis::Client>,
-    pool: &r2d2::Pool<redis::Client>,
-    rx: &mpsc::Receiver<RedisJob>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if !connection.is_open() {
-        *connection = pool.get()?;
+impl WorkerConnection {
+    fn get(&mut self, route: usize) -> Result<&mut dyn ConnectionLike, Box<dyn std::error::Error>> {
+        match self {
+            WorkerConnection::Redis { pools, connections } => {
+                let connection = &mut connections[route];
+                if !connection
+                    .as_ref()
+                    .is_some_and(|connection| connection.is_open())
+                {
+                    workers::set(workers::State::Reconnecting);
+                    let reconnected = pools[route].get();
+                    workers::set(workers::State::Processing);
+                    *connection = Some(reconnected?);
+                }
+                Ok(&mut **connection.as_mut().unwrap())
+            }
+            WorkerConnection::Fake(connection) => Ok(connection),
+        }
     }
 
-    let mut pipe = redis::pipe();
+    /// Query a pipeline, reconnecting and querying it again once when the server rejected the
+    /// credentials of the connection, e.g. an expired token, or the write as a replica, e.g. the
+    /// former primary once the endpoint was moved to the new one.
+    fn query<T: FromRedisValue>(
+        &mut self,
+        endpoint: usize,
+        pipe: &redis::Pipeline,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        match pipe.query(self.get(endpoint)?) {
+            Err(e) if credentials::is_auth_error(&e) || connection::is_read_only_error(&e) => {
+                warn!("reconnecting to endpoint {endpoint}: {e}");
+                if let WorkerConnection::Redis { connections, .. } = self {
+                    connections[endpoint] = None;
+                }
+                Ok(pipe.query(self.get(endpoint)?)?)
+            }
+            result => Ok(result?),
+        }
+    }
 
-    add_job_to_pipeline(received, &mut pipe);
+    /// Execute a transaction, returning the reply of each of its commands, `None` when a
+    /// watched key changed. Fails when none of them was applied.
+    fn exec(
+        &mut self,
+        endpoint: usize,
+        pipe: &redis::Pipeline,
+    ) -> Result<RedisResult<connection::TransactionReplies>, Box<dyn std::error::Error>> {
+        self.get(endpoint)?;
+        Ok(match self {
+            WorkerConnection::Redis { connections, .. } => {
+                connections[endpoint].as_mut().unwrap().transaction(pipe)
+            }
+            WorkerConnection::Fake(connection) => connection.transaction(pipe),
+        })
+    }
 
-    for received in rx.try_iter() {
-        add_job_to_pipeline(received, &mut pipe);
+    /// Execute a transaction like `exec`, reconnecting and executing it again once like
+    /// `query`.
+    fn transaction(
+        &mut self,
+        endpoint: usize,
+        pipe: &redis::Pipeline,
+    ) -> Result<Vec<RedisResult<Value>>, Box<dyn std::error::Error>> {
+        let replies = match self.exec(endpoint, pipe)? {
+            Err(e) if credentials::is_auth_error(&e) || connection::is_read_only_error(&e) => {
+                warn!("reconnecting to endpoint {endpoint}: {e}");
+                if let WorkerConnection::Redis { connections, .. } = self {
+                    connections[endpoint] = None;
+                }
+                self.exec(endpoint, pipe)??
+            }
+            result => result?,
+        };
+        Ok(replies.ok_or("transaction aborted")?)
     }
+}
 
-    pipe.query::<()>(connection)?;
+/// Number of commands and of distinct keys of a pipeline, for reporting.
+fn pipeline_size(pipe: &redis::Pipeline) -> (usize, usize) {
+    let mut keys = BTreeSet::new();
+    let mut commands = 0;
+    for cmd in pipe.cmd_iter() {
+        commands += 1;
+        let mut args = cmd.args_iter();
+        let key = match args.next() {
+            // the script and the number of keys come first
+            Some(redis::Arg::Simple(b"EVAL")) => args.nth(2),
+            _ => args.next(),
+        };
+        if let Some(redis::Arg::Simple(key)) = key {
+            keys.insert(key);
+        }
+    }
+    (commands, keys.len())
+}
 
-    Ok(())
+/// Log an operation that took longer than the configured threshold.
+fn report_if_slow(operation: &str, elapsed: Duration, commands: usize, keys: usize) {
+    let Some(threshold) = current_config().slow_operation_threshold else {
+        return;
+    };
+    if elapsed >= threshold {
+        warn!(
+            "slow {operation}: {:.1} ms for {commands} commands on {keys} keys",
+            elapsed.as_secs_f64() * 1000.0
+        );
+    }
 }
 
-#[pymethods]
-impl RedisBackend {
-    #[new]
-    fn new(config: &PyDict, metric: &PyAny, histogram_bucket: Option<String>) -> PyResult<Self> {
-        // producer
-        let redis_job_tx_mutex = REDIS_JOB_TX.get().unwrap();
-        let redis_job_tx = redis_job_tx_mutex.lock().unwrap();
-        let cloned_tx = redis_job_tx.clone();
+fn handle_generate_metrics_job(
+    pipeline: redis::Pipeline,
+    route: usize,
+    connection: &mut WorkerConnection,
+) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    fault::before_command()?;
 
-        let py = metric.py();
-        let collector = metric.getattr(intern!(metric.py(), "_collector"))?;
+    let endpoint = failover::endpoint(&current_config(), route);
+    let values: Vec<Value> = connection.query(endpoint, &pipeline)?;
 
-        let mut key_name: String = metric
-            .getattr(intern!(py, "_collector"))?
-            .getattr(intern!(py, "name"))?
-            .extract()?;
+    let (commands, keys) = pipeline_size(&pipeline);
+    report_if_slow("scrape pipeline", started.elapsed(), commands, keys);
+    Ok(values)
+}
 
-        if let Some(bucket_id) = histogram_bucket.clone() {
-            key_name = format!("{key_name}:{bucket_id}");
-        }
+/// Execute a write transaction, returning the outcome of its jobs.
+fn execute_backend_action_pipeline(
+    pipe: redis::Pipeline,
+    job_commands: &[JobCommands],
+    endpoint: usize,
+    connection: &mut WorkerConnection,
+) -> Result<Written, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    fault::before_command()?;
 
-        // BTreeMap is used to order by key so that the labels_hash will
-        // always be sorted
-        let mut default_labels: Option<BTreeMap<&str, &str>> = None;
-        let mut metric_labels: Option<BTreeMap<&str, &str>> = None;
+    let (commands, keys) = pipeline_size(&pipe);
+    ratelimit::spend(current_config().max_commands_per_second, commands);
+    let replies = connection.transaction(endpoint, &pipe)?;
 
-        let py_metric_labels = metric.getattr(intern!(py, "_labels"))?;
-        if py_metric_labels.is_true()? {
-            let labels: BTreeMap<&str, &str> = py_metric_labels.extract()?;
-            metric_labels = Some(labels);
-        }
+    report_if_slow("write pipeline", started.elapsed(), commands, keys);
+    Ok(Written::new(job_commands, &replies))
+}
+
+/// Apply the jobs for serializers Redis can't increment: read the current values under WATCH,
+/// apply the jobs in order and write the results back in a transaction, retrying when another
+/// client modified the keys in between.
+fn execute_serialized_jobs(
+    jobs: &[RedisJob],
+    serializer: ValueSerializer,
+    route: usize,
+    endpoint: usize,
+    connection: &mut WorkerConnection,
+) -> Result<Written, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    fault::before_command()?;
+
+    // every distinct key/field in order of first appearance
+    let mut series: Vec<(&str, Option<&str>)> = vec![];
+    for job in jobs {
+        let entry = (job.key_name.as_str(), job.labels_hash.as_deref());
+        if !series.contains(&entry) {
+            series.push(entry);
+        }
+    }
+    let keys: BTreeSet<&str> = series.iter().map(|(key_name, _)| *key_name).collect();
+    let limit = current_config().max_commands_per_second;
+
+    for _ in 0..MAX_TRANSACTION_ATTEMPTS {
+        // the WATCH and the reads, the writes are counted once built
+        ratelimit::spend(limit, 1 + series.len());
+        redis::cmd("WATCH")
+            .arg(&keys)
+            .query::<()>(connection.get(endpoint)?)?;
+
+        let mut read = redis::pipe();
+        for (key_name, labels_hash) in &series {
+            match labels_hash {
+                Some(labels_hash) => read.hget(*key_name, *labels_hash),
+                None => read.get(*key_name),
+            };
+        }
+        let current: Vec<PipelineResult> = read.query(connection.get(endpoint)?)?;
+        let mut values: Vec<f64> = current
+            .iter()
+            .map(|value| match value {
+                PipelineResult::Float(float) => *float,
+                PipelineResult::Hash(_) => 0.0,
+            })
+            .collect();
+
+        for job in jobs {
+            let entry = (job.key_name.as_str(), job.labels_hash.as_deref());
+            let index = series.iter().position(|series| *series == entry).unwrap();
+            match job.action {
+                BackendAction::Inc | BackendAction::Dec => values[index] += job.value,
+                BackendAction::Set => values[index] = job.value,
+            }
+        }
+
+        let mut write = redis::pipe();
+        write.atomic();
+        // the writes of every key, the jobs of a key share them
+        let mut key_writes: HashMap<&str, Range<usize>> = HashMap::new();
+        // the fields of a hash are written with a single HSET
+        let mut hashes: BTreeMap<&str, Vec<(&str, String)>> = BTreeMap::new();
+        for ((key_name, labels_hash), value) in series.iter().zip(&values) {
+            let encoded = serializer.encode(*value);
+            match labels_hash {
+                Some(labels_hash) => hashes
+                    .entry(*key_name)
+                    .or_default()
+                    .push((*labels_hash, encoded)),
+                None => {
+                    let start = write.cmd_iter().count();
+                    write.set(*key_name, encoded).ignore();
+                    key_writes.insert(key_name, start..start + 1);
+                }
+            }
+        }
+        let features = features::current();
+        if route == DEFAULT_ROUTE {
+            add_key_names_to_pipeline(&features, &mut write);
+            add_label_sets_to_pipeline(&features, &mut write);
+        }
+        for (key_name, fields) in &hashes {
+            let start = write.cmd_iter().count();
+            batch::add_hash_fields_to_pipeline(key_name, fields, &features, &mut write);
+            key_writes.insert(key_name, start..write.cmd_iter().count());
+        }
+        for job in jobs {
+            add_expire_to_pipeline(&job.key_name, job.expire_at, &mut write);
+            add_lease_to_pipeline(job, &features, &mut write);
+            add_created_to_pipeline(job, &mut write);
+            add_last_updated_to_pipeline(job, &mut write);
+            add_digest_to_pipeline(job, &features, &mut write);
+            add_observation_to_pipeline(job, &mut write);
+            // the job left the series as it was, the estimate replaces it
+            if let Some(items) = &job.unique_items {
+                unique::add_to_pipeline(
+                    items,
+                    &job.key_name,
+                    &job.labels_hash,
+                    job.expire_at,
+                    &mut write,
+                );
+            }
+        }
+
+        ratelimit::spend(limit, write.cmd_iter().count());
+        // EXEC replies nil when a watched key changed
+        if let Some(replies) = connection.exec(endpoint, &write)?? {
+            report_if_slow(
+                "write transaction",
+                started.elapsed(),
+                jobs.len(),
+                keys.len(),
+            );
+            let job_commands: Vec<JobCommands> = jobs
+                .iter()
+                .map(|job| JobCommands {
+                    writes: key_writes[job.key_name.as_str()].clone(),
+                    reply: None,
+                })
+                .collect();
+            return Ok(Written::new(&job_commands, &replies));
+        }
+        // the expiries queued in the aborted transaction were never applied
+        refresh::forget();
+    }
+
+    Err("the watched keys kept changing, transaction aborted".into())
+}
+
+/// Connection and circuit of the writes made inline in synchronous mode, connected on first use.
+struct DirectWriter {
+    connector: Connector,
+    connection: Option<WorkerConnection>,
+    circuit: failover::Circuit<RedisJob>,
+}
+
+static DIRECT_WRITER: Mutex<Option<DirectWriter>> = Mutex::new(None);
+
+/// Write jobs inline without the GIL, in synchronous mode. Failures are handled like the worker
+/// does, dead lettering the failed jobs or counting them as dropped, and `false` is returned
+/// when the write failed or the workers couldn't be started.
+fn write_synchronously(jobs: Vec<RedisJob>) -> bool {
+    // restarted in a forked child, which can't share the connection of its parent
+    if let Err(e) = ensure_workers(None) {
+        error!("{e}");
+        drops::record(dead_letter_jobs(&jobs));
+        return false;
+    }
+    let config = current_config();
+    let result = Python::with_gil(|py| {
+        py.allow_threads(|| {
+            let over_limit = ratelimit::delay(config.max_commands_per_second);
+            if let (Some(delay), Backpressure::Coalesce) = (over_limit, config.backpressure) {
+                thread::sleep(delay);
+            }
+            let mut writer = DIRECT_WRITER.lock().unwrap();
+            let Some(writer) = writer.as_mut() else {
+                return Err("no connection for the synchronous writes".to_string());
+            };
+            let connection = writer
+                .connection
+                .get_or_insert_with(|| writer.connector.connect());
+            let circuit = &mut writer.circuit;
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                execute_jobs(&config, jobs, over_limit.is_some(), connection, circuit)
+            }))
+            .unwrap_or_else(|payload| {
+                panics::worker_panicked(config.panic_policy, payload);
+                Ok(())
+            })
+            .map_err(|e| e.to_string())
+        })
+    });
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            error!("{e}");
+            false
+        }
+    }
+}
+
+/// Queues of the write workers.
+#[derive(Debug, Clone)]
+struct JobQueues(Vec<mpsc::Sender<Vec<RedisJob>>>);
+
+impl JobQueues {
+    /// Worker writing the jobs of a metric, picked by the key prefix shared by all its keys so
+    /// that the writes of a key are executed in the order they were sent, whatever the batch.
+    fn worker(&self, resolved_prefix: &str) -> usize {
+        (keys::fnv1a(resolved_prefix.as_bytes()) % self.0.len() as u64) as usize
+    }
+}
+
+/// Send jobs of the metric with the `resolved_prefix` to its write worker, accounted for by
+/// `memory_usage` and `_flush`, or write them inline in synchronous mode. `false` when the worker
+/// is gone or the inline write failed.
+fn queue_jobs(redis_job_tx: &JobQueues, resolved_prefix: &str, jobs: Vec<RedisJob>) -> bool {
+    if current_config().synchronous {
+        return write_synchronously(jobs);
+    }
+    let job_count = jobs.len();
+    let track_pending_writes = current_config().track_pending_writes;
+    if track_pending_writes {
+        pending::queued(jobs.iter().map(pending_write));
+    }
+    memory::jobs_queued(job_count);
+    let worker = redis_job_tx.worker(resolved_prefix);
+    flush::batch_queued(worker);
+    let Err(mpsc::SendError(jobs)) = redis_job_tx.0[worker].send(jobs) else {
+        return true;
+    };
+    if track_pending_writes {
+        pending::taken(jobs.iter().map(pending_write));
+    }
+    memory::jobs_taken(job_count);
+    flush::batches_executed(worker, 1);
+    false
+}
+
+/// Series and write of a job, as tracked until the worker takes it.
+fn pending_write(job: &RedisJob) -> (&str, &Option<String>, pending::Write) {
+    let write = match job.action {
+        BackendAction::Inc | BackendAction::Dec => pending::Write::Add(job.value),
+        BackendAction::Set => pending::Write::Set(job.value),
+    };
+    (&job.key_name, &job.labels_hash, write)
+}
+
+/// Write the jobs of a route to one of the endpoints, returning their outcome once Redis applied
+/// them.
+fn write_jobs(
+    jobs: &[RedisJob],
+    route: usize,
+    endpoint: usize,
+    connection: &mut WorkerConnection,
+) -> Result<Written, Box<dyn std::error::Error>> {
+    // the refreshes tracked are the ones of the endpoint of the route
+    let rerouted = endpoint != route;
+    if rerouted {
+        refresh::forget();
+    }
+    let config = current_config();
+    // without EXPIRE NX a key recreated since its last refresh would be left without an expiry
+    let interval = match features::current().expire_options {
+        true => config.expire_refresh_interval(),
+        false => Duration::ZERO,
+    };
+    let result = refresh::throttled(interval, || match config.serializer {
+        ValueSerializer::Float => {
+            let mut pipe = redis::pipe();
+            // a histogram observation spans several keys, a scrape must see all of them or none
+            pipe.atomic();
+            let job_commands = add_jobs_to_pipeline(jobs, route, &mut pipe);
+            execute_backend_action_pipeline(pipe, &job_commands, endpoint, connection)
+        }
+        // replies are only offered with the float serializer
+        serializer => execute_serialized_jobs(jobs, serializer, route, endpoint, connection),
+    });
+    if rerouted || result.is_err() {
+        refresh::forget();
+    }
+    result
+}
+
+/// Write the jobs of the default route through its circuit: to the failover endpoint while the
+/// default one fails, journaling them to replay them into the default endpoint once it's back.
+fn write_jobs_with_failover(
+    jobs: &[RedisJob],
+    failover: usize,
+    circuit: &mut failover::Circuit<RedisJob>,
+    connection: &mut WorkerConnection,
+) -> Result<Written, Box<dyn std::error::Error>> {
+    let config = current_config();
+    if circuit.is_open() && circuit.probe_due(config.failover_probe_interval) {
+        // the journal goes first, in the same transaction as the jobs
+        let mut batch = circuit.take_journal();
+        let journaled = batch.len();
+        batch.extend(jobs.iter().cloned());
+        match write_jobs(&batch, DEFAULT_ROUTE, DEFAULT_ROUTE, connection) {
+            Ok(mut written) => {
+                info!("default endpoint recovered, {journaled} writes reconciled");
+                circuit.close();
+                return Ok(written.split_off(journaled));
+            }
+            Err(e) => {
+                warn!("default endpoint still failing: {e}");
+                batch.truncate(journaled);
+                circuit.journal(batch);
+            }
+        }
+    }
+
+    if !circuit.is_open() {
+        match write_jobs(jobs, DEFAULT_ROUTE, DEFAULT_ROUTE, connection) {
+            Ok(written) => {
+                circuit.write_succeeded();
+                return Ok(written);
+            }
+            Err(e) if !circuit.write_failed(config.failover_threshold) => return Err(e),
+            Err(e) => warn!("default endpoint failing, writing to the failover endpoint: {e}"),
+        }
+    }
+
+    let written = write_jobs(jobs, DEFAULT_ROUTE, failover, connection)?;
+    // the outcome was already reported to the callers, the replay doesn't report again, and the
+    // failed writes are dead lettered instead
+    let overflow = circuit.journal(
+        jobs.iter()
+            .zip(&written.outcomes)
+            .filter(|(_, outcome)| outcome.is_ok())
+            .map(|(job, _)| RedisJob {
+                ack_tx: None,
+                reply_tx: None,
+                ..job.clone()
+            }),
+    );
+    drops::record(overflow);
+    Ok(written)
+}
+
+fn handle_backend_action_job(
+    worker: usize,
+    received: Vec<RedisJob>,
+    connection: &mut WorkerConnection,
+    circuit: &mut failover::Circuit<RedisJob>,
+    rx: &mpsc::Receiver<Vec<RedisJob>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = current_config();
+    let over_limit = ratelimit::delay(config.max_commands_per_second);
+    if let (Some(delay), Backpressure::Coalesce) = (over_limit, config.backpressure) {
+        // the writes queued meanwhile are taken with this batch, folded into one write per series
+        thread::sleep(delay);
+    }
+
+    let mut jobs = vec![];
+    let mut batches = 0;
+    for batch in iter::once(received).chain(rx.try_iter()) {
+        batches += 1;
+        jobs.extend(batch);
+    }
+    memory::jobs_taken(jobs.len());
+    let _executed = flush::Executed { worker, batches };
+    if config.track_pending_writes {
+        pending::taken(jobs.iter().map(pending_write));
+    }
+    execute_jobs(&config, jobs, over_limit.is_some(), connection, circuit)
+}
+
+/// Write the jobs to their endpoints, reporting the outcome to the callers waiting for it, or
+/// shed them when over `max_commands_per_second` with the shed policy.
+fn execute_jobs(
+    config: &RedisConfig,
+    jobs: Vec<RedisJob>,
+    over_limit: bool,
+    connection: &mut WorkerConnection,
+    circuit: &mut failover::Circuit<RedisJob>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let job_count = jobs.len();
+    let mut jobs_by_route: BTreeMap<usize, Vec<RedisJob>> = BTreeMap::new();
+    for job in jobs {
+        jobs_by_route.entry(job.route).or_default().push(job);
+    }
+    if over_limit && config.backpressure == Backpressure::Shed {
+        shed_jobs(jobs_by_route.into_values().flatten(), job_count);
+        return Ok(());
+    }
+
+    // each endpoint is written to, and fails, on its own
+    let mut failures = vec![];
+    for (route, jobs) in jobs_by_route {
+        let result = match config.failover_route() {
+            Some(failover) if route == DEFAULT_ROUTE => {
+                write_jobs_with_failover(&jobs, failover, circuit, connection)
+            }
+            _ => write_jobs(&jobs, route, route, connection),
+        };
+
+        // Redis applies the commands of a transaction that didn't fail, only the jobs whose own
+        // write failed are dead lettered
+        let written = result.unwrap_or_else(|e| Written::failed(jobs.len(), &e.to_string()));
+        let failed: Vec<RedisJob> = jobs
+            .iter()
+            .zip(&written.outcomes)
+            .filter(|(_, outcome)| outcome.is_err())
+            .map(|(job, _)| job.clone())
+            .collect();
+        drops::record(dead_letter_jobs(&failed));
+
+        for (job, outcome) in jobs.into_iter().zip(&written.outcomes) {
+            if let Some(ack_tx) = job.ack_tx {
+                let _ = ack_tx.send(outcome.as_ref().map(|_| ()).map_err(Clone::clone));
+            }
+            if let Some(reply_tx) = job.reply_tx {
+                let reply = match outcome {
+                    Ok(Some(value)) => Ok(*value),
+                    Ok(None) => Err("missing reply".to_string()),
+                    Err(e) => Err(e.clone()),
+                };
+                let _ = reply_tx.send(reply);
+            }
+        }
+
+        if let Some(Err(e)) = written.outcomes.iter().find(|outcome| outcome.is_err()) {
+            failures.push(e.clone());
+        }
+        failures.extend(written.errors);
+    }
+
+    match failures.is_empty() {
+        true => Ok(()),
+        false => Err(failures.join(", ").into()),
+    }
+}
+
+/// Drop the jobs taken while over `max_commands_per_second`, failing the confirmed writes and
+/// the writes awaiting a reply.
+fn shed_jobs(jobs: impl Iterator<Item = RedisJob>, job_count: usize) {
+    let e = "shed, over max_commands_per_second".to_string();
+    let mut dropped = 0;
+    for job in jobs {
+        match (job.ack_tx, job.reply_tx) {
+            (None, None) => dropped += 1,
+            (ack_tx, reply_tx) => {
+                if let Some(ack_tx) = ack_tx {
+                    let _ = ack_tx.send(Err(e.clone()));
+                }
+                if let Some(reply_tx) = reply_tx {
+                    let _ = reply_tx.send(Err(e.clone()));
+                }
+            }
+        }
+    }
+    warn!("{job_count} writes {e}");
+    drops::record(dropped);
+}
+
+/// Save the failed jobs to the dead letter file when configured, returning how many were lost.
+fn dead_letter_jobs(jobs: &[RedisJob]) -> usize {
+    // confirmed writes and writes awaiting a reply already reported the failure to the caller
+    let dropped: Vec<&RedisJob> = jobs
+        .iter()
+        .filter(|job| job.ack_tx.is_none() && job.reply_tx.is_none())
+        .collect();
+    if dropped.is_empty() {
+        return 0;
+    }
+
+    let config = current_config();
+    let Some(path) = &config.dead_letter_path else {
+        return dropped.len();
+    };
+
+    match dead_letter::append(path, &dropped) {
+        Ok(()) => {
+            info!("{} jobs written to the dead letter file", dropped.len());
+            0
+        }
+        Err(e) => {
+            error!("failed to write the dead letter file: {e}");
+            dropped.len()
+        }
+    }
+}
+
+/// Start the consumer threads and point the producers of new backends to them. Calling it again
+/// replaces the workers, the previous ones exit once the backends still using them are dropped.
+/// Writes and reads use distinct connections so that neither waits behind the other on a socket.
+fn start_workers(writes: Connector, reads: Connector) {
+    let write_workers = current_config().worker_threads.max(1);
+    // producer / consumer, one queue per write worker
+    let (txs, rxs): (Vec<_>, Vec<_>) = (0..write_workers).map(|_| mpsc::channel()).unzip();
+    let redis_job_tx_mutex = REDIS_JOB_TX.get_or_init(|| Mutex::new(JobQueues(txs.clone())));
+    *redis_job_tx_mutex.lock().unwrap() = JobQueues(txs);
+
+    let (pipeline_tx, pipeline_rx) = crossbeam::channel::unbounded();
+    let redis_pipeline_job_tx_mutex =
+        REDIS_PIPELINE_JOB_TX.get_or_init(|| Mutex::new(pipeline_tx.clone()));
+    *redis_pipeline_job_tx_mutex.lock().unwrap() = pipeline_tx;
+    let (exposition_tx, exposition_rx) = crossbeam::channel::unbounded();
+    let redis_exposition_job_tx_mutex =
+        REDIS_EXPOSITION_JOB_TX.get_or_init(|| Mutex::new(exposition_tx.clone()));
+    *redis_exposition_job_tx_mutex.lock().unwrap() = exposition_tx;
+
+    for i in 0..PIPELINE_THREADS {
+        let cloned_pipeline_rx = pipeline_rx.clone();
+        let cloned_exposition_rx = exposition_rx.clone();
+        let connector = reads.clone();
+        info!("Starting pipeline thread....{i}");
+        // the write workers come first
+        spawn_worker(write_workers + i, move || {
+            workers::set(workers::State::Reconnecting);
+            let mut connection = connector.connect();
+            workers::set(workers::State::Idle);
+            while let Some(received) = lanes::next_job(&cloned_exposition_rx, &cloned_pipeline_rx) {
+                workers::set(workers::State::Processing);
+                // on panic the result sender is dropped and the scrape fails
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let values = handle_generate_metrics_job(
+                        received.pipeline,
+                        received.route,
+                        &mut connection,
+                    );
+                    let values = values.map_err(|e| PyException::new_err(e.to_string()));
+
+                    // nobody waits for a queued pipeline, its failure would go unnoticed
+                    if let Err(mpsc::SendError(RedisPipelineJobResult { values: Err(e) })) =
+                        received.result_tx.send(RedisPipelineJobResult { values })
+                    {
+                        error!("queued pipeline failed: {e}");
+                    }
+                }));
+                if let Err(payload) = result {
+                    panics::worker_panicked(current_config().panic_policy, payload);
+                }
+                workers::set(workers::State::Idle);
+            }
+        });
+    }
+
+    if current_config().synchronous {
+        *DIRECT_WRITER.lock().unwrap() = Some(DirectWriter {
+            connector: writes,
+            connection: None,
+            circuit: failover::Circuit::new(),
+        });
+        return;
+    }
+    *DIRECT_WRITER.lock().unwrap() = None;
+    for (i, rx) in rxs.into_iter().enumerate() {
+        let connector = writes.clone();
+        info!("Starting BackendAction thread....{i}");
+        spawn_worker(i, move || {
+            workers::set(workers::State::Reconnecting);
+            let mut connection = connector.connect();
+            workers::set(workers::State::Idle);
+            // each worker fails over on its own, journaling the writes of its keys
+            let mut circuit = failover::Circuit::new();
+            while let Ok(received) = rx.recv() {
+                workers::set(workers::State::Processing);
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    handle_backend_action_job(i, received, &mut connection, &mut circuit, &rx)
+                }));
+                match result {
+                    Ok(result) => result.unwrap_or_else(|e| error!("{}", e.to_string())),
+                    Err(payload) => panics::worker_panicked(current_config().panic_policy, payload),
+                }
+                workers::set(workers::State::Idle);
+            }
+        });
+    }
+}
+
+/// Spawn the worker thread numbered `index`, named after it so that it's recognizable in thread
+/// dumps, its state reported by `worker_states` until it exits.
+fn spawn_worker(index: usize, work: impl FnOnce() + Send + 'static) {
+    let name = format!("{}{index}", workers::THREAD_NAME_PREFIX);
+    thread::Builder::new()
+        .name(name.clone())
+        .spawn(move || {
+            let _registration = workers::register(&name);
+            work();
+        })
+        .unwrap();
+}
+
+/// Where the workers of the process write.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Store {
+    Redis,
+    Fake,
+}
+
+/// Lock `WORKERS`, waiting without the GIL: the thread starting the workers releases it while
+/// connecting, and may need it back to call the credential provider.
+fn lock_workers() -> MutexGuard<'static, Option<(u32, Store)>> {
+    loop {
+        match WORKERS.try_lock() {
+            Ok(workers) => return workers,
+            Err(TryLockError::WouldBlock) => {
+                Python::with_gil(|py| py.allow_threads(|| thread::sleep(Duration::from_millis(1))))
+            }
+            Err(TryLockError::Poisoned(e)) => panic!("{e}"),
+        }
+    }
+}
+
+/// Make the configuration current and start the workers writing to the store, recording them in
+/// the locked `WORKERS`.
+fn initialize_workers(
+    store: Store,
+    config: Arc<RedisConfig>,
+    workers: &mut Option<(u32, Store)>,
+) -> PyResult<()> {
+    panics::install_hook();
+    *REDIS_CONFIG.get_or_init(Default::default).lock().unwrap() = config.clone();
+    *COLLECTOR_NAMESPACES.lock().unwrap() = None;
+    if workers.is_some_and(|(pid, _)| pid != process::id()) {
+        workers::reset();
+        memory::reset_queued_jobs();
+        flush::reset();
+        pending::clear();
+    }
+
+    let (writes, reads) = match store {
+        Store::Redis => Python::with_gil(|py| {
+            py.allow_threads(|| {
+                let write_pools = create_redis_pools(&config, config.worker_threads)?;
+                let read_pools = create_redis_pools(&config, PIPELINE_THREADS)?;
+                // the routes are assumed to run the same server as the default endpoint
+                let mut connection = read_pools[DEFAULT_ROUTE]
+                    .get()
+                    .map_err(|e| PyException::new_err(e.to_string()))?;
+                features::init(&mut *connection);
+                drop(connection);
+                Ok::<_, PyErr>((Connector::Redis(write_pools), Connector::Redis(read_pools)))
+            })
+        })?,
+        Store::Fake => {
+            features::init(&mut fake::FakeConnection::new(fake_redis()));
+            (Connector::Fake(fake_redis()), Connector::Fake(fake_redis()))
+        }
+    };
+    start_workers(writes, reads);
+    *workers = Some((process::id(), store));
+    Ok(())
+}
+
+/// Start the workers of the process when they are missing: restarted with the current
+/// configuration in a forked child, or started from the configuration of a backend in a child
+/// started with spawn, where the backends arrive pickled.
+fn ensure_workers(backend: Option<(Store, &PyDict)>) -> PyResult<()> {
+    // held while starting so that concurrent backends start them once
+    let mut workers = lock_workers();
+    let (store, config) = match (*workers, backend) {
+        (Some((pid, _)), _) if pid == process::id() => return Ok(()),
+        (Some((_, store)), _) => {
+            info!("process forked, restarting the workers");
+            (store, current_config())
+        }
+        (None, Some((Store::Redis, config))) => {
+            (Store::Redis, Arc::new(RedisConfig::from_pydict(config)?))
+        }
+        (None, Some((Store::Fake, config))) => (
+            Store::Fake,
+            Arc::new(RedisConfig::from_pydict(fake_config(config)?)?),
+        ),
+        (None, None) => return Err(PyException::new_err("the backend is not initialized")),
+    };
+    initialize_workers(store, config, &mut workers)
+}
+
+/// Replies of a pipeline decoded as metric values.
+fn execute_pipeline_job(
+    py: Python,
+    route: usize,
+    pipeline: redis::Pipeline,
+) -> PyResult<Vec<PipelineResult>> {
+    execute_pipeline(py, route, pipeline)?
+        .iter()
+        .map(|value| from_redis_value(value).map_err(|e| PyException::new_err(e.to_string())))
+        .collect()
+}
+
+fn execute_pipeline(py: Python, route: usize, pipeline: redis::Pipeline) -> PyResult<Vec<Value>> {
+    execute_pipeline_in(py, Lane::Default, route, pipeline)
+}
+
+fn execute_pipeline_in(
+    py: Python,
+    lane: Lane,
+    route: usize,
+    pipeline: redis::Pipeline,
+) -> PyResult<Vec<Value>> {
+    ensure_workers(None)?;
+    let send_tx = {
+        let redis_pipeline_job_tx_job_tx_mutex = match lane {
+            Lane::Exposition => REDIS_EXPOSITION_JOB_TX.get().unwrap(),
+            Lane::Default => REDIS_PIPELINE_JOB_TX.get().unwrap(),
+        };
+        let redis_pipeline_job_tx = redis_pipeline_job_tx_job_tx_mutex.lock().unwrap();
+        redis_pipeline_job_tx.clone()
+    };
+
+    let (tx, rx) = mpsc::channel();
+
+    send_tx
+        .send(RedisPipelineJob {
+            result_tx: tx,
+            pipeline,
+            route,
+        })
+        .unwrap();
+
+    match py.allow_threads(move || rx.recv()) {
+        Ok(job_result) => job_result.values,
+        Err(_) => {
+            panics::raise_pending()?;
+            Err(PyException::new_err("pipeline job dropped by the worker"))
+        }
+    }
+}
+
+#[pymethods]
+impl RedisBackend {
+    #[new]
+    fn new(config: &PyDict, metric: &PyAny, histogram_bucket: Option<String>) -> PyResult<Self> {
+        ensure_workers(Some((Store::Redis, config)))?;
+        // producer
+        let redis_job_tx_mutex = REDIS_JOB_TX.get().unwrap();
+        let redis_job_tx = redis_job_tx_mutex.lock().unwrap();
+        let cloned_tx = redis_job_tx.clone();
+
+        let py = metric.py();
+        let collector = metric.getattr(intern!(metric.py(), "_collector"))?;
+
+        let collector_name: &str = collector.getattr(intern!(py, "name"))?.extract()?;
+        let backend_config = current_config();
+        let resolved_prefix = namespaced(
+            collector_namespace(&backend_config, collector)?.as_deref(),
+            collector_name,
+        );
+
+        // the bound must be formatted like when reading the buckets to find the same key
+        let histogram_bucket = histogram_bucket.map(|bucket| match bucket.parse() {
+            Ok(bound) => bucket_suffix(bound),
+            Err(_) => bucket,
+        });
+        let key_name = redis_key(match &histogram_bucket {
+            Some(bucket_id) => format!("{resolved_prefix}:{bucket_id}"),
+            None => resolved_prefix.clone(),
+        });
+
+        // BTreeMap is used to order by key so that the labels_hash will
+        // always be sorted
+        let mut default_labels: Option<BTreeMap<&str, &str>> = None;
+        let mut metric_labels: Option<BTreeMap<&str, &str>> = None;
+
+        let py_metric_labels = metric.getattr(intern!(py, "_labels"))?;
+        if py_metric_labels.is_true()? {
+            let labels: BTreeMap<&str, &str> = py_metric_labels.extract()?;
+            metric_labels = Some(labels);
+        }
 
         // default labels
         if collector
@@ -254,188 +2034,1544 @@ impl RedisBackend {
             } else {
                 metric_labels
             }
-        };
+        };
+
+        // the quantile of a summary sample is not part of the series identity
+        let collector_type: &str = collector.getattr(intern!(py, "type_"))?.extract()?;
+        let to_hash = to_hash.map(|mut labels| {
+            if collector_type == "summary" {
+                labels.remove(QUANTILE_LABEL);
+            }
+            labels
+        });
+
+        let base_labels: BTreeMap<String, String> = to_hash
+            .iter()
+            .flatten()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        let labels_hash = to_hash.map(|labels| {
+            labelsets::compact(
+                labels::labels_hash(labels, backend_config.key_layout),
+                backend_config.max_labels_length,
+            )
+        });
+
+        let expire_at = backend_config.expire_at.get(collector_name).copied();
+        let confirmed_writes = backend_config.confirmed_writes.contains(collector_name);
+        let route = backend_config.route(collector_name);
+        let last_updated_key = backend_config
+            .track_last_update
+            .contains(collector_name)
+            .then(|| last_updated_key(&resolved_prefix));
+        let created_key = backend_config
+            .track_created
+            .contains(collector_name)
+            .then(|| created_key(&resolved_prefix));
+        let observes_quantiles = collector_type == "summary"
+            && histogram_bucket.as_deref() == Some("sum")
+            && backend_config
+                .summary_quantiles
+                .contains_key(collector_name);
+        let storage = backend_config.storage(collector_name);
+        let histogram_bounds = match (collector_type, &histogram_bucket) {
+            ("histogram", None) => Some(histogram_bounds(collector)?),
+            // a summary without quantiles only has its count and sum
+            ("summary", None) => Some(vec![]),
+            _ => None,
+        };
+        let observes_values = match histogram_bucket.as_deref() {
+            Some("sum") => matches!(collector_type, "histogram" | "summary"),
+            _ => histogram_bounds.is_some(),
+        };
+        let raw_observations = backend_config
+            .raw_observations
+            .get(collector_name)
+            .copied()
+            .filter(|_| observes_values);
+        let required_labels = collector.getattr(intern!(py, "_required_labels"))?;
+        let required_labels: BTreeSet<String> = match required_labels.is_none() {
+            true => BTreeSet::new(),
+            false => required_labels
+                .iter()?
+                .map(|name| name.and_then(PyAny::extract))
+                .collect::<PyResult<_>>()?,
+        };
+        let top_k = match backend_config.top_k_labels.get(collector_name) {
+            Some(top_k) if !required_labels.contains(&top_k.label) => {
+                return Err(PyValueError::new_err(format!(
+                    "top_k_labels label {} is not a label of {collector_name}",
+                    top_k.label
+                )))
+            }
+            Some(top_k) => Some((
+                top_k.label.clone(),
+                topk::tracker(&resolved_prefix, top_k, route),
+            )),
+            None => None,
+        };
+
+        let new_backend = Self {
+            config: config.into(),
+            metric: MetricRef::new(metric)?,
+            histogram_bucket,
+            redis_job_tx: cloned_tx,
+            pid: process::id(),
+            resolved_prefix,
+            key_name,
+            labels_hash,
+            expire_at,
+            confirmed_writes,
+            last_updated_key,
+            created_key,
+            observes_quantiles,
+            raw_observations,
+            observed: AtomicU64::new(0),
+            storage,
+            histogram_bounds,
+            base_labels,
+            required_labels,
+            labels_cache: Mutex::new(labels::LabelsCache::new(LABELS_CACHE_CAPACITY)),
+            top_k,
+            route,
+        };
+
+        memory::backend_created(new_backend.heap_bytes());
+        new_backend._initialize_key();
+        Ok(new_backend)
+    }
+
+    #[classmethod]
+    fn _initialize(_cls: &PyType, config: &PyDict) -> PyResult<()> {
+        let config = Arc::new(RedisConfig::from_pydict(config)?);
+        initialize_workers(Store::Redis, config, &mut lock_workers())?;
+
+        info!("RedisBackend initialized");
+        Ok(())
+    }
+
+    /// Restart the worker threads and their connection in a freshly forked process. Forks are
+    /// detected on the next operation anyway, this is for servers managing the lifecycle of their
+    /// workers themselves, called from gunicorn's `post_fork(server, worker)` hook or from a
+    /// function decorated with uwsgi's `uwsgidecorators.postfork`.
+    #[classmethod]
+    fn handle_post_fork(_cls: &PyType) -> PyResult<()> {
+        let mut workers = lock_workers();
+        let Some((_, store)) = *workers else {
+            // nothing started before the fork, the first backend of the process will
+            return Ok(());
+        };
+        initialize_workers(store, current_config(), &mut workers)
+    }
+
+    /// Pickled as the arguments of the constructor, which starts the workers in a process that
+    /// doesn't have them, like a multiprocessing child started with spawn.
+    fn __reduce__(slf: &PyCell<Self>) -> PyResult<(PyObject, PyObject)> {
+        let py = slf.py();
+        let backend = slf.borrow();
+        let metric = backend.metric.get(py)?;
+        if metric.is_none(py) {
+            return Err(PyException::new_err(
+                "cannot pickle a backend whose metric was garbage collected",
+            ));
+        }
+        let args = (
+            backend.config.clone_ref(py),
+            metric,
+            backend.histogram_bucket.clone(),
+        );
+        Ok((slf.get_type().into_py(py), args.into_py(py)))
+    }
+
+    #[getter]
+    fn metric(&self, py: Python) -> PyResult<PyObject> {
+        self.metric.get(py)
+    }
+
+    /// Preflight check of a configuration without starting the workers: validates it, resolves
+    /// the host, connects, pings and writes a probe key. Returns `{"ok": bool, "checks": [...]}`
+    /// with the outcome of each step, stopping at the first failure.
+    #[classmethod]
+    fn _check(cls: &PyType, config: &PyDict) -> PyResult<PyObject> {
+        let py = cls.py();
+        let steps = match RedisConfig::from_pydict(config) {
+            Ok(config) => {
+                let mut steps = vec![check::Step::new("config", Ok("valid".to_string()))];
+                steps.extend(py.allow_threads(|| check::run(&config)));
+                steps
+            }
+            Err(e) => vec![check::Step::new("config", Err(e.to_string()))],
+        };
+        check::report(py, &steps)
+    }
+
+    #[classmethod]
+    fn _generate_samples(cls: &PyType, registry: &PyAny) -> PyResult<SampleSet> {
+        let py = cls.py();
+        let config = current_config();
+        drops::warn(py, config.drop_warning_interval)?;
+        panics::guard(py, config.panic_policy, || {
+            Self::generate_samples(py, registry)
+        })
+    }
+
+    /// Samples of a registry as `(collector, OutSample)` pairs, reading `chunk_size` collectors at
+    /// a time as the pairs are consumed: rendering or streaming starts with the first chunk, and
+    /// the memory held stays the same however large the registry.
+    #[classmethod]
+    #[pyo3(signature = (registry, chunk_size=64))]
+    fn _generate_samples_iter(
+        cls: &PyType,
+        registry: &PyAny,
+        chunk_size: usize,
+    ) -> PyResult<samples::SampleIter> {
+        let py = cls.py();
+        if chunk_size == 0 {
+            return Err(PyValueError::new_err("invalid chunk_size: 0"));
+        }
+        drops::warn(py, current_config().drop_warning_interval)?;
+        let collectors = registry.call_method0(intern!(py, "collect"))?.iter()?;
+        let registry: PyObject = registry.into();
+        let generate: samples::GenerateChunk = Box::new(move |py, chunk| {
+            panics::guard(py, current_config().panic_policy, || {
+                Self::collectors_samples(py, registry.as_ref(py), chunk)
+            })
+        });
+        Ok(samples::SampleIter::new(collectors, chunk_size, generate))
+    }
+
+    /// Exposition of a registry in the Prometheus text format (`prometheus`, alias `text`) or in
+    /// the OpenMetrics one (`openmetrics`). With `render_cache_ttl` configured the text is shared
+    /// through Redis for that long: the process holding a lease on it renders it while the others
+    /// wait for its result, rendering it themselves if it doesn't show up before the lease
+    /// expires, see `render_lease_timeout`. Registries are told apart by their namespace, see
+    /// `registry_namespaces`.
+    #[classmethod]
+    #[pyo3(signature = (registry, format="prometheus"))]
+    fn render_metrics(cls: &PyType, registry: &PyAny, format: &str) -> PyResult<String> {
+        let py = cls.py();
+        let format = samples::Format::parse(format)
+            .ok_or_else(|| PyValueError::new_err(format!("unknown format: {format}")))?;
+        let config = current_config();
+        let Some(ttl) = config.render_cache_ttl else {
+            return Ok(Self::_generate_samples(cls, registry)?.render(format));
+        };
+
+        let namespace = registry_namespace(&config, registry).unwrap_or("default");
+        // rendered from the metrics of the key prefix, the other services render their own
+        let cache_key = format!(
+            "{}{}:{namespace}:{}",
+            config.key_prefix,
+            keys::RENDER_CACHE_KEY,
+            format.as_str()
+        );
+        let lock_key = format!("{cache_key}:lock");
+        let lease_timeout = config.render_lease_timeout.unwrap_or(ttl);
+        // tells this lease apart from the ones taken by the other processes after it expired
+        let lease = format!(
+            "{}:{}",
+            process::id(),
+            clock::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        );
+        let deadline = Instant::now() + lease_timeout;
+        loop {
+            let mut pipe = redis::pipe();
+            pipe.get(&cache_key);
+            let cached = execute_pipeline_in(py, Lane::Exposition, DEFAULT_ROUTE, pipe)?.pop();
+            if let Some(Value::Data(rendered)) = cached {
+                return Ok(String::from_utf8_lossy(&rendered).into_owned());
+            }
+
+            // the lease expires, so a process dying while rendering only delays the others
+            // until they take it over
+            let mut pipe = redis::pipe();
+            pipe.cmd("SET")
+                .arg(&lock_key)
+                .arg(&lease)
+                .arg("NX")
+                .arg("PX")
+                .arg(lease_timeout.as_millis() as u64);
+            let locked = execute_pipeline(py, DEFAULT_ROUTE, pipe)?.pop() == Some(Value::Okay);
+            if locked || Instant::now() >= deadline {
+                break;
+            }
+            py.allow_threads(|| thread::sleep(RENDER_CACHE_POLL_INTERVAL));
+        }
+
+        let rendered = Self::_generate_samples(cls, registry).map(|samples| samples.render(format));
+        let mut pipe = redis::pipe();
+        if let Ok(rendered) = &rendered {
+            pipe.cmd("SET")
+                .arg(&cache_key)
+                .arg(rendered)
+                .arg("PX")
+                .arg(ttl.as_millis() as u64)
+                .ignore();
+        }
+        // released on failures too, so that the others don't wait for the lease to expire
+        pipe.cmd("EVAL")
+            .arg(RELEASE_LEASE_SCRIPT)
+            .arg(1)
+            .arg(&lock_key)
+            .arg(&lease)
+            .ignore();
+        execute_pipeline(py, DEFAULT_ROUTE, pipe)?;
+        rendered
+    }
+
+    /// Forget the labeled series of the process not written for `idle_series_timeout`, writing
+    /// their last values to the `archive_sink` when configured, deleting their fields from Redis
+    /// with `delete_idle_series`, and drop their children from the collectors of `registry` so
+    /// that they are garbage collected. Returns how many series were evicted, call it
+    /// periodically to bound the memory and the cardinality of metrics labeled by path or user.
+    #[classmethod]
+    #[pyo3(signature = (registry=None))]
+    fn evict_idle_series(cls: &PyType, registry: Option<&PyAny>) -> PyResult<usize> {
+        let py = cls.py();
+        let config = current_config();
+        let Some(timeout) = config.idle_series_timeout else {
+            return Ok(0);
+        };
+        let since = clock::now().checked_sub(timeout).unwrap_or(UNIX_EPOCH);
+        let idle = idle::take_idle(since);
+        if idle.is_empty() {
+            return Ok(0);
+        }
+
+        // archived before anything is deleted, an archive failing leaves them in Redis
+        if let Some(sink) = &config.archive_sink {
+            let records = archive_records(py, &config, &idle)?;
+            sink.write(py, &records, clock::unix_timestamp())?;
+        }
+
+        if config.delete_idle_series {
+            let mut pipes: BTreeMap<usize, redis::Pipeline> = BTreeMap::new();
+            for (series, written) in &idle {
+                let pipe = pipes.entry(written.route).or_default();
+                for key_name in &written.key_names {
+                    pipe.hdel(key_name, &series.labels_hash).ignore();
+                }
+            }
+            for (route, pipe) in pipes {
+                execute_pipeline(py, route, pipe)?;
+            }
+        }
+
+        if let Some(registry) = registry {
+            let idle: HashSet<&idle::Series> = idle.iter().map(|(series, _)| series).collect();
+            for collector in registry.call_method0(intern!(py, "collect"))?.iter()? {
+                let collector = collector?;
+                let children = match collector.getattr(intern!(py, "_labeled_metrics")) {
+                    Ok(children) => children,
+                    // not a pytheus collector
+                    Err(_) => continue,
+                };
+                let Ok(children) = children.downcast::<PyDict>() else {
+                    continue;
+                };
+                for (labels, child) in children.copy()?.iter() {
+                    let backend = child.getattr(intern!(py, "_metric_value_backend"))?;
+                    let Ok(backend) = backend.extract::<PyRef<RedisBackend>>() else {
+                        continue;
+                    };
+                    let Some(labels_hash) = &backend.labels_hash else {
+                        continue;
+                    };
+                    let series = idle::Series {
+                        resolved_prefix: backend.resolved_prefix.clone(),
+                        labels_hash: labels_hash.clone(),
+                    };
+                    if idle.contains(&series) {
+                        children.del_item(labels)?;
+                        children::forget(collector, child)?;
+                    }
+                }
+            }
+        }
+        Ok(idle.len())
+    }
+
+    /// Push the samples of a registry to a sink every `interval` seconds from a Rust thread, for
+    /// batch jobs and short-lived workers that aren't scraped: `{"pushgateway": url, "job": name,
+    /// "grouping": {label: value}}`, `{"remote_write": url}`, `{"file": path, "format": name}` or
+    /// `{"graphite": "host:port", "template": "{name}.{labels}"}`.
+    /// Replaces the exporter already running, see `stop_exporter`.
+    #[classmethod]
+    fn start_exporter(
+        _cls: &PyType,
+        registry: &PyAny,
+        sink: &PyDict,
+        interval: f64,
+    ) -> PyResult<()> {
+        let py = registry.py();
+        let sink = export::Sink::from_pydict(sink)?;
+        let interval = match Duration::try_from_secs_f64(interval) {
+            Ok(interval) if !interval.is_zero() => interval,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "invalid interval: {interval}"
+                )))
+            }
+        };
+        let registry: PyObject = registry.into();
+        let collect: export::Collect =
+            Box::new(move |py| Self::generate_samples(py, registry.as_ref(py)));
+        export::start(py, collect, sink, interval);
+        Ok(())
+    }
+
+    /// Stop the exporter once it exported the writes queued so far, `False` when none was running.
+    #[classmethod]
+    fn stop_exporter(cls: &PyType) -> bool {
+        export::stop(cls.py())
+    }
+
+    /// State of the worker threads of the process, as `{"name", "state", "since"}` dicts: the
+    /// thread name, `idle`, `processing`, `reconnecting` or `dead` and the unix timestamp it's
+    /// been in that state since. Workers replaced by a new configuration are reported dead.
+    #[classmethod]
+    fn worker_states(cls: &PyType) -> PyResult<Vec<PyObject>> {
+        let py = cls.py();
+        workers::states()
+            .into_iter()
+            .map(|(name, state, since)| {
+                let dict = PyDict::new(py);
+                dict.set_item(intern!(py, "name"), name)?;
+                dict.set_item(intern!(py, "state"), state.as_str())?;
+                dict.set_item(intern!(py, "since"), since)?;
+                Ok(dict.into())
+            })
+            .collect()
+    }
+
+    /// Number of metric updates lost since startup, rejected by the queue or failed without being
+    /// dead lettered.
+    #[classmethod]
+    fn dropped_jobs(_cls: &PyType) -> u64 {
+        drops::dropped_jobs()
+    }
+
+    /// Block until every job sent to the worker before the call was executed against Redis, e.g.
+    /// before a batch job exits or pushes to a gateway. Returns `False` when `timeout` seconds
+    /// elapsed first, waits as long as needed without one.
+    #[classmethod]
+    #[pyo3(signature = (timeout=None))]
+    fn _flush(cls: &PyType, timeout: Option<f64>) -> PyResult<bool> {
+        let timeout = match timeout {
+            Some(seconds) => Some(
+                Duration::try_from_secs_f64(seconds)
+                    .map_err(|_| PyValueError::new_err(format!("invalid timeout: {seconds}")))?,
+            ),
+            None => None,
+        };
+        // the jobs of the workers of the parent are forgotten once they are restarted
+        if lock_workers().is_some() {
+            ensure_workers(None)?;
+        }
+        let flushed = cls.py().allow_threads(|| flush::wait(timeout));
+        panics::raise_pending()?;
+        Ok(flushed)
+    }
+
+    /// Series of every metric of a registry as known to this process, by metric name, without
+    /// reading Redis: their key, labels and the writes queued for the worker when
+    /// `track_pending_writes` is configured. Meant for debugging, e.g. logged on a crash.
+    #[classmethod]
+    fn snapshot(_cls: &PyType, registry: &PyAny) -> PyResult<PyObject> {
+        snapshot::registry_snapshot(registry)
+    }
+
+    /// Key, labels and pending writes of the series of the backend, see `snapshot`. The value
+    /// lives in Redis, it's `None`.
+    fn _snapshot(&self, py: Python) -> PyResult<PyObject> {
+        let pending = PyDict::new(py);
+        for key_name in self.key_names() {
+            if let Some(writes) = pending::get(&key_name, &self.labels_hash) {
+                let writes_dict = PyDict::new(py);
+                writes_dict.set_item("jobs", writes.jobs)?;
+                writes_dict.set_item("delta", writes.delta)?;
+                writes_dict.set_item("set", writes.set)?;
+                pending.set_item(key_name, writes_dict)?;
+            }
+        }
+        let snapshot = PyDict::new(py);
+        snapshot.set_item("key", &self.key_name)?;
+        let labels = (!self.base_labels.is_empty()).then_some(&self.base_labels);
+        snapshot.set_item("labels", labels)?;
+        snapshot.set_item("value", py.None())?;
+        snapshot.set_item("pending", pending)?;
+        Ok(snapshot.into())
+    }
+
+    /// Memory footprint of the backend in this process: live backends, jobs queued for the
+    /// worker, labels and key hashes cached, and an estimate of the bytes they hold.
+    #[classmethod]
+    fn memory_usage(cls: &PyType) -> PyResult<PyObject> {
+        let py = cls.py();
+        let (labels_hashes, labels_bytes) = labels::cached();
+        let (key_hashes, key_bytes) = keys::recorded();
+        let (_, idle_bytes) = idle::tracked();
+        let usage = memory::MemoryUsage::current(
+            labels_hashes + key_hashes,
+            labels_bytes + key_bytes + idle_bytes,
+            mem::size_of::<RedisJob>(),
+        );
+
+        let dict = PyDict::new(py);
+        dict.set_item(intern!(py, "backends"), usage.backends)?;
+        dict.set_item(intern!(py, "queued_jobs"), usage.queued_jobs)?;
+        dict.set_item(intern!(py, "cached_hashes"), usage.cached_hashes)?;
+        dict.set_item(intern!(py, "estimated_bytes"), usage.estimated_bytes)?;
+        Ok(dict.into())
+    }
+
+    /// Re-apply the jobs stored in the dead letter file, returning how many were replayed.
+    /// If the replay fails the jobs that weren't applied are written back to the file.
+    #[classmethod]
+    fn replay_dead_letters(cls: &PyType) -> PyResult<usize> {
+        let py = cls.py();
+        let config = current_config();
+        let Some(path) = &config.dead_letter_path else {
+            return Ok(0);
+        };
+
+        let jobs = dead_letter::take(path).map_err(|e| PyException::new_err(e.to_string()))?;
+        if jobs.is_empty() {
+            return Ok(0);
+        }
+
+        // replayed through the write worker so that they are stored with the configured serializer
+        ensure_workers(None)?;
+        let redis_job_tx = REDIS_JOB_TX.get().unwrap().lock().unwrap().clone();
+        // one acknowledgement per job, to write back only the ones that weren't applied
+        let (ack_txs, ack_rxs): (Vec<_>, Vec<_>) = jobs.iter().map(|_| mpsc::channel()).unzip();
+        let replayed = jobs
+            .iter()
+            .zip(ack_txs)
+            .map(|(job, ack_tx)| RedisJob {
+                // the route may have been removed from the configuration since
+                route: match job.route < config.endpoints().len()
+                    && Some(job.route) != config.failover_route()
+                {
+                    true => job.route,
+                    false => DEFAULT_ROUTE,
+                },
+                ack_tx: Some(ack_tx),
+                ..job.clone()
+            })
+            .collect();
+        // the jobs of several metrics are replayed in one transaction, not ordered with the
+        // writes of the backends
+        queue_jobs(&redis_job_tx, "", replayed);
+
+        let results: Vec<Result<(), String>> = py.allow_threads(move || {
+            ack_rxs
+                .iter()
+                .map(|ack_rx| {
+                    ack_rx
+                        .recv()
+                        .unwrap_or_else(|_| Err("job dropped by the worker".to_string()))
+                })
+                .collect()
+        });
+        let failed: Vec<RedisJob> = jobs
+            .iter()
+            .zip(&results)
+            .filter(|(_, result)| result.is_err())
+            .map(|(job, _)| job.clone())
+            .collect();
+        if let Some(Err(e)) = results.iter().find(|result| result.is_err()) {
+            dead_letter_jobs(&failed);
+            return Err(PyException::new_err(format!(
+                "dead letter replay failed: {e}"
+            )));
+        }
+
+        info!("{} dead letter jobs replayed", jobs.len());
+        Ok(jobs.len())
+    }
+
+    fn _initialize_key(&self) {
+        // without all its labels the backend only writes series labeled at call time
+        if self.labels_hash.is_none() && !self.required_labels.is_empty() {
+            return;
+        }
+        // the series of a label value that's not a heavy hitter is never written
+        if self.top_k.is_some() {
+            return;
+        }
+        let jobs: Vec<RedisJob> = self
+            .key_names()
+            .into_iter()
+            .map(|key_name| RedisJob {
+                // creating the series is not an update
+                last_updated_key: None,
+                ..self.job(key_name, self.labels_hash.clone(), BackendAction::Inc, 0.0)
+            })
+            .collect();
+        self.track_writes(&jobs);
+        if !queue_jobs(&self.redis_job_tx, &self.resolved_prefix, jobs) {
+            error!("`_initialize_key` operation failed")
+        }
+    }
+
+    /// The `labels` of a call complete the ones of the backend to pick the series to write,
+    /// skipping the creation of a labeled child for metrics with many series. `expire` sets the
+    /// expiry of the series in seconds from now for this write, e.g. for leases: on the field of
+    /// a labeled series, which needs a server with field expiry (Redis 7.4), or on the key of an
+    /// unlabeled one, whose expiry scrapes reset to the one of the metric. With `return_value` the call waits for the write
+    /// and returns the value of the series right after it, with the float serializer only and
+    /// not for metrics stored as time series or documents.
+    #[pyo3(signature = (value, labels=None, expire=None, return_value=false))]
+    fn inc(
+        &self,
+        py: Python,
+        value: f64,
+        labels: Option<BTreeMap<String, String>>,
+        expire: Option<f64>,
+        return_value: bool,
+    ) -> PyResult<Option<f64>> {
+        if !return_value {
+            self.send_job(py, BackendAction::Inc, value, labels, expire, "inc")?;
+            return Ok(None);
+        }
+        if current_config().serializer != ValueSerializer::Float {
+            return Err(PyValueError::new_err(
+                "return_value is only supported with the float serializer",
+            ));
+        }
+        if self.storage != Storage::Keys {
+            return Err(PyValueError::new_err(format!(
+                "return_value is not supported for metrics stored as {}",
+                self.storage.name()
+            )));
+        }
+
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let job = RedisJob {
+            reply_tx: Some(reply_tx),
+            ..self.series_job(BackendAction::Inc, value, labels, expire)?
+        };
+        self.send_jobs(py, vec![job], "inc")?;
+        match py.allow_threads(move || reply_rx.recv()) {
+            Ok(Ok(value)) => Ok(Some(value)),
+            Ok(Err(e)) => Err(PyException::new_err(format!("`inc` operation failed: {e}"))),
+            Err(_) => Err(PyException::new_err(
+                "`inc` operation failed: job dropped by the worker",
+            )),
+        }
+    }
+
+    #[pyo3(signature = (value, labels=None, expire=None))]
+    fn dec(
+        &self,
+        py: Python,
+        value: f64,
+        labels: Option<BTreeMap<String, String>>,
+        expire: Option<f64>,
+    ) -> PyResult<()> {
+        self.send_job(py, BackendAction::Dec, -value, labels, expire, "dec")
+    }
+
+    #[pyo3(signature = (value, labels=None, expire=None))]
+    fn set(
+        &self,
+        py: Python,
+        value: f64,
+        labels: Option<BTreeMap<String, String>>,
+        expire: Option<f64>,
+    ) -> PyResult<()> {
+        self.send_job(py, BackendAction::Set, value, labels, expire, "set")
+    }
+
+    /// Increment many series of the metric at once, each update being the labels completing the
+    /// ones of the backend and a delta. The labels are hashed in Rust and the updates sent as a
+    /// single batch, written in one pipeline.
+    fn update_batch(
+        &self,
+        py: Python,
+        updates: Vec<(BTreeMap<String, String>, f64)>,
+    ) -> PyResult<()> {
+        if self.histogram_bounds.is_some() {
+            return Err(PyException::new_err(
+                "`update_batch` is not supported by histogram and summary backends, use `observe`",
+            ));
+        }
+        let jobs = updates
+            .into_iter()
+            .map(|(labels, delta)| self.series_job(BackendAction::Inc, delta, Some(labels), None))
+            .collect::<PyResult<Vec<_>>>()?;
+        if jobs.is_empty() {
+            return Ok(());
+        }
+        self.send_jobs(py, jobs, "update_batch")
+    }
+
+    /// Record an observation on a backend created for a whole histogram or summary: every bucket
+    /// the value falls in, `+Inf` included, is incremented together with `count` and `sum` in
+    /// the same transaction.
+    #[pyo3(signature = (value, labels=None))]
+    fn observe(
+        &self,
+        py: Python,
+        value: f64,
+        labels: Option<BTreeMap<String, String>>,
+    ) -> PyResult<()> {
+        let Some(bounds) = &self.histogram_bounds else {
+            return Err(PyException::new_err(
+                "`observe` is only supported by histogram and summary backends",
+            ));
+        };
+        if value.is_nan() {
+            return Err(PyValueError::new_err("cannot observe NaN"));
+        }
+
+        let labels_hash = self.series_hash(self.top_k_labels(labels))?;
+        let job =
+            |key_name, value| self.job(key_name, labels_hash.clone(), BackendAction::Inc, value);
+        let mut jobs: Vec<RedisJob> = bounds
+            .iter()
+            .filter(|bound| value <= **bound)
+            .map(|bound| job(self.bucket_key(*bound), 1.0))
+            .collect();
+        jobs.push(job(self.bucket_key_for("count"), 1.0));
+        jobs.push(RedisJob {
+            observations: self.observations_target(&labels_hash),
+            ..job(self.bucket_key_for("sum"), value)
+        });
+        self.send_jobs(py, jobs, "observe")
+    }
+
+    /// Record observations already sorted into buckets, e.g. the delta of a histogram exported
+    /// by another library: `bucket_counts` are the observations per bucket (not cumulative) in
+    /// the order of the upper bounds of the histogram, `+Inf` included, and `sum` their sum.
+    #[pyo3(signature = (bucket_counts, sum, labels=None))]
+    fn observe_buckets(
+        &self,
+        py: Python,
+        bucket_counts: Vec<u64>,
+        sum: f64,
+        labels: Option<BTreeMap<String, String>>,
+    ) -> PyResult<()> {
+        let Some(bounds) = self
+            .histogram_bounds
+            .as_ref()
+            .filter(|bounds| !bounds.is_empty())
+        else {
+            return Err(PyException::new_err(
+                "`observe_buckets` is only supported by histogram backends",
+            ));
+        };
+        if bucket_counts.len() != bounds.len() {
+            return Err(PyValueError::new_err(format!(
+                "expected {} bucket counts, got {}",
+                bounds.len(),
+                bucket_counts.len()
+            )));
+        }
+
+        let labels_hash = self.series_hash(self.top_k_labels(labels))?;
+        let job =
+            |key_name, value| self.job(key_name, labels_hash.clone(), BackendAction::Inc, value);
+        let mut count = 0;
+        let mut jobs = vec![];
+        for (bound, bucket_count) in bounds.iter().zip(bucket_counts) {
+            count += bucket_count;
+            if count > 0 {
+                jobs.push(job(self.bucket_key(*bound), count as f64));
+            }
+        }
+        if count == 0 {
+            return Ok(());
+        }
+        jobs.push(job(self.bucket_key_for("count"), count as f64));
+        jobs.push(job(self.bucket_key_for("sum"), sum));
+        self.send_jobs(py, jobs, "observe_buckets")
+    }
+
+    fn get(&self) -> f64 {
+        // This returns the float 0.0 because it's only called when an existing collector is not
+        // able to find the data in the cache, meaning that it was not initialized yet.
+        0.0
+    }
+
+    /// Unix timestamp of the last update of the series, `None` if it was never updated or the
+    /// metric is not listed in `track_last_update`.
+    fn last_updated(&self, py: Python) -> PyResult<Option<f64>> {
+        self.series_timestamp(py, &self.last_updated_key)
+    }
+
+    /// Seconds elapsed since the last update of the series, `None` when unknown.
+    fn staleness(&self, py: Python) -> PyResult<Option<f64>> {
+        Ok(self
+            .last_updated(py)?
+            .map(|timestamp| (clock::unix_timestamp() - timestamp).max(0.0)))
+    }
+
+    /// Unix timestamp of the first write of the series, `None` if unknown or the metric is not
+    /// listed in `track_created`.
+    fn created(&self, py: Python) -> PyResult<Option<f64>> {
+        self.series_timestamp(py, &self.created_key)
+    }
+
+    /// Add items to the HyperLogLog of the series, e.g. user ids, the series being a gauge of the
+    /// approximate number of distinct items added without storing them. The update is written
+    /// like the other ones, with the float serializer only and for metrics stored as keys.
+    #[pyo3(signature = (items, labels=None))]
+    fn add_unique(
+        &self,
+        py: Python,
+        items: Vec<String>,
+        labels: Option<BTreeMap<String, String>>,
+    ) -> PyResult<()> {
+        if current_config().serializer != ValueSerializer::Float {
+            return Err(PyException::new_err(
+                "`add_unique` is only supported with the float serializer",
+            ));
+        }
+        if self.storage != Storage::Keys {
+            return Err(PyValueError::new_err(format!(
+                "`add_unique` is not supported for metrics stored as {}",
+                self.storage.name()
+            )));
+        }
+        if !features::current().scripting {
+            return Err(PyException::new_err(
+                "`add_unique` needs a server with scripting",
+            ));
+        }
+        if items.is_empty() {
+            return Ok(());
+        }
+        let labels_hash = self.series_hash(labels)?;
+        let job = RedisJob {
+            unique_items: Some(unique::Items {
+                hll_key: unique::hll_key(&self.resolved_prefix, &labels_hash),
+                items,
+            }),
+            // the series is only written by the items
+            ..self.job(self.key_name.clone(), labels_hash, BackendAction::Inc, 0.0)
+        };
+        self.send_jobs(py, vec![job], "add_unique")
+    }
+
+    /// Raw observations recorded for the series, oldest first, for the histograms and summaries
+    /// listed in `raw_observations`. Called on the sum of a histogram or summary, or on a backend
+    /// covering a whole histogram.
+    #[pyo3(signature = (labels=None))]
+    fn raw_observations(
+        &self,
+        py: Python,
+        labels: Option<BTreeMap<String, String>>,
+    ) -> PyResult<Vec<f64>> {
+        if self.raw_observations.is_none() {
+            return Err(PyException::new_err(
+                "raw observations are not recorded for this backend",
+            ));
+        }
+        let labels_hash = self.series_hash(labels)?;
+        let mut pipe = redis::pipe();
+        pipe.lrange(
+            observations::key(&self.resolved_prefix, &labels_hash),
+            0,
+            -1,
+        );
+        match execute_pipeline(py, self.route, pipe)?.pop() {
+            Some(reply) => {
+                from_redis_value(&reply).map_err(|e| PyException::new_err(e.to_string()))
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Current values of many backends read in one round trip, in order. Metrics can be passed in
+    /// place of their backend, series that were never written read as 0.
+    #[classmethod]
+    fn get_many(cls: &PyType, backends: &PyAny) -> PyResult<Vec<f64>> {
+        let py = cls.py();
+        // position of a read in the result and where the series is stored, every series of the
+        // key being read when it's stored by a module
+        type Read = (usize, Storage, Option<String>);
+        // one pipeline by endpoint
+        let mut pipes: BTreeMap<usize, (redis::Pipeline, Vec<Read>)> = BTreeMap::new();
+        let features = features::current();
+        let mut count = 0;
+        for item in backends.iter()? {
+            let item = item?;
+            let backend: PyRef<RedisBackend> = match item.extract() {
+                Ok(backend) => backend,
+                Err(_) => item
+                    .getattr(intern!(py, "_metric_value_backend"))?
+                    .extract()?,
+            };
+            let (pipe, positions) = pipes.entry(backend.route).or_default();
+            let storage = backend.storage.on(&features);
+            match (storage, &backend.labels_hash) {
+                (Storage::Keys, Some(labels_hash)) => {
+                    pipe.hget(&backend.key_name, labels_hash);
+                }
+                (Storage::Keys, None) => {
+                    pipe.get(&backend.key_name);
+                }
+                (storage, labels_hash) => add_stored_read_to_pipeline(
+                    storage,
+                    &backend.key_name,
+                    labels_hash.is_some(),
+                    backend.expire_at,
+                    &features,
+                    pipe,
+                ),
+            };
+            positions.push((count, storage, backend.labels_hash.clone()));
+            count += 1;
+        }
+
+        let mut values = vec![0.0; count];
+        for (route, (pipe, positions)) in pipes {
+            for (value, (position, storage, labels_hash)) in execute_pipeline(py, route, pipe)?
+                .into_iter()
+                .zip(positions)
+            {
+                let value = stored_reply(storage, value, labels_hash.is_some());
+                let value: PipelineResult =
+                    from_redis_value(&value).map_err(|e| PyException::new_err(e.to_string()))?;
+                values[position] = match (value, labels_hash) {
+                    (PipelineResult::Float(value), _) => value,
+                    (PipelineResult::Hash(hash), Some(labels_hash)) if storage != Storage::Keys => {
+                        hash.get(&labels_hash).copied().unwrap_or_default()
+                    }
+                    (PipelineResult::Hash(_), _) => {
+                        return Err(PyException::new_err("unexpected hash value"))
+                    }
+                };
+            }
+        }
+        Ok(values)
+    }
+
+    /// Creation timestamps of every series of a tracked metric by labels hash (empty for the
+    /// unlabeled series), for tooling pruning series by age. The metric is looked up in the
+    /// namespace of `registry` when it has one.
+    #[classmethod]
+    #[pyo3(signature = (name, registry=None))]
+    fn created_timestamps(
+        cls: &PyType,
+        name: &str,
+        registry: Option<&PyAny>,
+    ) -> PyResult<BTreeMap<String, f64>> {
+        let config = current_config();
+        let namespace = registry.and_then(|registry| registry_namespace(&config, registry));
+        let mut pipe = redis::pipe();
+        pipe.hgetall(created_key(&namespaced(namespace, name)));
+        let route = config.route(name);
+        match execute_pipeline_job(cls.py(), route, pipe)?.pop() {
+            Some(PipelineResult::Hash(timestamps)) => Ok(timestamps),
+            _ => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Series of the metrics tracked with `track_last_update` not updated for `window` seconds,
+    /// as `{"metric", "labels", "last_updated", "idle_seconds"}` dicts sorted by metric and labels,
+    /// to spot cardinality leaks before enforcing limits. The metrics are looked up in the
+    /// namespace of `registry` when it has one.
+    #[classmethod]
+    #[pyo3(signature = (window, registry=None))]
+    fn idle_series(cls: &PyType, window: f64, registry: Option<&PyAny>) -> PyResult<Vec<PyObject>> {
+        let py = cls.py();
+        let config = current_config();
+        let namespace = registry.and_then(|registry| registry_namespace(&config, registry));
+        let now = clock::unix_timestamp();
+        let metrics = BTreeSet::from_iter(&config.track_last_update);
+
+        let mut pipes: BTreeMap<usize, (redis::Pipeline, Vec<&String>)> = BTreeMap::new();
+        for metric in metrics {
+            let (pipe, metrics) = pipes.entry(config.route(metric)).or_default();
+            pipe.hgetall(last_updated_key(&namespaced(namespace, metric)));
+            metrics.push(metric);
+        }
+
+        let mut idle = vec![];
+        for (route, (pipe, metrics)) in pipes {
+            let values = execute_pipeline_job(py, route, pipe)?;
+            for (metric, value) in metrics.into_iter().zip(values) {
+                let PipelineResult::Hash(timestamps) = value else {
+                    continue;
+                };
+                for (field, last_updated) in timestamps {
+                    if now - last_updated >= window {
+                        idle.push((metric, series_labels(&field)?, last_updated));
+                    }
+                }
+            }
+        }
+        idle.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+
+        idle.into_iter()
+            .map(|(metric, labels, last_updated)| {
+                let series = PyDict::new(py);
+                series.set_item("metric", metric)?;
+                series.set_item("labels", labels)?;
+                series.set_item("last_updated", last_updated)?;
+                series.set_item("idle_seconds", now - last_updated)?;
+                Ok(series.into())
+            })
+            .collect()
+    }
+
+    /// Number of stored series of every metric of a registry, by metric name: the label sets of
+    /// a labeled metric, 1 or 0 for an unlabeled one depending on whether it was written. Meant
+    /// for alerting on cardinality growth.
+    #[classmethod]
+    fn series_count(cls: &PyType, registry: &PyAny) -> PyResult<BTreeMap<String, usize>> {
+        let py = cls.py();
+        let config = current_config();
+        let namespace = registry_namespace(&config, registry);
+
+        let features = features::current();
+        // the metric and where its series are stored
+        let mut pipes: BTreeMap<usize, (redis::Pipeline, Vec<(String, Storage)>)> = BTreeMap::new();
+        for collector in registry.call_method0(intern!(py, "collect"))?.iter()? {
+            let collector = collector?;
+            let name: String = collector.getattr(intern!(py, "name"))?.extract()?;
+            let prefix = namespaced(namespace, &name);
+            let collector_type: &str = collector.getattr(intern!(py, "type_"))?.extract()?;
+            // every series of a histogram or summary has a count
+            let key_name = match collector_type {
+                "counter" | "gauge" => redis_key(prefix),
+                "histogram" | "summary" => redis_key(format!("{prefix}:count")),
+                _ => continue,
+            };
+            let has_labels = collector
+                .getattr(intern!(py, "_required_labels"))?
+                .is_true()?;
+
+            let storage = config.storage(&name).on(&features);
+            let (pipe, names) = pipes.entry(config.route(&name)).or_default();
+            match (storage, has_labels) {
+                (Storage::Keys, true) => {
+                    pipe.hlen(key_name);
+                }
+                (Storage::Keys, false) => {
+                    pipe.exists(key_name);
+                }
+                (Storage::TimeSeries, _) => timeseries::add_read_to_pipeline(&key_name, pipe),
+                (Storage::Documents, _) => {
+                    pipe.cmd("JSON.GET").arg(key_name);
+                }
+            };
+            names.push((name, storage));
+        }
+
+        let mut counts = BTreeMap::new();
+        for (route, (pipe, names)) in pipes {
+            let values = execute_pipeline(py, route, pipe)?;
+            for ((name, storage), value) in names.into_iter().zip(values) {
+                let count: usize = match storage {
+                    Storage::Keys => {
+                        from_redis_value(&value).map_err(|e| PyException::new_err(e.to_string()))?
+                    }
+                    Storage::TimeSeries => timeseries::series_count(&value),
+                    Storage::Documents => documents::series_count(&value),
+                };
+                counts.insert(name, count);
+            }
+        }
+        Ok(counts)
+    }
 
-        let labels_hash = {
-            if let Some(labels) = to_hash {
-                match serde_json::to_string(&labels) {
-                    Ok(hash) => Some(hash),
-                    Err(e) => return Err(PyException::new_err(e.to_string())),
-                }
-            } else {
-                None
+    /// Create the children of the labeled metrics of a registry for the series already stored,
+    /// called at startup once the metrics are defined so that a restarted process exposes every
+    /// series right away rather than as each label set is used again. Only the metrics stored as
+    /// keys are restored, the label sets missing a required label are skipped. Returns the number
+    /// of series restored.
+    #[classmethod]
+    fn warm_start(cls: &PyType, registry: &PyAny) -> PyResult<usize> {
+        let py = cls.py();
+        let config = current_config();
+        let namespace = registry_namespace(&config, registry);
+
+        let features = features::current();
+        let mut pipes: BTreeMap<usize, redis::Pipeline> = BTreeMap::new();
+        // the metrics read by the pipeline of every route, with their required labels
+        let mut metrics: BTreeMap<usize, Vec<(&PyAny, BTreeSet<String>)>> = BTreeMap::new();
+        for collector in registry.call_method0(intern!(py, "collect"))?.iter()? {
+            let collector = collector?;
+            let name: String = collector.getattr(intern!(py, "name"))?.extract()?;
+            let required_labels = collector.getattr(intern!(py, "_required_labels"))?;
+            if required_labels.is_none() {
+                continue;
             }
-        };
+            let required_labels: BTreeSet<String> = required_labels
+                .iter()?
+                .map(|label| label?.extract())
+                .collect::<PyResult<_>>()?;
+            if required_labels.is_empty() || config.storage(&name).on(&features) != Storage::Keys {
+                continue;
+            }
+            let prefix = namespaced(namespace, &name);
+            let collector_type: &str = collector.getattr(intern!(py, "type_"))?.extract()?;
+            // every series of a histogram or summary has a count
+            let key_name = match collector_type {
+                "counter" | "gauge" => redis_key(prefix),
+                "histogram" | "summary" => redis_key(format!("{prefix}:count")),
+                _ => continue,
+            };
+            // the metric of a collector still registered may have been garbage collected
+            let metric = collector.getattr(intern!(py, "_metric"))?;
+            if metric.is_none() {
+                continue;
+            }
+            let route = config.route(&name);
+            pipes.entry(route).or_default().cmd("HKEYS").arg(key_name);
+            metrics
+                .entry(route)
+                .or_default()
+                .push((metric, required_labels));
+        }
 
-        let new_backend = Self {
-            config: config.into(),
-            metric: metric.into(),
-            histogram_bucket,
-            redis_job_tx: cloned_tx,
-            key_name,
-            labels_hash,
-        };
+        let mut replies = BTreeMap::new();
+        for (route, pipe) in pipes {
+            replies.insert(route, execute_pipeline(py, route, pipe)?);
+        }
+        learn_label_sets(py, &replies)?;
 
-        new_backend._initialize_key();
-        Ok(new_backend)
+        let mut created = 0;
+        for (values, metrics) in replies.into_values().zip(metrics.into_values()) {
+            for (value, (metric, required_labels)) in values.iter().zip(metrics) {
+                let fields: Vec<String> =
+                    from_redis_value(value).map_err(|e| PyException::new_err(e.to_string()))?;
+                for field in fields {
+                    // the labels of a compact field may never have been stored
+                    let Some(field) = labelsets::readable(&field) else {
+                        continue;
+                    };
+                    let Ok(labels) = serde_json::from_str::<BTreeMap<String, String>>(&field)
+                    else {
+                        continue;
+                    };
+                    if !required_labels
+                        .iter()
+                        .all(|label| labels.contains_key(label))
+                    {
+                        continue;
+                    }
+                    let kwargs = PyDict::new(py);
+                    for label in &required_labels {
+                        kwargs.set_item(label, &labels[label])?;
+                    }
+                    metric.call_method(intern!(py, "labels"), (), Some(kwargs))?;
+                    created += 1;
+                }
+            }
+        }
+        Ok(created)
     }
 
+    /// Bytes used in Redis by every metric of a registry, by metric name, from `MEMORY USAGE`
+    /// of its keys: for finding the metrics responsible for the growth of Redis. Nested values
+    /// are sampled `samples` at a time like by the command, and the keys are sent in batches
+    /// of `batch_size` with a `pause` in seconds in between, to spare a busy server.
     #[classmethod]
-    fn _initialize(_cls: &PyType, config: &PyDict) -> PyResult<()> {
-        // using the PyAny::get_item so that it will raise a KeyError on missing key
-        let host: &str = PyAny::get_item(config, intern!(config.py(), "host"))?.extract()?;
-        let port: u16 = PyAny::get_item(config, intern!(config.py(), "port"))?.extract()?;
-
-        let pool = match create_redis_pool(host, port) {
-            Ok(pool) => pool,
-            Err(e) => return Err(PyException::new_err(e.to_string())),
-        };
-
-        // producer / consumer
-        let (tx, rx) = mpsc::channel();
-        REDIS_JOB_TX.get_or_init(|| Mutex::new(tx));
-
-        let (pipeline_tx, pipeline_rx) = crossbeam::channel::unbounded();
-        REDIS_PIPELINE_JOB_TX.get_or_init(|| Mutex::new(pipeline_tx));
-
-        for i in 0..4 {
-            let cloned_pipeline_rx = pipeline_rx.clone();
-            let pool = pool.clone();
-            info!("Starting pipeline thread....{i}");
-            thread::spawn(move || {
-                // the first connection happens at startup so we let it panic
-                let mut connection = pool.get().unwrap();
-                while let Ok(received) = cloned_pipeline_rx.recv() {
-                    let values =
-                        handle_generate_metrics_job(received.pipeline, &mut connection, &pool);
-                    let values = values.map_err(|e| PyException::new_err(e.to_string()));
-
-                    // NOTE: might want to log the failure
-                    let _ = received.result_tx.send(RedisPipelineJobResult { values });
-                }
-            });
+    #[pyo3(signature = (registry, samples=5, batch_size=100, pause=0.01))]
+    fn redis_memory_usage(
+        cls: &PyType,
+        registry: &PyAny,
+        samples: usize,
+        batch_size: usize,
+        pause: f64,
+    ) -> PyResult<BTreeMap<String, u64>> {
+        let py = cls.py();
+        if batch_size == 0 {
+            return Err(PyValueError::new_err("invalid batch_size: 0"));
         }
+        let pause = Duration::try_from_secs_f64(pause)
+            .map_err(|_| PyValueError::new_err(format!("invalid pause: {pause}")))?;
+        let config = current_config();
+        let namespace = registry_namespace(&config, registry);
 
-        info!("Starting BackendAction thread....");
-        thread::spawn(move || loop {
-            // the first connection happens at startup so we let it panic
-            let mut connection = pool.get().unwrap();
-            while let Ok(received) = rx.recv() {
-                handle_backend_action_job(received, &mut connection, &pool, &rx)
-                    .unwrap_or_else(|e| error!("{}", e.to_string()));
+        let mut usage = BTreeMap::new();
+        // the metric of every key, by endpoint
+        let mut keys: BTreeMap<usize, Vec<(String, String)>> = BTreeMap::new();
+        for collector in registry.call_method0(intern!(py, "collect"))?.iter()? {
+            let reads = Self::collector_reads(&config, namespace, collector?)?;
+            usage.insert(reads.name.clone(), 0);
+            let route_keys = keys.entry(reads.route).or_default();
+            for key_name in reads.keys.into_iter().chain(reads.companions) {
+                route_keys.push((reads.name.clone(), key_name));
             }
-        });
+        }
 
-        info!("RedisBackend initialized");
-        Ok(())
+        let mut first = true;
+        for (route, keys) in keys {
+            for batch in keys.chunks(batch_size) {
+                if !mem::take(&mut first) && !pause.is_zero() {
+                    py.allow_threads(|| thread::sleep(pause));
+                }
+                let mut pipe = redis::pipe();
+                for (_, key_name) in batch {
+                    pipe.cmd("MEMORY")
+                        .arg("USAGE")
+                        .arg(key_name)
+                        .arg("SAMPLES")
+                        .arg(samples);
+                }
+                for ((name, _), bytes) in batch.iter().zip(execute_pipeline(py, route, pipe)?) {
+                    // missing keys have no usage
+                    let bytes: Option<u64> = from_redis_value(&bytes)
+                        .map_err(|e| PyException::new_err(e.to_string()))?;
+                    *usage.get_mut(name).unwrap() += bytes.unwrap_or_default();
+                }
+            }
+        }
+        Ok(usage)
     }
+}
 
-    #[classmethod]
-    fn _generate_samples(cls: &PyType, registry: &PyAny) -> PyResult<PyObject> {
-        let py = cls.py();
+impl RedisBackend {
+    fn generate_samples(py: Python, registry: &PyAny) -> PyResult<SampleSet> {
         let collectors = registry.call_method0(intern!(py, "collect"))?;
+        Self::collectors_samples(py, registry, collectors)
+    }
 
-        let metric_collectors: PyResult<Vec<&PyAny>> = collectors
-            .iter()?
-            .map(|i| i.and_then(PyAny::extract))
-            .collect();
-
-        let mut samples_result_dict = SamplesResultDict::new();
+    /// Samples of some collectors of a registry, read in one pipeline per endpoint.
+    fn collectors_samples(py: Python, registry: &PyAny, collectors: &PyAny) -> PyResult<SampleSet> {
+        let mut sample_set = SampleSet::new();
 
-        let mut pipe = redis::pipe();
+        let config = current_config();
+        let namespace = registry_namespace(&config, registry);
+        let features = features::current();
+        // one pipeline by endpoint, and the endpoint and number of replies of each collector
+        let mut pipes: BTreeMap<usize, redis::Pipeline> = BTreeMap::new();
+        let mut replies = vec![];
+        // samples of the gauges read from their callback, by position of the collector
+        let mut callback_samples = BTreeMap::new();
 
         // TODO: need to support custom collectors
-        for metric_collector in metric_collectors? {
-            let samples_list: Vec<OutSample> = vec![];
+        for metric_collector in collectors.iter()? {
+            // a registry failing midway still exposes the collectors listed so far
+            let metric_collector = match metric_collector {
+                Ok(metric_collector) => metric_collector,
+                Err(e) => {
+                    sample_set.push_error(None, e.to_string());
+                    break;
+                }
+            };
+            let reads =
+                Self::collector_reads(&config, namespace, metric_collector).and_then(|reads| {
+                    sample_set.push(metric_collector, config.units.get(&reads.name).cloned())?;
+                    Ok(reads)
+                });
+            let reads = match reads {
+                Ok(reads) => reads,
+                Err(e) => {
+                    let name = metric_collector
+                        .getattr(intern!(py, "name"))
+                        .and_then(PyAny::extract)
+                        .ok();
+                    sample_set.push_error(name, e.to_string());
+                    continue;
+                }
+            };
 
-            samples_result_dict.collectors.push(metric_collector.into());
-            samples_result_dict.samples_vec.push(samples_list);
+            if let Some(source) = config.gauge_callbacks.get(&reads.name) {
+                // evaluated by the scrape, nothing is read from Redis
+                callback_samples.insert(
+                    replies.len(),
+                    Self::callback_samples(metric_collector, source),
+                );
+                replies.push((reads.route, 0, None));
+                continue;
+            }
 
-            let key_name: &str = metric_collector.getattr(intern!(py, "name"))?.extract()?;
+            let pipe = pipes.entry(reads.route).or_insert_with(|| {
+                let mut pipe = redis::pipe();
+                // read every key at the same moment so that the buckets, count and sum of a
+                // histogram are consistent with each other
+                pipe.atomic();
+                pipe
+            });
+            let storage = reads.storage.on(&features);
+            for key_name in &reads.keys {
+                add_stored_read_to_pipeline(
+                    storage,
+                    key_name,
+                    reads.has_labels,
+                    reads.expire_at,
+                    &features,
+                    pipe,
+                );
+            }
+            for companion_key in &reads.companions {
+                add_expire_to_pipeline(companion_key, reads.expire_at, pipe);
+                pipe.hgetall(companion_key);
+            }
+            // the replies of the keys stored by a module are reshaped before decoding
+            let stored_keys =
+                (storage != Storage::Keys).then_some((storage, reads.keys.len(), reads.has_labels));
+            replies.push((
+                reads.route,
+                reads.keys.len() + reads.companions.len(),
+                stored_keys,
+            ));
+        }
 
-            let collector_type: &str = metric_collector.getattr(intern!(py, "type_"))?.extract()?;
-            let has_labels: bool = metric_collector
-                .getattr(intern!(py, "_required_labels"))?
-                .is_true()?;
+        let mut values = BTreeMap::new();
+        for (route, pipe) in pipes {
+            values.insert(
+                route,
+                execute_pipeline_in(py, Lane::Exposition, route, pipe)?,
+            );
+        }
+        learn_label_sets(py, &values)?;
+        let mut values_iterators: BTreeMap<usize, _> = values
+            .iter()
+            .map(|(route, values)| (*route, values.iter()))
+            .collect();
 
-            match collector_type {
-                "counter" | "gauge" => {
-                    pipe.expire(key_name, EXPIRE_KEY_SECONDS).ignore();
-                    if has_labels {
-                        pipe.hgetall(key_name);
-                    } else {
-                        pipe.get(key_name);
-                    }
-                }
-                "summary" => {
-                    for suffix in ["count", "sum"] {
-                        let key_with_suffix = format!("{}:{}", key_name, suffix);
-                        pipe.expire(key_with_suffix.clone(), EXPIRE_KEY_SECONDS)
-                            .ignore();
-                        if has_labels {
-                            pipe.hgetall(key_with_suffix);
-                        } else {
-                            pipe.get(key_with_suffix.clone());
-                        }
-                    }
+        let mut failed = vec![];
+        for (index, ((collector, samples_list), (route, count, stored_keys))) in
+            sample_set.iter_mut().zip(replies).enumerate()
+        {
+            if let Some(samples) = callback_samples.remove(&index) {
+                match samples {
+                    Ok(samples) => *samples_list = samples,
+                    Err(e) => failed.push((index, e)),
                 }
-                "histogram" => {
-                    let extra_suffixes = ["+Inf", "count", "sum"];
-                    let upper_bounds: Vec<f64> = metric_collector
-                        .getattr(intern!(py, "_metric"))?
-                        .getattr(intern!(py, "_upper_bounds"))?
-                        .extract()?;
-                    let upper_bounds = &upper_bounds[..upper_bounds.len() - 1]; // remove inf
-                    let upper_bounds: Vec<String> =
-                        upper_bounds.iter().map(|bound| bound.to_string()).collect();
-
-                    let suffixes = upper_bounds
-                        .iter()
-                        .map(|bound| bound.as_str())
-                        .chain(extra_suffixes);
-
-                    for suffix in suffixes {
-                        let key_with_suffix = format!("{}:{}", key_name, suffix);
-                        pipe.expire(key_with_suffix.clone(), EXPIRE_KEY_SECONDS)
-                            .ignore();
-                        if has_labels {
-                            pipe.hgetall(key_with_suffix);
-                        } else {
-                            pipe.get(key_with_suffix.clone());
-                        }
-                    }
+                continue;
+            }
+            let mut collector_values: Vec<&Value> = values_iterators
+                .get_mut(&route)
+                .unwrap()
+                .take(count)
+                .collect();
+            let stored: Vec<Value>;
+            if let Some((storage, keys, has_labels)) = stored_keys {
+                stored = collector_values[..keys]
+                    .iter()
+                    .map(|value| stored_reply(storage, (*value).clone(), has_labels))
+                    .collect();
+                collector_values.splice(..keys, &stored);
+            }
+            match Self::collector_samples(&config, collector.as_ref(py), &collector_values) {
+                Ok(samples) => *samples_list = samples,
+                Err(e) => failed.push((index, e)),
+            }
+        }
+        // removed from the end so that the indexes stay valid
+        let mut errors: Vec<(String, PyErr)> = failed
+            .into_iter()
+            .rev()
+            .map(|(index, e)| (sample_set.remove(index).name, e))
+            .collect();
+        errors.reverse();
+        for (name, e) in errors {
+            sample_set.push_error(Some(name), e.to_string());
+        }
+
+        Self::add_quantile_samples(py, &config, namespace, &mut sample_set)?;
+        sample_set.omit_zero_series(&config.omit_zero_series);
+        sample_set.relabel(&config.relabel);
+        sample_set.rename(&config.metric_renames);
+        if let Some(prefix) = config.name_prefix() {
+            sample_set.prefix_names(&prefix);
+        }
+        Ok(sample_set)
+    }
+
+    /// Samples of the quantiles of the summaries listed in `summary_quantiles`, read from the
+    /// t-digest of every series found by the scrape and placed before the count of the series.
+    fn add_quantile_samples(
+        py: Python,
+        config: &RedisConfig,
+        namespace: Option<&str>,
+        sample_set: &mut SampleSet,
+    ) -> PyResult<()> {
+        let features = features::current();
+        if config.summary_quantiles.is_empty() || !features.tdigest || !features.scripting {
+            return Ok(());
+        }
+
+        let mut pipes: BTreeMap<usize, redis::Pipeline> = BTreeMap::new();
+        // the family, the count sample of the series and the endpoint of every read
+        let mut reads = vec![];
+        for (index, (collector, samples)) in sample_set.iter_mut().enumerate() {
+            let collector = collector.as_ref(py);
+            let collector_type: &str = collector.getattr(intern!(py, "type_"))?.extract()?;
+            let name: String = collector.getattr(intern!(py, "name"))?.extract()?;
+            let Some(quantiles) = config.summary_quantiles.get(&name) else {
+                continue;
+            };
+            if collector_type != "summary" || quantiles.is_empty() {
+                continue;
+            }
+            let prefix = namespaced(namespace, &name);
+            let route = config.route(&name);
+            let expire_at = config.expire_at.get(&name).copied();
+            for (position, sample) in samples.iter().enumerate() {
+                if sample.suffix != "_count" {
+                    continue;
                 }
-                _ => (),
+                let labels_hash = sample.labels.as_ref().map(|labels| {
+                    let labels_hash = labels::labels_hash(
+                        labels
+                            .iter()
+                            .map(|(name, value)| (name.as_str(), value.as_str())),
+                        config.key_layout,
+                    );
+                    labelsets::compact(labels_hash, config.max_labels_length)
+                });
+                let key_name = digest_key(&prefix, &labels_hash);
+                let pipe = pipes.entry(route).or_insert_with(redis::pipe);
+                add_expire_to_pipeline(&key_name, expire_at, pipe);
+                pipe.cmd("EVAL")
+                    .arg(DIGEST_QUANTILE_SCRIPT)
+                    .arg(1)
+                    .arg(&key_name)
+                    .arg(quantiles);
+                reads.push((index, position, route, quantiles));
             }
         }
 
-        let send_tx = {
-            let redis_pipeline_job_tx_job_tx_mutex = REDIS_PIPELINE_JOB_TX.get().unwrap();
-            let redis_pipeline_job_tx = redis_pipeline_job_tx_job_tx_mutex.lock().unwrap();
-            redis_pipeline_job_tx.clone()
-        };
+        let mut values = BTreeMap::new();
+        for (route, pipe) in pipes {
+            let replies = execute_pipeline_in(py, Lane::Exposition, route, pipe)?;
+            values.insert(route, replies.into_iter());
+        }
+        // quantile and value by family and position of the count sample of the series
+        let mut found: BTreeMap<(usize, usize), Vec<(f64, f64)>> = BTreeMap::new();
+        for (index, position, route, quantiles) in reads {
+            let reply: Vec<String> =
+                from_redis_value(&values.get_mut(&route).unwrap().next().unwrap())
+                    .map_err(|e| PyException::new_err(e.to_string()))?;
+            let series_quantiles = quantiles
+                .iter()
+                .zip(reply)
+                .map(|(quantile, value)| (*quantile, value.parse().unwrap_or(f64::NAN)))
+                .collect();
+            found.insert((index, position), series_quantiles);
+        }
 
-        let (tx, rx) = mpsc::channel();
+        for (index, (_, samples)) in sample_set.iter_mut().enumerate() {
+            // inserted from the end so that the positions stay valid
+            for ((_, position), series_quantiles) in found.range((index, 0)..(index + 1, 0)).rev() {
+                let labels = &samples[*position].labels;
+                let quantile_samples: Vec<OutSample> = series_quantiles
+                    .iter()
+                    .map(|(quantile, value)| OutSample {
+                        suffix: String::new(),
+                        labels: labels.clone(),
+                        quantile: Some(*quantile),
+                        value: *value,
+                    })
+                    .collect();
+                samples.splice(position..position, quantile_samples);
+            }
+        }
+        Ok(())
+    }
 
-        send_tx
-            .send(RedisPipelineJob {
-                result_tx: tx,
-                pipeline: pipe,
+    /// Samples of a gauge read from its callback, with the default labels of the collector.
+    fn callback_samples(collector: &PyAny, source: &callbacks::Source) -> PyResult<Vec<OutSample>> {
+        let py = collector.py();
+        let collector_type: &str = collector.getattr(intern!(py, "type_"))?.extract()?;
+        if collector_type != "gauge" {
+            return Err(PyValueError::new_err(format!(
+                "callbacks are only supported by gauges, not by a {collector_type}"
+            )));
+        }
+        let default_labels: BTreeMap<String, String> = match collector
+            .getattr(intern!(py, "_default_labels_count"))?
+            .is_true()?
+        {
+            true => collector
+                .getattr(intern!(py, "_default_labels"))?
+                .extract()?,
+            false => BTreeMap::new(),
+        };
+        let samples = source
+            .read(py)?
+            .into_iter()
+            .map(|(labels, value)| {
+                let labels = match labels {
+                    Some(labels) => {
+                        Some(default_labels.clone().into_iter().chain(labels).collect())
+                    }
+                    None if default_labels.is_empty() => None,
+                    None => Some(default_labels.clone()),
+                };
+                OutSample::new(String::new(), labels, value)
             })
-            .unwrap();
+            .collect();
+        Ok(samples)
+    }
 
-        let job_result = py.allow_threads(move || rx.recv().unwrap());
-        let values = job_result.values?;
-        let mut values_iterator = values.iter();
+    /// Keys a scrape reads for a collector, each one getting a single reply.
+    fn collector_reads(
+        config: &RedisConfig,
+        namespace: Option<&str>,
+        collector: &PyAny,
+    ) -> PyResult<CollectorReads> {
+        let py = collector.py();
+        let name: String = collector.getattr(intern!(py, "name"))?.extract()?;
+        let prefix = namespaced(namespace, &name);
 
-        for (collector, samples_list) in samples_result_dict
-            .collectors
-            .iter_mut()
-            .zip(samples_result_dict.samples_vec.iter_mut())
-        {
-            let collector_type: String =
-                collector.getattr(py, intern!(py, "type_"))?.extract(py)?;
+        let collector_type: &str = collector.getattr(intern!(py, "type_"))?.extract()?;
+        let has_labels: bool = collector
+            .getattr(intern!(py, "_required_labels"))?
+            .is_true()?;
 
+        let keys = match collector_type {
+            "counter" | "gauge" => vec![redis_key(prefix.clone())],
+            "summary" => ["count", "sum"]
+                .iter()
+                .map(|suffix| redis_key(format!("{prefix}:{suffix}")))
+                .collect(),
+            "histogram" => histogram_suffixes(collector)?
+                .iter()
+                .map(|suffix| redis_key(format!("{prefix}:{suffix}")))
+                .collect(),
+            _ => vec![],
+        };
+        let companions = companion_samples(config, &name, &prefix)
+            .into_iter()
+            .map(|(_, companion_key)| companion_key)
+            .collect();
+
+        Ok(CollectorReads {
+            route: config.route(&name),
+            expire_at: config.expire_at.get(&name).copied(),
+            storage: config.storage(&name),
+            name,
+            has_labels,
+            keys,
+            companions,
+        })
+    }
+
+    /// Samples of a collector from the replies to its reads.
+    fn collector_samples(
+        config: &RedisConfig,
+        collector: &PyAny,
+        values: &[&Value],
+    ) -> PyResult<Vec<OutSample>> {
+        let py = collector.py();
+        let decoded: Vec<PipelineResult> = values
+            .iter()
+            .map(|value| from_redis_value(value))
+            .collect::<RedisResult<_>>()
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        let mut values_iterator = decoded.iter();
+        let mut samples_list = vec![];
+
+        let collector_type: String = collector.getattr(intern!(py, "type_"))?.extract()?;
+        if matches!(
+            collector_type.as_str(),
+            "counter" | "gauge" | "summary" | "histogram"
+        ) {
             let mut current_value = values_iterator.next().unwrap();
 
             match collector_type.as_str() {
@@ -452,11 +3588,8 @@ impl RedisBackend {
                                     Err(e) => return Err(PyException::new_err(e.to_string())),
                                 }
                             };
-                            let out_sample = OutSample::new(
-                                "".to_string(),
-                                Some(labels_map),
-                                value.parse::<f64>().unwrap(),
-                            );
+                            let out_sample =
+                                OutSample::new("".to_string(), Some(labels_map), *value);
                             samples_list.push(out_sample);
                         }
                     }
@@ -470,8 +3603,8 @@ impl RedisBackend {
                                 float
                             } else {
                                 return Err(PyException::new_err(
-                                            "Critical library error while building metrics. Expected float found hash",
-                                        ));
+                                                "Critical library error while building metrics. Expected float found hash",
+                                            ));
                             }
                         };
 
@@ -502,11 +3635,8 @@ impl RedisBackend {
                                     Err(e) => return Err(PyException::new_err(e.to_string())),
                                 }
                             };
-                            let out_sample = OutSample::new(
-                                "_count".to_string(),
-                                Some(labels_map),
-                                value.parse::<f64>().unwrap(),
-                            );
+                            let out_sample =
+                                OutSample::new("_count".to_string(), Some(labels_map), *value);
                             ordered_samples
                                 .entry(labels)
                                 .or_insert(vec![])
@@ -520,11 +3650,8 @@ impl RedisBackend {
                                     Err(e) => return Err(PyException::new_err(e.to_string())),
                                 }
                             };
-                            let out_sample = OutSample::new(
-                                "_sum".to_string(),
-                                Some(labels_map),
-                                value.parse::<f64>().unwrap(),
-                            );
+                            let out_sample =
+                                OutSample::new("_sum".to_string(), Some(labels_map), *value);
                             ordered_samples
                                 .entry(labels)
                                 .or_insert(vec![])
@@ -539,21 +3666,9 @@ impl RedisBackend {
                 "histogram" => match current_value {
                     PipelineResult::Float(float) => {
                         let mut first_iteration = true;
-                        let extra_suffixes = ["+Inf", "count", "sum"];
-                        let upper_bounds: Vec<f64> = collector
-                            .getattr(py, intern!(py, "_metric"))?
-                            .getattr(py, intern!(py, "_upper_bounds"))?
-                            .extract(py)?;
-                        let upper_bounds = &upper_bounds[..upper_bounds.len() - 1]; // remove inf
-                        let upper_bounds: Vec<String> =
-                            upper_bounds.iter().map(|bound| bound.to_string()).collect();
-
-                        let suffixes = upper_bounds
-                            .iter()
-                            .map(|bound| bound.as_str())
-                            .chain(extra_suffixes);
+                        let suffixes = histogram_suffixes(collector)?;
 
-                        for suffix in suffixes {
+                        for suffix in &suffixes {
                             let mut float = float;
                             if !first_iteration {
                                 current_value = values_iterator.next().unwrap();
@@ -562,14 +3677,14 @@ impl RedisBackend {
                                         float
                                     } else {
                                         return Err(PyException::new_err(
-                                            "Critical library error while building metrics. Expected float found hash",
-                                        ));
+                                                "Critical library error while building metrics. Expected float found hash",
+                                            ));
                                     }
                                 };
                             } else {
                                 first_iteration = false;
                             }
-                            match suffix {
+                            match suffix.as_str() {
                                 "count" => {
                                     let out_sample =
                                         OutSample::new("_count".to_string(), None, *float);
@@ -582,7 +3697,8 @@ impl RedisBackend {
                                 }
                                 _ => {
                                     let mut labels_map = BTreeMap::new();
-                                    labels_map.insert("le".to_string(), suffix.to_string());
+                                    labels_map
+                                        .insert("le".to_string(), samples::normalize_bound(suffix));
                                     let out_sample = OutSample::new(
                                         "_bucket".to_string(),
                                         Some(labels_map),
@@ -595,23 +3711,11 @@ impl RedisBackend {
                     }
                     PipelineResult::Hash(hash) => {
                         let mut first_iteration = true;
-                        let extra_suffixes = ["+Inf", "count", "sum"];
-                        let upper_bounds: Vec<f64> = collector
-                            .getattr(py, intern!(py, "_metric"))?
-                            .getattr(py, intern!(py, "_upper_bounds"))?
-                            .extract(py)?;
-                        let upper_bounds = &upper_bounds[..upper_bounds.len() - 1]; // remove inf
-                        let upper_bounds: Vec<String> =
-                            upper_bounds.iter().map(|bound| bound.to_string()).collect();
-
-                        let suffixes = upper_bounds
-                            .iter()
-                            .map(|bound| bound.as_str())
-                            .chain(extra_suffixes);
+                        let suffixes = histogram_suffixes(collector)?;
 
                         let mut ordered_samples = BTreeMap::new();
 
-                        for suffix in suffixes {
+                        for suffix in &suffixes {
                             let mut hash = hash;
                             if !first_iteration {
                                 current_value = values_iterator.next().unwrap();
@@ -620,14 +3724,14 @@ impl RedisBackend {
                                         map
                                     } else {
                                         return Err(PyException::new_err(
-                                            "Critical library error while building metrics. Expected hash",
-                                        ));
+                                                "Critical library error while building metrics. Expected hash",
+                                            ));
                                     }
                                 };
                             } else {
                                 first_iteration = false;
                             }
-                            match suffix {
+                            match suffix.as_str() {
                                 "count" => {
                                     for (labels, value) in hash {
                                         let labels_map: BTreeMap<String, String> = {
@@ -641,7 +3745,7 @@ impl RedisBackend {
                                         let out_sample = OutSample::new(
                                             "_count".to_string(),
                                             Some(labels_map),
-                                            value.parse::<f64>().unwrap(),
+                                            *value,
                                         );
                                         ordered_samples
                                             .entry(labels)
@@ -662,7 +3766,7 @@ impl RedisBackend {
                                         let out_sample = OutSample::new(
                                             "_sum".to_string(),
                                             Some(labels_map),
-                                            value.parse::<f64>().unwrap(),
+                                            *value,
                                         );
                                         ordered_samples
                                             .entry(labels)
@@ -680,11 +3784,14 @@ impl RedisBackend {
                                                 }
                                             }
                                         };
-                                        labels_map.insert("le".to_string(), suffix.to_string());
+                                        labels_map.insert(
+                                            "le".to_string(),
+                                            samples::normalize_bound(suffix),
+                                        );
                                         let out_sample = OutSample::new(
                                             "_bucket".to_string(),
                                             Some(labels_map),
-                                            value.parse::<f64>().unwrap(),
+                                            *value,
                                         );
                                         ordered_samples
                                             .entry(labels)
@@ -703,57 +3810,406 @@ impl RedisBackend {
             }
         }
 
-        samples_result_dict.into_py(py)
+        let name: String = collector.getattr(intern!(py, "name"))?.extract()?;
+        for (suffix, _) in companion_samples(config, &name, &name) {
+            if let Some(PipelineResult::Hash(timestamps)) = values_iterator.next() {
+                for (field, timestamp) in timestamps {
+                    samples_list.push(OutSample::new(
+                        suffix.to_string(),
+                        series_labels(field)?,
+                        *timestamp,
+                    ));
+                }
+            }
+        }
+
+        Ok(samples_list)
     }
 
-    fn _initialize_key(&self) {
-        self.redis_job_tx
-            .send(RedisJob {
-                action: BackendAction::Inc,
-                key_name: self.key_name.clone(),
-                labels_hash: self.labels_hash.clone(), // I wonder if only the String inside should be cloned into a new Some
-                value: 0.0,
-            })
-            .unwrap_or_else(|_| error!("`_initialize_key` operation failed"));
+    fn series_timestamp(&self, py: Python, key_name: &Option<String>) -> PyResult<Option<f64>> {
+        let Some(key_name) = key_name else {
+            return Ok(None);
+        };
+
+        let mut pipe = redis::pipe();
+        pipe.hget(key_name, series_field(&self.labels_hash));
+        match execute_pipeline_job(py, self.route, pipe)?.first() {
+            // a missing field reads as 0, which is never a real timestamp
+            Some(PipelineResult::Float(timestamp)) if *timestamp > 0.0 => Ok(Some(*timestamp)),
+            _ => Ok(None),
+        }
     }
 
-    fn inc(&self, value: f64) {
-        self.redis_job_tx
-            .send(RedisJob {
-                action: BackendAction::Inc,
-                key_name: self.key_name.clone(),
-                labels_hash: self.labels_hash.clone(), // I wonder if only the String inside should be cloned into a new Some
-                value,
-            })
-            .unwrap_or_else(|_| error!("`inc` operation failed"));
+    fn bucket_key(&self, bound: f64) -> String {
+        self.bucket_key_for(&bucket_suffix(bound))
     }
 
-    fn dec(&self, value: f64) {
-        self.redis_job_tx
-            .send(RedisJob {
-                action: BackendAction::Dec,
-                key_name: self.key_name.clone(),
-                labels_hash: self.labels_hash.clone(),
-                value: -value,
-            })
-            .unwrap_or_else(|_| error!("`dec` operation failed"));
+    fn bucket_key_for(&self, suffix: &str) -> String {
+        redis_key(format!("{}:{suffix}", self.resolved_prefix))
     }
 
-    fn set(&self, value: f64) {
-        self.redis_job_tx
-            .send(RedisJob {
-                action: BackendAction::Set,
-                key_name: self.key_name.clone(),
-                labels_hash: self.labels_hash.clone(),
-                value,
-            })
-            .unwrap_or_else(|_| error!("`set` operation failed"));
+    /// Keys written by the backend: its own key, or every bucket, `count` and `sum` key for a
+    /// backend covering a whole histogram.
+    fn key_names(&self) -> Vec<String> {
+        match &self.histogram_bounds {
+            Some(bounds) => bounds
+                .iter()
+                .map(|bound| self.bucket_key(*bound))
+                .chain(["count", "sum"].map(|suffix| self.bucket_key_for(suffix)))
+                .collect(),
+            None => vec![self.key_name.clone()],
+        }
     }
 
-    fn get(&self) -> f64 {
-        // This returns the float 0.0 because it's only called when an existing collector is not
-        // able to find the data in the cache, meaning that it was not initialized yet.
-        0.0
+    fn job(
+        &self,
+        key_name: String,
+        labels_hash: Option<String>,
+        action: BackendAction,
+        value: f64,
+    ) -> RedisJob {
+        let digest_key = match action {
+            BackendAction::Inc if self.observes_quantiles => {
+                Some(digest_key(&self.resolved_prefix, &labels_hash))
+            }
+            _ => None,
+        };
+        RedisJob {
+            action,
+            key_name,
+            labels_hash,
+            value,
+            expire_at: self.expire_at,
+            lease_at: None,
+            last_updated_key: self.last_updated_key.clone(),
+            created_key: self.created_key.clone(),
+            digest_key,
+            observations: None,
+            unique_items: None,
+            storage: self.storage,
+            route: self.route,
+            ack_tx: None,
+            reply_tx: None,
+        }
+    }
+
+    fn send_job(
+        &self,
+        py: Python,
+        action: BackendAction,
+        value: f64,
+        labels: Option<BTreeMap<String, String>>,
+        expire: Option<f64>,
+        operation: &str,
+    ) -> PyResult<()> {
+        let job = self.series_job(action, value, labels, expire)?;
+        self.send_jobs(py, vec![job], operation)
+    }
+
+    /// Job writing the series picked by the labels of a call, see `inc`.
+    fn series_job(
+        &self,
+        action: BackendAction,
+        value: f64,
+        labels: Option<BTreeMap<String, String>>,
+        expire: Option<f64>,
+    ) -> PyResult<RedisJob> {
+        let lease_at = match expire {
+            Some(seconds) if !seconds.is_finite() || seconds <= 0.0 => {
+                return Err(PyValueError::new_err(format!(
+                    "expire must be a positive number of seconds: {seconds}"
+                )))
+            }
+            Some(seconds) => Some((clock::unix_timestamp() + seconds).ceil() as usize),
+            None => None,
+        };
+        let labels_hash = self.series_hash(self.top_k_labels(labels))?;
+        if lease_at.is_some() && labels_hash.is_some() && !features::current().hexpire {
+            return Err(PyException::new_err(
+                "`expire` on a labeled series needs a server with field expiry (Redis 7.4)",
+            ));
+        }
+        let observations = match action {
+            BackendAction::Inc if self.histogram_bucket.as_deref() == Some("sum") => {
+                self.observations_target(&labels_hash)
+            }
+            _ => None,
+        };
+        Ok(RedisJob {
+            lease_at,
+            observations,
+            ..self.job(self.key_name.clone(), labels_hash, action, value)
+        })
+    }
+
+    /// Where an observation of the series is recorded raw, `None` when it's not configured for
+    /// the metric or sampled out.
+    fn observations_target(&self, labels_hash: &Option<String>) -> Option<observations::Target> {
+        let recording = self.raw_observations?;
+        let count = self.observed.fetch_add(1, Ordering::Relaxed);
+        recording.sampled(count).then(|| observations::Target {
+            key_name: observations::key(&self.resolved_prefix, labels_hash),
+            maxlen: recording.maxlen,
+        })
+    }
+
+    /// Labels of a write in heavy-hitters mode: the value of the tracked label is counted and
+    /// replaced when it's not one of the top ones, see `top_k_labels`.
+    fn top_k_labels(
+        &self,
+        labels: Option<BTreeMap<String, String>>,
+    ) -> Option<BTreeMap<String, String>> {
+        let Some((label, tracker)) = &self.top_k else {
+            return labels;
+        };
+        // a missing label is reported by `series_hash`
+        let value = labels
+            .as_ref()
+            .and_then(|labels| labels.get(label))
+            .or_else(|| self.base_labels.get(label));
+        let Some(other) =
+            value.and_then(|value| Python::with_gil(|py| topk::admit(py, tracker, value)))
+        else {
+            return labels;
+        };
+        let mut labels = labels.unwrap_or_default();
+        labels.insert(label.clone(), other);
+        Some(labels)
+    }
+
+    /// Hash of the series written by a call: the one of the backend, or the one of its labels
+    /// completed by the labels of the call.
+    fn series_hash(&self, labels: Option<BTreeMap<String, String>>) -> PyResult<Option<String>> {
+        let Some(labels) = labels else {
+            return match self.labels_hash.is_none() && !self.required_labels.is_empty() {
+                true => Err(PyValueError::new_err(format!(
+                    "missing labels, required: {}",
+                    Vec::from_iter(self.required_labels.iter().cloned()).join(", ")
+                ))),
+                false => Ok(self.labels_hash.clone()),
+            };
+        };
+
+        let mut cache = self.labels_cache.lock().unwrap();
+        let hash = cache.get_or_insert_with(labels, |labels| {
+            if let Some(name) = labels
+                .keys()
+                .find(|name| !self.required_labels.contains(*name))
+            {
+                return Err(PyValueError::new_err(format!("unknown label: {name}")));
+            }
+            let mut series_labels = self.base_labels.clone();
+            series_labels.extend(labels.clone());
+            if let Some(name) = self
+                .required_labels
+                .iter()
+                .find(|name| !series_labels.contains_key(*name))
+            {
+                return Err(PyValueError::new_err(format!("missing label: {name}")));
+            }
+            let config = current_config();
+            let labels_hash = labels::labels_hash(
+                series_labels
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str())),
+                config.key_layout,
+            );
+            Ok(labelsets::compact(labels_hash, config.max_labels_length))
+        })?;
+        Ok(Some(hash))
+    }
+
+    /// Bytes held by the backend, accounted for as long as it lives.
+    fn heap_bytes(&self) -> usize {
+        let strings = [
+            Some(&self.resolved_prefix),
+            Some(&self.key_name),
+            self.labels_hash.as_ref(),
+            self.histogram_bucket.as_ref(),
+            self.last_updated_key.as_ref(),
+            self.created_key.as_ref(),
+        ];
+        mem::size_of::<Self>()
+            + strings
+                .into_iter()
+                .flatten()
+                .map(String::len)
+                .sum::<usize>()
+            + self.histogram_bounds.as_ref().map_or(0, Vec::len) * mem::size_of::<f64>()
+            + self
+                .base_labels
+                .iter()
+                .map(|(name, value)| name.len() + value.len())
+                .sum::<usize>()
+            + self.required_labels.iter().map(String::len).sum::<usize>()
+    }
+
+    /// Record the write of the labeled series of the jobs when idle series are evicted.
+    fn track_writes(&self, jobs: &[RedisJob]) {
+        if current_config().idle_series_timeout.is_none() {
+            return;
+        }
+        let now = clock::now();
+        for job in jobs {
+            if let Some(labels_hash) = &job.labels_hash {
+                let series = idle::Series {
+                    resolved_prefix: self.resolved_prefix.clone(),
+                    labels_hash: labels_hash.clone(),
+                };
+                idle::touch(series, [job.key_name.clone()], job.route, now);
+            }
+        }
+    }
+
+    fn send_jobs(&self, py: Python, mut jobs: Vec<RedisJob>, operation: &str) -> PyResult<()> {
+        panics::raise_pending()?;
+        self.track_writes(&jobs);
+        let ack_rx = if self.confirmed_writes {
+            let (tx, rx) = mpsc::channel();
+            for job in &mut jobs {
+                job.ack_tx = Some(tx.clone());
+            }
+            Some(rx)
+        } else {
+            None
+        };
+
+        let redis_job_tx = match self.pid == process::id() {
+            true => self.redis_job_tx.clone(),
+            false => {
+                ensure_workers(None)?;
+                REDIS_JOB_TX.get().unwrap().lock().unwrap().clone()
+            }
+        };
+
+        let drop_warning_interval = current_config().drop_warning_interval;
+        let job_count = jobs.len();
+        let overflowed = fault::queue_overflow();
+        if overflowed || !queue_jobs(&redis_job_tx, &self.resolved_prefix, jobs) {
+            // a failed inline write already accounted for its jobs and acknowledged the
+            // confirmed ones with its error
+            let written_inline = !overflowed && current_config().synchronous;
+            if ack_rx.is_none() || !written_inline {
+                if ack_rx.is_some() {
+                    return Err(PyException::new_err(format!(
+                        "`{operation}` operation failed"
+                    )));
+                }
+                error!("`{operation}` operation failed");
+                if !written_inline {
+                    drops::record(job_count);
+                }
+                return drops::warn(py, drop_warning_interval);
+            }
+        }
+        drops::warn(py, drop_warning_interval)?;
+
+        let Some(ack_rx) = ack_rx else {
+            return Ok(());
+        };
+        // the jobs are executed in the same pipeline, so they all share its outcome
+        let results: Vec<Result<(), String>> =
+            py.allow_threads(move || ack_rx.iter().take(job_count).collect());
+        match results.iter().find_map(|result| result.as_ref().err()) {
+            Some(e) => Err(PyException::new_err(format!(
+                "`{operation}` operation failed: {e}"
+            ))),
+            None if results.len() < job_count => Err(PyException::new_err(format!(
+                "`{operation}` operation failed: job dropped by the worker"
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for RedisBackend {
+    fn drop(&mut self) {
+        memory::backend_dropped(self.heap_bytes());
+    }
+}
+
+/// Number of series of every metric of a registry kept in process memory, by metric name: the
+/// labeled children created so far, 1 for an unlabeled metric.
+fn local_series_count(registry: &PyAny) -> PyResult<BTreeMap<String, usize>> {
+    let py = registry.py();
+    let mut counts = BTreeMap::new();
+    for collector in registry.call_method0(intern!(py, "collect"))?.iter()? {
+        let collector = collector?;
+        let name: String = collector.getattr(intern!(py, "name"))?.extract()?;
+        let has_labels = collector
+            .getattr(intern!(py, "_required_labels"))?
+            .is_true()?;
+        let count = match has_labels {
+            true => collector.getattr(intern!(py, "_labeled_metrics"))?.len()?,
+            false => 1,
+        };
+        counts.insert(name, count);
+    }
+    Ok(counts)
+}
+
+/// `RedisBackend` storing its data in process memory instead of a Redis server, with the same
+/// key naming, hash layout and expiry, meant for test environments.
+#[pyclass(extends=RedisBackend)]
+struct FakeRedisBackend {}
+
+static FAKE_REDIS: OnceLock<Arc<Mutex<fake::FakeRedis>>> = OnceLock::new();
+
+fn fake_redis() -> Arc<Mutex<fake::FakeRedis>> {
+    FAKE_REDIS.get_or_init(Default::default).clone()
+}
+
+/// Configuration of the fake: host and port are not needed, the rest is honoured.
+fn fake_config(config: &PyDict) -> PyResult<&PyDict> {
+    let py = config.py();
+    let config = config.copy()?;
+    for (key, default) in [("host", "fake".into_py(py)), ("port", 0.into_py(py))] {
+        if !config.contains(key)? {
+            config.set_item(key, default)?;
+        }
+    }
+    Ok(config)
+}
+
+#[pymethods]
+impl FakeRedisBackend {
+    #[new]
+    fn new(
+        config: &PyDict,
+        metric: &PyAny,
+        histogram_bucket: Option<String>,
+    ) -> PyResult<(Self, RedisBackend)> {
+        ensure_workers(Some((Store::Fake, config)))?;
+        Ok((
+            Self {},
+            RedisBackend::new(config, metric, histogram_bucket)?,
+        ))
+    }
+
+    #[classmethod]
+    fn _initialize(_cls: &PyType, config: &PyDict) -> PyResult<()> {
+        let config = Arc::new(RedisConfig::from_pydict(fake_config(config)?)?);
+        initialize_workers(Store::Fake, config, &mut lock_workers())?;
+
+        info!("FakeRedisBackend initialized");
+        Ok(())
+    }
+
+    /// Run a command directly against the in-memory store, e.g. `execute_command("GET", "name")`.
+    #[classmethod]
+    #[pyo3(signature = (*args))]
+    fn execute_command(cls: &PyType, args: Vec<&PyAny>) -> PyResult<PyObject> {
+        let py = cls.py();
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.str().map(|arg| arg.to_string()))
+            .collect::<PyResult<_>>()?;
+        let value = fake_redis()
+            .lock()
+            .unwrap()
+            .execute(&args)
+            .map_err(|e| PyException::new_err(e.to_string()))?;
+        Ok(fake::value_to_py(py, &value))
     }
 }
 
@@ -761,23 +4217,48 @@ impl RedisBackend {
 struct SingleProcessBackend {
     #[pyo3(get)]
     config: Py<PyDict>,
-    #[pyo3(get)]
-    metric: Py<PyAny>,
+    metric: MetricRef,
     #[pyo3(get)]
     histogram_bucket: Option<String>,
     value: Mutex<f64>,
+    // items added to a unique counter, allocated by the first one
+    unique: Mutex<Option<unique::HyperLogLog>>,
 }
 
 #[pymethods]
 impl SingleProcessBackend {
     #[new]
-    fn new(config: &PyDict, metric: &PyAny, histogram_bucket: Option<String>) -> Self {
-        Self {
+    fn new(config: &PyDict, metric: &PyAny, histogram_bucket: Option<String>) -> PyResult<Self> {
+        Ok(Self {
             config: config.into(),
-            metric: metric.into(),
+            metric: MetricRef::new(metric)?,
             histogram_bucket,
             value: Mutex::new(0.0),
-        }
+            unique: Mutex::new(None),
+        })
+    }
+
+    #[getter]
+    fn metric(&self, py: Python) -> PyResult<PyObject> {
+        self.metric.get(py)
+    }
+
+    /// Number of series of every metric of a registry, see `RedisBackend.series_count`.
+    #[classmethod]
+    fn series_count(_cls: &PyType, registry: &PyAny) -> PyResult<BTreeMap<String, usize>> {
+        local_series_count(registry)
+    }
+
+    /// Series of every metric of a registry with their value, see `RedisBackend.snapshot`.
+    #[classmethod]
+    fn snapshot(_cls: &PyType, registry: &PyAny) -> PyResult<PyObject> {
+        snapshot::registry_snapshot(registry)
+    }
+
+    fn _snapshot(&self, py: Python) -> PyResult<PyObject> {
+        let snapshot = PyDict::new(py);
+        snapshot.set_item("value", self.get())?;
+        Ok(snapshot.into())
     }
 
     fn inc(&mut self, value: f64) {
@@ -799,29 +4280,64 @@ impl SingleProcessBackend {
         let data = self.value.lock().unwrap();
         *data
     }
+
+    /// Add items to the HyperLogLog of the backend, see `RedisBackend.add_unique`.
+    fn add_unique(&self, items: Vec<String>) {
+        let mut unique = self.unique.lock().unwrap();
+        let hll = unique.get_or_insert_with(Default::default);
+        for item in &items {
+            hll.add(item);
+        }
+        *self.value.lock().unwrap() = hll.count();
+    }
 }
 
 #[pyclass]
 struct SingleProcessAtomicBackend {
     #[pyo3(get)]
     config: Py<PyDict>,
-    #[pyo3(get)]
-    metric: Py<PyAny>,
+    metric: MetricRef,
     #[pyo3(get)]
     histogram_bucket: Option<String>,
     value: atomic::AtomicF64,
+    // items added to a unique counter, allocated by the first one
+    unique: Mutex<Option<unique::HyperLogLog>>,
 }
 
 #[pymethods]
 impl SingleProcessAtomicBackend {
     #[new]
-    fn new(config: &PyDict, metric: &PyAny, histogram_bucket: Option<String>) -> Self {
-        Self {
+    fn new(config: &PyDict, metric: &PyAny, histogram_bucket: Option<String>) -> PyResult<Self> {
+        Ok(Self {
             config: config.into(),
-            metric: metric.into(),
+            metric: MetricRef::new(metric)?,
             histogram_bucket,
             value: atomic::AtomicF64::new(0.0),
-        }
+            unique: Mutex::new(None),
+        })
+    }
+
+    #[getter]
+    fn metric(&self, py: Python) -> PyResult<PyObject> {
+        self.metric.get(py)
+    }
+
+    /// Number of series of every metric of a registry, see `RedisBackend.series_count`.
+    #[classmethod]
+    fn series_count(_cls: &PyType, registry: &PyAny) -> PyResult<BTreeMap<String, usize>> {
+        local_series_count(registry)
+    }
+
+    /// Series of every metric of a registry with their value, see `RedisBackend.snapshot`.
+    #[classmethod]
+    fn snapshot(_cls: &PyType, registry: &PyAny) -> PyResult<PyObject> {
+        snapshot::registry_snapshot(registry)
+    }
+
+    fn _snapshot(&self, py: Python) -> PyResult<PyObject> {
+        let snapshot = PyDict::new(py);
+        snapshot.set_item("value", self.get())?;
+        Ok(snapshot.into())
     }
 
     fn inc(&mut self, value: f64) {
@@ -839,16 +4355,95 @@ impl SingleProcessAtomicBackend {
     fn get(&self) -> f64 {
         self.value.load(Ordering::Relaxed)
     }
+
+    /// Add items to the HyperLogLog of the backend, see `RedisBackend.add_unique`.
+    fn add_unique(&self, items: Vec<String>) {
+        let mut unique = self.unique.lock().unwrap();
+        let hll = unique.get_or_insert_with(Default::default);
+        for item in &items {
+            hll.add(item);
+        }
+        self.value.store(hll.count(), Ordering::Relaxed);
+    }
 }
 
 /// A Python module implemented in Rust.
 #[pymodule]
-fn pytheus_backend_rs(_py: Python, m: &PyModule) -> PyResult<()> {
+fn pytheus_backend_rs(py: Python, m: &PyModule) -> PyResult<()> {
     pyo3_log::init();
 
     m.add_class::<RedisBackend>()?;
+    m.add_class::<FakeRedisBackend>()?;
     m.add_class::<SingleProcessBackend>()?;
     m.add_class::<SingleProcessAtomicBackend>()?;
     m.add_class::<OutSample>()?;
+    m.add_class::<SampleSet>()?;
+    m.add_class::<samples::SampleFamily>()?;
+    m.add_class::<samples::SampleIter>()?;
+    m.add_class::<parity::ParityBackend>()?;
+    m.add_class::<fanout::FanOutBackend>()?;
+    m.add_class::<registry::CollectorRegistry>()?;
+    m.add_class::<metrics::MetricCollector>()?;
+    m.add_class::<metrics::Metric>()?;
+    m.add_class::<metrics::Counter>()?;
+    m.add_class::<metrics::Gauge>()?;
+    m.add_class::<metrics::Histogram>()?;
+    m.add_class::<metrics::Summary>()?;
+    m.add_class::<metrics::UniqueCounter>()?;
+    m.add_class::<ewma::Ewma>()?;
+    m.add_class::<otel::OtelExporter>()?;
+    m.add_class::<client::PrometheusClientValue>()?;
+    m.add_class::<client::PrometheusClientCollector>()?;
+    m.add_function(wrap_pyfunction!(client::install_prometheus_client, m)?)?;
+    m.add_function(wrap_pyfunction!(children::labels_fast, m)?)?;
+    m.add_function(wrap_pyfunction!(bench::benchmark, m)?)?;
+    m.add_function(wrap_pyfunction!(doctor::doctor, m)?)?;
+    m.add_function(wrap_pyfunction!(info::build_info, m)?)?;
+    m.add_function(wrap_pyfunction!(info::py_server_info, m)?)?;
+    m.add_class::<clock::TestClock>()?;
+    m.add_function(wrap_pyfunction!(clock::set_clock, m)?)?;
+    m.add_function(wrap_pyfunction!(fault::inject_fault, m)?)?;
+    m.add_function(wrap_pyfunction!(fault::clear_faults, m)?)?;
+    m.add_function(wrap_pyfunction!(plugins::register_backend, m)?)?;
+    m.add_function(wrap_pyfunction!(plugins::backend_class, m)?)?;
+    m.add_function(wrap_pyfunction!(plugins::backend_capabilities, m)?)?;
+    m.add_function(wrap_pyfunction!(plugins::registered_backends, m)?)?;
+    m.add_function(wrap_pyfunction!(plugins::load_backend, m)?)?;
+
+    let builtin = |supports_multiprocess, supports_observe| plugins::Capabilities {
+        supports_multiprocess,
+        supports_observe,
+    };
+    // importing the module again replaces them
+    for (name, class, capabilities) in [
+        ("redis", py.get_type::<RedisBackend>(), builtin(true, true)),
+        (
+            "fake_redis",
+            py.get_type::<FakeRedisBackend>(),
+            builtin(false, true),
+        ),
+        (
+            "single_process",
+            py.get_type::<SingleProcessBackend>(),
+            builtin(false, false),
+        ),
+        (
+            "single_process_atomic",
+            py.get_type::<SingleProcessAtomicBackend>(),
+            builtin(false, false),
+        ),
+        (
+            "fanout",
+            py.get_type::<fanout::FanOutBackend>(),
+            builtin(false, false),
+        ),
+        (
+            "parity",
+            py.get_type::<parity::ParityBackend>(),
+            builtin(false, false),
+        ),
+    ] {
+        plugins::register(name, class, capabilities, true)?;
+    }
     Ok(())
 }
@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// live backends and the bytes they hold, jobs sent to the worker and not taken yet
+static BACKENDS: AtomicUsize = AtomicUsize::new(0);
+static BACKEND_BYTES: AtomicUsize = AtomicUsize::new(0);
+static QUEUED_JOBS: AtomicUsize = AtomicUsize::new(0);
+
+fn saturating_sub(counter: &AtomicUsize, value: usize) {
+    let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+        Some(current.saturating_sub(value))
+    });
+}
+
+pub fn backend_created(bytes: usize) {
+    BACKENDS.fetch_add(1, Ordering::Relaxed);
+    BACKEND_BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+pub fn backend_dropped(bytes: usize) {
+    saturating_sub(&BACKENDS, 1);
+    saturating_sub(&BACKEND_BYTES, bytes);
+}
+
+/// Count jobs about to be sent to the worker, before sending so that the worker never takes
+/// jobs that were not counted yet.
+pub fn jobs_queued(count: usize) {
+    QUEUED_JOBS.fetch_add(count, Ordering::Relaxed);
+}
+
+/// Jobs taken by the worker, or that failed to be sent.
+pub fn jobs_taken(count: usize) {
+    saturating_sub(&QUEUED_JOBS, count);
+}
+
+/// Forget the jobs of the workers of the parent after a fork, the child never takes them.
+pub fn reset_queued_jobs() {
+    QUEUED_JOBS.store(0, Ordering::Relaxed);
+}
+
+/// Memory footprint of the backend in the process. The bytes are an estimate of the data held by
+/// the backend, excluding allocator overhead and the Python objects of the metrics.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MemoryUsage {
+    pub backends: usize,
+    pub queued_jobs: usize,
+    pub cached_hashes: usize,
+    pub estimated_bytes: usize,
+}
+
+impl MemoryUsage {
+    /// Usage of the backends and queue, with the hashes cached and the bytes they hold.
+    pub fn current(cached_hashes: usize, cached_bytes: usize, job_bytes: usize) -> Self {
+        let queued_jobs = QUEUED_JOBS.load(Ordering::Relaxed);
+        Self {
+            backends: BACKENDS.load(Ordering::Relaxed),
+            queued_jobs,
+            cached_hashes,
+            estimated_bytes: BACKEND_BYTES.load(Ordering::Relaxed)
+                + cached_bytes
+                + queued_jobs * job_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn counters_never_underflow() {
+        backend_created(100);
+        jobs_queued(3);
+        jobs_taken(2);
+        let usage = MemoryUsage::current(2, 50, 10);
+        assert!(usage.backends >= 1);
+        assert!(usage.estimated_bytes >= 100 + 50);
+        assert_eq!(usage.cached_hashes, 2);
+
+        backend_dropped(100);
+        reset_queued_jobs();
+        jobs_taken(5);
+        assert_eq!(MemoryUsage::current(0, 0, 10).queued_jobs, 0);
+    }
+}
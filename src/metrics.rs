@@ -0,0 +1,587 @@
+use crate::MetricRef;
+use pyo3::exceptions::PyValueError;
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyString, PyTuple};
+use pyo3::{PyTraverseError, PyVisit};
+use std::collections::BTreeMap;
+
+/// Buckets of a histogram created without any, like in pytheus.
+const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+    Summary,
+    UniqueCounter,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+            Kind::Summary => "summary",
+            // exposed as the gauge of its estimate
+            Kind::UniqueCounter => "gauge",
+        }
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_' || first == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    // names starting with `__` are reserved for internal use
+    !name.starts_with("__")
+        && chars
+            .next()
+            .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Checked labels of a collector: valid names, not reserved by its type, defaults for some of
+/// them only.
+fn check_labels(
+    kind: Kind,
+    required_labels: &[String],
+    default_labels: &BTreeMap<String, String>,
+) -> PyResult<()> {
+    for name in required_labels {
+        let reserved = match kind {
+            Kind::Histogram => name == "le",
+            Kind::Summary => name == "quantile",
+            _ => false,
+        };
+        if !valid_label_name(name) || reserved {
+            return Err(PyValueError::new_err(format!("invalid label name: {name}")));
+        }
+    }
+    if let Some(name) = default_labels
+        .keys()
+        .find(|name| !required_labels.contains(name))
+    {
+        return Err(PyValueError::new_err(format!(
+            "default label {name} is not a required label"
+        )));
+    }
+    Ok(())
+}
+
+/// Upper bounds of a histogram, sorted and ending with `+Inf`.
+fn upper_bounds(buckets: Option<Vec<f64>>) -> PyResult<Vec<f64>> {
+    let mut bounds = buckets.unwrap_or_else(|| DEFAULT_BUCKETS.to_vec());
+    if bounds.iter().any(|bound| bound.is_nan()) {
+        return Err(PyValueError::new_err("histogram buckets can't be NaN"));
+    }
+    bounds.sort_by(f64::total_cmp);
+    bounds.dedup();
+    if bounds.last() != Some(&f64::INFINITY) {
+        bounds.push(f64::INFINITY);
+    }
+    Ok(bounds)
+}
+
+/// Collector of a Rust metric, registered in place of pytheus' and exposing the attributes the
+/// backends and the exposition read.
+#[pyclass(weakref)]
+pub struct MetricCollector {
+    #[pyo3(get)]
+    name: String,
+    #[pyo3(get)]
+    description: String,
+    kind: Kind,
+    #[pyo3(get, name = "_required_labels")]
+    required_labels: Option<Vec<String>>,
+    #[pyo3(get, name = "_default_labels")]
+    default_labels: BTreeMap<String, String>,
+    /// Children with all their labels, by the values of the required labels.
+    #[pyo3(get, name = "_labeled_metrics")]
+    labeled_metrics: Py<PyDict>,
+    // the metric created by the user, which holds the collector
+    metric: Option<MetricRef>,
+}
+
+impl MetricCollector {
+    fn new(
+        py: Python,
+        kind: Kind,
+        name: &str,
+        description: &str,
+        required_labels: Option<Vec<String>>,
+        default_labels: Option<BTreeMap<String, String>>,
+    ) -> PyResult<Self> {
+        if !valid_name(name) {
+            return Err(PyValueError::new_err(format!(
+                "invalid metric name: {name}"
+            )));
+        }
+        let default_labels = default_labels.unwrap_or_default();
+        check_labels(
+            kind,
+            required_labels.as_deref().unwrap_or(&[]),
+            &default_labels,
+        )?;
+        Ok(Self {
+            name: name.to_string(),
+            description: description.to_string(),
+            kind,
+            required_labels,
+            default_labels,
+            labeled_metrics: PyDict::new(py).into(),
+            metric: None,
+        })
+    }
+}
+
+#[pymethods]
+impl MetricCollector {
+    #[getter]
+    fn type_(&self) -> &'static str {
+        self.kind.as_str()
+    }
+
+    #[getter(_default_labels_count)]
+    fn default_labels_count(&self) -> usize {
+        self.default_labels.len()
+    }
+
+    #[getter(_metric)]
+    fn metric(&self, py: Python) -> PyResult<PyObject> {
+        match &self.metric {
+            Some(metric) => metric.get(py),
+            None => Ok(py.None()),
+        }
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        visit.call(&self.labeled_metrics)
+    }
+
+    fn __clear__(&mut self) {
+        Python::with_gil(|py| self.labeled_metrics.as_ref(py).clear());
+    }
+}
+
+/// Base of the Rust metric classes: a metric with some of its labels, writing to the backend
+/// loaded in pytheus once all its labels are known. Calls go from Rust to the backend without
+/// running Python code when the backend is implemented in Rust.
+#[pyclass(subclass, weakref)]
+pub struct Metric {
+    kind: Kind,
+    #[pyo3(get, name = "_collector")]
+    collector: Py<MetricCollector>,
+    labels: BTreeMap<String, String>,
+    #[pyo3(get, name = "_metric_value_backend")]
+    backend: Option<PyObject>,
+    #[pyo3(get, name = "_upper_bounds")]
+    upper_bounds: Option<Vec<f64>>,
+}
+
+impl Metric {
+    /// Create the metric of a collector, registering the collector.
+    fn create(
+        py: Python,
+        collector: MetricCollector,
+        upper_bounds: Option<Vec<f64>>,
+        registry: Option<&PyAny>,
+    ) -> PyResult<PyObject> {
+        let kind = collector.kind;
+        let collector = Py::new(py, collector)?;
+        let metric = Self::instantiate(
+            py,
+            Self {
+                kind,
+                collector: collector.clone_ref(py),
+                labels: BTreeMap::new(),
+                backend: None,
+                upper_bounds,
+            },
+        )?;
+        // histogram backends read the buckets of the metric through the collector
+        collector.borrow_mut(py).metric = Some(MetricRef::new(metric.as_ref(py))?);
+        Self::attach_backend(py, &metric)?;
+
+        let registry = match registry {
+            Some(registry) => registry,
+            None => py
+                .import(intern!(py, "pytheus.registry"))?
+                .getattr(intern!(py, "REGISTRY"))?,
+        };
+        registry.call_method1(intern!(py, "register"), (collector,))?;
+        Ok(metric)
+    }
+
+    fn instantiate(py: Python, metric: Self) -> PyResult<PyObject> {
+        Ok(match metric.kind {
+            Kind::Counter => Py::new(py, (Counter {}, metric))?.into_py(py),
+            Kind::Gauge => Py::new(py, (Gauge {}, metric))?.into_py(py),
+            Kind::Histogram => Py::new(py, (Histogram {}, metric))?.into_py(py),
+            Kind::Summary => Py::new(py, (Summary {}, metric))?.into_py(py),
+            Kind::UniqueCounter => Py::new(py, (UniqueCounter {}, metric))?.into_py(py),
+        })
+    }
+
+    /// Create the backend of a metric with all its labels, the backend reads them from the
+    /// metric so it must exist first.
+    fn attach_backend(py: Python, metric: &PyObject) -> PyResult<()> {
+        let cell: &PyCell<Metric> = metric.as_ref(py).downcast()?;
+        if !cell.borrow().observable(py) {
+            return Ok(());
+        }
+        let backend = py
+            .import(intern!(py, "pytheus.backends"))?
+            .getattr(intern!(py, "get_backend"))?
+            .call1((metric,))?;
+        cell.borrow_mut().backend = Some(backend.into());
+        Ok(())
+    }
+
+    /// Whether every required label has a value, set on the metric or by default.
+    fn observable(&self, py: Python) -> bool {
+        let collector = self.collector.borrow(py);
+        collector.required_labels.iter().flatten().all(|name| {
+            self.labels.contains_key(name) || collector.default_labels.contains_key(name)
+        })
+    }
+
+    /// Call a method of the backend, failing for metrics still missing labels.
+    fn write(&self, py: Python, method: &PyString, value: f64) -> PyResult<()> {
+        self.call_backend(py, method, (value,))
+    }
+
+    fn call_backend(
+        &self,
+        py: Python,
+        method: &PyString,
+        args: impl IntoPy<Py<PyTuple>>,
+    ) -> PyResult<()> {
+        match &self.backend {
+            Some(backend) => {
+                backend.call_method1(py, method, args)?;
+                Ok(())
+            }
+            None => Err(PyValueError::new_err(
+                "missing labels, set them with `labels()` first",
+            )),
+        }
+    }
+}
+
+#[pymethods]
+impl Metric {
+    #[getter(_labels)]
+    fn labels_dict(&self) -> Option<BTreeMap<String, String>> {
+        (!self.labels.is_empty()).then(|| self.labels.clone())
+    }
+
+    /// Child of the metric with more of its labels, from a dict and/or keyword arguments. The
+    /// children with all their labels are cached by the collector.
+    #[pyo3(signature = (labels=None, **kwargs))]
+    fn labels(
+        &self,
+        py: Python,
+        labels: Option<BTreeMap<String, String>>,
+        kwargs: Option<BTreeMap<String, String>>,
+    ) -> PyResult<PyObject> {
+        let collector = self.collector.borrow(py);
+        let required_labels = collector.required_labels.as_deref().unwrap_or(&[]);
+        let mut merged = self.labels.clone();
+        for (name, value) in labels
+            .into_iter()
+            .flatten()
+            .chain(kwargs.into_iter().flatten())
+        {
+            if !required_labels.contains(&name) {
+                return Err(PyValueError::new_err(format!("unknown label: {name}")));
+            }
+            merged.insert(name, value);
+        }
+
+        let child = Self {
+            kind: self.kind,
+            collector: self.collector.clone_ref(py),
+            labels: merged,
+            backend: None,
+            upper_bounds: self.upper_bounds.clone(),
+        };
+        if !child.observable(py) {
+            // partially labeled children are not cached, they can't be written to
+            return Self::instantiate(py, child);
+        }
+
+        let key = PyTuple::new(
+            py,
+            required_labels.iter().map(|name| {
+                child
+                    .labels
+                    .get(name)
+                    .or(collector.default_labels.get(name))
+            }),
+        );
+        let labeled_metrics = collector.labeled_metrics.clone_ref(py).into_ref(py);
+        if let Some(cached) = labeled_metrics.get_item(key) {
+            return Ok(cached.into());
+        }
+        drop(collector);
+        let child = Self::instantiate(py, child)?;
+        Self::attach_backend(py, &child)?;
+        labeled_metrics.set_item(key, &child)?;
+        Ok(child)
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        visit.call(&self.collector)?;
+        if let Some(backend) = &self.backend {
+            visit.call(backend)?;
+        }
+        Ok(())
+    }
+
+    fn __clear__(&mut self) {
+        self.backend = None;
+    }
+}
+
+/// Counter implemented in Rust, taking the arguments of pytheus' `Counter`.
+#[pyclass(extends=Metric)]
+pub struct Counter {}
+
+#[pymethods]
+impl Counter {
+    #[new]
+    #[pyo3(signature = (name, description, required_labels=None, default_labels=None, registry=None))]
+    fn new(
+        py: Python,
+        name: &str,
+        description: &str,
+        required_labels: Option<Vec<String>>,
+        default_labels: Option<BTreeMap<String, String>>,
+        registry: Option<&PyAny>,
+    ) -> PyResult<Py<Self>> {
+        let collector = MetricCollector::new(
+            py,
+            Kind::Counter,
+            name,
+            description,
+            required_labels,
+            default_labels,
+        )?;
+        Metric::create(py, collector, None, registry)?.extract(py)
+    }
+
+    #[pyo3(signature = (value=1.0))]
+    fn inc(slf: PyRef<Self>, value: f64) -> PyResult<()> {
+        if value < 0.0 {
+            return Err(PyValueError::new_err("counters can only be incremented"));
+        }
+        let py = slf.py();
+        slf.as_ref().write(py, intern!(py, "inc"), value)
+    }
+}
+
+/// Gauge implemented in Rust, taking the arguments of pytheus' `Gauge`.
+#[pyclass(extends=Metric)]
+pub struct Gauge {}
+
+#[pymethods]
+impl Gauge {
+    #[new]
+    #[pyo3(signature = (name, description, required_labels=None, default_labels=None, registry=None))]
+    fn new(
+        py: Python,
+        name: &str,
+        description: &str,
+        required_labels: Option<Vec<String>>,
+        default_labels: Option<BTreeMap<String, String>>,
+        registry: Option<&PyAny>,
+    ) -> PyResult<Py<Self>> {
+        let collector = MetricCollector::new(
+            py,
+            Kind::Gauge,
+            name,
+            description,
+            required_labels,
+            default_labels,
+        )?;
+        Metric::create(py, collector, None, registry)?.extract(py)
+    }
+
+    #[pyo3(signature = (value=1.0))]
+    fn inc(slf: PyRef<Self>, value: f64) -> PyResult<()> {
+        let py = slf.py();
+        slf.as_ref().write(py, intern!(py, "inc"), value)
+    }
+
+    #[pyo3(signature = (value=1.0))]
+    fn dec(slf: PyRef<Self>, value: f64) -> PyResult<()> {
+        let py = slf.py();
+        slf.as_ref().write(py, intern!(py, "dec"), value)
+    }
+
+    fn set(slf: PyRef<Self>, value: f64) -> PyResult<()> {
+        let py = slf.py();
+        slf.as_ref().write(py, intern!(py, "set"), value)
+    }
+}
+
+/// Histogram implemented in Rust, taking the arguments of pytheus' `Histogram`. An observation
+/// is a single call to `observe` of the backend, which must support whole histograms like
+/// `RedisBackend`.
+#[pyclass(extends=Metric)]
+pub struct Histogram {}
+
+#[pymethods]
+impl Histogram {
+    #[new]
+    #[pyo3(signature = (name, description, required_labels=None, default_labels=None, registry=None, buckets=None))]
+    fn new(
+        py: Python,
+        name: &str,
+        description: &str,
+        required_labels: Option<Vec<String>>,
+        default_labels: Option<BTreeMap<String, String>>,
+        registry: Option<&PyAny>,
+        buckets: Option<Vec<f64>>,
+    ) -> PyResult<Py<Self>> {
+        let collector = MetricCollector::new(
+            py,
+            Kind::Histogram,
+            name,
+            description,
+            required_labels,
+            default_labels,
+        )?;
+        Metric::create(py, collector, Some(upper_bounds(buckets)?), registry)?.extract(py)
+    }
+
+    fn observe(slf: PyRef<Self>, value: f64) -> PyResult<()> {
+        let py = slf.py();
+        slf.as_ref().write(py, intern!(py, "observe"), value)
+    }
+
+    /// Record observations already sorted into the buckets of the histogram, see
+    /// `RedisBackend.observe_buckets`.
+    fn observe_buckets(slf: PyRef<Self>, bucket_counts: Vec<u64>, sum: f64) -> PyResult<()> {
+        let py = slf.py();
+        slf.as_ref()
+            .call_backend(py, intern!(py, "observe_buckets"), (bucket_counts, sum))
+    }
+}
+
+/// Summary without quantiles implemented in Rust, taking the arguments of pytheus' `Summary`:
+/// its count and sum, enough for averages. An observation is a single call to `observe` of the
+/// backend updating both atomically, which must support whole summaries like `RedisBackend`.
+#[pyclass(extends=Metric)]
+pub struct Summary {}
+
+#[pymethods]
+impl Summary {
+    #[new]
+    #[pyo3(signature = (name, description, required_labels=None, default_labels=None, registry=None))]
+    fn new(
+        py: Python,
+        name: &str,
+        description: &str,
+        required_labels: Option<Vec<String>>,
+        default_labels: Option<BTreeMap<String, String>>,
+        registry: Option<&PyAny>,
+    ) -> PyResult<Py<Self>> {
+        let collector = MetricCollector::new(
+            py,
+            Kind::Summary,
+            name,
+            description,
+            required_labels,
+            default_labels,
+        )?;
+        Metric::create(py, collector, None, registry)?.extract(py)
+    }
+
+    fn observe(slf: PyRef<Self>, value: f64) -> PyResult<()> {
+        let py = slf.py();
+        slf.as_ref().write(py, intern!(py, "observe"), value)
+    }
+}
+
+/// Approximate number of distinct items, e.g. users or keys, implemented in Rust: the items are
+/// added to a HyperLogLog by the backend, which must support `add_unique` like `RedisBackend`,
+/// and the estimate is exposed as a gauge. The items themselves are never stored.
+#[pyclass(extends=Metric)]
+pub struct UniqueCounter {}
+
+#[pymethods]
+impl UniqueCounter {
+    #[new]
+    #[pyo3(signature = (name, description, required_labels=None, default_labels=None, registry=None))]
+    fn new(
+        py: Python,
+        name: &str,
+        description: &str,
+        required_labels: Option<Vec<String>>,
+        default_labels: Option<BTreeMap<String, String>>,
+        registry: Option<&PyAny>,
+    ) -> PyResult<Py<Self>> {
+        let collector = MetricCollector::new(
+            py,
+            Kind::UniqueCounter,
+            name,
+            description,
+            required_labels,
+            default_labels,
+        )?;
+        Metric::create(py, collector, None, registry)?.extract(py)
+    }
+
+    /// Add items, converted to strings.
+    #[pyo3(signature = (*items))]
+    fn add(slf: PyRef<Self>, items: &PyTuple) -> PyResult<()> {
+        let py = slf.py();
+        let items = items
+            .iter()
+            .map(|item| Ok(item.str()?.to_string()))
+            .collect::<PyResult<Vec<String>>>()?;
+        if items.is_empty() {
+            return Ok(());
+        }
+        slf.as_ref()
+            .call_backend(py, intern!(py, "add_unique"), (items,))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn names_and_buckets() {
+        assert!(valid_name("http_requests:total"));
+        assert!(!valid_name("1st") && !valid_name("") && !valid_name("with-dash"));
+        assert!(valid_label_name("method") && valid_label_name("_private"));
+        assert!(!valid_label_name("__reserved") && !valid_label_name("a:b"));
+
+        let labels = ["le".to_string()];
+        assert!(check_labels(Kind::Histogram, &labels, &BTreeMap::new()).is_err());
+        assert!(check_labels(Kind::Counter, &labels, &BTreeMap::new()).is_ok());
+        let labels = ["quantile".to_string()];
+        assert!(check_labels(Kind::Summary, &labels, &BTreeMap::new()).is_err());
+
+        assert_eq!(
+            upper_bounds(Some(vec![1.0, 0.5, 1.0])).unwrap(),
+            [0.5, 1.0, f64::INFINITY]
+        );
+        assert_eq!(upper_bounds(None).unwrap().len(), DEFAULT_BUCKETS.len() + 1);
+    }
+}
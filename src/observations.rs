@@ -0,0 +1,112 @@
+use crate::{add_expire_to_pipeline, redis_key, series_field};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+const DEFAULT_MAXLEN: usize = 1000;
+
+/// How the raw observations of a histogram or summary are recorded, next to its buckets or
+/// quantiles, for an exact analysis offline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Recording {
+    /// Observations kept by series, the oldest ones are trimmed.
+    pub maxlen: usize,
+    /// Share of the observations recorded, evenly spaced.
+    pub sample_rate: f64,
+}
+
+impl Recording {
+    /// Recording from the config: the number of observations kept, or
+    /// `{"maxlen": n, "sample_rate": rate}`.
+    pub fn from_py(name: &str, recording: &PyAny) -> PyResult<Self> {
+        let recording = match recording.downcast::<PyDict>() {
+            Ok(options) => Self {
+                maxlen: match options.get_item("maxlen") {
+                    Some(maxlen) => maxlen.extract()?,
+                    None => DEFAULT_MAXLEN,
+                },
+                sample_rate: match options.get_item("sample_rate") {
+                    Some(sample_rate) => sample_rate.extract()?,
+                    None => 1.0,
+                },
+            },
+            Err(_) => Self {
+                maxlen: recording.extract()?,
+                sample_rate: 1.0,
+            },
+        };
+        if recording.maxlen == 0 {
+            return Err(PyValueError::new_err(format!(
+                "invalid raw observations maxlen for {name}: 0"
+            )));
+        }
+        if !(recording.sample_rate > 0.0 && recording.sample_rate <= 1.0) {
+            return Err(PyValueError::new_err(format!(
+                "invalid raw observations sample_rate for {name}: {}",
+                recording.sample_rate
+            )));
+        }
+        Ok(recording)
+    }
+
+    /// Whether the observation numbered `count` by its backend is recorded.
+    pub fn sampled(&self, count: u64) -> bool {
+        ((count + 1) as f64 * self.sample_rate).floor() > (count as f64 * self.sample_rate).floor()
+    }
+}
+
+/// List of the recorded observations of a series.
+pub fn key(resolved_prefix: &str, labels_hash: &Option<String>) -> String {
+    redis_key(format!(
+        "{resolved_prefix}:observations:{}",
+        series_field(labels_hash)
+    ))
+}
+
+/// Where an observation is recorded.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub key_name: String,
+    pub maxlen: usize,
+}
+
+/// Append the observation to the list of its series, trimmed to the last `maxlen` ones.
+pub fn add_to_pipeline(
+    target: &Target,
+    value: f64,
+    expire_at: Option<usize>,
+    pipe: &mut redis::Pipeline,
+) {
+    pipe.rpush(&target.key_name, value).ignore();
+    pipe.ltrim(&target.key_name, -(target.maxlen as isize), -1)
+        .ignore();
+    add_expire_to_pipeline(&target.key_name, expire_at, pipe);
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn sampling() {
+        let every = Recording {
+            maxlen: 10,
+            sample_rate: 1.0,
+        };
+        assert!((0..100).all(|count| every.sampled(count)));
+
+        let quarter = Recording {
+            sample_rate: 0.25,
+            ..every
+        };
+        let sampled: Vec<u64> = (0..12).filter(|count| quarter.sampled(*count)).collect();
+        assert_eq!(sampled, vec![3, 7, 11]);
+
+        let third = Recording {
+            sample_rate: 1.0 / 3.0,
+            ..every
+        };
+        assert_eq!((0..300).filter(|count| third.sampled(*count)).count(), 100);
+    }
+}
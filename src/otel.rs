@@ -0,0 +1,398 @@
+use crate::metrics::{Counter, Gauge, Histogram};
+use crate::{flush, lock_workers};
+use log::warn;
+use pyo3::exceptions::PyValueError;
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyType};
+use pyo3::{PyTraverseError, PyVisit};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+type Labels = BTreeMap<String, String>;
+
+// `AggregationTemporality.DELTA` of the OpenTelemetry SDK
+const DELTA: i64 = 1;
+
+/// Prometheus name of an OpenTelemetry metric or attribute, the characters Prometheus doesn't
+/// allow replaced with `_`, e.g. the dots of `http.server.duration`.
+fn prometheus_name(name: &str, colons: bool) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c,
+            ':' if colons => c,
+            _ => '_',
+        })
+        .collect();
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+/// Increment since the last export of a cumulative value, the whole value when it went back
+/// down because the process recording it restarted.
+fn cumulative_delta(last: Option<f64>, value: f64, monotonic: bool) -> f64 {
+    match last {
+        Some(last) if !monotonic || value >= last => value - last,
+        _ => value,
+    }
+}
+
+/// Observations per bucket and sum since the last export of a cumulative histogram, everything
+/// when a count went back down.
+fn cumulative_buckets(last: Option<&(Vec<u64>, f64)>, counts: &[u64], sum: f64) -> (Vec<u64>, f64) {
+    match last {
+        Some((last_counts, last_sum))
+            if last_counts.len() == counts.len()
+                && last_counts
+                    .iter()
+                    .zip(counts)
+                    .all(|(last, count)| count >= last) =>
+        {
+            let counts = counts
+                .iter()
+                .zip(last_counts)
+                .map(|(count, last)| count - last);
+            (counts.collect(), sum - last_sum)
+        }
+        _ => (counts.to_vec(), sum),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+/// Rust metric recording an OpenTelemetry metric, labeled by the attributes of its first data
+/// points.
+struct Recorder {
+    kind: Kind,
+    metric: PyObject,
+    label_names: Vec<String>,
+}
+
+/// OpenTelemetry SDK metric exporter writing to the metrics of a registry, created on their
+/// first export as Rust metrics stored by the backend loaded in pytheus: services migrating to
+/// the OpenTelemetry API keep exposing Prometheus metrics aggregated in Redis. Sums and
+/// histograms are asked for as deltas and added to the series, gauges set them. Exponential
+/// histograms are not supported.
+#[pyclass]
+pub struct OtelExporter {
+    registry: Option<PyObject>,
+    recorders: HashMap<String, Recorder>,
+    // values of the series exported as cumulative, when the temporality was overridden
+    last_values: HashMap<(String, Labels), f64>,
+    last_buckets: HashMap<(String, Labels), (Vec<u64>, f64)>,
+    #[pyo3(get, name = "_preferred_temporality")]
+    preferred_temporality: PyObject,
+    #[pyo3(get, name = "_preferred_aggregation")]
+    preferred_aggregation: PyObject,
+}
+
+impl OtelExporter {
+    fn recorder(
+        &mut self,
+        py: Python,
+        name: &str,
+        metric: &PyAny,
+        kind: Kind,
+        point: &PyAny,
+    ) -> PyResult<&Recorder> {
+        if let Some(recorder) = self.recorders.get(name) {
+            if recorder.kind != kind {
+                return Err(PyValueError::new_err(format!(
+                    "{name} was exported as a {:?} before",
+                    recorder.kind
+                )));
+            }
+        } else {
+            let label_names: Vec<String> = attributes(point)?.into_keys().collect();
+            let kwargs = PyDict::new(py);
+            if !label_names.is_empty() {
+                kwargs.set_item("required_labels", &label_names)?;
+            }
+            kwargs.set_item("registry", &self.registry)?;
+            if kind == Kind::Histogram {
+                let bounds: Vec<f64> = point.getattr(intern!(py, "explicit_bounds"))?.extract()?;
+                kwargs.set_item("buckets", bounds)?;
+            }
+            let class: &PyType = match kind {
+                Kind::Counter => py.get_type::<Counter>(),
+                Kind::Gauge => py.get_type::<Gauge>(),
+                Kind::Histogram => py.get_type::<Histogram>(),
+            };
+            let description = metric.getattr(intern!(py, "description"))?;
+            let created = class.call((name, description), Some(kwargs))?;
+            self.recorders.insert(
+                name.to_string(),
+                Recorder {
+                    kind,
+                    metric: created.into(),
+                    label_names,
+                },
+            );
+        }
+        Ok(&self.recorders[name])
+    }
+
+    /// Record the data points of a metric, failing on the first one that can't be recorded.
+    fn record(&mut self, py: Python, metric: &PyAny) -> PyResult<()> {
+        let data = metric.getattr(intern!(py, "data"))?;
+        let kind = match data.get_type().name()? {
+            "Sum" if data.getattr(intern!(py, "is_monotonic"))?.is_true()? => Kind::Counter,
+            "Sum" | "Gauge" => Kind::Gauge,
+            "Histogram" => Kind::Histogram,
+            other => {
+                warn!("OpenTelemetry {other} metrics are not supported");
+                return Ok(());
+            }
+        };
+        let delta = match data.getattr(intern!(py, "aggregation_temporality")) {
+            Ok(temporality) => {
+                temporality
+                    .getattr(intern!(py, "value"))?
+                    .extract::<i64>()?
+                    == DELTA
+            }
+            // gauges
+            Err(_) => false,
+        };
+        let summed = data.get_type().name()? == "Sum";
+        let name = prometheus_name(metric.getattr(intern!(py, "name"))?.extract()?, true);
+
+        for point in data.getattr(intern!(py, "data_points"))?.iter()? {
+            let point = point?;
+            let recorder = self.recorder(py, &name, metric, kind, point)?;
+            let labels = attributes(point)?;
+            if let Some(extra) = labels
+                .keys()
+                .find(|label| !recorder.label_names.contains(label))
+            {
+                return Err(PyValueError::new_err(format!(
+                    "attribute {extra} of {name} is not a label of its first data points"
+                )));
+            }
+            let child = match recorder.label_names.is_empty() {
+                true => recorder.metric.clone_ref(py),
+                // the attributes missing from a data point are exposed as empty labels
+                false => {
+                    let mut all = labels.clone();
+                    for label in &recorder.label_names {
+                        all.entry(label.clone()).or_default();
+                    }
+                    recorder
+                        .metric
+                        .call_method1(py, intern!(py, "labels"), (all,))?
+                }
+            };
+            let series = (name.clone(), labels);
+
+            if kind == Kind::Histogram {
+                let counts: Vec<u64> = point.getattr(intern!(py, "bucket_counts"))?.extract()?;
+                let sum: f64 = point.getattr(intern!(py, "sum"))?.extract()?;
+                let (counts, sum) = match delta {
+                    true => (counts, sum),
+                    false => {
+                        let observed =
+                            cumulative_buckets(self.last_buckets.get(&series), &counts, sum);
+                        self.last_buckets.insert(series, (counts, sum));
+                        observed
+                    }
+                };
+                child.call_method1(py, intern!(py, "observe_buckets"), (counts, sum))?;
+                continue;
+            }
+
+            let value: f64 = point.getattr(intern!(py, "value"))?.extract()?;
+            if !summed {
+                child.call_method1(py, intern!(py, "set"), (value,))?;
+                continue;
+            }
+            let increment = match delta {
+                true => value,
+                false => {
+                    let last = self.last_values.insert(series, value);
+                    cumulative_delta(last, value, kind == Kind::Counter)
+                }
+            };
+            if increment != 0.0 {
+                child.call_method1(py, intern!(py, "inc"), (increment,))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Attributes of a data point as labels.
+fn attributes(point: &PyAny) -> PyResult<Labels> {
+    let py = point.py();
+    let attributes = point.getattr(intern!(py, "attributes"))?;
+    if attributes.is_none() {
+        return Ok(Labels::new());
+    }
+    let attributes: &PyDict = attributes.downcast()?;
+    attributes
+        .iter()
+        .map(|(name, value)| {
+            let value = match value.extract::<String>() {
+                Ok(value) => value,
+                Err(_) => value.str()?.extract()?,
+            };
+            Ok((prometheus_name(name.extract()?, false), value))
+        })
+        .collect()
+}
+
+#[pymethods]
+impl OtelExporter {
+    /// Exporter recording to the metrics of `registry`, pytheus' default registry when `None`.
+    #[new]
+    #[pyo3(signature = (registry=None))]
+    fn new(py: Python, registry: Option<PyObject>) -> PyResult<Self> {
+        let sdk = py.import(intern!(py, "opentelemetry.sdk.metrics"))?;
+        let temporality = py
+            .import(intern!(py, "opentelemetry.sdk.metrics.export"))?
+            .getattr(intern!(py, "AggregationTemporality"))?;
+        let preferred_temporality = PyDict::new(py);
+        for instrument in [
+            "Counter",
+            "UpDownCounter",
+            "Histogram",
+            "ObservableCounter",
+            "ObservableUpDownCounter",
+        ] {
+            preferred_temporality.set_item(
+                sdk.getattr(instrument)?,
+                temporality.getattr(intern!(py, "DELTA"))?,
+            )?;
+        }
+        Ok(Self {
+            registry,
+            recorders: HashMap::new(),
+            last_values: HashMap::new(),
+            last_buckets: HashMap::new(),
+            preferred_temporality: preferred_temporality.into(),
+            preferred_aggregation: PyDict::new(py).into(),
+        })
+    }
+
+    /// Record the metrics collected by a reader, returning `MetricExportResult.FAILURE` when
+    /// some couldn't be recorded. The writes are queued, the timeout doesn't apply.
+    #[pyo3(signature = (metrics_data, **_kwargs))]
+    fn export(
+        &mut self,
+        py: Python,
+        metrics_data: &PyAny,
+        _kwargs: Option<&PyDict>,
+    ) -> PyResult<PyObject> {
+        let mut failed = false;
+        for resource_metrics in metrics_data
+            .getattr(intern!(py, "resource_metrics"))?
+            .iter()?
+        {
+            for scope_metrics in resource_metrics?
+                .getattr(intern!(py, "scope_metrics"))?
+                .iter()?
+            {
+                for metric in scope_metrics?.getattr(intern!(py, "metrics"))?.iter()? {
+                    let metric = metric?;
+                    if let Err(e) = self.record(py, metric) {
+                        warn!("OpenTelemetry metric not recorded: {e}");
+                        failed = true;
+                    }
+                }
+            }
+        }
+        let result = py
+            .import(intern!(py, "opentelemetry.sdk.metrics.export"))?
+            .getattr(intern!(py, "MetricExportResult"))?;
+        Ok(match failed {
+            true => result.getattr(intern!(py, "FAILURE"))?,
+            false => result.getattr(intern!(py, "SUCCESS"))?,
+        }
+        .into())
+    }
+
+    /// Wait for the writes of the exported metrics, `False` when the timeout elapsed first.
+    #[pyo3(signature = (timeout_millis=30_000.0))]
+    fn force_flush(&self, py: Python, timeout_millis: f64) -> bool {
+        if lock_workers().is_none() {
+            return true;
+        }
+        let timeout = Duration::from_secs_f64(timeout_millis.max(0.0) / 1000.0);
+        py.allow_threads(|| flush::wait(Some(timeout)))
+    }
+
+    #[pyo3(signature = (timeout_millis=30_000.0, **_kwargs))]
+    fn shutdown(&self, py: Python, timeout_millis: f64, _kwargs: Option<&PyDict>) {
+        self.force_flush(py, timeout_millis);
+    }
+
+    /// `PeriodicExportingMetricReader` exporting with this exporter, to give to a `MeterProvider`.
+    #[pyo3(signature = (export_interval_millis=None))]
+    fn reader(slf: PyRef<Self>, export_interval_millis: Option<f64>) -> PyResult<PyObject> {
+        let py = slf.py();
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("export_interval_millis", export_interval_millis)?;
+        let reader = py
+            .import(intern!(py, "opentelemetry.sdk.metrics.export"))?
+            .getattr(intern!(py, "PeriodicExportingMetricReader"))?
+            .call((slf,), Some(kwargs))?;
+        Ok(reader.into())
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        if let Some(registry) = &self.registry {
+            visit.call(registry)?;
+        }
+        for recorder in self.recorders.values() {
+            visit.call(&recorder.metric)?;
+        }
+        visit.call(&self.preferred_temporality)?;
+        visit.call(&self.preferred_aggregation)
+    }
+
+    fn __clear__(&mut self) {
+        self.registry = None;
+        self.recorders.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn otel_conversions() {
+        assert_eq!(
+            prometheus_name("http.server.duration", true),
+            "http_server_duration"
+        );
+        assert_eq!(prometheus_name("a:b-c", true), "a:b_c");
+        assert_eq!(prometheus_name("net.peer:name", false), "net_peer_name");
+        assert_eq!(prometheus_name("5xx", false), "_5xx");
+
+        assert_eq!(cumulative_delta(None, 3.0, true), 3.0);
+        assert_eq!(cumulative_delta(Some(3.0), 5.0, true), 2.0);
+        // restarted
+        assert_eq!(cumulative_delta(Some(3.0), 1.0, true), 1.0);
+        assert_eq!(cumulative_delta(Some(3.0), 1.0, false), -2.0);
+
+        assert_eq!(cumulative_buckets(None, &[1, 2], 4.0), (vec![1, 2], 4.0));
+        let last = (vec![1, 2], 4.0);
+        assert_eq!(
+            cumulative_buckets(Some(&last), &[1, 5], 10.0),
+            (vec![0, 3], 6.0)
+        );
+        assert_eq!(
+            cumulative_buckets(Some(&last), &[0, 5], 2.0),
+            (vec![0, 5], 2.0)
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use log::error;
+use pyo3::exceptions::{PyRuntimeError, PyRuntimeWarning};
+use pyo3::prelude::*;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, Once};
+
+/// What to do when Rust code panics instead of letting it take down a worker thread or surface as
+/// an opaque `PanicException`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PanicPolicy {
+    /// Raise a `RuntimeError`, for worker panics on the next operation of a backend.
+    #[default]
+    Raise,
+    /// Emit a `RuntimeWarning` and carry on.
+    Warn,
+    /// Abort the process.
+    Abort,
+}
+
+impl PanicPolicy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "raise" => Some(PanicPolicy::Raise),
+            "warn" => Some(PanicPolicy::Warn),
+            "abort" => Some(PanicPolicy::Abort),
+            _ => None,
+        }
+    }
+}
+
+// worker panic waiting to be raised by the next backend operation
+static PENDING_PANIC: Mutex<Option<String>> = Mutex::new(None);
+static HOOK: Once = Once::new();
+
+fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Route panic reports to the logger instead of stderr, the policy decides what happens next.
+pub fn install_hook() {
+    HOOK.call_once(|| {
+        panic::set_hook(Box::new(|info| error!("{info}")));
+    });
+}
+
+fn warn(py: Python, message: &str) {
+    let warning = py.get_type::<PyRuntimeWarning>();
+    if let Err(e) = PyErr::warn(py, warning, message, 1) {
+        e.restore(py);
+    }
+}
+
+/// Handle a panic caught in a worker thread, which keeps running afterwards.
+pub fn worker_panicked(policy: PanicPolicy, payload: Box<dyn Any + Send>) {
+    let message = format!("worker thread panicked: {}", message(&*payload));
+    match policy {
+        PanicPolicy::Raise => *PENDING_PANIC.lock().unwrap() = Some(message),
+        PanicPolicy::Warn => Python::with_gil(|py| warn(py, &message)),
+        PanicPolicy::Abort => {
+            error!("{message}, aborting");
+            std::process::abort();
+        }
+    }
+}
+
+/// Raise the panic of a worker that happened since the last call, if any.
+pub fn raise_pending() -> PyResult<()> {
+    match PENDING_PANIC.lock().unwrap().take() {
+        Some(message) => Err(PyRuntimeError::new_err(message)),
+        None => Ok(()),
+    }
+}
+
+/// Run code called from Python, applying the policy if it panics. With `warn` the default value
+/// is returned after the warning.
+pub fn guard<T: Default>(
+    py: Python,
+    policy: PanicPolicy,
+    f: impl FnOnce() -> PyResult<T>,
+) -> PyResult<T> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = message(&*payload);
+            match policy {
+                PanicPolicy::Raise => Err(PyRuntimeError::new_err(message)),
+                PanicPolicy::Warn => {
+                    warn(py, &message);
+                    Ok(T::default())
+                }
+                PanicPolicy::Abort => {
+                    error!("{message}, aborting");
+                    std::process::abort();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(PanicPolicy::parse("warn"), Some(PanicPolicy::Warn));
+        assert_eq!(PanicPolicy::parse("bob"), None);
+    }
+
+    #[test]
+    fn pending_panic() {
+        let payload = panic::catch_unwind(|| panic!("boom")).unwrap_err();
+        worker_panicked(PanicPolicy::Raise, payload);
+        assert_eq!(
+            PENDING_PANIC.lock().unwrap().as_deref(),
+            Some("worker thread panicked: boom")
+        );
+        assert!(raise_pending().is_err());
+        assert!(raise_pending().is_ok());
+    }
+}
@@ -0,0 +1,339 @@
+use crate::plugins;
+use log::warn;
+use pyo3::exceptions::PyException;
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyType};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Primary,
+    Secondary,
+}
+
+thread_local! {
+    // which child `get` reads from, switched while collecting the samples of a side
+    static READ_SIDE: Cell<Side> = const { Cell::new(Side::Primary) };
+}
+
+struct ParitySetup {
+    primary: Py<PyType>,
+    secondary: Py<PyType>,
+    tolerance: f64,
+}
+
+static PARITY_SETUP: OnceLock<Mutex<Option<ParitySetup>>> = OnceLock::new();
+static DIVERGENCES: Mutex<Vec<Divergence>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, PartialEq)]
+struct Divergence {
+    metric: String,
+    suffix: String,
+    labels: BTreeMap<String, String>,
+    primary: Option<f64>,
+    secondary: Option<f64>,
+}
+
+fn child_config<'py>(config: &'py PyDict, key: &str) -> PyResult<&'py PyDict> {
+    match config.get_item(key) {
+        Some(child_config) => Ok(child_config.downcast()?),
+        None => Ok(PyDict::new(config.py())),
+    }
+}
+
+fn child_class<'py>(config: &'py PyDict, key: &str) -> PyResult<&'py PyType> {
+    // using the PyAny::get_item so that it will raise a KeyError on missing key
+    plugins::resolve(PyAny::get_item(config, key)?)
+}
+
+type SampleKey = (String, BTreeMap<String, String>);
+
+/// Samples of one collector indexed by suffix and labels.
+fn index_samples(samples: &PyAny) -> PyResult<BTreeMap<SampleKey, f64>> {
+    let py = samples.py();
+    let mut indexed = BTreeMap::new();
+    for sample in samples.iter()? {
+        let sample = sample?;
+        let suffix: String = sample.getattr(intern!(py, "suffix"))?.extract()?;
+        let labels: Option<BTreeMap<String, String>> =
+            sample.getattr(intern!(py, "labels"))?.extract()?;
+        let value: f64 = sample.getattr(intern!(py, "value"))?.extract()?;
+        indexed.insert((suffix, labels.unwrap_or_default()), value);
+    }
+    Ok(indexed)
+}
+
+fn values_match(primary: f64, secondary: f64, tolerance: f64) -> bool {
+    if primary == secondary {
+        return true;
+    }
+    let scale = primary.abs().max(secondary.abs()).max(1.0);
+    (primary - secondary).abs() <= tolerance * scale
+}
+
+fn compare_samples(
+    metric: &str,
+    primary: &BTreeMap<SampleKey, f64>,
+    secondary: &BTreeMap<SampleKey, f64>,
+    tolerance: f64,
+) -> Vec<Divergence> {
+    let mut keys: Vec<&SampleKey> = primary.keys().chain(secondary.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let primary_value = primary.get(key).copied();
+            let secondary_value = secondary.get(key).copied();
+            let matching = match (primary_value, secondary_value) {
+                (Some(p), Some(s)) => values_match(p, s, tolerance),
+                _ => false,
+            };
+            (!matching).then(|| Divergence {
+                metric: metric.to_string(),
+                suffix: key.0.clone(),
+                labels: key.1.clone(),
+                primary: primary_value,
+                secondary: secondary_value,
+            })
+        })
+        .collect()
+}
+
+/// Samples for every collector of the registry as seen by one side, using the backend class
+/// `_generate_samples` when available and reading the children through `collect` otherwise.
+fn side_samples(
+    backend_class: &PyType,
+    side: Side,
+    registry: &PyAny,
+    collectors: &[&PyAny],
+) -> PyResult<Vec<PyObject>> {
+    let py = registry.py();
+    if backend_class.hasattr(intern!(py, "_generate_samples"))? {
+        let samples = backend_class.call_method1(intern!(py, "_generate_samples"), (registry,))?;
+        return collectors
+            .iter()
+            .map(|collector| {
+                Ok(samples
+                    .get_item(*collector)
+                    .map(|samples| samples.into_py(py))
+                    .unwrap_or_else(|_| PyList::empty(py).into()))
+            })
+            .collect();
+    }
+
+    READ_SIDE.with(|read_side| read_side.set(side));
+    let samples = collectors
+        .iter()
+        .map(|collector| {
+            let samples: Vec<&PyAny> = collector
+                .call_method0(intern!(py, "collect"))?
+                .iter()?
+                .collect::<PyResult<_>>()?;
+            Ok(PyList::new(py, samples).into())
+        })
+        .collect();
+    READ_SIDE.with(|read_side| read_side.set(Side::Primary));
+    samples
+}
+
+/// Backend forwarding every operation to two backends and comparing their samples at exposition
+/// time, to verify that both produce identical numbers. The primary backend is the one exposed.
+///
+/// Config: `primary`/`secondary` backend classes or names of registered backends, optional
+/// `primary_config`/`secondary_config` and a relative `tolerance` for float comparisons.
+#[pyclass]
+pub struct ParityBackend {
+    #[pyo3(get)]
+    primary: PyObject,
+    #[pyo3(get)]
+    secondary: PyObject,
+}
+
+#[pymethods]
+impl ParityBackend {
+    #[new]
+    #[pyo3(signature = (config, metric, histogram_bucket=None))]
+    fn new(config: &PyDict, metric: &PyAny, histogram_bucket: Option<String>) -> PyResult<Self> {
+        let py = config.py();
+        let create = |class_key: &str, config_key: &str| -> PyResult<PyObject> {
+            let class = child_class(config, class_key)?;
+            let child_config = child_config(config, config_key)?;
+            Ok(class
+                .call1((child_config, metric, histogram_bucket.clone()))?
+                .into_py(py))
+        };
+
+        Ok(Self {
+            primary: create("primary", "primary_config")?,
+            secondary: create("secondary", "secondary_config")?,
+        })
+    }
+
+    #[classmethod]
+    fn _initialize(_cls: &PyType, config: &PyDict) -> PyResult<()> {
+        let py = config.py();
+        let primary = child_class(config, "primary")?;
+        let secondary = child_class(config, "secondary")?;
+        let tolerance: f64 = match config.get_item("tolerance") {
+            Some(tolerance) => tolerance.extract()?,
+            None => 1e-9,
+        };
+
+        for (class, config_key) in [(primary, "primary_config"), (secondary, "secondary_config")] {
+            if class.hasattr(intern!(py, "_initialize"))? {
+                class.call_method1(
+                    intern!(py, "_initialize"),
+                    (child_config(config, config_key)?,),
+                )?;
+            }
+        }
+
+        *PARITY_SETUP.get_or_init(Default::default).lock().unwrap() = Some(ParitySetup {
+            primary: primary.into(),
+            secondary: secondary.into(),
+            tolerance,
+        });
+        Ok(())
+    }
+
+    #[classmethod]
+    fn _generate_samples(cls: &PyType, registry: &PyAny) -> PyResult<PyObject> {
+        let py = cls.py();
+        let (primary, secondary, tolerance) = {
+            let setup = PARITY_SETUP.get_or_init(Default::default).lock().unwrap();
+            let Some(setup) = setup.as_ref() else {
+                return Err(PyException::new_err("ParityBackend is not initialized"));
+            };
+            (
+                setup.primary.clone_ref(py),
+                setup.secondary.clone_ref(py),
+                setup.tolerance,
+            )
+        };
+
+        let collectors: Vec<&PyAny> = registry
+            .call_method0(intern!(py, "collect"))?
+            .iter()?
+            .collect::<PyResult<_>>()?;
+
+        let primary_samples =
+            side_samples(primary.as_ref(py), Side::Primary, registry, &collectors)?;
+        let secondary_samples =
+            side_samples(secondary.as_ref(py), Side::Secondary, registry, &collectors)?;
+
+        let result = PyDict::new(py);
+        let mut divergences = vec![];
+        for ((collector, primary), secondary) in collectors
+            .iter()
+            .zip(primary_samples)
+            .zip(secondary_samples)
+        {
+            let name: String = collector.getattr(intern!(py, "name"))?.extract()?;
+            divergences.extend(compare_samples(
+                &name,
+                &index_samples(primary.as_ref(py))?,
+                &index_samples(secondary.as_ref(py))?,
+                tolerance,
+            ));
+            result.set_item(*collector, primary)?;
+        }
+
+        for divergence in &divergences {
+            warn!("backends diverge: {divergence:?}");
+        }
+        DIVERGENCES.lock().unwrap().extend(divergences);
+
+        Ok(result.into())
+    }
+
+    /// Divergences found so far, as a list of dicts.
+    #[classmethod]
+    fn divergences(cls: &PyType) -> PyResult<PyObject> {
+        let py = cls.py();
+        let divergences = PyList::empty(py);
+        for divergence in DIVERGENCES.lock().unwrap().iter() {
+            let entry = PyDict::new(py);
+            entry.set_item("metric", &divergence.metric)?;
+            entry.set_item("suffix", &divergence.suffix)?;
+            entry.set_item("labels", divergence.labels.clone())?;
+            entry.set_item("primary", divergence.primary)?;
+            entry.set_item("secondary", divergence.secondary)?;
+            divergences.append(entry)?;
+        }
+        Ok(divergences.into())
+    }
+
+    #[classmethod]
+    fn clear_divergences(_cls: &PyType) {
+        DIVERGENCES.lock().unwrap().clear();
+    }
+
+    fn inc(&self, py: Python, value: f64) -> PyResult<()> {
+        self.primary
+            .call_method1(py, intern!(py, "inc"), (value,))?;
+        self.secondary
+            .call_method1(py, intern!(py, "inc"), (value,))?;
+        Ok(())
+    }
+
+    fn dec(&self, py: Python, value: f64) -> PyResult<()> {
+        self.primary
+            .call_method1(py, intern!(py, "dec"), (value,))?;
+        self.secondary
+            .call_method1(py, intern!(py, "dec"), (value,))?;
+        Ok(())
+    }
+
+    fn set(&self, py: Python, value: f64) -> PyResult<()> {
+        self.primary
+            .call_method1(py, intern!(py, "set"), (value,))?;
+        self.secondary
+            .call_method1(py, intern!(py, "set"), (value,))?;
+        Ok(())
+    }
+
+    fn get(&self, py: Python) -> PyResult<PyObject> {
+        let child = match READ_SIDE.with(Cell::get) {
+            Side::Primary => &self.primary,
+            Side::Secondary => &self.secondary,
+        };
+        child.call_method0(py, intern!(py, "get"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn compare() {
+        let labels = BTreeMap::from([("bob".to_string(), "cat".to_string())]);
+        let primary = BTreeMap::from([
+            (("".to_string(), BTreeMap::new()), 1.0),
+            (("".to_string(), labels.clone()), 0.1 + 0.2),
+        ]);
+        let secondary = BTreeMap::from([
+            (("".to_string(), labels.clone()), 0.3),
+            (("_sum".to_string(), BTreeMap::new()), 2.0),
+        ]);
+
+        let divergences = compare_samples("name", &primary, &secondary, 1e-9);
+        assert_eq!(divergences.len(), 2);
+        assert_eq!(divergences[0].primary, Some(1.0));
+        assert_eq!(divergences[0].secondary, None);
+        assert_eq!(divergences[1].suffix, "_sum");
+        assert_eq!(divergences[1].primary, None);
+    }
+
+    #[test]
+    fn tolerance() {
+        assert!(values_match(0.1 + 0.2, 0.3, 1e-9));
+        assert!(!values_match(1.0, 1.1, 1e-9));
+        assert!(values_match(1.0, 1.1, 0.1));
+    }
+}
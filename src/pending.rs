@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+// key and labels hash of a series
+type Series = (String, Option<String>);
+
+// writes sent to the worker and not taken by it yet, when `track_pending_writes` is configured
+static PENDING: Mutex<BTreeMap<Series, Pending>> = Mutex::new(BTreeMap::new());
+
+/// Write of a series as queued for the worker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Write {
+    Add(f64),
+    Set(f64),
+}
+
+/// Writes of a series waiting for the worker: their increments summed up and the last value set.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pending {
+    pub jobs: usize,
+    pub delta: f64,
+    pub set: Option<f64>,
+    sets: usize,
+}
+
+/// Record writes sent to the worker, before sending them so that they're never taken first.
+pub fn queued<'a>(writes: impl IntoIterator<Item = (&'a str, &'a Option<String>, Write)>) {
+    let mut pending = PENDING.lock().unwrap();
+    for (key_name, labels_hash, write) in writes {
+        let series = pending
+            .entry((key_name.to_string(), labels_hash.clone()))
+            .or_default();
+        series.jobs += 1;
+        match write {
+            Write::Add(value) => series.delta += value,
+            Write::Set(value) => {
+                series.sets += 1;
+                series.set = Some(value);
+            }
+        }
+    }
+}
+
+/// Forget writes taken by the worker, or that failed to be sent. The worker takes them in the
+/// order they were queued, a value set stays pending until the last set was taken.
+pub fn taken<'a>(writes: impl IntoIterator<Item = (&'a str, &'a Option<String>, Write)>) {
+    let mut pending = PENDING.lock().unwrap();
+    for (key_name, labels_hash, write) in writes {
+        let series = (key_name.to_string(), labels_hash.clone());
+        // queued before tracking was configured
+        let Some(entry) = pending.get_mut(&series) else {
+            continue;
+        };
+        entry.jobs -= 1;
+        if entry.jobs == 0 {
+            pending.remove(&series);
+            continue;
+        }
+        match write {
+            Write::Add(value) => entry.delta -= value,
+            Write::Set(_) => {
+                entry.sets -= 1;
+                if entry.sets == 0 {
+                    entry.set = None;
+                }
+            }
+        }
+    }
+}
+
+/// Pending writes of a series, `None` when the worker took all of them.
+pub fn get(key_name: &str, labels_hash: &Option<String>) -> Option<Pending> {
+    PENDING
+        .lock()
+        .unwrap()
+        .get(&(key_name.to_string(), labels_hash.clone()))
+        .copied()
+}
+
+/// Forget every pending write, when the workers holding them are gone.
+pub fn clear() {
+    PENDING.lock().unwrap().clear();
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn pending_until_taken() {
+        let labels_hash = Some("{}".to_string());
+        let key_name = "pending_until_taken";
+        let writes = [Write::Add(2.0), Write::Set(5.0), Write::Add(1.0)];
+        queued(writes.map(|write| (key_name, &labels_hash, write)));
+        assert_eq!(
+            get(key_name, &labels_hash),
+            Some(Pending {
+                jobs: 3,
+                delta: 3.0,
+                set: Some(5.0),
+                sets: 1
+            })
+        );
+
+        taken(
+            writes[..2]
+                .iter()
+                .map(|write| (key_name, &labels_hash, *write)),
+        );
+        let pending = get(key_name, &labels_hash).unwrap();
+        assert_eq!((pending.jobs, pending.delta, pending.set), (1, 1.0, None));
+
+        taken([(key_name, &labels_hash, writes[2])]);
+        assert_eq!(get(key_name, &labels_hash), None);
+        assert_eq!(get(key_name, &None), None);
+    }
+}
@@ -0,0 +1,118 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyString, PyType};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// What the core can expect from a backend.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Capabilities {
+    /// Series are shared by the processes using it, like with Redis.
+    pub supports_multiprocess: bool,
+    /// A backend created for a whole histogram or summary records observations with `observe`.
+    pub supports_observe: bool,
+}
+
+#[derive(Debug)]
+struct Plugin {
+    class: Py<PyType>,
+    capabilities: Capabilities,
+}
+
+static PLUGINS: Mutex<BTreeMap<String, Plugin>> = Mutex::new(BTreeMap::new());
+
+fn unknown(name: &str) -> PyErr {
+    PyValueError::new_err(format!("unknown backend: {name}"))
+}
+
+/// Register a backend class under a name, failing when the name is taken unless replacing it.
+pub fn register(
+    name: &str,
+    class: &PyType,
+    capabilities: Capabilities,
+    replace: bool,
+) -> PyResult<()> {
+    let mut plugins = PLUGINS.lock().unwrap();
+    if !replace && plugins.contains_key(name) {
+        return Err(PyValueError::new_err(format!(
+            "backend already registered: {name}"
+        )));
+    }
+    plugins.insert(
+        name.to_string(),
+        Plugin {
+            class: class.into(),
+            capabilities,
+        },
+    );
+    Ok(())
+}
+
+/// Backend class configured either as a class or as the name it was registered under.
+pub fn resolve(backend: &PyAny) -> PyResult<&PyType> {
+    match backend.downcast::<PyString>() {
+        Ok(name) => {
+            backend_class(backend.py(), name.to_str()?).map(|class| class.into_ref(backend.py()))
+        }
+        Err(_) => Ok(backend.downcast()?),
+    }
+}
+
+/// Register a backend implementation, a Rust or Python class taking `(config, metric,
+/// histogram_bucket)`, so that it can be selected by name with `load_backend` or in the config of
+/// the fan-out and parity backends.
+#[pyfunction]
+#[pyo3(signature = (name, backend, supports_multiprocess=false, supports_observe=false, replace=false))]
+pub fn register_backend(
+    name: &str,
+    backend: &PyType,
+    supports_multiprocess: bool,
+    supports_observe: bool,
+    replace: bool,
+) -> PyResult<()> {
+    let capabilities = Capabilities {
+        supports_multiprocess,
+        supports_observe,
+    };
+    register(name, backend, capabilities, replace)
+}
+
+/// Class of a registered backend.
+#[pyfunction]
+pub fn backend_class(py: Python, name: &str) -> PyResult<Py<PyType>> {
+    match PLUGINS.lock().unwrap().get(name) {
+        Some(plugin) => Ok(plugin.class.clone_ref(py)),
+        None => Err(unknown(name)),
+    }
+}
+
+/// Capability flags of a registered backend.
+#[pyfunction]
+pub fn backend_capabilities(py: Python, name: &str) -> PyResult<PyObject> {
+    let capabilities = match PLUGINS.lock().unwrap().get(name) {
+        Some(plugin) => plugin.capabilities,
+        None => return Err(unknown(name)),
+    };
+    let result = PyDict::new(py);
+    result.set_item("supports_multiprocess", capabilities.supports_multiprocess)?;
+    result.set_item("supports_observe", capabilities.supports_observe)?;
+    Ok(result.into())
+}
+
+/// Names of the registered backends, in order.
+#[pyfunction]
+pub fn registered_backends() -> Vec<String> {
+    PLUGINS.lock().unwrap().keys().cloned().collect()
+}
+
+/// Load a registered backend in pytheus, see `pytheus.backends.load_backend`.
+#[pyfunction]
+#[pyo3(signature = (name, config=None))]
+pub fn load_backend(py: Python, name: &str, config: Option<&PyDict>) -> PyResult<()> {
+    let class = backend_class(py, name)?;
+    py.import(intern!(py, "pytheus.backends"))?
+        .getattr(intern!(py, "load_backend"))?
+        .call1((class, config))?;
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What the write worker does with the writes arriving while it's over the
+/// `max_commands_per_second` limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Backpressure {
+    /// Wait for the limit, the writes queued meanwhile are folded into one write per series.
+    #[default]
+    Coalesce,
+    /// Drop them, counted as dropped jobs.
+    Shed,
+}
+
+impl Backpressure {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "coalesce" => Some(Backpressure::Coalesce),
+            "shed" => Some(Backpressure::Shed),
+            _ => None,
+        }
+    }
+}
+
+/// Token bucket of the commands sent by the write workers, holding up to a second of them. The
+/// commands are counted once sent, a pipeline larger than the balance leaves it negative until
+/// it's paid back.
+#[derive(Debug)]
+struct Bucket {
+    balance: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant, rate: f64) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.balance = (self.balance + elapsed.as_secs_f64() * rate).min(rate);
+        self.refilled_at = now;
+    }
+
+    /// How long until the balance is paid back, `None` when it isn't negative.
+    fn delay(&mut self, now: Instant, rate: f64) -> Option<Duration> {
+        self.refill(now, rate);
+        (self.balance < 0.0).then(|| Duration::from_secs_f64(-self.balance / rate))
+    }
+
+    fn spend(&mut self, now: Instant, rate: f64, commands: usize) {
+        self.refill(now, rate);
+        self.balance -= commands as f64;
+    }
+}
+
+// shared by the write workers, the limit is on all the commands of the process
+static BUCKET: Mutex<Option<Bucket>> = Mutex::new(None);
+
+fn with_bucket<T>(rate: u32, f: impl FnOnce(&mut Bucket, f64) -> T) -> T {
+    let mut bucket = BUCKET.lock().unwrap();
+    let bucket = bucket.get_or_insert_with(|| Bucket {
+        balance: rate as f64,
+        refilled_at: Instant::now(),
+    });
+    f(bucket, rate as f64)
+}
+
+/// How long the write workers are over the limit of commands per second, `None` when it isn't
+/// or there's no limit.
+pub fn delay(limit: Option<u32>) -> Option<Duration> {
+    let rate = limit?;
+    with_bucket(rate, |bucket, rate| bucket.delay(Instant::now(), rate))
+}
+
+/// Count commands sent by a write worker against the limit.
+pub fn spend(limit: Option<u32>, commands: usize) {
+    if let Some(rate) = limit {
+        with_bucket(rate, |bucket, rate| {
+            bucket.spend(Instant::now(), rate, commands)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            balance: 100.0,
+            refilled_at: start,
+        };
+        bucket.spend(start, 100.0, 60);
+        assert_eq!(bucket.delay(start, 100.0), None);
+        bucket.spend(start, 100.0, 90);
+        assert_eq!(bucket.delay(start, 100.0), Some(Duration::from_millis(500)));
+
+        // paid back at the rate, and never saving more than a second of commands
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.delay(later, 100.0), None);
+        bucket.refill(later + Duration::from_secs(60), 100.0);
+        assert_eq!(bucket.balance, 100.0);
+    }
+}
@@ -0,0 +1,84 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+// keys tracked by a thread before the ones due again are pruned
+const MAX_TRACKED_KEYS: usize = 65536;
+
+thread_local! {
+    // last sliding expiry added by the thread for every key, the threads writing batches each
+    // track their own
+    static REFRESHED: RefCell<HashMap<String, SystemTime>> = RefCell::new(HashMap::new());
+    // interval of the batch the thread is building, none outside of one
+    static INTERVAL: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// Build a batch throttling the sliding expiries it refreshes to one per key every `interval`,
+/// read once for the whole batch.
+pub fn throttled<T>(interval: Duration, build: impl FnOnce() -> T) -> T {
+    struct Restore(Duration);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            INTERVAL.with(|interval| interval.set(self.0));
+        }
+    }
+    let _restore = Restore(INTERVAL.with(|current| current.replace(interval)));
+    build()
+}
+
+/// Whether the sliding expiry of a key is due again, recording the refresh when it is: the
+/// thread never refreshed the key or did so at least the interval of the batch ago. Always due
+/// outside of a throttled batch.
+pub fn due(key_name: &str, now: SystemTime) -> bool {
+    let interval = INTERVAL.with(Cell::get);
+    if interval.is_zero() {
+        return true;
+    }
+    // a clock going back makes every key due
+    let fresh = |refreshed_at: &SystemTime| {
+        now.duration_since(*refreshed_at)
+            .is_ok_and(|elapsed| elapsed < interval)
+    };
+    REFRESHED.with(|refreshed| {
+        let mut refreshed = refreshed.borrow_mut();
+        if refreshed.get(key_name).is_some_and(fresh) {
+            return false;
+        }
+        if refreshed.len() >= MAX_TRACKED_KEYS {
+            refreshed.retain(|_, refreshed_at| fresh(refreshed_at));
+        }
+        refreshed.insert(key_name.to_string(), now);
+        true
+    })
+}
+
+/// Forget the refreshes of the thread, after a pipeline that failed or a transaction that
+/// aborted and may not have applied them, or that was sent to another endpoint.
+pub fn forget() {
+    REFRESHED.with(|refreshed| refreshed.borrow_mut().clear());
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn refreshed_once_per_interval() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        throttled(Duration::from_secs(60), || {
+            assert!(due("counter", now));
+            assert!(!due("counter", now + Duration::from_secs(59)));
+            assert!(due("gauge", now + Duration::from_secs(59)));
+            assert!(due("counter", now + Duration::from_secs(60)));
+            // the clock went back
+            assert!(due("counter", now));
+
+            throttled(Duration::ZERO, || assert!(due("counter", now)));
+            assert!(!due("counter", now));
+            forget();
+            assert!(due("gauge", now + Duration::from_secs(60)));
+        });
+        assert!(due("gauge", now + Duration::from_secs(60)));
+    }
+}
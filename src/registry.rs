@@ -0,0 +1,129 @@
+use log::warn;
+use pyo3::exceptions::PyKeyError;
+use pyo3::intern;
+use pyo3::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// Collectors by name in registration order, registering and unregistering in `O(log n)`.
+#[derive(Debug)]
+struct Collectors<T> {
+    next: u64,
+    order_by_name: HashMap<String, u64>,
+    ordered: BTreeMap<u64, T>,
+}
+
+impl<T> Default for Collectors<T> {
+    fn default() -> Self {
+        Self {
+            next: 0,
+            order_by_name: HashMap::new(),
+            ordered: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Collectors<T> {
+    /// Whether the collector was registered, `false` when its name is taken.
+    fn register(&mut self, name: String, collector: T) -> bool {
+        if self.order_by_name.contains_key(&name) {
+            return false;
+        }
+        self.order_by_name.insert(name, self.next);
+        self.ordered.insert(self.next, collector);
+        self.next += 1;
+        true
+    }
+
+    fn unregister(&mut self, name: &str) -> Option<T> {
+        let order = self.order_by_name.remove(name)?;
+        self.ordered.remove(&order)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &T> {
+        self.ordered.values()
+    }
+}
+
+/// Registry of collectors usable in place of pytheus' `CollectorRegistry`, for registries too
+/// large for its Python locking and iteration. A collector whose name is already registered is
+/// ignored with a warning like in pytheus.
+#[pyclass]
+pub struct CollectorRegistry {
+    #[pyo3(get, set)]
+    prefix: Option<String>,
+    collectors: Mutex<Collectors<PyObject>>,
+}
+
+#[pymethods]
+impl CollectorRegistry {
+    #[new]
+    #[pyo3(signature = (prefix=None))]
+    fn new(prefix: Option<String>) -> Self {
+        Self {
+            prefix,
+            collectors: Mutex::default(),
+        }
+    }
+
+    fn register(&self, collector: &PyAny) -> PyResult<()> {
+        let name: String = collector
+            .getattr(intern!(collector.py(), "name"))?
+            .extract()?;
+        let registered = self
+            .collectors
+            .lock()
+            .unwrap()
+            .register(name.clone(), collector.into());
+        if !registered {
+            warn!("collector with name '{name}' already registered");
+        }
+        Ok(())
+    }
+
+    fn unregister(&self, collector: &PyAny) -> PyResult<()> {
+        let name: String = collector
+            .getattr(intern!(collector.py(), "name"))?
+            .extract()?;
+        match self.collectors.lock().unwrap().unregister(&name) {
+            Some(_) => Ok(()),
+            None => Err(PyKeyError::new_err(name)),
+        }
+    }
+
+    /// Snapshot of the registered collectors, in registration order: registering while a scrape
+    /// iterates over it is safe.
+    fn collect(&self, py: Python) -> Vec<PyObject> {
+        let collectors = self.collectors.lock().unwrap();
+        collectors
+            .iter()
+            .map(|collector| collector.clone_ref(py))
+            .collect()
+    }
+
+    fn __len__(&self) -> usize {
+        self.collectors.lock().unwrap().ordered.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn registration_order_and_duplicates() {
+        let mut collectors = Collectors::default();
+        assert!(collectors.register("a".to_string(), 1));
+        assert!(collectors.register("b".to_string(), 2));
+        assert!(!collectors.register("a".to_string(), 3));
+        assert!(collectors.register("c".to_string(), 4));
+        assert_eq!(Vec::from_iter(collectors.iter()), [&1, &2, &4]);
+
+        assert_eq!(collectors.unregister("b"), Some(2));
+        assert_eq!(collectors.unregister("b"), None);
+        // a name registered again goes last
+        assert!(collectors.register("b".to_string(), 5));
+        assert_eq!(Vec::from_iter(collectors.iter()), [&1, &4, &5]);
+    }
+}
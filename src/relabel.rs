@@ -0,0 +1,235 @@
+use crate::OutSample;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// labels of the samples within a series, not identity labels to relabel
+const RESERVED_LABELS: [&str; 2] = ["le", "quantile"];
+
+#[derive(Debug, Clone)]
+enum Action {
+    Drop,
+    Rename { target: String },
+    Set { value: String },
+    Replace { regex: Regex, replacement: String },
+}
+
+/// Relabeling of the series of some metrics applied when generating their samples, so that the
+/// labels written by the services can be fixed without redeploying them.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    label: String,
+    action: Action,
+    // every metric when unset
+    metrics: Option<HashSet<String>>,
+}
+
+fn required<'a>(rule: &'a PyDict, key: &str) -> PyResult<&'a PyAny> {
+    rule.get_item(key)
+        .ok_or_else(|| PyValueError::new_err(format!("missing relabel {key}")))
+}
+
+fn check_label(name: &str) -> PyResult<()> {
+    match RESERVED_LABELS.contains(&name) {
+        true => Err(PyValueError::new_err(format!("cannot relabel {name}"))),
+        false => Ok(()),
+    }
+}
+
+impl Rule {
+    /// Rule from the config: `{"action": action, "label": name, "metrics": [names]}` where the
+    /// action is `drop`, `rename` to a `target` label, `set` to a static `value` or `replace` the
+    /// values fully matching a `regex` with a `replacement` that can refer to its groups with
+    /// `$1`. A label left empty is dropped.
+    pub fn from_py(rule: &PyAny) -> PyResult<Self> {
+        let rule: &PyDict = rule.downcast()?;
+        let label: String = required(rule, "label")?.extract()?;
+        check_label(&label)?;
+        let action: &str = required(rule, "action")?.extract()?;
+        let action = match action {
+            "drop" => Action::Drop,
+            "rename" => {
+                let target: String = required(rule, "target")?.extract()?;
+                check_label(&target)?;
+                Action::Rename { target }
+            }
+            "set" => Action::Set {
+                value: required(rule, "value")?.extract()?,
+            },
+            "replace" => {
+                let regex: &str = required(rule, "regex")?.extract()?;
+                // anchored like the relabeling of Prometheus
+                let regex = Regex::new(&format!("^(?:{regex})$")).map_err(|e| {
+                    PyValueError::new_err(format!("invalid relabel regex {regex}: {e}"))
+                })?;
+                Action::Replace {
+                    regex,
+                    replacement: required(rule, "replacement")?.extract()?,
+                }
+            }
+            action => {
+                return Err(PyValueError::new_err(format!(
+                    "unknown relabel action: {action}"
+                )))
+            }
+        };
+        let metrics = match rule.get_item("metrics") {
+            Some(metrics) if !metrics.is_none() => Some(
+                metrics
+                    .iter()?
+                    .map(|name| name?.extract())
+                    .collect::<PyResult<_>>()?,
+            ),
+            _ => None,
+        };
+        Ok(Self {
+            label,
+            action,
+            metrics,
+        })
+    }
+
+    fn apply(&self, labels: &mut BTreeMap<String, String>) {
+        let value = match &self.action {
+            Action::Drop => None,
+            Action::Rename { target } => {
+                if let Some(value) = labels.remove(&self.label) {
+                    labels.insert(target.clone(), value);
+                }
+                return;
+            }
+            Action::Set { value } => Some(value.clone()),
+            Action::Replace { regex, replacement } => {
+                let Some(value) = labels.get(&self.label) else {
+                    return;
+                };
+                if !regex.is_match(value) {
+                    return;
+                }
+                Some(regex.replace(value, replacement.as_str()).into_owned())
+            }
+        };
+        match value.filter(|value| !value.is_empty()) {
+            Some(value) => labels.insert(self.label.clone(), value),
+            None => labels.remove(&self.label),
+        };
+    }
+}
+
+/// Relabel the samples of a metric. Series that end up with the same labels are merged into one,
+/// summing their values.
+pub fn apply(rules: &[Rule], name: &str, samples: &mut Vec<OutSample>) {
+    let rules: Vec<&Rule> = rules
+        .iter()
+        .filter(|rule| {
+            rule.metrics
+                .as_ref()
+                .is_none_or(|metrics| metrics.contains(name))
+        })
+        .collect();
+    if rules.is_empty() {
+        return;
+    }
+
+    let mut relabeled: Vec<OutSample> = Vec::with_capacity(samples.len());
+    // position of every sample by suffix, labels and quantile
+    let mut positions: HashMap<_, usize> = HashMap::new();
+    for mut sample in samples.drain(..) {
+        let mut labels = sample.labels.take().unwrap_or_default();
+        for rule in &rules {
+            rule.apply(&mut labels);
+        }
+        sample.labels = (!labels.is_empty()).then_some(labels);
+
+        let key = (
+            sample.suffix.clone(),
+            sample.labels.clone(),
+            sample.quantile.map(f64::to_bits),
+        );
+        match positions.get(&key) {
+            Some(position) => relabeled[*position].value += sample.value,
+            None => {
+                positions.insert(key, relabeled.len());
+                relabeled.push(sample);
+            }
+        }
+    }
+    *samples = relabeled;
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn rule(label: &str, action: Action) -> Rule {
+        Rule {
+            label: label.to_string(),
+            action,
+            metrics: None,
+        }
+    }
+
+    fn sample(labels: &[(&str, &str)], value: f64) -> OutSample {
+        let labels = labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        OutSample::new("".to_string(), Some(labels), value)
+    }
+
+    #[test]
+    fn relabel_and_merge() {
+        let rules = [
+            rule("pod", Action::Drop),
+            rule(
+                "svc",
+                Action::Rename {
+                    target: "service".to_string(),
+                },
+            ),
+            rule(
+                "path",
+                Action::Replace {
+                    regex: Regex::new(r"^(?:/users/\d+(/.*)?)$").unwrap(),
+                    replacement: "/users/:id$1".to_string(),
+                },
+            ),
+        ];
+        let mut samples = vec![
+            sample(&[("pod", "a"), ("svc", "api"), ("path", "/users/1")], 1.0),
+            sample(&[("pod", "b"), ("svc", "api"), ("path", "/users/2")], 2.0),
+            sample(
+                &[("pod", "a"), ("svc", "api"), ("path", "/users/3/cart")],
+                4.0,
+            ),
+            sample(&[("pod", "a")], 8.0),
+        ];
+        apply(&rules, "requests", &mut samples);
+        assert_eq!(
+            samples,
+            [
+                sample(&[("service", "api"), ("path", "/users/:id")], 3.0),
+                sample(&[("service", "api"), ("path", "/users/:id/cart")], 4.0),
+                OutSample::new("".to_string(), None, 8.0),
+            ]
+        );
+
+        let rules = [Rule {
+            metrics: Some(HashSet::from(["other".to_string()])),
+            ..rule(
+                "pod",
+                Action::Set {
+                    value: "".to_string(),
+                },
+            )
+        }];
+        let mut unchanged = vec![sample(&[("pod", "a")], 1.0)];
+        apply(&rules, "requests", &mut unchanged);
+        assert_eq!(unchanged, [sample(&[("pod", "a")], 1.0)]);
+        apply(&rules, "other", &mut unchanged);
+        assert_eq!(unchanged, [OutSample::new("".to_string(), None, 1.0)]);
+    }
+}
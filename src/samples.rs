@@ -0,0 +1,958 @@
+use crate::{relabel, OutSample};
+use pyo3::exceptions::{PyKeyError, PyValueError};
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyIterator, PyList, PyString};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::iter;
+use std::mem;
+
+/// Name a metric is exposed under instead of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rename {
+    pub name: String,
+    /// Also expose it under its own name, for the transition of the dashboards and alerts.
+    pub keep_original: bool,
+}
+
+impl Rename {
+    /// Rename from the config: the exposed name or `{"name": name, "keep_original": bool}`.
+    pub fn from_py(rename: &PyAny) -> PyResult<Self> {
+        if let Ok(name) = rename.extract() {
+            return Ok(Self {
+                name,
+                keep_original: false,
+            });
+        }
+        let rename: &PyDict = rename.downcast()?;
+        let Some(name) = rename.get_item("name") else {
+            return Err(PyValueError::new_err("missing metric_renames name"));
+        };
+        Ok(Self {
+            name: name.extract()?,
+            keep_original: match rename.get_item("keep_original") {
+                Some(keep_original) => keep_original.extract()?,
+                None => false,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Prometheus,
+    OpenMetrics,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "prometheus" | "text" => Some(Format::Prometheus),
+            "openmetrics" => Some(Format::OpenMetrics),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Prometheus => "prometheus",
+            Format::OpenMetrics => "openmetrics",
+        }
+    }
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', r"\\").replace('\n', r"\n")
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Shortest representation of a float that round-trips, formatted like Python's `repr`.
+fn python_repr(value: f64) -> String {
+    // `{:e}` gives the shortest round-tripping digits, e.g. `-1.5e-7`
+    let scientific = format!("{value:e}");
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+
+    if !(-4..16).contains(&exponent) {
+        let mantissa = match digits.len() {
+            1 => digits,
+            _ => format!("{}.{}", &digits[..1], &digits[1..]),
+        };
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!("{sign}{mantissa}e{exponent_sign}{:02}", exponent.abs())
+    } else if exponent < 0 {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        format!("{sign}0.{zeros}{digits}")
+    } else {
+        let integer_len = exponent as usize + 1;
+        if digits.len() <= integer_len {
+            let zeros = "0".repeat(integer_len - digits.len());
+            format!("{sign}{digits}{zeros}.0")
+        } else {
+            let (integer, fraction) = digits.split_at(integer_len);
+            format!("{sign}{integer}.{fraction}")
+        }
+    }
+}
+
+/// Python's `str` of a float.
+pub fn python_str(value: f64) -> String {
+    if value == f64::INFINITY {
+        "inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-inf".to_string()
+    } else if value.is_nan() {
+        "nan".to_string()
+    } else {
+        python_repr(value)
+    }
+}
+
+/// Prometheus representation of a float, matching `floatToGoString` of the official Python
+/// client so that `le`/`quantile` labels and sample values are identical whichever client
+/// produced them. This includes its `e+0` exponent prefix for large values.
+pub fn float_to_go_string(value: f64) -> String {
+    if value == f64::INFINITY {
+        return "+Inf".to_string();
+    } else if value == f64::NEG_INFINITY {
+        return "-Inf".to_string();
+    } else if value.is_nan() {
+        return "NaN".to_string();
+    }
+
+    let repr = python_repr(value);
+    match repr.find('.') {
+        // Go switches to exponents sooner than Python
+        Some(dot) if value > 0.0 && dot > 6 => {
+            let mantissa = format!("{}.{}{}", &repr[..1], &repr[1..dot], &repr[dot + 1..]);
+            let mantissa = mantissa.trim_end_matches(['0', '.']);
+            format!("{mantissa}e+0{}", dot - 1)
+        }
+        _ => repr,
+    }
+}
+
+/// Canonical form of a bucket bound in the keys and `le` labels. Integral bounds keep the form
+/// the keys were first named with, e.g. `1`, so that the series already stored are still found,
+/// the others are formatted like the Python client.
+pub fn format_bound(bound: f64) -> String {
+    match bound.is_finite() && bound.fract() == 0.0 {
+        true => bound.to_string(),
+        false => float_to_go_string(bound),
+    }
+}
+
+/// Canonical form of a bucket bound given as a string, left untouched if it's not a number.
+pub fn normalize_bound(bound: &str) -> String {
+    match bound.parse::<f64>() {
+        Ok(bound) => format_bound(bound),
+        Err(_) => bound.to_string(),
+    }
+}
+
+/// Sorted bucket bounds of a histogram ending with exactly one `+Inf` bucket, whether or not the
+/// given bounds include it.
+pub fn bucket_bounds(upper_bounds: &[f64]) -> Vec<f64> {
+    let mut bounds: Vec<f64> = upper_bounds
+        .iter()
+        .copied()
+        .filter(|bound| *bound < f64::INFINITY)
+        .collect();
+    bounds.sort_by(f64::total_cmp);
+    bounds.dedup();
+    bounds.push(f64::INFINITY);
+    bounds
+}
+
+// the quantile goes after the identity labels
+fn format_labels(sample: &OutSample) -> String {
+    let mut labels: Vec<String> = sample
+        .labels
+        .iter()
+        .flatten()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape_label_value(value)))
+        .collect();
+    if let Some(quantile) = sample.quantile {
+        labels.push(format!("quantile=\"{}\"", float_to_go_string(quantile)));
+    }
+    match labels.is_empty() {
+        true => String::new(),
+        false => format!("{{{}}}", labels.join(",")),
+    }
+}
+
+// label of the upper bound of histogram buckets
+const BUCKET_LABEL: &str = "le";
+
+/// Labels identifying the series of a sample, the upper bound of a bucket aside.
+fn series_labels(sample: &OutSample) -> impl Iterator<Item = (&String, &String)> {
+    sample
+        .labels
+        .iter()
+        .flatten()
+        .filter(|(name, _)| name.as_str() != BUCKET_LABEL)
+}
+
+/// Position of a sample within its series: its quantile or the numeric upper bound of its bucket.
+fn series_position(sample: &OutSample) -> f64 {
+    sample
+        .quantile
+        .or_else(|| sample.labels.as_ref()?.get(BUCKET_LABEL)?.parse().ok())
+        .unwrap_or(f64::NEG_INFINITY)
+}
+
+/// Samples in exposition order: consecutive samples of the same series sorted by quantile, and
+/// buckets by the numeric value of `le` rather than its string.
+fn ordered_samples(samples: &[OutSample]) -> Vec<&OutSample> {
+    let mut ordered: Vec<&OutSample> = samples.iter().collect();
+    let mut start = 0;
+    while start < ordered.len() {
+        let first = ordered[start];
+        let end = ordered[start..]
+            .iter()
+            .position(|sample| {
+                sample.suffix != first.suffix || !series_labels(sample).eq(series_labels(first))
+            })
+            .map_or(ordered.len(), |len| start + len);
+        ordered[start..end].sort_by(|a, b| series_position(a).total_cmp(&series_position(b)));
+        start = end;
+    }
+    ordered
+}
+
+/// Samples of one collector along with the metadata needed to expose them.
+#[derive(Debug, Clone)]
+#[pyclass]
+pub struct SampleFamily {
+    #[pyo3(get)]
+    pub name: String,
+    #[pyo3(get)]
+    pub type_: String,
+    #[pyo3(get)]
+    pub help: String,
+    #[pyo3(get)]
+    pub unit: Option<String>,
+    #[pyo3(get)]
+    pub(crate) samples: Vec<OutSample>,
+    /// Other names the family is also exposed under, e.g. its former name during a renaming.
+    #[pyo3(get)]
+    pub aliases: Vec<String>,
+}
+
+impl SampleFamily {
+    /// Every name the family is exposed under, its own first.
+    fn names(&self) -> impl Iterator<Item = &String> {
+        iter::once(&self.name).chain(&self.aliases)
+    }
+
+    /// Family name as OpenMetrics requires it: counters without the `_total` their samples carry
+    /// and suffixed by the unit.
+    fn openmetrics_name(&self, name: &str) -> String {
+        let mut name = match self.type_.as_str() {
+            "counter" => name.strip_suffix("_total").unwrap_or(name),
+            _ => name,
+        }
+        .to_string();
+        if let Some(unit) = &self.unit {
+            if !name.ends_with(&format!("_{unit}")) {
+                name = format!("{name}_{unit}");
+            }
+        }
+        name
+    }
+
+    fn render(&self, format: Format, output: &mut String) {
+        for name in self.names() {
+            self.render_as(name, format, output);
+        }
+    }
+
+    fn render_as(&self, name: &str, format: Format, output: &mut String) {
+        let family_name = match format {
+            Format::Prometheus => name.to_string(),
+            Format::OpenMetrics => self.openmetrics_name(name),
+        };
+        let counter_total = format == Format::OpenMetrics && self.type_ == "counter";
+
+        let _ = writeln!(output, "# HELP {family_name} {}", escape_help(&self.help));
+        let _ = writeln!(output, "# TYPE {family_name} {}", self.type_);
+        if let (Format::OpenMetrics, Some(unit)) = (format, &self.unit) {
+            let _ = writeln!(output, "# UNIT {family_name} {unit}");
+        }
+        for sample in ordered_samples(&self.samples) {
+            let suffix = match sample.suffix.as_str() {
+                "" if counter_total => "_total",
+                suffix => suffix,
+            };
+            let _ = writeln!(
+                output,
+                "{family_name}{suffix}{} {}",
+                format_labels(sample),
+                float_to_go_string(sample.value)
+            );
+        }
+    }
+}
+
+// collector left out, by name when it could be read, with the reason
+type CollectorError = (Option<String>, String);
+
+/// Result of `_generate_samples`: the samples of every collector of a registry with their
+/// metadata. Indexing by collector keeps it usable wherever pytheus expects a dict of samples.
+#[derive(Debug, Default)]
+#[pyclass]
+pub struct SampleSet {
+    collectors: Vec<PyObject>,
+    families: Vec<SampleFamily>,
+    errors: Vec<CollectorError>,
+}
+
+impl SampleSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an empty family for the collector, reading its metadata. The unit set on the collector
+    /// takes precedence over the given one.
+    pub fn push(&mut self, collector: &PyAny, unit: Option<String>) -> PyResult<()> {
+        let py = collector.py();
+        let unit = match collector.getattr(intern!(py, "unit")) {
+            Ok(collector_unit) if !collector_unit.is_none() => Some(collector_unit.extract()?),
+            _ => unit,
+        };
+        self.families.push(SampleFamily {
+            name: collector.getattr(intern!(py, "name"))?.extract()?,
+            type_: collector.getattr(intern!(py, "type_"))?.extract()?,
+            help: collector.getattr(intern!(py, "description"))?.extract()?,
+            unit,
+            samples: vec![],
+            aliases: vec![],
+        });
+        self.collectors.push(collector.into());
+        Ok(())
+    }
+
+    /// Leave out a collector whose samples couldn't be generated.
+    pub(crate) fn remove(&mut self, index: usize) -> SampleFamily {
+        self.collectors.remove(index);
+        self.families.remove(index)
+    }
+
+    pub(crate) fn push_error(&mut self, collector: Option<String>, error: String) {
+        self.errors.push((collector, error));
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (&PyObject, &mut Vec<OutSample>)> {
+        self.collectors
+            .iter()
+            .zip(self.families.iter_mut().map(|family| &mut family.samples))
+    }
+
+    /// Leave out the series of the metrics that were never written: counters at zero, histograms
+    /// and summaries without observations. Gauges are kept, zero is a value they are set to.
+    pub(crate) fn omit_zero_series(&mut self, names: &HashSet<String>) {
+        for family in &mut self.families {
+            if !names.contains(&family.name) {
+                continue;
+            }
+            let total_suffix = match family.type_.as_str() {
+                "counter" => "",
+                "histogram" | "summary" => "_count",
+                _ => continue,
+            };
+            let zero: HashSet<Vec<(&String, &String)>> = family
+                .samples
+                .iter()
+                .filter(|sample| sample.suffix == total_suffix && sample.value == 0.0)
+                .map(|sample| series_labels(sample).collect())
+                .collect();
+            if zero.is_empty() {
+                continue;
+            }
+            let kept: Vec<bool> = family
+                .samples
+                .iter()
+                .map(|sample| !zero.contains(&series_labels(sample).collect::<Vec<_>>()))
+                .collect();
+            let mut kept = kept.into_iter();
+            family.samples.retain(|_| kept.next().unwrap());
+        }
+    }
+
+    pub(crate) fn relabel(&mut self, rules: &[relabel::Rule]) {
+        if rules.is_empty() {
+            return;
+        }
+        for family in &mut self.families {
+            relabel::apply(rules, &family.name, &mut family.samples);
+        }
+    }
+
+    /// Expose the families under the name they are mapped to, also keeping their own name as an
+    /// alias for the ones configured so.
+    pub(crate) fn rename(&mut self, renames: &HashMap<String, Rename>) {
+        for family in &mut self.families {
+            let Some(rename) = renames.get(&family.name) else {
+                continue;
+            };
+            let name = mem::replace(&mut family.name, rename.name.clone());
+            if rename.keep_original {
+                family.aliases.push(name);
+            }
+        }
+    }
+
+    /// Prepend the prefix to every name the families are exposed under.
+    pub(crate) fn prefix_names(&mut self, prefix: &str) {
+        for family in &mut self.families {
+            for name in iter::once(&mut family.name).chain(&mut family.aliases) {
+                name.insert_str(0, prefix);
+            }
+        }
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        let mut output = String::new();
+        for family in &self.families {
+            family.render(format, &mut output);
+        }
+        if format == Format::OpenMetrics {
+            output.push_str("# EOF\n");
+        }
+        output
+    }
+
+    /// Series of every sample as exposed in the Prometheus format, their labels naming them with
+    /// `__name__`, e.g. for the remote write protocol.
+    pub fn timeseries(&self) -> Vec<(BTreeMap<String, String>, f64)> {
+        self.families
+            .iter()
+            .flat_map(|family| {
+                family.names().flat_map(|name| {
+                    family.samples.iter().map(move |sample| {
+                        let mut labels = sample.exposed_labels().unwrap_or_default();
+                        labels.insert("__name__".to_string(), format!("{name}{}", sample.suffix));
+                        (labels, sample.value)
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// Every sample with its collector, in order, and the errors.
+    fn into_pairs(self) -> (Vec<(PyObject, OutSample)>, Vec<CollectorError>) {
+        let pairs = self
+            .collectors
+            .into_iter()
+            .zip(self.families)
+            .flat_map(|(collector, family)| {
+                family
+                    .samples
+                    .into_iter()
+                    .map(move |sample| (collector.clone(), sample))
+            })
+            .collect();
+        (pairs, self.errors)
+    }
+
+    /// Index of the family for a collector, or for a metric name when given a string.
+    fn position(&self, key: &PyAny) -> Option<usize> {
+        if let Ok(name) = key.downcast::<PyString>() {
+            let name = name.to_str().ok()?;
+            return self
+                .families
+                .iter()
+                .position(|family| family.names().any(|other| other == name));
+        }
+        let py = key.py();
+        self.collectors
+            .iter()
+            .position(|collector| collector.as_ref(py).is(key))
+    }
+}
+
+#[pymethods]
+impl SampleSet {
+    fn __len__(&self) -> usize {
+        self.families.len()
+    }
+
+    fn __iter__(&self, py: Python) -> PyResult<Py<PyIterator>> {
+        let collectors = PyList::new(py, &self.collectors);
+        Ok(PyIterator::from_object(py, collectors)?.into())
+    }
+
+    fn __contains__(&self, key: &PyAny) -> bool {
+        self.position(key).is_some()
+    }
+
+    fn __getitem__(&self, key: &PyAny) -> PyResult<Vec<OutSample>> {
+        match self.position(key) {
+            Some(index) => Ok(self.families[index].samples.clone()),
+            None => Err(PyKeyError::new_err(key.into_py(key.py()))),
+        }
+    }
+
+    #[pyo3(signature = (key, default=None))]
+    fn get(&self, py: Python, key: &PyAny, default: Option<PyObject>) -> PyObject {
+        match self.position(key) {
+            Some(index) => self.families[index].samples.clone().into_py(py),
+            None => default.unwrap_or_else(|| py.None()),
+        }
+    }
+
+    fn keys(&self) -> Vec<PyObject> {
+        self.collectors.clone()
+    }
+
+    fn values(&self) -> Vec<Vec<OutSample>> {
+        self.families
+            .iter()
+            .map(|family| family.samples.clone())
+            .collect()
+    }
+
+    fn items(&self) -> Vec<(PyObject, Vec<OutSample>)> {
+        self.collectors.iter().cloned().zip(self.values()).collect()
+    }
+
+    /// Collectors left out because generating their samples failed, as `{"collector", "error"}`
+    /// dicts, the collector being `None` when even its name couldn't be read.
+    #[getter]
+    fn errors(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        errors_to_py(py, &self.errors)
+    }
+
+    fn families(&self) -> Vec<SampleFamily> {
+        self.families.clone()
+    }
+
+    fn family(&self, name: &str) -> Option<SampleFamily> {
+        self.families
+            .iter()
+            .find(|family| family.names().any(|other| other == name))
+            .cloned()
+    }
+
+    /// Render the samples in the Prometheus text format (`prometheus`, alias `text`) or in the
+    /// OpenMetrics one (`openmetrics`).
+    #[pyo3(name = "render", signature = (format="prometheus"))]
+    fn py_render(&self, format: &str) -> PyResult<String> {
+        match Format::parse(format) {
+            Some(format) => Ok(self.render(format)),
+            None => Err(PyValueError::new_err(format!("unknown format: {format}"))),
+        }
+    }
+}
+
+fn errors_to_py(py: Python, errors: &[CollectorError]) -> PyResult<Vec<PyObject>> {
+    errors
+        .iter()
+        .map(|(collector, error)| {
+            let dict = PyDict::new(py);
+            dict.set_item("collector", collector)?;
+            dict.set_item("error", error)?;
+            Ok(dict.into())
+        })
+        .collect()
+}
+
+/// Samples of a chunk of collectors of the registry.
+pub type GenerateChunk = Box<dyn Fn(Python, &PyList) -> PyResult<SampleSet> + Send>;
+
+/// Lazy iterator of `(collector, OutSample)` pairs, reading the collectors of the registry a
+/// chunk at a time as the pairs are consumed, so that only one chunk is held in memory.
+#[pyclass]
+pub struct SampleIter {
+    collectors: Py<PyIterator>,
+    chunk_size: usize,
+    generate: GenerateChunk,
+    pairs: VecDeque<(PyObject, OutSample)>,
+    errors: Vec<CollectorError>,
+    exhausted: bool,
+}
+
+impl SampleIter {
+    pub fn new(collectors: &PyIterator, chunk_size: usize, generate: GenerateChunk) -> Self {
+        Self {
+            collectors: collectors.into(),
+            chunk_size,
+            generate,
+            pairs: VecDeque::new(),
+            errors: vec![],
+            exhausted: false,
+        }
+    }
+
+    // next collectors of the registry, the iteration stops at the first error like a scrape
+    fn next_chunk<'py>(&mut self, py: Python<'py>) -> &'py PyList {
+        let chunk = PyList::empty(py);
+        let mut collectors = self.collectors.as_ref(py);
+        while !self.exhausted && chunk.len() < self.chunk_size {
+            match collectors.next() {
+                Some(Ok(collector)) => chunk.append(collector).unwrap(),
+                Some(Err(e)) => {
+                    self.errors.push((None, e.to_string()));
+                    self.exhausted = true;
+                }
+                None => self.exhausted = true,
+            }
+        }
+        chunk
+    }
+}
+
+#[pymethods]
+impl SampleIter {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python) -> PyResult<Option<(PyObject, OutSample)>> {
+        while self.pairs.is_empty() {
+            let chunk = self.next_chunk(py);
+            if chunk.is_empty() {
+                return Ok(None);
+            }
+            let (pairs, errors) = (self.generate)(py, chunk)?.into_pairs();
+            self.pairs.extend(pairs);
+            self.errors.extend(errors);
+        }
+        Ok(self.pairs.pop_front())
+    }
+
+    /// Collectors left out so far, see `SampleSet.errors`.
+    #[getter]
+    fn errors(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        errors_to_py(py, &self.errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn counter() -> SampleFamily {
+        SampleFamily {
+            name: "requests".to_string(),
+            type_: "counter".to_string(),
+            help: "with \\ and\nnewline".to_string(),
+            unit: None,
+            aliases: vec![],
+            samples: vec![
+                OutSample::new("".to_string(), None, 1.0),
+                OutSample::new(
+                    "".to_string(),
+                    Some(BTreeMap::from([("path".to_string(), "a\"b".to_string())])),
+                    f64::INFINITY,
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn render_prometheus() {
+        let mut output = String::new();
+        counter().render(Format::Prometheus, &mut output);
+        assert_eq!(
+            output,
+            "# HELP requests with \\\\ and\\nnewline\n\
+             # TYPE requests counter\n\
+             requests 1.0\n\
+             requests{path=\"a\\\"b\"} +Inf\n"
+        );
+    }
+
+    #[test]
+    fn render_openmetrics() {
+        let set = SampleSet {
+            families: vec![counter()],
+            ..Default::default()
+        };
+        let output = set.render(Format::OpenMetrics);
+        assert!(output.starts_with("# HELP requests "));
+        assert!(output.contains("\nrequests_total 1.0\n"));
+        assert!(output.ends_with("+Inf\n# EOF\n"));
+    }
+
+    #[test]
+    fn rename() {
+        let mut set = SampleSet {
+            families: vec![counter()],
+            ..Default::default()
+        };
+        let renames = HashMap::from([(
+            "requests".to_string(),
+            Rename {
+                name: "http_requests".to_string(),
+                keep_original: true,
+            },
+        )]);
+        set.rename(&renames);
+        let output = set.render(Format::Prometheus);
+        assert!(output.starts_with("# HELP http_requests "));
+        assert!(output.contains("\nhttp_requests 1.0\n"));
+        assert!(output.contains("+Inf\n# HELP requests "));
+        assert!(output.ends_with("requests{path=\"a\\\"b\"} +Inf\n"));
+        assert_eq!(set.timeseries().len(), 4);
+    }
+
+    #[test]
+    fn omit_zero_series() {
+        let series = |path: &str, le: &str, value: f64| {
+            let labels = BTreeMap::from([
+                ("path".to_string(), path.to_string()),
+                ("le".to_string(), le.to_string()),
+            ]);
+            OutSample::new("_bucket".to_string(), Some(labels), value)
+        };
+        let total = |path: &str, suffix: &str, value: f64| {
+            let labels = BTreeMap::from([("path".to_string(), path.to_string())]);
+            OutSample::new(suffix.to_string(), Some(labels), value)
+        };
+        let mut set = SampleSet {
+            families: vec![SampleFamily {
+                name: "latency".to_string(),
+                type_: "histogram".to_string(),
+                help: "desc".to_string(),
+                unit: None,
+                aliases: vec![],
+                samples: vec![
+                    series("/", "1.0", 0.0),
+                    series("/", "+Inf", 1.0),
+                    total("/", "_count", 1.0),
+                    total("/", "_sum", 2.0),
+                    series("/idle", "1.0", 0.0),
+                    series("/idle", "+Inf", 0.0),
+                    total("/idle", "_count", 0.0),
+                    total("/idle", "_sum", 0.0),
+                ],
+            }],
+            ..Default::default()
+        };
+        set.omit_zero_series(&HashSet::from(["other".to_string()]));
+        assert_eq!(set.families[0].samples.len(), 8);
+        set.omit_zero_series(&HashSet::from(["latency".to_string()]));
+        assert_eq!(
+            set.families[0].samples,
+            [
+                series("/", "1.0", 0.0),
+                series("/", "+Inf", 1.0),
+                total("/", "_count", 1.0),
+                total("/", "_sum", 2.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn render_unit() {
+        let family = SampleFamily {
+            name: "request_duration".to_string(),
+            type_: "counter".to_string(),
+            help: "desc".to_string(),
+            unit: Some("seconds".to_string()),
+            aliases: vec![],
+            samples: vec![OutSample::new("".to_string(), None, 1.5)],
+        };
+
+        let mut output = String::new();
+        family.render(Format::OpenMetrics, &mut output);
+        assert_eq!(
+            output,
+            "# HELP request_duration_seconds desc\n\
+             # TYPE request_duration_seconds counter\n\
+             # UNIT request_duration_seconds seconds\n\
+             request_duration_seconds_total 1.5\n"
+        );
+
+        let mut output = String::new();
+        family.render(Format::Prometheus, &mut output);
+        assert!(!output.contains("# UNIT"));
+        assert!(output.contains("\nrequest_duration 1.5\n"));
+    }
+
+    #[test]
+    fn go_string() {
+        let corpus = [
+            (1.0, "1.0"),
+            (0.1, "0.1"),
+            (0.1 + 0.2, "0.30000000000000004"),
+            (2.5, "2.5"),
+            (123456.0, "123456.0"),
+            (1e6, "1e+06"),
+            (1234567.5, "1.2345675e+06"),
+            (1e10, "1e+010"),
+            (123456789.0, "1.23456789e+08"),
+            (1e15, "1e+015"),
+            (1e16, "1e+16"),
+            (1e-5, "1e-05"),
+            (0.0001, "0.0001"),
+            (0.0, "0.0"),
+            (-0.0, "-0.0"),
+            (-1e7, "-10000000.0"),
+            (5e-324, "5e-324"),
+            (f64::MAX, "1.7976931348623157e+308"),
+            (f64::INFINITY, "+Inf"),
+            (f64::NEG_INFINITY, "-Inf"),
+            (f64::NAN, "NaN"),
+        ];
+        for (value, expected) in corpus {
+            assert_eq!(float_to_go_string(value), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn normalized_bounds() {
+        assert_eq!(normalize_bound("1"), "1");
+        assert_eq!(normalize_bound("1.0"), "1");
+        assert_eq!(normalize_bound("0.00001"), "1e-05");
+        assert_eq!(normalize_bound("+Inf"), "+Inf");
+        assert_eq!(
+            normalize_bound("0.30000000000000004"),
+            "0.30000000000000004"
+        );
+        assert_eq!(normalize_bound("bob"), "bob");
+        // the bucket keys of the pytheus layout are read back as the same le
+        assert_eq!(normalize_bound(&python_str(f64::INFINITY)), "+Inf");
+        assert_eq!(normalize_bound(&python_str(2.5e6)), "2500000");
+    }
+
+    #[test]
+    fn python_str_of_bounds() {
+        assert_eq!(python_str(1.0), "1.0");
+        assert_eq!(python_str(2.5e6), "2500000.0");
+        assert_eq!(python_str(1e16), "1e+16");
+        assert_eq!(python_str(f64::INFINITY), "inf");
+    }
+
+    #[test]
+    fn inf_bucket() {
+        assert_eq!(
+            bucket_bounds(&[2.0, 1.0, f64::INFINITY]),
+            [1.0, 2.0, f64::INFINITY]
+        );
+        assert_eq!(bucket_bounds(&[1.0]), [1.0, f64::INFINITY]);
+        assert_eq!(bucket_bounds(&[]), [f64::INFINITY]);
+        assert_eq!(
+            float_to_go_string(*bucket_bounds(&[]).last().unwrap()),
+            "+Inf"
+        );
+    }
+
+    #[test]
+    fn quantiles() {
+        let sample = |quantile: &str, value: f64| {
+            let labels = BTreeMap::from([
+                ("quantile".to_string(), quantile.to_string()),
+                ("zone".to_string(), "a".to_string()),
+            ]);
+            OutSample::new("".to_string(), Some(labels), value)
+        };
+        let family = SampleFamily {
+            name: "latency".to_string(),
+            type_: "summary".to_string(),
+            help: "desc".to_string(),
+            unit: None,
+            aliases: vec![],
+            samples: vec![
+                sample("0.99", 3.0),
+                sample("0.50", 1.0),
+                sample("0.9", 2.0),
+                OutSample::new("_count".to_string(), None, 3.0),
+            ],
+        };
+        assert_eq!(family.samples[1].quantile, Some(0.5));
+
+        let mut output = String::new();
+        family.render(Format::Prometheus, &mut output);
+        assert_eq!(
+            output,
+            "# HELP latency desc\n\
+             # TYPE latency summary\n\
+             latency{zone=\"a\",quantile=\"0.5\"} 1.0\n\
+             latency{zone=\"a\",quantile=\"0.9\"} 2.0\n\
+             latency{zone=\"a\",quantile=\"0.99\"} 3.0\n\
+             latency_count 3.0\n"
+        );
+    }
+
+    #[test]
+    fn numeric_bucket_order() {
+        let bucket = |zone: &str, le: &str, value| {
+            let labels = [("zone", zone), ("le", le)]
+                .map(|(name, value)| (name.to_string(), value.to_string()));
+            OutSample::new("_bucket".to_string(), Some(BTreeMap::from(labels)), value)
+        };
+        let family = SampleFamily {
+            name: "latency".to_string(),
+            type_: "histogram".to_string(),
+            help: "desc".to_string(),
+            unit: None,
+            aliases: vec![],
+            samples: vec![
+                bucket("a", "10.0", 3.0),
+                bucket("a", "+Inf", 4.0),
+                bucket("a", "2.5", 2.0),
+                bucket("a", "0.5", 1.0),
+                bucket("b", "1.0", 5.0),
+                bucket("b", "0.1", 0.0),
+                OutSample::new("_count".to_string(), None, 9.0),
+            ],
+        };
+        let mut output = String::new();
+        family.render(Format::Prometheus, &mut output);
+        let samples: Vec<&str> = output.lines().skip(2).collect();
+        assert_eq!(
+            samples,
+            [
+                "latency_bucket{le=\"0.5\",zone=\"a\"} 1.0",
+                "latency_bucket{le=\"2.5\",zone=\"a\"} 2.0",
+                "latency_bucket{le=\"10.0\",zone=\"a\"} 3.0",
+                "latency_bucket{le=\"+Inf\",zone=\"a\"} 4.0",
+                "latency_bucket{le=\"0.1\",zone=\"b\"} 0.0",
+                "latency_bucket{le=\"1.0\",zone=\"b\"} 5.0",
+                "latency_count 9.0",
+            ]
+        );
+    }
+
+    #[test]
+    fn sample_values() {
+        let family = SampleFamily {
+            name: "bytes".to_string(),
+            type_: "gauge".to_string(),
+            help: "desc".to_string(),
+            unit: None,
+            aliases: vec![],
+            samples: [123456789.0, 1e-7, -2.5e20, f64::NAN, f64::NEG_INFINITY]
+                .into_iter()
+                .map(|value| OutSample::new("".to_string(), None, value))
+                .collect(),
+        };
+        let mut output = String::new();
+        family.render(Format::Prometheus, &mut output);
+        let values: Vec<&str> = output
+            .lines()
+            .skip(2)
+            .map(|line| line.rsplit_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(
+            values,
+            ["1.23456789e+08", "1e-07", "-2.5e+20", "NaN", "-Inf"]
+        );
+    }
+}
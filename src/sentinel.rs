@@ -0,0 +1,131 @@
+use crate::connection::{Connection, SocketOptions};
+use log::warn;
+use redis::{ConnectionLike, ErrorKind, RedisError, RedisResult, Value};
+use rustls::ClientConfig;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+// an unreachable sentinel shouldn't hold the connection up, the next one is asked
+const SENTINEL_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Sentinels monitoring the master the default endpoint connects to, asked for its address by
+/// every new connection so that a failover is followed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sentinel {
+    pub sentinels: Vec<(String, u16)>,
+    pub service_name: String,
+}
+
+impl Sentinel {
+    fn ask(
+        &self,
+        host: &str,
+        port: u16,
+        options: &SocketOptions,
+        tls: Option<&Arc<ClientConfig>>,
+    ) -> RedisResult<Option<(String, u16)>> {
+        let options = SocketOptions {
+            connect_timeout: Some(SENTINEL_CONNECT_TIMEOUT),
+            ..*options
+        };
+        let mut connection = Connection::open(host, port, &options, tls)?;
+        let master: Option<(String, String)> = redis::cmd("SENTINEL")
+            .arg("get-master-addr-by-name")
+            .arg(&self.service_name)
+            .query(&mut connection)?;
+        let Some((host, port)) = master else {
+            return Ok(None);
+        };
+        match port.parse() {
+            Ok(port) => Ok(Some((host, port))),
+            Err(_) => Err(RedisError::from((
+                ErrorKind::TypeError,
+                "invalid master port",
+                port,
+            ))),
+        }
+    }
+
+    /// Host and port of the current master, from the first sentinel that knows it.
+    pub fn master(
+        &self,
+        options: &SocketOptions,
+        tls: Option<&Arc<ClientConfig>>,
+    ) -> RedisResult<(String, u16)> {
+        for (host, port) in &self.sentinels {
+            match self.ask(host, *port, options, tls) {
+                Ok(Some(master)) => return Ok(master),
+                Ok(None) => warn!("sentinel {host}:{port} doesn't know {}", self.service_name),
+                Err(e) => warn!("sentinel {host}:{port} failed: {e}"),
+            }
+        }
+        // failing like an unreachable endpoint
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no sentinel knows the master {}", self.service_name),
+        )
+        .into())
+    }
+}
+
+/// Whether a connection is still to a master, the former one is demoted to a replica by a
+/// failover.
+pub fn is_master(connection: &mut dyn ConnectionLike) -> RedisResult<bool> {
+    let role: Value = redis::cmd("ROLE").query(connection)?;
+    Ok(match role {
+        Value::Bulk(role) => matches!(role.first(), Some(Value::Data(role)) if role == b"master"),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // sentinel answering one request with a reply
+    fn sentinel(reply: &'static [u8]) -> (u16, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream.write_all(reply).unwrap();
+        });
+        (port, server)
+    }
+
+    #[test]
+    fn master_discovery() {
+        let unreachable = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (unaware, unaware_server) = sentinel(b"*-1\r\n");
+        let (aware, aware_server) = sentinel(b"*2\r\n$8\r\n10.0.0.2\r\n$4\r\n6380\r\n");
+        let sentinel = Sentinel {
+            sentinels: [unreachable, unaware, aware]
+                .into_iter()
+                .map(|port| ("127.0.0.1".to_string(), port))
+                .collect(),
+            service_name: "metrics".to_string(),
+        };
+        let master = sentinel.master(&SocketOptions::default(), None).unwrap();
+        assert_eq!(master, ("10.0.0.2".to_string(), 6380));
+        unaware_server.join().unwrap();
+        aware_server.join().unwrap();
+
+        let nobody = Sentinel {
+            sentinels: vec![("127.0.0.1".to_string(), unreachable)],
+            ..sentinel
+        };
+        let err = nobody.master(&SocketOptions::default(), None).unwrap_err();
+        assert!(err.is_io_error());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::clock::unix_timestamp;
+use serde_json::{json, Value};
+
+/// How values are stored in Redis.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ValueSerializer {
+    /// Plain float string, incremented in place by Redis.
+    #[default]
+    Float,
+    /// JSON `[value, timestamp]` pair recording when the value was last written. Redis can't
+    /// increment it, so updates are applied with a read-modify-write transaction.
+    FloatTimestamp,
+}
+
+impl ValueSerializer {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "float" => Some(ValueSerializer::Float),
+            "float_timestamp" => Some(ValueSerializer::FloatTimestamp),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, value: f64) -> String {
+        match self {
+            ValueSerializer::Float => value.to_string(),
+            ValueSerializer::FloatTimestamp => json!([value, unix_timestamp()]).to_string(),
+        }
+    }
+}
+
+/// Decode a stored value whichever serializer wrote it, so that keys written before a change of
+/// serializer stay readable.
+pub fn decode(raw: &str) -> Option<f64> {
+    if let Ok(value) = raw.parse::<f64>() {
+        return Some(value);
+    }
+    match serde_json::from_str(raw).ok()? {
+        Value::Array(items) => items.first()?.as_f64(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for serializer in [ValueSerializer::Float, ValueSerializer::FloatTimestamp] {
+            assert_eq!(decode(&serializer.encode(2.7)), Some(2.7));
+            assert_eq!(decode(&serializer.encode(-1.0)), Some(-1.0));
+        }
+        assert_eq!(decode("bob"), None);
+        assert_eq!(decode("{}"), None);
+    }
+
+    #[test]
+    fn float_timestamp_layout() {
+        let encoded: Value =
+            serde_json::from_str(&ValueSerializer::FloatTimestamp.encode(1.5)).unwrap();
+        let items = encoded.as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert!(items[1].as_f64().unwrap() > 0.0);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::keys::fnv1a;
+
+// points of each endpoint on the ring, enough to spread the metrics evenly
+const POINTS_PER_ENDPOINT: usize = 160;
+
+/// Consistent hashing of metric names over endpoints: adding or removing an endpoint only moves
+/// the metrics of its share of the ring.
+#[derive(Debug, Default)]
+pub struct HashRing {
+    // sorted by position, with the index of the endpoint owning the point
+    points: Vec<(u64, usize)>,
+}
+
+// FNV-1a of similar inputs only differ in the low bits, the finalizer of MurmurHash3 spreads them
+fn position(bytes: &[u8]) -> u64 {
+    let mut hash = fnv1a(bytes);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+impl HashRing {
+    /// Ring of the endpoints identified by `host:port`, with the index each one is routed to.
+    pub fn new(endpoints: &[(&str, u16, usize)]) -> Self {
+        let mut points: Vec<(u64, usize)> = endpoints
+            .iter()
+            .flat_map(|(host, port, index)| {
+                (0..POINTS_PER_ENDPOINT).map(move |point| {
+                    (
+                        position(format!("{host}:{port}-{point}").as_bytes()),
+                        *index,
+                    )
+                })
+            })
+            .collect();
+        points.sort_unstable();
+        Self { points }
+    }
+
+    /// Index of the endpoint owning a metric, `None` on an empty ring.
+    pub fn get(&self, name: &str) -> Option<usize> {
+        let hash = position(name.as_bytes());
+        let point = self
+            .points
+            .partition_point(|(position, _)| *position < hash);
+        // past the last point wraps around to the first
+        self.points
+            .get(point)
+            .or(self.points.first())
+            .map(|(_, index)| *index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn consistent_and_balanced() {
+        let names: Vec<String> = (0..3000).map(|i| format!("metric_{i}")).collect();
+        let two = HashRing::new(&[("a", 6379, 0), ("b", 6379, 1)]);
+        let three = HashRing::new(&[("a", 6379, 0), ("b", 6379, 1), ("c", 6379, 2)]);
+
+        let mut counts = [0; 3];
+        for name in &names {
+            let index = three.get(name).unwrap();
+            counts[index] += 1;
+            // adding c only moves metrics to c
+            if index != 2 {
+                assert_eq!(two.get(name), Some(index));
+            }
+        }
+        assert!(counts.iter().all(|count| *count > 700), "{counts:?}");
+        assert_eq!(HashRing::default().get("metric"), None);
+    }
+}
@@ -0,0 +1,54 @@
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+
+/// Series of a collector: its labeled children, or the metric itself when it has no labels.
+pub fn series(collector: &PyAny) -> PyResult<Vec<&PyAny>> {
+    let py = collector.py();
+    if collector
+        .getattr(intern!(py, "_required_labels"))?
+        .is_true()?
+    {
+        let children: &PyDict = collector
+            .getattr(intern!(py, "_labeled_metrics"))?
+            .downcast()?;
+        return Ok(children.values().iter().collect());
+    }
+    let metric = collector.getattr(intern!(py, "_metric"))?;
+    Ok(match metric.is_none() {
+        true => vec![],
+        false => vec![metric],
+    })
+}
+
+/// State of every series of a registry as held in process memory by their backends, by metric
+/// name, without reaching the store: e.g. logged by a crashing process to see what was about to
+/// be written. Each series is described by the `_snapshot` of its backend when it has one, along
+/// with the name of the backend class and the labels of the series.
+pub fn registry_snapshot(registry: &PyAny) -> PyResult<PyObject> {
+    let py = registry.py();
+    let result = PyDict::new(py);
+    for collector in registry.call_method0(intern!(py, "collect"))?.iter()? {
+        let collector = collector?;
+        let entries = PyList::empty(py);
+        for child in series(collector)? {
+            let Ok(backend) = child.getattr(intern!(py, "_metric_value_backend")) else {
+                continue;
+            };
+            if backend.is_none() {
+                continue;
+            }
+            let entry = match backend.hasattr(intern!(py, "_snapshot"))? {
+                true => backend.call_method0(intern!(py, "_snapshot"))?.downcast()?,
+                false => PyDict::new(py),
+            };
+            entry.set_item("backend", backend.get_type().name()?)?;
+            if !entry.contains("labels")? {
+                entry.set_item("labels", child.getattr(intern!(py, "_labels")).ok())?;
+            }
+            entries.append(entry)?;
+        }
+        result.set_item(collector.getattr(intern!(py, "name"))?, entries)?;
+    }
+    Ok(result.into())
+}
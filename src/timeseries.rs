@@ -0,0 +1,147 @@
+use crate::batch::{KeyWrites, SeriesWrite};
+use crate::{add_expire_to_pipeline, redis_key};
+use redis::Value;
+use std::time::Duration;
+
+// labels of every time series, finding the series of a key with TS.MGET
+const KEY_LABEL: &str = "__key__";
+const SERIES_LABEL: &str = "__series__";
+
+/// Time series of one series of a key, by hash field (`None` for unlabeled keys).
+pub fn series_key(key_name: &str, field: Option<&str>) -> String {
+    redis_key(format!("{key_name}:ts:{}", field.unwrap_or_default()))
+}
+
+/// Add the writes of a key as samples of the time series of its series, created on the first
+/// write with the retention and the labels to find them back.
+pub fn add_key_writes_to_pipeline(
+    key: &KeyWrites,
+    retention: Option<Duration>,
+    pipe: &mut redis::Pipeline,
+) {
+    for (field, write) in &key.fields {
+        let series_key = series_key(key.key_name, *field);
+        match write {
+            SeriesWrite::Incr(value) => pipe.cmd("TS.INCRBY").arg(&series_key).arg(*value),
+            SeriesWrite::Set(value) => pipe.cmd("TS.ADD").arg(&series_key).arg("*").arg(*value),
+        };
+        if let Some(retention) = retention {
+            pipe.arg("RETENTION").arg(retention.as_millis() as u64);
+        }
+        if let SeriesWrite::Set(_) = write {
+            // several sets within a millisecond keep the last one
+            pipe.arg("ON_DUPLICATE").arg("LAST");
+        }
+        pipe.arg("LABELS").arg(KEY_LABEL).arg(key.key_name);
+        if let Some(field) = field {
+            pipe.arg(SERIES_LABEL).arg(*field);
+        }
+        pipe.ignore();
+        add_expire_to_pipeline(&series_key, key.expire_at, pipe);
+    }
+}
+
+/// Read the latest sample of every time series of a key, see `stored_reply`.
+pub fn add_read_to_pipeline(key_name: &str, pipe: &mut redis::Pipeline) {
+    pipe.cmd("TS.MGET")
+        .arg("WITHLABELS")
+        .arg("FILTER")
+        .arg(format!("{KEY_LABEL}={key_name}"));
+}
+
+fn series_field(labels: &[Value]) -> Option<&Value> {
+    labels.iter().find_map(|label| match label {
+        Value::Bulk(pair) => match pair.as_slice() {
+            [Value::Data(name), field] if name == SERIES_LABEL.as_bytes() => Some(field),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Reply of `TS.MGET` shaped like the read of the key stored as usual: the value of an unlabeled
+/// key, or the flat `HGETALL` reply of the values by labels hash.
+pub fn stored_reply(reply: &Value, has_labels: bool) -> Value {
+    let Value::Bulk(entries) = reply else {
+        return reply.clone();
+    };
+    let mut fields = vec![];
+    for entry in entries {
+        let Value::Bulk(entry) = entry else {
+            continue;
+        };
+        // the sample is empty for a series without any
+        let [_, Value::Bulk(labels), Value::Bulk(sample)] = entry.as_slice() else {
+            continue;
+        };
+        let [_, value] = sample.as_slice() else {
+            continue;
+        };
+        if !has_labels {
+            return value.clone();
+        }
+        if let Some(field) = series_field(labels) {
+            fields.extend([field.clone(), value.clone()]);
+        }
+    }
+    match has_labels {
+        true => Value::Bulk(fields),
+        false => Value::Nil,
+    }
+}
+
+/// Number of time series in a reply of `TS.MGET`.
+pub fn series_count(reply: &Value) -> usize {
+    match reply {
+        Value::Bulk(entries) => entries.len(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn data(value: &str) -> Value {
+        Value::Data(value.as_bytes().to_vec())
+    }
+
+    fn entry(field: Option<&str>, value: Option<&str>) -> Value {
+        let mut labels = vec![Value::Bulk(vec![data(KEY_LABEL), data("requests")])];
+        if let Some(field) = field {
+            labels.push(Value::Bulk(vec![data(SERIES_LABEL), data(field)]));
+        }
+        let sample = match value {
+            Some(value) => vec![Value::Int(1700000000000), data(value)],
+            None => vec![],
+        };
+        Value::Bulk(vec![
+            data("series"),
+            Value::Bulk(labels),
+            Value::Bulk(sample),
+        ])
+    }
+
+    #[test]
+    fn reply_as_stored() {
+        let labeled = Value::Bulk(vec![
+            entry(Some(r#"{"path":"a"}"#), Some("2")),
+            entry(Some(r#"{"path":"b"}"#), None),
+            entry(Some(r#"{"path":"c"}"#), Some("1.5")),
+        ]);
+        assert_eq!(
+            stored_reply(&labeled, true),
+            Value::Bulk(vec![
+                data(r#"{"path":"a"}"#),
+                data("2"),
+                data(r#"{"path":"c"}"#),
+                data("1.5"),
+            ])
+        );
+
+        let unlabeled = Value::Bulk(vec![entry(None, Some("3"))]);
+        assert_eq!(stored_reply(&unlabeled, false), data("3"));
+        assert_eq!(stored_reply(&Value::Bulk(vec![]), false), Value::Nil);
+    }
+}
@@ -0,0 +1,168 @@
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{
+    Certificate, CertificateError, ClientConfig, Error, OwnedTrustAnchor, PrivateKey,
+    RootCertStore, ServerName,
+};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// TLS of the connections, for the managed services requiring it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsOptions {
+    /// PEM file of the certificate authorities trusted instead of the Mozilla roots.
+    pub ca_cert: Option<PathBuf>,
+    /// PEM files of the certificate chain and private key authenticating the client.
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+    /// Check that the certificate of the server was issued for its host, only the chain is
+    /// verified otherwise, for servers reached by an address their certificate doesn't name.
+    pub check_hostname: bool,
+}
+
+/// Verifier accepting a trusted certificate issued for another host.
+struct AnyHostnameVerifier(WebPkiVerifier);
+
+impl ServerCertVerifier for AnyHostnameVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        // the name is verified last, once the chain is trusted
+        match self.0.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        ) {
+            Err(Error::InvalidCertificate(CertificateError::NotValidForName)) => {
+                Ok(ServerCertVerified::assertion())
+            }
+            verified => verified,
+        }
+    }
+}
+
+fn pem_items(path: &Path) -> Result<Vec<rustls_pemfile::Item>, String> {
+    let file = File::open(path).map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let mut items = vec![];
+    loop {
+        match rustls_pemfile::read_one(&mut reader) {
+            Ok(Some(item)) => items.push(item),
+            Ok(None) => return Ok(items),
+            Err(e) => return Err(format!("invalid PEM file {}: {e}", path.display())),
+        }
+    }
+}
+
+fn certificates(path: &Path) -> Result<Vec<Certificate>, String> {
+    let certificates: Vec<Certificate> = pem_items(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    match certificates.is_empty() {
+        true => Err(format!("no certificate in {}", path.display())),
+        false => Ok(certificates),
+    }
+}
+
+fn private_key(path: &Path) -> Result<PrivateKey, String> {
+    pem_items(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("no private key in {}", path.display()))
+}
+
+fn root_store(ca_cert: Option<&Path>) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    let Some(ca_cert) = ca_cert else {
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        return Ok(roots);
+    };
+    for certificate in certificates(ca_cert)? {
+        roots
+            .add(&certificate)
+            .map_err(|e| format!("invalid certificate in {}: {e}", ca_cert.display()))?;
+    }
+    Ok(roots)
+}
+
+/// Client config of the TLS connections, reading the certificates and key once.
+pub fn client_config(options: &TlsOptions) -> Result<Arc<ClientConfig>, String> {
+    let roots = root_store(options.ca_cert.as_deref())?;
+    let verifier = WebPkiVerifier::new(roots, None);
+    let verifier: Arc<dyn ServerCertVerifier> = match options.check_hostname {
+        true => Arc::new(verifier),
+        false => Arc::new(AnyHostnameVerifier(verifier)),
+    };
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier);
+    let config = match &options.client_cert {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(certificates(cert)?, private_key(key)?)
+            .map_err(|e| format!("invalid client certificate: {e}"))?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn invalid_files() {
+        let options = TlsOptions {
+            ca_cert: None,
+            client_cert: None,
+            check_hostname: false,
+        };
+        assert!(client_config(&options).is_ok());
+
+        let missing = TlsOptions {
+            ca_cert: Some(PathBuf::from("/nonexistent/ca.pem")),
+            ..options.clone()
+        };
+        let err = client_config(&missing).unwrap_err();
+        assert!(err.starts_with("cannot read /nonexistent/ca.pem"), "{err}");
+
+        let path = std::env::temp_dir().join(format!("pytheus-tls-{}.pem", std::process::id()));
+        File::create(&path)
+            .unwrap()
+            .write_all(b"not a pem\n")
+            .unwrap();
+        let empty = TlsOptions {
+            ca_cert: Some(path.clone()),
+            ..options
+        };
+        let err = client_config(&empty).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.starts_with("no certificate in"), "{err}");
+    }
+}
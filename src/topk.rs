@@ -0,0 +1,245 @@
+use crate::{execute_pipeline, features, redis_key};
+use log::warn;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use redis::{from_redis_value, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// counters kept by the local tracker for every heavy hitter, the more the less a newcomer can
+// push out one that's been steady
+const CAPACITY_FACTOR: usize = 4;
+// how often the heavy hitters of the server are fetched, with the counts observed meanwhile
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Heavy-hitters mode of a labeled metric: only the `k` most written values of `label` keep
+/// their series, the writes with any other value go to the series labeled `other`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopK {
+    pub label: String,
+    pub k: usize,
+    /// Value of the label the rest is aggregated into.
+    pub other: String,
+    /// Count the values with the `TOPK` commands of RedisBloom, shared by every process, rather
+    /// than in each process. Counted locally when the server lacks them.
+    pub server: bool,
+}
+
+impl TopK {
+    /// Mode from the config: `{"label": name, "k": n, "other": value, "server": bool}`.
+    pub fn from_py(name: &str, top_k: &PyAny) -> PyResult<Self> {
+        let top_k: &PyDict = top_k.downcast()?;
+        let Some(label) = top_k.get_item("label") else {
+            return Err(PyValueError::new_err(format!(
+                "missing top_k_labels label for {name}"
+            )));
+        };
+        let top_k = Self {
+            label: label.extract()?,
+            k: match top_k.get_item("k") {
+                Some(k) => k.extract()?,
+                None => 10,
+            },
+            other: match top_k.get_item("other") {
+                Some(other) => other.extract()?,
+                None => "other".to_string(),
+            },
+            server: match top_k.get_item("server") {
+                Some(server) => server.extract()?,
+                None => false,
+            },
+        };
+        if top_k.k == 0 {
+            return Err(PyValueError::new_err(format!(
+                "invalid top_k_labels k for {name}: 0"
+            )));
+        }
+        Ok(top_k)
+    }
+}
+
+/// Space-saving counters: a value not counted yet replaces the least counted one, inheriting
+/// its count as an overestimate, so that the heavy hitters are found in bounded memory.
+#[derive(Debug, Default)]
+struct SpaceSaving {
+    capacity: usize,
+    counts: HashMap<String, u64>,
+}
+
+impl SpaceSaving {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counts: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        if let Some(count) = self.counts.get_mut(value) {
+            *count += 1;
+            return;
+        }
+        let mut count = 1;
+        if self.counts.len() >= self.capacity {
+            let least = self
+                .counts
+                .iter()
+                .min_by_key(|(_, count)| **count)
+                .map(|(value, count)| (value.clone(), *count));
+            if let Some((least, least_count)) = least {
+                self.counts.remove(&least);
+                count += least_count;
+            }
+        }
+        self.counts.insert(value.to_string(), count);
+    }
+
+    /// Whether the value is among the `k` most counted ones, ties included.
+    fn is_top(&self, value: &str, k: usize) -> bool {
+        let Some(count) = self.counts.get(value) else {
+            return false;
+        };
+        self.counts.values().filter(|other| *other > count).count() < k
+    }
+}
+
+/// Heavy hitters of a metric, shared by all its backends in the process.
+#[derive(Debug)]
+pub struct Tracker {
+    top_k: TopK,
+    local: SpaceSaving,
+    // sketch of the server in server mode, with the counts not sent yet and the heavy hitters
+    // it last reported
+    sketch_key: String,
+    route: usize,
+    pending: HashMap<String, u64>,
+    heavy: HashSet<String>,
+    synced_at: Option<Instant>,
+    syncing: bool,
+}
+
+type Trackers = Mutex<HashMap<String, Arc<Mutex<Tracker>>>>;
+
+static TRACKERS: OnceLock<Trackers> = OnceLock::new();
+
+/// Tracker of the metric with the keys prefixed by `resolved_prefix`, created on first use and
+/// again when its mode changed.
+pub fn tracker(resolved_prefix: &str, top_k: &TopK, route: usize) -> Arc<Mutex<Tracker>> {
+    let mut trackers = TRACKERS.get_or_init(Default::default).lock().unwrap();
+    if let Some(tracker) = trackers.get(resolved_prefix) {
+        if tracker.lock().unwrap().top_k == *top_k {
+            return tracker.clone();
+        }
+    }
+    let tracker = Arc::new(Mutex::new(Tracker {
+        top_k: top_k.clone(),
+        local: SpaceSaving::new(top_k.k * CAPACITY_FACTOR),
+        sketch_key: redis_key(format!("{resolved_prefix}:topk")),
+        route,
+        pending: HashMap::new(),
+        heavy: HashSet::new(),
+        synced_at: None,
+        syncing: false,
+    }));
+    trackers.insert(resolved_prefix.to_string(), tracker.clone());
+    tracker
+}
+
+/// Count a write with `value` for the label and tell the value the write keeps, `None` when
+/// it's a heavy hitter. In server mode the counts are sent to the server at most every
+/// `SYNC_INTERVAL` by the write that finds them due, which waits for the round trip.
+pub fn admit(py: Python, tracker: &Mutex<Tracker>, value: &str) -> Option<String> {
+    let mut state = tracker.lock().unwrap();
+    if !(state.top_k.server && features::current().topk) {
+        state.local.add(value);
+        let top = state.local.is_top(value, state.top_k.k);
+        return (!top).then(|| state.top_k.other.clone());
+    }
+
+    *state.pending.entry(value.to_string()).or_default() += 1;
+    let due = !state.syncing
+        && state
+            .synced_at
+            .is_none_or(|synced_at| synced_at.elapsed() >= SYNC_INTERVAL);
+    if due {
+        state.syncing = true;
+        let pending = std::mem::take(&mut state.pending);
+        let (sketch_key, route, k, first) = (
+            state.sketch_key.clone(),
+            state.route,
+            state.top_k.k,
+            state.synced_at.is_none(),
+        );
+        // the connection is used without the GIL, holding the lock would block other threads
+        drop(state);
+        let heavy = sync(py, &sketch_key, route, k, first, pending);
+        state = tracker.lock().unwrap();
+        state.syncing = false;
+        state.synced_at = Some(Instant::now());
+        match heavy {
+            Ok(heavy) => state.heavy = heavy,
+            Err(e) => warn!("could not sync the heavy hitters of {sketch_key}: {e}"),
+        }
+    }
+    let top = state.heavy.contains(value);
+    (!top).then(|| state.top_k.other.clone())
+}
+
+/// Send the counts to the sketch of the server and read back its heavy hitters.
+fn sync(
+    py: Python,
+    sketch_key: &str,
+    route: usize,
+    k: usize,
+    first: bool,
+    pending: HashMap<String, u64>,
+) -> PyResult<HashSet<String>> {
+    if first {
+        // fails once the sketch exists
+        let mut pipe = redis::pipe();
+        pipe.cmd("TOPK.RESERVE").arg(sketch_key).arg(k);
+        let _ = execute_pipeline(py, route, pipe);
+    }
+    let mut pipe = redis::pipe();
+    if !pending.is_empty() {
+        let cmd = pipe.cmd("TOPK.INCRBY").arg(sketch_key);
+        for (value, count) in &pending {
+            cmd.arg(value).arg(count);
+        }
+        cmd.ignore();
+    }
+    pipe.cmd("TOPK.LIST").arg(sketch_key);
+    let heavy: Vec<Value> = execute_pipeline(py, route, pipe)?;
+    let heavy: Vec<Option<String>> = match heavy.last() {
+        Some(heavy) => from_redis_value(heavy).map_err(|e| PyValueError::new_err(e.to_string()))?,
+        None => vec![],
+    };
+    Ok(heavy.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn space_saving() {
+        let mut counters = SpaceSaving::new(4);
+        for value in ["a", "a", "a", "b", "b", "c"] {
+            counters.add(value);
+        }
+        assert!(counters.is_top("a", 1) && !counters.is_top("b", 1));
+        assert!(counters.is_top("b", 2) && !counters.is_top("c", 2));
+
+        // the long tail churns through the spare counters without reaching the top
+        for value in 0..100 {
+            counters.add("a");
+            counters.add(&value.to_string());
+        }
+        assert_eq!(counters.counts.len(), 4);
+        assert!(counters.is_top("a", 1));
+        assert!(!counters.is_top("99", 1));
+    }
+}
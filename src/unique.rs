@@ -0,0 +1,123 @@
+use crate::{add_expire_to_pipeline, redis_key, series_field};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// adds the items to the HyperLogLog of a series and stores its estimate as the value of the
+// series, so that the scrape reads it like any gauge
+pub const UNIQUE_ADD_SCRIPT: &str = "redis.call('PFADD', KEYS[2], unpack(ARGV, 2)) \
+    local count = redis.call('PFCOUNT', KEYS[2]) \
+    if ARGV[1] == '' then redis.call('SET', KEYS[1], count) \
+    else redis.call('HSET', KEYS[1], ARGV[1], count) end \
+    return count";
+
+/// HyperLogLog of the items added to a series of a unique counter.
+pub fn hll_key(resolved_prefix: &str, labels_hash: &Option<String>) -> String {
+    redis_key(format!(
+        "{resolved_prefix}:hll:{}",
+        series_field(labels_hash)
+    ))
+}
+
+/// Items a write adds to the HyperLogLog of a series of a unique counter.
+#[derive(Debug, Clone)]
+pub struct Items {
+    pub hll_key: String,
+    pub items: Vec<String>,
+}
+
+/// Add the items to the HyperLogLog of the series and set the series to its estimate.
+pub fn add_to_pipeline(
+    items: &Items,
+    key_name: &str,
+    labels_hash: &Option<String>,
+    expire_at: Option<usize>,
+    pipe: &mut redis::Pipeline,
+) {
+    pipe.cmd("EVAL")
+        .arg(UNIQUE_ADD_SCRIPT)
+        .arg(2)
+        .arg(key_name)
+        .arg(&items.hll_key)
+        .arg(labels_hash.as_deref().unwrap_or_default())
+        .arg(&items.items)
+        .ignore();
+    add_expire_to_pipeline(key_name, expire_at, pipe);
+    add_expire_to_pipeline(&items.hll_key, expire_at, pipe);
+}
+
+// registers of the local HyperLogLog, the standard error is 1.04 / sqrt(2^PRECISION), 0.8%
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// HyperLogLog of the single-process backends, estimating the number of distinct items added
+/// in a fixed 16 KiB like Redis does, without keeping them.
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub fn add(&mut self, item: &str) {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let hash = hasher.finish();
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // the sentinel bit bounds the run of zeros of the remaining bits
+        let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    /// Estimated number of distinct items, corrected by linear counting for small cardinalities.
+    pub fn count(&self) -> f64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-(*register as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            return (m * (m / zeros as f64).ln()).round();
+        }
+        estimate.round()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn distinct_count() {
+        let mut hll = HyperLogLog::default();
+        assert_eq!(hll.count(), 0.0);
+        for _ in 0..3 {
+            for user in 0..10 {
+                hll.add(&format!("user-{user}"));
+            }
+        }
+        assert_eq!(hll.count(), 10.0);
+
+        for user in 0..100_000 {
+            hll.add(&format!("user-{user}"));
+        }
+        let error = (hll.count() - 100_000.0).abs() / 100_000.0;
+        assert!(error < 0.03, "{}", hll.count());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::clock;
+use std::cell::Cell;
+use std::sync::Mutex;
+
+/// Name of the worker threads, numbered from the write workers.
+pub const THREAD_NAME_PREFIX: &str = "pytheus-redis-worker-";
+
+/// What a worker thread is doing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    /// Waiting for a job.
+    Idle,
+    Processing,
+    /// Connecting again to an endpoint, before carrying on with its job.
+    Reconnecting,
+    /// Exited, once its workers were replaced, or panicked outside of a job.
+    Dead,
+}
+
+impl State {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Processing => "processing",
+            State::Reconnecting => "reconnecting",
+            State::Dead => "dead",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Worker {
+    name: String,
+    state: State,
+    // unix timestamp of the last change of state
+    since: f64,
+}
+
+static WORKERS: Mutex<Vec<Worker>> = Mutex::new(Vec::new());
+
+thread_local! {
+    // slot of the calling thread when it's a worker
+    static SLOT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Registration of the calling thread as a worker, marking it dead when dropped, including
+/// while unwinding from a panic.
+pub struct Registration {
+    slot: usize,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        set_slot(self.slot, State::Dead);
+        SLOT.with(|slot| slot.set(None));
+    }
+}
+
+fn set_slot(slot: usize, state: State) {
+    if let Some(worker) = WORKERS.lock().unwrap().get_mut(slot) {
+        worker.state = state;
+        worker.since = clock::unix_timestamp();
+    }
+}
+
+/// Register the calling thread as an idle worker named after it, reusing the slot of a dead
+/// worker of the same name.
+pub fn register(name: &str) -> Registration {
+    let mut workers = WORKERS.lock().unwrap();
+    let worker = Worker {
+        name: name.to_string(),
+        state: State::Idle,
+        since: clock::unix_timestamp(),
+    };
+    let dead = workers
+        .iter()
+        .position(|other| other.name == name && other.state == State::Dead);
+    let slot = match dead {
+        Some(slot) => {
+            workers[slot] = worker;
+            slot
+        }
+        None => {
+            workers.push(worker);
+            workers.len() - 1
+        }
+    };
+    SLOT.with(|current| current.set(Some(slot)));
+    Registration { slot }
+}
+
+/// Record the state of the calling thread, nothing when it's not a worker.
+pub fn set(state: State) {
+    if let Some(slot) = SLOT.with(Cell::get) {
+        set_slot(slot, state);
+    }
+}
+
+/// Forget the workers, in a forked child where their threads don't exist.
+pub fn reset() {
+    WORKERS.lock().unwrap().clear();
+}
+
+/// Name, state and unix timestamp of the last change of state of every worker.
+pub fn states() -> Vec<(String, State, f64)> {
+    WORKERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|worker| (worker.name.clone(), worker.state, worker.since))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::thread;
+
+    fn state(name: &str) -> Vec<State> {
+        states()
+            .into_iter()
+            .filter(|(other, _, _)| other == name)
+            .map(|(_, state, _)| state)
+            .collect()
+    }
+
+    #[test]
+    fn worker_states() {
+        let name = "test-worker-states";
+        set(State::Processing);
+        assert!(state(name).is_empty());
+
+        let worker = thread::spawn(move || {
+            let _registration = register(name);
+            set(State::Processing);
+            assert_eq!(state(name), [State::Processing]);
+            panic!("worker failed");
+        });
+        assert!(worker.join().is_err());
+        assert_eq!(state(name), [State::Dead]);
+
+        // the dead worker is replaced
+        let _registration = register(name);
+        assert_eq!(state(name), [State::Idle]);
+    }
+}
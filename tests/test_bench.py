@@ -0,0 +1,22 @@
+import pytest
+
+from pytheus.metrics import Counter
+from pytheus_backend_rs import SingleProcessAtomicBackend, benchmark
+
+
+def test_benchmark():
+    counter = Counter("bench", "desc")
+    backend = SingleProcessAtomicBackend({}, counter)
+    report = benchmark(backend, threads=2, iterations=100)
+
+    assert report["operations"] == 200
+    assert backend.get() == 200.0
+    assert report["throughput"] > 0
+    assert report["latency_p50_us"] <= report["latency_p99_us"] <= report["latency_max_us"]
+
+
+def test_benchmark_unknown_operation():
+    counter = Counter("bench", "desc")
+    backend = SingleProcessAtomicBackend({}, counter)
+    with pytest.raises(ValueError):
+        benchmark(backend, operation="observe")
@@ -0,0 +1,1619 @@
+import gc
+import json
+import logging
+import socket
+import threading
+import time
+import warnings
+import weakref
+import pytest
+
+from pytheus.backends import load_backend
+from pytheus.metrics import Counter, Histogram, Gauge
+from pytheus.registry import CollectorRegistry
+from pytheus_backend_rs import (
+    CollectorRegistry as RustCollectorRegistry,
+    Ewma,
+    Counter as RustCounter,
+    Gauge as RustGauge,
+    Histogram as RustHistogram,
+    Summary as RustSummary,
+    UniqueCounter,
+    FakeRedisBackend,
+    OutSample,
+    RedisBackend,
+    SampleSet,
+    SingleProcessAtomicBackend,
+    TestClock,
+    build_info,
+    inject_fault,
+    labels_fast,
+    set_clock,
+)
+from pytheus.exposition import generate_metrics
+
+
+@pytest.fixture(autouse=True)
+def load_fake_redis_backend():
+    load_backend(FakeRedisBackend, {})
+    FakeRedisBackend.execute_command("FLUSHALL")
+
+
+def test_is_a_redis_backend():
+    counter = Counter("name", "desc")
+    backend = counter._metric_value_backend
+    assert isinstance(backend, FakeRedisBackend)
+    assert isinstance(backend, RedisBackend)
+    assert backend.key_name == "name"
+
+
+def test_debug_properties():
+    counter = Counter("name", "desc", required_labels=["bob"])
+    backend = FakeRedisBackend({}, counter.labels(bob="cat"), histogram_bucket="1")
+    assert backend.resolved_prefix == "name"
+    assert backend.key_name == "name:1"
+    assert backend.labels_hash == '{"bob":"cat"}'
+
+    with pytest.raises(AttributeError):
+        backend.key_name = "other"
+
+
+def test_reduce():
+    counter = Counter("reduced", "desc")
+    backend = counter._metric_value_backend
+    cls, args = backend.__reduce__()
+    assert cls is FakeRedisBackend
+    assert args == (backend.config, backend.metric, None)
+
+    clone = cls(*args)
+    clone.inc(2.0)
+    time.sleep(0.01)
+    assert FakeRedisBackend.execute_command("GET", "reduced") == "2"
+
+
+def test_metric_is_weakly_referenced():
+    registry = CollectorRegistry()
+    counter = Counter("collected", "desc", registry=registry)
+    backend = counter._metric_value_backend
+    assert backend.metric is counter
+
+    metric = weakref.ref(counter)
+    del counter, registry
+    gc.collect()
+    assert metric() is None
+    assert backend.metric is None
+    with pytest.raises(Exception, match="garbage collected"):
+        backend.__reduce__()
+
+
+def test_handle_post_fork():
+    before = Counter("before_fork", "desc")
+    FakeRedisBackend.handle_post_fork()
+    after = Counter("after_fork", "desc")
+    before.inc(1.0)
+    after.inc(2.0)
+    time.sleep(0.01)
+    assert FakeRedisBackend.execute_command("GET", "before_fork") == "1"
+    assert FakeRedisBackend.execute_command("GET", "after_fork") == "2"
+
+
+def test_get_many():
+    gauge = Gauge("many", "desc", required_labels=["bob"])
+    gauge.labels(bob="cat").set(2.0)
+    gauge.labels(bob="dog").set(3.5)
+    counter = Counter("many_counter", "desc")
+    counter.inc(4.0)
+    time.sleep(0.01)
+
+    backends = [
+        gauge.labels(bob="cat")._metric_value_backend,
+        gauge.labels(bob="dog")._metric_value_backend,
+        counter,
+    ]
+    assert FakeRedisBackend.get_many(backends) == [2.0, 3.5, 4.0]
+    assert FakeRedisBackend.get_many([]) == []
+
+
+def test_call_time_labels():
+    counter = Counter("call_time", "desc", required_labels=["bob"])
+    backend = FakeRedisBackend({}, counter)
+    backend.inc(2.0, labels={"bob": "cat"})
+    backend.inc(1.0, labels={"bob": "cat"})
+    counter.labels(bob="cat").inc(1.0)
+    time.sleep(0.01)
+    assert FakeRedisBackend.execute_command("HGET", "call_time", '{"bob":"cat"}') == "4"
+
+    with pytest.raises(ValueError, match="missing labels"):
+        backend.inc(1.0)
+    with pytest.raises(ValueError, match="unknown label"):
+        backend.inc(1.0, labels={"other": "cat"})
+
+
+def test_inc_return_value():
+    counter = Counter("thresholds", "desc", required_labels=["bob"])
+    backend = FakeRedisBackend({}, counter)
+    backend.inc(1.0, labels={"bob": "cat"})
+    assert backend.inc(2.0, labels={"bob": "cat"}, return_value=True) == 3.0
+    assert backend.inc(1.0, labels={"bob": "dog"}, return_value=True) == 1.0
+    assert backend.inc(1.0, labels={"bob": "dog"}) is None
+
+
+def test_update_batch():
+    counter = Counter("batched", "desc", required_labels=["bob"])
+    backend = FakeRedisBackend({}, counter)
+    backend.update_batch([({"bob": "cat"}, 1.0), ({"bob": "dog"}, 2.0), ({"bob": "cat"}, 3.0)])
+    time.sleep(0.01)
+    assert FakeRedisBackend.execute_command("HGETALL", "batched") == [
+        '{"bob":"cat"}',
+        "4",
+        '{"bob":"dog"}',
+        "2",
+    ]
+
+    with pytest.raises(ValueError, match="unknown label"):
+        backend.update_batch([({"other": "cat"}, 1.0)])
+
+
+def test_series_count():
+    registry = CollectorRegistry()
+    counter = Counter("counted", "desc", required_labels=["bob"], registry=registry)
+    histogram = Histogram("counted_latency", "desc", registry=registry)
+    Gauge("never_written", "desc", registry=registry)
+    for bob in ["cat", "dog", "bird"]:
+        counter.labels(bob=bob).inc()
+    histogram.observe(0.1)
+    time.sleep(0.01)
+    FakeRedisBackend.execute_command("DEL", "never_written")
+
+    assert FakeRedisBackend.series_count(registry) == {
+        "counted": 3,
+        "counted_latency": 1,
+        "never_written": 0,
+    }
+
+
+def test_warm_start():
+    registry = CollectorRegistry()
+    counter = Counter("restored", "desc", required_labels=["bob"], registry=registry)
+    histogram = Histogram(
+        "restored_latency", "desc", required_labels=["bob"], registry=registry
+    )
+    for bob in ["cat", "dog"]:
+        counter.labels(bob=bob).inc()
+    histogram.labels(bob="bird").observe(0.1)
+    time.sleep(0.01)
+
+    # the same metrics in a restarted process
+    restarted = CollectorRegistry()
+    counter = Counter("restored", "desc", required_labels=["bob"], registry=restarted)
+    histogram = Histogram(
+        "restored_latency", "desc", required_labels=["bob"], registry=restarted
+    )
+    Gauge("unlabeled", "desc", registry=restarted)
+
+    assert FakeRedisBackend.warm_start(restarted) == 3
+    assert len(counter._collector._labeled_metrics) == 2
+    assert len(histogram._collector._labeled_metrics) == 1
+
+
+def test_redis_memory_usage():
+    registry = CollectorRegistry()
+    counter = Counter("heavy", "desc", required_labels=["user"], registry=registry)
+    Counter("light", "desc", registry=registry).inc()
+    Gauge("unwritten", "desc", registry=registry)
+    for user in range(50):
+        counter.labels(user=str(user)).inc()
+    time.sleep(0.01)
+    FakeRedisBackend.execute_command("DEL", "unwritten")
+
+    usage = FakeRedisBackend.redis_memory_usage(registry, batch_size=1, pause=0)
+    assert usage["heavy"] > usage["light"] > 0
+    assert usage["unwritten"] == 0
+    with pytest.raises(ValueError, match="invalid batch_size"):
+        FakeRedisBackend.redis_memory_usage(registry, batch_size=0)
+
+
+def test_snapshot(monkeypatch):
+    monkeypatch.setenv("PYTHEUS_FAULT_INJECTION", "1")
+    load_backend(FakeRedisBackend, {"track_pending_writes": True})
+    registry = CollectorRegistry()
+    counter = Counter("snapshotted", "desc", required_labels=["bob"], registry=registry)
+    gauge = Gauge("snapshotted_gauge", "desc", registry=registry)
+
+    # the worker is stuck writing the first increment, the next writes are pending
+    inject_fault("latency", latency_ms=200)
+    counter.labels(bob="cat").inc()
+    time.sleep(0.05)
+    counter.labels(bob="cat").inc(2)
+    gauge.set(5)
+    gauge.inc(1)
+
+    snapshot = FakeRedisBackend.snapshot(registry)
+    [cat] = snapshot["snapshotted"]
+    assert cat["backend"] == "FakeRedisBackend"
+    assert cat["labels"] == {"bob": "cat"}
+    assert cat["value"] is None
+    assert cat["pending"] == {cat["key"]: {"jobs": 1, "delta": 2.0, "set": None}}
+    [gauge_series] = snapshot["snapshotted_gauge"]
+    assert gauge_series["labels"] is None
+    assert gauge_series["pending"] == {
+        gauge_series["key"]: {"jobs": 2, "delta": 1.0, "set": 5.0}
+    }
+
+    assert FakeRedisBackend._flush(timeout=5)
+    snapshot = FakeRedisBackend.snapshot(registry)
+    assert all(not series["pending"] for entries in snapshot.values() for series in entries)
+
+
+def test_gauge_callbacks(tmp_path):
+    depth = tmp_path / "depth"
+    depth.write_text("12\n")
+    queue = {"high": 3.0}
+    load_backend(
+        FakeRedisBackend,
+        {
+            "gauge_callbacks": {
+                "queue_depth": lambda: [({"priority": name}, size) for name, size in queue.items()],
+                "file_depth": {"file": str(depth)},
+                "open_fds": {"proc": "open_fds"},
+                "broken": lambda: 1 / 0,
+            }
+        },
+    )
+    registry = CollectorRegistry()
+    Gauge("queue_depth", "desc", required_labels=["priority"], registry=registry)
+    Gauge("file_depth", "desc", registry=registry)
+    Gauge("open_fds", "desc", registry=registry)
+    Gauge("broken", "desc", registry=registry)
+
+    samples = FakeRedisBackend._generate_samples(registry)
+    assert samples["queue_depth"] == [OutSample("", {"priority": "high"}, 3.0)]
+    assert samples["file_depth"] == [OutSample("", None, 12.0)]
+    assert samples["open_fds"][0].value > 0
+    assert "broken" not in samples
+    [error] = samples.errors
+    assert error["collector"] == "broken"
+    assert "ZeroDivisionError" in error["error"]
+
+    # evaluated by every scrape
+    queue["low"] = 1.0
+    depth.write_text("13\n")
+    samples = FakeRedisBackend._generate_samples(registry)
+    assert len(samples["queue_depth"]) == 2
+    assert samples["file_depth"] == [OutSample("", None, 13.0)]
+
+    with pytest.raises(ValueError, match="unknown proc stat"):
+        FakeRedisBackend._initialize({"gauge_callbacks": {"x": {"proc": "nope"}}})
+
+
+
+def test_ewma_gauge():
+    clock = TestClock(1_700_000_000)
+    set_clock(clock)
+    try:
+        latency = Ewma(60)
+        requests = Ewma(10, rate=True)
+        load_backend(
+            FakeRedisBackend,
+            {"gauge_callbacks": {"latency_ewma": latency, "requests_rate": requests}},
+        )
+        registry = CollectorRegistry()
+        Gauge("latency_ewma", "desc", registry=registry)
+        Gauge("requests_rate", "desc", registry=registry)
+
+        latency.observe(10)
+        latency.observe(20)
+        clock.advance(60)
+        # the older observations weigh half as much after a half life
+        latency.observe(30)
+        assert latency.get() == 22.5
+        for _ in range(600):
+            requests.observe(5)
+            clock.advance(1)
+
+        samples = FakeRedisBackend._generate_samples(registry)
+        assert samples["latency_ewma"] == [OutSample("", None, 22.5)]
+        assert samples["requests_rate"][0].value == pytest.approx(5, rel=0.05)
+        # decays without observations
+        clock.advance(10)
+        assert requests() == pytest.approx(samples["requests_rate"][0].value / 2)
+
+        with pytest.raises(ValueError, match="invalid half_life"):
+            Ewma(0)
+    finally:
+        set_clock(None)
+
+def test_generate_samples_iter():
+    registry = CollectorRegistry()
+    counters = [Counter(f"iter_{i}", "desc", registry=registry) for i in range(5)]
+    for i, counter in enumerate(counters):
+        counter.inc(i)
+    time.sleep(0.01)
+
+    samples = FakeRedisBackend._generate_samples_iter(registry, chunk_size=2)
+    pairs = list(samples)
+    assert [(collector.name, sample) for collector, sample in pairs] == [
+        (f"iter_{i}", OutSample("", None, float(i))) for i in range(5)
+    ]
+    assert samples.errors == []
+    assert list(samples) == []
+
+    with pytest.raises(ValueError, match="invalid chunk_size"):
+        FakeRedisBackend._generate_samples_iter(registry, chunk_size=0)
+
+
+def test_periodic_export(tmp_path):
+    registry = CollectorRegistry()
+    counter = Counter("exported", "desc", registry=registry)
+    counter.inc()
+    path = tmp_path / "metrics.prom"
+    FakeRedisBackend.start_exporter(registry, {"file": str(path)}, 0.05)
+    time.sleep(0.2)
+    assert "exported 1.0" in path.read_text()
+
+    # exported a last time once stopped, with the writes queued until then
+    counter.inc()
+    assert FakeRedisBackend.stop_exporter()
+    assert "exported 2.0" in path.read_text()
+    assert not FakeRedisBackend.stop_exporter()
+
+    with pytest.raises(ValueError, match="only http urls"):
+        FakeRedisBackend.start_exporter(registry, {"remote_write": "https://x/write"}, 1)
+    with pytest.raises(ValueError, match="invalid interval"):
+        FakeRedisBackend.start_exporter(registry, {"file": str(path)}, 0)
+
+
+def test_graphite_export():
+    server = socket.create_server(("127.0.0.1", 0))
+    port = server.getsockname()[1]
+    registry = CollectorRegistry()
+    Counter("carbon", "desc", required_labels=["bob"], registry=registry).labels(bob="cat").inc(3)
+    time.sleep(0.01)
+
+    FakeRedisBackend.start_exporter(
+        registry, {"graphite": f"127.0.0.1:{port}", "template": "app.{name}.{labels}"}, 60
+    )
+    assert FakeRedisBackend.stop_exporter()
+    connection, _ = server.accept()
+    with connection, server:
+        line = connection.makefile().readline()
+    path, value, timestamp = line.split()
+    assert (path, value) == ("app.carbon.bob.cat", "3")
+    assert int(timestamp) > 0
+
+    with pytest.raises(ValueError, match="invalid graphite address"):
+        FakeRedisBackend.start_exporter(registry, {"graphite": "carbon"}, 60)
+
+
+def test_counter_layout():
+    counter = Counter("counter", "desc")
+    counter.inc(2.7)
+    time.sleep(0.01)
+    assert FakeRedisBackend.execute_command("GET", "counter") == "2.7"
+    assert FakeRedisBackend.execute_command("TTL", "counter") == 3600
+
+
+def test_labeled_layout():
+    gauge = Gauge("gauge", "desc", required_labels=["bob"])
+    gauge.labels(bob="cat").set(3)
+    time.sleep(0.01)
+    assert FakeRedisBackend.execute_command("HGETALL", "gauge") == ['{"bob":"cat"}', "3.0"]
+
+
+def test_expire_at():
+    load_backend(FakeRedisBackend, {"expire_at": {"expired": 1}})
+    counter = Counter("expired", "desc")
+    counter.inc()
+    time.sleep(0.01)
+    assert FakeRedisBackend.execute_command("GET", "expired") is None
+
+
+def test_expiry_with_test_clock():
+    clock = TestClock(1_700_000_000)
+    set_clock(clock)
+    try:
+        counter = Counter("counter", "desc")
+        counter.inc()
+        time.sleep(0.01)
+        clock.advance(3599)
+        assert FakeRedisBackend.execute_command("GET", "counter") == "1"
+        clock.advance(1)
+        assert FakeRedisBackend.execute_command("GET", "counter") is None
+    finally:
+        set_clock(None)
+
+
+def test_expire_override():
+    clock = TestClock(1_700_000_000)
+    set_clock(clock)
+    try:
+        gauge = Gauge("lease", "desc")
+        gauge._metric_value_backend.set(1.0, expire=300)
+        time.sleep(0.01)
+        clock.advance(299)
+        assert FakeRedisBackend.execute_command("GET", "lease") == "1.0"
+        clock.advance(1)
+        assert FakeRedisBackend.execute_command("GET", "lease") is None
+
+        with pytest.raises(ValueError):
+            gauge._metric_value_backend.set(1.0, expire=0)
+
+        # without field expiry a lease would expire every series of the metric
+        gauge = Gauge("leases", "desc", required_labels=["holder"])
+        backend = FakeRedisBackend({}, gauge)
+        backend.set(1.0, labels={"holder": "cat"})
+        with pytest.raises(Exception, match="needs a server with field expiry"):
+            backend.set(1.0, labels={"holder": "dog"}, expire=1)
+        time.sleep(0.01)
+        clock.advance(2)
+        assert FakeRedisBackend.execute_command("HGET", "leases", '{"holder":"cat"}') == "1.0"
+    finally:
+        set_clock(None)
+
+
+def test_expire_jitter():
+    load_backend(FakeRedisBackend, {"expire_jitter": 600})
+    ttls = set()
+    for i in range(20):
+        Counter(f"jittered_{i}", "desc").inc()
+    time.sleep(0.01)
+    for i in range(20):
+        ttl = FakeRedisBackend.execute_command("TTL", f"jittered_{i}")
+        assert 3600 <= ttl <= 4200
+        ttls.add(ttl)
+    assert len(ttls) > 1
+
+
+def test_expire_key_seconds():
+    load_backend(FakeRedisBackend, {"expire_key_seconds": 86400})
+    Counter("long_lived", "desc").inc()
+    assert FakeRedisBackend._flush(5)
+    assert FakeRedisBackend.execute_command("TTL", "long_lived") == 86400
+
+    with pytest.raises(ValueError, match="invalid expire_key_seconds"):
+        FakeRedisBackend._initialize({"expire_key_seconds": 0})
+    load_backend(FakeRedisBackend, {})
+
+
+def test_dead_letters_partial_transaction(monkeypatch, tmp_path):
+    monkeypatch.setenv("PYTHEUS_FAULT_INJECTION", "1")
+    dead_letter_path = tmp_path / "dead_letters.jsonl"
+    load_backend(
+        FakeRedisBackend,
+        {"dead_letter_path": str(dead_letter_path), "confirmed_writes": ["audited"]},
+    )
+    broken = Counter("broken", "desc")
+    healthy = Counter("healthy", "desc")
+    audited = Counter("audited", "desc")
+    assert FakeRedisBackend._flush(5)
+    FakeRedisBackend.execute_command("SET", "broken", "not a number")
+
+    # the worker is stuck on the first write, the next ones are written in one transaction
+    inject_fault("latency", latency_ms=200)
+    Gauge("stalled", "desc").set(1)
+    time.sleep(0.05)
+    broken.inc(3)
+    healthy.inc(2)
+    # applied, whatever became of the other writes of the transaction
+    audited.inc(1)
+    assert FakeRedisBackend._flush(5)
+    assert FakeRedisBackend.execute_command("GET", "healthy") == "2"
+    assert FakeRedisBackend.execute_command("GET", "audited") == "1"
+
+    FakeRedisBackend.execute_command("DEL", "broken")
+    assert FakeRedisBackend.replay_dead_letters() == 1
+    assert FakeRedisBackend.execute_command("GET", "broken") == "3"
+    # applied by the transaction, not replayed
+    assert FakeRedisBackend.execute_command("GET", "healthy") == "2"
+    load_backend(FakeRedisBackend, {})
+
+
+def test_expire_refresh_fraction():
+    load_backend(FakeRedisBackend, {"expire_key_seconds": 100, "expire_refresh_fraction": 0.5})
+    clock = TestClock(1_700_000_000)
+    set_clock(clock)
+    try:
+        counter = Counter("hot", "desc")
+        counter.inc()
+        assert FakeRedisBackend._flush(5)
+        clock.advance(30)
+        # not refreshed again before half the expiry
+        counter.inc()
+        assert FakeRedisBackend._flush(5)
+        assert FakeRedisBackend.execute_command("TTL", "hot") == 70
+        clock.advance(20)
+        counter.inc()
+        assert FakeRedisBackend._flush(5)
+        assert FakeRedisBackend.execute_command("TTL", "hot") == 100
+        # deleted within the interval, recreated with an expiry
+        FakeRedisBackend.execute_command("DEL", "hot")
+        clock.advance(10)
+        counter.inc()
+        assert FakeRedisBackend._flush(5)
+        assert FakeRedisBackend.execute_command("TTL", "hot") == 100
+    finally:
+        set_clock(None)
+
+    with pytest.raises(ValueError, match="invalid expire_refresh_fraction"):
+        FakeRedisBackend._initialize({"expire_refresh_fraction": 1.0})
+    load_backend(FakeRedisBackend, {})
+
+
+def test_key_prefix():
+    load_backend(FakeRedisBackend, {"key_prefix": "myapp:metrics:"})
+    registry = CollectorRegistry()
+    counter = Counter("shared_name", "desc", registry=registry)
+    histogram = Histogram("shared_latency", "desc", buckets=[1.0], registry=registry)
+    counter.inc()
+    histogram.observe(0.5)
+    assert FakeRedisBackend._flush(5)
+    assert FakeRedisBackend.execute_command("GET", "myapp:metrics:shared_name") == "1"
+    assert FakeRedisBackend.execute_command("GET", "shared_name") is None
+    assert FakeRedisBackend.execute_command("GET", "myapp:metrics:shared_latency:1") == "1"
+    assert "shared_name 1.0" in generate_metrics(registry)
+    load_backend(FakeRedisBackend, {})
+
+
+def test_key_prefix_readers():
+    api = CollectorRegistry()
+    load_backend(
+        FakeRedisBackend,
+        {
+            "key_prefix": "myapp:",
+            "registry_namespaces": {"api": api},
+            "track_created": ["prefixed"],
+            "track_last_update": ["prefixed"],
+        },
+    )
+    clock = TestClock(1_700_000_000)
+    set_clock(clock)
+    try:
+        counter = Counter("prefixed", "desc", required_labels=["bob"])
+        counter.labels(bob="cat").inc()
+        api_counter = Counter("prefixed", "desc", required_labels=["bob"], registry=api)
+        clock.advance(10)
+        api_counter.labels(bob="dog").inc()
+        assert FakeRedisBackend._flush(5)
+        clock.advance(110)
+
+        assert FakeRedisBackend.created_timestamps("prefixed", api) == {
+            '{"bob":"dog"}': 1_700_000_010
+        }
+        assert [series["labels"] for series in FakeRedisBackend.idle_series(60, api)] == [
+            {"bob": "dog"}
+        ]
+
+        assert FakeRedisBackend.created_timestamps("prefixed") == {
+            '{"bob":"cat"}': 1_700_000_000
+        }
+        assert FakeRedisBackend.idle_series(60) == [
+            {
+                "metric": "prefixed",
+                "labels": {"bob": "cat"},
+                "last_updated": 1_700_000_000,
+                "idle_seconds": 120,
+            }
+        ]
+    finally:
+        set_clock(None)
+        load_backend(FakeRedisBackend, {})
+
+
+def test_generate_metrics():
+    registry = CollectorRegistry()
+    histogram = Histogram(
+        "histogram", "desc", buckets=[1, 2, 3], required_labels=["bob"], registry=registry
+    )
+    histogram.labels(bob="cat").observe(2.7)
+
+    time.sleep(0.1)
+    metrics_output = generate_metrics(registry)
+    assert metrics_output == (
+        "# HELP histogram desc\n"
+        "# TYPE histogram histogram\n"
+        'histogram_bucket{bob="cat",le="1"} 0.0\n'
+        'histogram_bucket{bob="cat",le="2"} 0.0\n'
+        'histogram_bucket{bob="cat",le="3"} 1.0\n'
+        'histogram_bucket{bob="cat",le="+Inf"} 1.0\n'
+        'histogram_count{bob="cat"} 1.0\n'
+        'histogram_sum{bob="cat"} 2.7\n'
+    )
+
+
+def test_hashed_key_names():
+    load_backend(FakeRedisBackend, {"max_key_length": 20})
+    registry = CollectorRegistry()
+    counter = Counter("a_rather_long_metric_name", "desc", registry=registry)
+    short = Counter("short", "desc", registry=registry)
+    counter.inc(2.0)
+    short.inc(1.0)
+    time.sleep(0.01)
+
+    assert FakeRedisBackend.execute_command("GET", "a_rather_long_metric_name") is None
+    assert FakeRedisBackend.execute_command("GET", "short") == "1"
+    key_names = FakeRedisBackend.execute_command("HGETALL", "pytheus:key_names")
+    assert key_names[1::2] == ["a_rather_long_metric_name"]
+    assert "a_rather_long_metric_name 2.0" in generate_metrics(registry)
+
+
+def test_max_labels_length():
+    load_backend(FakeRedisBackend, {"max_labels_length": 32})
+    registry = CollectorRegistry()
+    histogram = Histogram(
+        "queries", "desc", required_labels=["query"], buckets=[1.0], registry=registry
+    )
+    query = "SELECT * FROM users WHERE id = ?"
+    histogram.labels(query=query).observe(0.5)
+    histogram.labels(query="ping").observe(2.0)
+    time.sleep(0.01)
+
+    fields = FakeRedisBackend.execute_command("HGETALL", "queries:count")[0::2]
+    compact = [field for field in fields if field.startswith("#")]
+    assert len(compact) == 1 and '{"query":"ping"}' in fields
+    # the labels are stored once, not in every key of the histogram
+    label_sets = FakeRedisBackend.execute_command("HGETALL", "pytheus:label_sets")
+    assert label_sets == [compact[0], json.dumps({"query": query}, separators=(",", ":"))]
+    assert f'queries_bucket{{query="{query}",le="1"}} 1.0' in generate_metrics(registry)
+
+    with pytest.raises(ValueError, match="max_labels_length is not supported"):
+        load_backend(FakeRedisBackend, {"key_layout": "pytheus", "max_labels_length": 64})
+
+
+def test_relabel():
+    load_backend(
+        FakeRedisBackend,
+        {
+            "relabel": [
+                {"action": "drop", "label": "pod"},
+                {"action": "rename", "label": "svc", "target": "service"},
+                {"action": "set", "label": "env", "value": "prod", "metrics": ["hits"]},
+                {
+                    "action": "replace",
+                    "label": "path",
+                    "regex": r"/users/\d+",
+                    "replacement": "/users/:id",
+                },
+            ]
+        },
+    )
+    registry = CollectorRegistry()
+    hits = Counter("hits", "desc", required_labels=["pod", "svc", "path"], registry=registry)
+    hits.labels(pod="a", svc="api", path="/users/1").inc()
+    hits.labels(pod="b", svc="api", path="/users/2").inc(2)
+    hits.labels(pod="a", svc="api", path="/").inc()
+    time.sleep(0.01)
+
+    metrics = generate_metrics(registry)
+    assert 'hits{env="prod",path="/users/:id",service="api"} 3.0' in metrics
+    assert 'hits{env="prod",path="/",service="api"} 1.0' in metrics
+    assert "pod=" not in metrics
+
+    with pytest.raises(ValueError, match="unknown relabel action"):
+        load_backend(FakeRedisBackend, {"relabel": [{"action": "bob", "label": "pod"}]})
+    with pytest.raises(ValueError, match="cannot relabel le"):
+        load_backend(FakeRedisBackend, {"relabel": [{"action": "drop", "label": "le"}]})
+    with pytest.raises(ValueError, match="invalid relabel regex"):
+        load_backend(
+            FakeRedisBackend,
+            {"relabel": [{"action": "replace", "label": "a", "regex": "(", "replacement": ""}]},
+        )
+
+
+def test_metric_renames():
+    load_backend(
+        FakeRedisBackend,
+        {
+            "metric_renames": {
+                "hits": "http_hits",
+                "misses": {"name": "http_misses", "keep_original": True},
+            }
+        },
+    )
+    registry = CollectorRegistry()
+    Counter("hits", "desc", registry=registry).inc()
+    Counter("misses", "desc", registry=registry).inc(2)
+    time.sleep(0.01)
+
+    rendered = FakeRedisBackend.render_metrics(registry)
+    assert "\nhttp_hits 1.0\n" in rendered and "\nhits " not in rendered
+    assert "\nhttp_misses 2.0\n" in rendered and "\nmisses 2.0\n" in rendered
+    family = FakeRedisBackend._generate_samples(registry).family("misses")
+    assert (family.name, family.aliases) == ("http_misses", ["misses"])
+
+
+def test_omit_zero_series():
+    load_backend(FakeRedisBackend, {"omit_zero_series": ["latency", "level"]})
+    registry = CollectorRegistry()
+    histogram = Histogram(
+        "latency", "desc", required_labels=["path"], buckets=[1.0], registry=registry
+    )
+    gauge = Gauge("level", "desc", required_labels=["path"], registry=registry)
+    histogram.labels(path="/").observe(0.5)
+    histogram.labels(path="/idle")
+    gauge.labels(path="/idle").set(0)
+    time.sleep(0.01)
+
+    metrics = generate_metrics(registry)
+    assert 'latency_count{path="/"} 1.0' in metrics
+    assert 'latency_bucket{path="/idle"' not in metrics
+    assert 'latency_count{path="/idle"}' not in metrics
+    # a gauge set to zero is kept
+    assert 'level{path="/idle"} 0.0' in metrics
+
+
+def test_namespace_and_subsystem():
+    load_backend(FakeRedisBackend, {"namespace": "shop", "subsystem": "http"})
+    registry = CollectorRegistry()
+    Counter("hits", "desc", registry=registry).inc()
+    time.sleep(0.01)
+
+    rendered = FakeRedisBackend.render_metrics(registry)
+    assert "# TYPE shop_http_hits counter\nshop_http_hits 1.0\n" in rendered
+    assert FakeRedisBackend._generate_samples(registry).family("shop_http_hits") is not None
+
+    with pytest.raises(ValueError, match="invalid namespace"):
+        load_backend(FakeRedisBackend, {"namespace": "my-shop"})
+
+
+def test_registry_namespaces():
+    api = CollectorRegistry()
+    worker = CollectorRegistry()
+    load_backend(FakeRedisBackend, {"registry_namespaces": {"api": api, "worker": worker}})
+    Counter("jobs", "desc", registry=api).inc(2.0)
+    Counter("jobs", "desc", registry=worker).inc(1.0)
+    time.sleep(0.01)
+
+    assert FakeRedisBackend.execute_command("GET", "api/jobs") == "2"
+    assert FakeRedisBackend.execute_command("GET", "worker/jobs") == "1"
+    assert "jobs 2.0" in generate_metrics(api)
+    assert "jobs 1.0" in generate_metrics(worker)
+
+
+def test_registry_namespaces_resolved_once():
+    class CountingRegistry(CollectorRegistry):
+        scans = 0
+
+        def collect(self):
+            CountingRegistry.scans += 1
+            return super().collect()
+
+    api = CountingRegistry()
+    load_backend(FakeRedisBackend, {"registry_namespaces": {"api": api}})
+    counter = Counter("requests", "desc", required_labels=["path"], registry=api)
+    scans = CountingRegistry.scans
+    for index in range(10):
+        counter.labels(path=f"/{index}").inc()
+    # the children reuse the namespace of their collector
+    assert CountingRegistry.scans == scans
+    assert FakeRedisBackend._flush(5)
+    assert FakeRedisBackend.execute_command("HGET", "api/requests", '{"path":"/3"}') == "1"
+    load_backend(FakeRedisBackend, {})
+
+
+def test_registry_namespaces_collectors_collected():
+    api = CollectorRegistry()
+    load_backend(FakeRedisBackend, {"registry_namespaces": {"api": api}})
+    counter = Counter("temporary", "desc", registry=api)
+    counter.inc()
+    collector = weakref.ref(counter._collector)
+
+    # the namespace resolved for the collector doesn't keep it alive
+    api.unregister(counter._collector)
+    del counter
+    gc.collect()
+    assert collector() is None
+    load_backend(FakeRedisBackend, {})
+
+
+def test_routes():
+    # every route of the fake shares the in-memory store
+    load_backend(
+        FakeRedisBackend,
+        {
+            "routes": [
+                {"host": "hot", "port": 6379, "metrics": ["hot"]},
+                {"host": "http", "port": 6379, "prefixes": ["http_"]},
+            ]
+        },
+    )
+    registry = CollectorRegistry()
+    hot = Counter("hot", "desc", registry=registry)
+    http = Counter("http_requests", "desc", registry=registry)
+    shared = Counter("shared", "desc", registry=registry)
+    assert [
+        metric._metric_value_backend.route for metric in (hot, http, shared)
+    ] == [1, 2, 0]
+
+    hot.inc(2.0)
+    http.inc(1.0)
+    time.sleep(0.01)
+    assert FakeRedisBackend.get_many([hot, http, shared]) == [2.0, 1.0, 0.0]
+    assert "hot 2.0" in generate_metrics(registry)
+
+
+def test_shards():
+    # every shard of the fake shares the in-memory store
+    load_backend(
+        FakeRedisBackend,
+        {"shards": [{"host": "shard1", "port": 6379}, {"host": "shard2", "port": 6379}]},
+    )
+    registry = CollectorRegistry()
+    counters = [Counter(f"counter_{i}", "desc", registry=registry) for i in range(20)]
+    routes = {counter._metric_value_backend.route for counter in counters}
+    assert routes == {0, 1, 2}
+
+    for i, counter in enumerate(counters):
+        counter.inc(i)
+    time.sleep(0.01)
+    # the per-shard pipelines are merged back in registry order
+    samples = FakeRedisBackend._generate_samples(registry)
+    for i, counter in enumerate(counters):
+        assert samples[counter._collector][0].value == i
+
+
+def test_render_cache():
+    load_backend(FakeRedisBackend, {"render_cache_ttl": 60})
+    registry = CollectorRegistry()
+    counter = Counter("cached", "desc", registry=registry)
+    counter.inc(1.0)
+    time.sleep(0.01)
+
+    rendered = FakeRedisBackend.render_metrics(registry)
+    assert rendered == generate_metrics(registry)
+    counter.inc(1.0)
+    time.sleep(0.01)
+    # served from the cache until it expires
+    assert FakeRedisBackend.render_metrics(registry) == rendered
+    assert FakeRedisBackend.execute_command("GET", "pytheus:render:default:prometheus") == rendered
+    assert FakeRedisBackend.render_metrics(registry, "openmetrics").endswith("# EOF\n")
+
+
+
+def test_render_lease():
+    load_backend(FakeRedisBackend, {"render_cache_ttl": 60, "render_lease_timeout": 0.05})
+    registry = CollectorRegistry()
+    Counter("leased", "desc", registry=registry).inc(1.0)
+    time.sleep(0.01)
+    lock = "pytheus:render:default:prometheus:lock"
+    FakeRedisBackend.execute_command("SET", lock, "other", "PX", 60_000)
+
+    # rendered once the lease timeout is over, leaving the lease of the other process alone
+    started = time.monotonic()
+    assert FakeRedisBackend.render_metrics(registry) == generate_metrics(registry)
+    assert time.monotonic() - started >= 0.05
+    assert FakeRedisBackend.execute_command("GET", lock) == "other"
+
+    FakeRedisBackend.execute_command("DEL", lock)
+    FakeRedisBackend.execute_command("DEL", "pytheus:render:default:prometheus")
+    FakeRedisBackend.render_metrics(registry)
+    assert FakeRedisBackend.execute_command("EXISTS", lock) == 0
+
+    with pytest.raises(ValueError, match="invalid render_lease_timeout"):
+        load_backend(FakeRedisBackend, {"render_lease_timeout": 0})
+
+def test_evict_idle_series():
+    clock = TestClock(1_700_000_000)
+    set_clock(clock)
+    try:
+        load_backend(FakeRedisBackend, {"idle_series_timeout": 60, "delete_idle_series": True})
+        registry = CollectorRegistry()
+        counter = Counter("visits", "desc", required_labels=["path"], registry=registry)
+        counter.labels({"path": "/old"}).inc()
+        clock.advance(30)
+        counter.labels({"path": "/new"}).inc()
+        time.sleep(0.01)
+
+        clock.advance(31)
+        assert FakeRedisBackend.evict_idle_series(registry) == 1
+        assert FakeRedisBackend.execute_command("HGETALL", "visits")[0::2] == ['{"path":"/new"}']
+        assert len(counter._collector._labeled_metrics) == 1
+        assert FakeRedisBackend.evict_idle_series(registry) == 0
+    finally:
+        set_clock(None)
+
+
+def test_archive_evicted_series(tmp_path):
+    clock = TestClock(1_700_000_000)
+    set_clock(clock)
+    archived = []
+    try:
+        for sink in (archived.extend, {"file": str(tmp_path / "archive.jsonl")}):
+            load_backend(
+                FakeRedisBackend,
+                {"idle_series_timeout": 60, "delete_idle_series": True, "archive_sink": sink},
+            )
+            FakeRedisBackend.execute_command("FLUSHALL")
+            registry = CollectorRegistry()
+            counter = Counter("archived", "desc", required_labels=["path"], registry=registry)
+            counter.labels({"path": "/old"}).inc(3)
+            time.sleep(0.01)
+            clock.advance(61)
+            assert FakeRedisBackend.evict_idle_series(registry) == 1
+
+        record = {
+            "metric": "archived",
+            "key": "archived",
+            "labels": {"path": "/old"},
+            "value": 3.0,
+            "last_write": 1_700_000_000.0,
+            "archived_at": 1_700_000_061.0,
+        }
+        assert archived == [record]
+        lines = (tmp_path / "archive.jsonl").read_text().splitlines()
+        [line] = [json.loads(line) for line in lines]
+        assert line["labels"] == {"path": "/old"} and line["value"] == 3.0
+        assert line["archived_at"] - line["last_write"] == 61
+        assert FakeRedisBackend.execute_command("HGETALL", "archived") == []
+    finally:
+        set_clock(None)
+
+
+def test_collector_errors_are_isolated():
+    registry = CollectorRegistry()
+    broken = Counter("broken", "desc", registry=registry)
+    healthy = Counter("healthy", "desc", registry=registry)
+    broken.inc()
+    healthy.inc(2.0)
+    time.sleep(0.01)
+    FakeRedisBackend.execute_command("SET", "broken", "bob")
+
+    samples = FakeRedisBackend._generate_samples(registry)
+    assert "broken" not in samples
+    assert samples["healthy"] == [OutSample("", None, 2.0)]
+    [error] = samples.errors
+    assert error["collector"] == "broken"
+    assert "not a valid metric value" in error["error"]
+
+
+def test_sample_set():
+    registry = CollectorRegistry()
+    counter = Counter("counter", "desc", registry=registry)
+    counter.inc(2)
+
+    time.sleep(0.01)
+    samples = FakeRedisBackend._generate_samples(registry)
+    assert isinstance(samples, SampleSet)
+    assert len(samples) == 1
+    assert list(samples) == [counter._collector]
+    assert samples[counter._collector] == [OutSample("", None, 2.0)]
+    assert samples["counter"] == samples[counter._collector]
+    assert samples.get("missing") is None
+    with pytest.raises(KeyError):
+        samples["missing"]
+
+    family = samples.family("counter")
+    assert (family.name, family.type_, family.help) == ("counter", "counter", "desc")
+    assert samples.render() == generate_metrics(registry)
+    assert samples.render("openmetrics") == (
+        "# HELP counter desc\n"
+        "# TYPE counter counter\n"
+        "counter_total 2.0\n"
+        "# EOF\n"
+    )
+    with pytest.raises(ValueError):
+        samples.render("bob")
+
+
+def test_float_timestamp_serializer():
+    load_backend(FakeRedisBackend, {"serializer": "float_timestamp"})
+    clock = TestClock(1_700_000_000)
+    set_clock(clock)
+    try:
+        registry = CollectorRegistry()
+        counter = Counter("counter", "desc", registry=registry)
+        counter.inc(2)
+        counter.inc(0.5)
+        time.sleep(0.01)
+        assert FakeRedisBackend.execute_command("GET", "counter") == "[2.5,1700000000.0]"
+        assert FakeRedisBackend.execute_command("TTL", "counter") == 3600
+        assert FakeRedisBackend._generate_samples(registry)["counter"] == [
+            OutSample("", None, 2.5)
+        ]
+    finally:
+        set_clock(None)
+
+
+def test_unknown_serializer():
+    with pytest.raises(ValueError):
+        load_backend(FakeRedisBackend, {"serializer": "bob"})
+
+
+def test_pytheus_key_layout():
+    load_backend(FakeRedisBackend, {"key_layout": "pytheus"})
+    registry = CollectorRegistry()
+    counter = Counter("counter", "desc", required_labels=["city"], registry=registry)
+    counter.labels(city="Zürich").inc(2)
+    histogram = Histogram("histogram", "desc", buckets=[1, 2.5e6], registry=registry)
+    histogram.observe(3)
+    time.sleep(0.01)
+
+    # the fields and keys written by the pure-Python backend of pytheus
+    field = json.dumps({"city": "Zürich"}, sort_keys=True)
+    assert FakeRedisBackend.execute_command("HGET", "counter", field) == "2"
+    assert FakeRedisBackend.execute_command("GET", "histogram:2500000.0") == "1"
+    assert FakeRedisBackend.execute_command("GET", "histogram:inf") == "1"
+
+    samples = FakeRedisBackend._generate_samples(registry)
+    assert [sample.labels for sample in samples["counter"]] == [{"city": "Zürich"}]
+    buckets = [sample.labels["le"] for sample in samples["histogram"] if sample.suffix == "_bucket"]
+    assert buckets == ["1", "2500000", "+Inf"]
+
+    with pytest.raises(ValueError, match="not supported with the pytheus key layout"):
+        load_backend(FakeRedisBackend, {"key_layout": "pytheus", "max_key_length": 64})
+    with pytest.raises(ValueError, match="unknown key layout"):
+        load_backend(FakeRedisBackend, {"key_layout": "bob"})
+
+
+def test_last_updated():
+    load_backend(
+        FakeRedisBackend,
+        {"track_last_update": ["tracked"], "last_updated_samples": True},
+    )
+    clock = TestClock(1_700_000_000)
+    set_clock(clock)
+    try:
+        registry = CollectorRegistry()
+        counter = Counter("tracked", "desc", required_labels=["bob"], registry=registry)
+        backend = counter.labels(bob="cat")._metric_value_backend
+        time.sleep(0.01)
+        assert backend.last_updated() is None
+
+        counter.labels(bob="cat").inc()
+        time.sleep(0.01)
+        clock.advance(30)
+        assert backend.last_updated() == 1_700_000_000
+        assert backend.staleness() == 30
+        assert OutSample(
+            "_last_updated", {"bob": "cat"}, 1_700_000_000
+        ) in FakeRedisBackend._generate_samples(registry)["tracked"]
+    finally:
+        set_clock(None)
+
+
+
+def test_raw_observations():
+    load_backend(
+        FakeRedisBackend,
+        {"raw_observations": {"latency": 3, "sampled": {"maxlen": 10, "sample_rate": 0.5}}},
+    )
+    registry = CollectorRegistry()
+    histogram = Histogram("latency", "desc", buckets=[1, 2], registry=registry)
+    sampled = Histogram("sampled", "desc", buckets=[1, 2], registry=registry)
+    for value in [0.5, 1.5, 2.5, 3.5]:
+        histogram.observe(value)
+        sampled.observe(value)
+    time.sleep(0.01)
+
+    # the oldest observations are trimmed, one out of two is kept when sampled
+    assert histogram._sum.raw_observations() == [1.5, 2.5, 3.5]
+    assert sampled._sum.raw_observations() == [1.5, 3.5]
+    counter = Counter("latency_total", "desc", registry=registry)
+    with pytest.raises(Exception, match="not recorded"):
+        counter._metric_value_backend.raw_observations()
+
+    with pytest.raises(ValueError, match="invalid raw observations sample_rate"):
+        load_backend(FakeRedisBackend, {"raw_observations": {"latency": {"sample_rate": 2}}})
+
+
+def test_top_k_labels():
+    load_backend(FakeRedisBackend, {"top_k_labels": {"requests": {"label": "customer", "k": 2}}})
+    registry = CollectorRegistry()
+    counter = Counter("requests", "desc", required_labels=["customer", "path"], registry=registry)
+    for customer in ["big", "big", "big", "large", "large"]:
+        counter.labels(customer=customer, path="/").inc()
+    for customer in range(20):
+        counter.labels(customer=str(customer), path="/").inc()
+    counter.labels(customer="big", path="/").inc()
+    time.sleep(0.01)
+
+    stored = FakeRedisBackend.execute_command("HGETALL", "requests")
+    values = {
+        json.loads(field)["customer"]: float(value)
+        for field, value in zip(stored[0::2], stored[1::2])
+    }
+    assert values == {"big": 4.0, "large": 2.0, "other": 20.0}
+
+    with pytest.raises(ValueError, match="not a label of requests"):
+        load_backend(FakeRedisBackend, {"top_k_labels": {"requests": {"label": "user"}}})
+        Counter("requests", "desc", required_labels=["customer"], registry=CollectorRegistry())
+
+
+def test_idle_series():
+    load_backend(FakeRedisBackend, {"track_last_update": ["visits", "empty"]})
+    clock = TestClock(1_700_000_000)
+    set_clock(clock)
+    try:
+        counter = Counter("visits", "desc", required_labels=["path"])
+        counter.labels(path="/old").inc()
+        time.sleep(0.01)
+        clock.advance(120)
+        counter.labels(path="/new").inc()
+        time.sleep(0.01)
+
+        assert FakeRedisBackend.idle_series(60) == [
+            {
+                "metric": "visits",
+                "labels": {"path": "/old"},
+                "last_updated": 1_700_000_000,
+                "idle_seconds": 120,
+            }
+        ]
+        assert len(FakeRedisBackend.idle_series(0)) == 2
+    finally:
+        set_clock(None)
+
+
+def test_last_updated_untracked():
+    counter = Counter("untracked", "desc")
+    counter.inc()
+    time.sleep(0.01)
+    assert counter._metric_value_backend.last_updated() is None
+
+
+def test_created():
+    load_backend(FakeRedisBackend, {"track_created": ["tracked"], "created_samples": True})
+    clock = TestClock(1_700_000_000)
+    set_clock(clock)
+    try:
+        registry = CollectorRegistry()
+        counter = Counter("tracked", "desc", required_labels=["bob"], registry=registry)
+        backend = counter.labels(bob="cat")._metric_value_backend
+        time.sleep(0.01)
+        clock.advance(10)
+        counter.labels(bob="cat").inc()
+        time.sleep(0.01)
+
+        assert backend.created() == 1_700_000_000
+        assert FakeRedisBackend.created_timestamps("tracked") == {
+            '{"bob":"cat"}': 1_700_000_000
+        }
+        assert OutSample(
+            "_created", {"bob": "cat"}, 1_700_000_000
+        ) in FakeRedisBackend._generate_samples(registry)["tracked"]
+    finally:
+        set_clock(None)
+
+
+def test_unit_metadata():
+    load_backend(FakeRedisBackend, {"units": {"request_duration": "seconds"}})
+    registry = CollectorRegistry()
+    counter = Counter("request_duration", "desc", registry=registry)
+    counter.inc(1.5)
+    time.sleep(0.01)
+
+    samples = FakeRedisBackend._generate_samples(registry)
+    assert samples.family("request_duration").unit == "seconds"
+    assert samples.render("openmetrics") == (
+        "# HELP request_duration_seconds desc\n"
+        "# TYPE request_duration_seconds counter\n"
+        "# UNIT request_duration_seconds seconds\n"
+        "request_duration_seconds_total 1.5\n"
+        "# EOF\n"
+    )
+
+
+def test_le_formatting():
+    registry = CollectorRegistry()
+    histogram = Histogram("histogram", "desc", buckets=[0.1, 1, 2.5e6], registry=registry)
+    histogram.observe(0.05)
+    time.sleep(0.01)
+
+    buckets = [
+        sample
+        for sample in FakeRedisBackend._generate_samples(registry)["histogram"]
+        if sample.suffix == "_bucket"
+    ]
+    assert [(sample.labels["le"], sample.value) for sample in buckets] == [
+        ("0.1", 1.0),
+        ("1", 1.0),
+        ("2500000", 1.0),
+        ("+Inf", 1.0),
+    ]
+
+
+def test_histogram_observe():
+    registry = CollectorRegistry()
+    histogram = Histogram("histogram", "desc", buckets=[1, 2], registry=registry)
+    backend = FakeRedisBackend({}, histogram)
+    backend.observe(1.5)
+    backend.observe(7)
+    time.sleep(0.01)
+
+    assert FakeRedisBackend.execute_command("GET", "histogram:1") == "0"
+    assert FakeRedisBackend.execute_command("GET", "histogram:2") == "1"
+    assert FakeRedisBackend.execute_command("GET", "histogram:+Inf") == "2"
+    assert FakeRedisBackend.execute_command("GET", "histogram:count") == "2"
+    assert FakeRedisBackend.execute_command("GET", "histogram:sum") == "8.5"
+
+    with pytest.raises(ValueError):
+        backend.observe(float("nan"))
+    with pytest.raises(Exception):
+        FakeRedisBackend({}, Counter("counter", "desc")).observe(1)
+
+
+def test_histogram_snapshot_consistency():
+    registry = CollectorRegistry()
+    histogram = Histogram("histogram", "desc", buckets=[1, 2], registry=registry)
+    backend = FakeRedisBackend({}, histogram)
+    stop = threading.Event()
+
+    def observe():
+        while not stop.is_set():
+            backend.observe(1.5)
+
+    thread = threading.Thread(target=observe)
+    thread.start()
+    try:
+        for _ in range(50):
+            samples = FakeRedisBackend._generate_samples(registry)["histogram"]
+            by_name = {
+                (sample.suffix, (sample.labels or {}).get("le")): sample.value
+                for sample in samples
+            }
+            assert by_name[("_bucket", "+Inf")] == by_name[("_count", None)]
+            assert by_name[("_sum", None)] == 1.5 * by_name[("_count", None)]
+    finally:
+        stop.set()
+        thread.join()
+
+
+def test_panic_policy():
+    load_backend(FakeRedisBackend, {"panic_policy": "warn"})
+    with pytest.raises(ValueError):
+        FakeRedisBackend._initialize({"panic_policy": "bob"})
+
+
+def test_dropped_jobs_warning(monkeypatch):
+    monkeypatch.setenv("PYTHEUS_FAULT_INJECTION", "1")
+    load_backend(FakeRedisBackend, {"drop_warning_interval": 3600})
+    counter = Counter("overflow", "desc")
+    dropped = FakeRedisBackend.dropped_jobs()
+
+    # far from any warning emitted by previous tests
+    set_clock(TestClock(time.time() + 10**7))
+    try:
+        inject_fault("queue_overflow", times=2)
+        with pytest.warns(RuntimeWarning, match="metric updates were dropped"):
+            counter.inc()
+        # rate limited, only counted
+        counter.inc()
+        assert FakeRedisBackend.dropped_jobs() == dropped + 2
+    finally:
+        set_clock(None)
+
+
+def test_max_commands_per_second():
+    load_backend(FakeRedisBackend, {"max_commands_per_second": 4})
+    counter = Counter("limited", "desc")
+    backend = FakeRedisBackend({}, counter)
+    started = time.monotonic()
+    for _ in range(5):
+        for _ in range(10):
+            backend.inc(1.0)
+        assert FakeRedisBackend._flush(10)
+    # coalesced, nothing is lost
+    assert FakeRedisBackend.execute_command("GET", "limited") == "50"
+    assert time.monotonic() - started > 0.1
+
+    load_backend(FakeRedisBackend, {"max_commands_per_second": 1, "backpressure": "shed"})
+    dropped = FakeRedisBackend.dropped_jobs()
+    with warnings.catch_warnings():
+        warnings.simplefilter("ignore")
+        for _ in range(3):
+            backend.inc(1.0)
+            assert FakeRedisBackend._flush(10)
+    assert FakeRedisBackend.dropped_jobs() > dropped
+    assert float(FakeRedisBackend.execute_command("GET", "limited")) < 53
+
+    with pytest.raises(ValueError, match="unknown backpressure policy"):
+        FakeRedisBackend._initialize({"backpressure": "bob"})
+    with pytest.raises(ValueError, match="invalid max_commands_per_second"):
+        FakeRedisBackend._initialize({"max_commands_per_second": 0})
+
+
+def test_synchronous(monkeypatch):
+    monkeypatch.setenv("PYTHEUS_FAULT_INJECTION", "1")
+    load_backend(
+        FakeRedisBackend, {"synchronous": True, "confirmed_writes": ["inline_confirmed"]}
+    )
+    counter = Counter("inline", "desc", required_labels=["bob"])
+    backend = FakeRedisBackend({}, counter)
+    backend.inc(2.0, labels={"bob": "cat"})
+    # written before the call returned, nothing is left to flush
+    assert FakeRedisBackend.execute_command("HGET", "inline", '{"bob":"cat"}') == "2"
+    assert backend.inc(1.0, labels={"bob": "cat"}, return_value=True) == 3.0
+
+    # a failed inline write is counted once, and raised for a confirmed write
+    dropped = FakeRedisBackend.dropped_jobs()
+    inject_fault("command_error")
+    backend.inc(1.0, labels={"bob": "cat"})
+    assert FakeRedisBackend.dropped_jobs() == dropped + 1
+    confirmed = Counter("inline_confirmed", "desc")
+    inject_fault("command_error")
+    with pytest.raises(Exception, match="injected fault"):
+        confirmed.inc()
+    assert FakeRedisBackend.dropped_jobs() == dropped + 1
+
+    load_backend(FakeRedisBackend, {})
+    backend.inc(1.0, labels={"bob": "cat"})
+    assert FakeRedisBackend._flush(5)
+    assert FakeRedisBackend.execute_command("HGET", "inline", '{"bob":"cat"}') == "4"
+
+
+def test_worker_threads():
+    load_backend(FakeRedisBackend, {"worker_threads": 3})
+    counter = Counter("spread", "desc", required_labels=["bob"])
+    for _ in range(50):
+        for bob in ("cat", "dog", "fish"):
+            counter.labels(bob=bob).inc()
+    assert FakeRedisBackend._flush(5)
+    for bob in ("cat", "dog", "fish"):
+        assert FakeRedisBackend.execute_command("HGET", "spread", f'{{"bob":"{bob}"}}') == "50"
+
+    # the keys of a histogram are written by one worker, whatever the operation
+    histogram = Histogram("spread_latency", "desc", buckets=[1.0])
+    backend = FakeRedisBackend({}, histogram, histogram_bucket="+Inf")
+    for _ in range(20):
+        histogram.observe(0.5)
+        backend.set(0.0)
+    assert FakeRedisBackend._flush(5)
+    assert float(FakeRedisBackend.execute_command("GET", "spread_latency:+Inf")) == 0
+
+    alive = {
+        worker["name"]
+        for worker in FakeRedisBackend.worker_states()
+        if worker["state"] != "dead"
+    }
+    # the pipeline threads are numbered after the write workers
+    assert {f"pytheus-redis-worker-{index}" for index in range(7)} <= alive
+
+    for invalid in (0, 65):
+        with pytest.raises(ValueError, match="invalid worker_threads"):
+            FakeRedisBackend._initialize({"worker_threads": invalid})
+    load_backend(FakeRedisBackend, {})
+
+
+def test_worker_states():
+    load_backend(FakeRedisBackend, {})
+    Counter("busy", "desc").inc()
+    assert FakeRedisBackend._flush(5)
+
+    alive = {
+        worker["name"]: worker
+        for worker in FakeRedisBackend.worker_states()
+        if worker["state"] != "dead"
+    }
+    assert {f"pytheus-redis-worker-{index}" for index in range(5)} <= set(alive)
+    writer = alive["pytheus-redis-worker-0"]
+    assert writer["state"] in ("idle", "processing")
+    assert writer["since"] <= time.time()
+
+
+def test_flush():
+    counter = Counter("flushed", "desc", required_labels=["bob"])
+    backend = FakeRedisBackend({}, counter)
+    for _ in range(100):
+        backend.inc(1.0, labels={"bob": "cat"})
+    assert FakeRedisBackend._flush(5)
+    assert FakeRedisBackend.execute_command("HGET", "flushed", '{"bob":"cat"}') == "100"
+    assert FakeRedisBackend._flush()
+
+    with pytest.raises(ValueError):
+        FakeRedisBackend._flush(-1)
+
+
+def test_memory_usage():
+    counter = Counter("footprint", "desc", required_labels=["bob"])
+    before = FakeRedisBackend.memory_usage()
+    backend = FakeRedisBackend({}, counter)
+    backend.inc(1.0, labels={"bob": "cat"})
+    time.sleep(0.01)
+
+    usage = FakeRedisBackend.memory_usage()
+    assert usage["backends"] == before["backends"] + 1
+    assert usage["cached_hashes"] == before["cached_hashes"] + 1
+    assert usage["estimated_bytes"] > before["estimated_bytes"]
+    assert usage["queued_jobs"] == 0
+
+    del backend
+    assert FakeRedisBackend.memory_usage() == before
+
+
+
+def test_rust_summary():
+    registry = CollectorRegistry()
+    summary = RustSummary("rust_summary", "desc", required_labels=["path"], registry=registry)
+    summary.labels(path="/").observe(0.25)
+    summary.labels(path="/").observe(0.75)
+    time.sleep(0.01)
+
+    assert FakeRedisBackend.execute_command("HGET", "rust_summary:count", '{"path":"/"}') == "2"
+    assert FakeRedisBackend.execute_command("HGET", "rust_summary:sum", '{"path":"/"}') == "1"
+    assert FakeRedisBackend._generate_samples(registry)["rust_summary"] == [
+        OutSample("_count", {"path": "/"}, 2.0),
+        OutSample("_sum", {"path": "/"}, 1.0),
+    ]
+    assert 'rust_summary_sum{path="/"} 1.0' in generate_metrics(registry)
+
+    with pytest.raises(ValueError, match="invalid label name"):
+        RustSummary("bad", "desc", required_labels=["quantile"], registry=registry)
+
+
+def test_unique_counter():
+    registry = CollectorRegistry()
+    visitors = UniqueCounter("visitors", "desc", required_labels=["page"], registry=registry)
+    for user in [1, 2, 3, 2, 1]:
+        visitors.labels(page="/").add(user)
+    visitors.labels(page="/about").add("alice", "bob")
+    # written by the write workers like the other updates
+    assert FakeRedisBackend._flush(5)
+
+    assert FakeRedisBackend._generate_samples(registry)["visitors"] == [
+        OutSample("", {"page": "/"}, 3.0),
+        OutSample("", {"page": "/about"}, 2.0),
+    ]
+    assert "# TYPE visitors gauge" in generate_metrics(registry)
+    # only the HyperLogLog of the series holds the items
+    assert FakeRedisBackend.execute_command("PFCOUNT", 'visitors:hll:{"page":"/"}') == 3
+
+
+def test_unique_counter_single_process():
+    load_backend(SingleProcessAtomicBackend, {})
+    visitors = UniqueCounter("visitors", "desc", registry=CollectorRegistry())
+    visitors.add(*range(1000), *range(500))
+    assert 980 <= visitors._metric_value_backend.get() <= 1020
+
+def test_rust_collector_registry(caplog):
+    registry = RustCollectorRegistry(prefix="app")
+    first = Counter("first", "desc", registry=registry)
+    second = Gauge("second", "desc", registry=registry)
+    assert [collector.name for collector in registry.collect()] == ["first", "second"]
+
+    Counter("first", "desc", registry=registry)
+    assert len(registry) == 2
+    assert "already registered" in caplog.text
+
+    first.inc(2.0)
+    time.sleep(0.01)
+    samples = FakeRedisBackend._generate_samples(registry)
+    assert list(samples) == registry.collect()
+    assert samples["first"] == [OutSample("", None, 2.0)]
+
+    registry.unregister(second._collector)
+    assert [collector.name for collector in registry.collect()] == ["first"]
+    with pytest.raises(KeyError):
+        registry.unregister(second._collector)
+    assert registry.prefix == "app"
+
+
+def test_rust_metrics():
+    registry = CollectorRegistry()
+    counter = RustCounter("rust_counter", "desc", registry=registry)
+    gauge = RustGauge("rust_gauge", "desc", required_labels=["bob"], registry=registry)
+    histogram = RustHistogram(
+        "rust_histogram", "desc", registry=registry, buckets=[1.0, 0.5]
+    )
+    counter.inc()
+    counter.inc(2.0)
+    gauge.labels(bob="cat").set(3.0)
+    gauge.labels({"bob": "cat"}).dec()
+    histogram.observe(0.7)
+    time.sleep(0.01)
+
+    assert FakeRedisBackend.execute_command("GET", "rust_counter") == "3"
+    assert FakeRedisBackend.execute_command("HGET", "rust_gauge", '{"bob":"cat"}') == "2"
+    assert FakeRedisBackend.execute_command("GET", "rust_histogram:1") == "1"
+    assert FakeRedisBackend.execute_command("GET", "rust_histogram:0.5") == "0"
+    assert gauge.labels(bob="cat") is gauge.labels(bob="cat")
+    assert histogram._upper_bounds == [0.5, 1.0, float("inf")]
+
+    samples = FakeRedisBackend._generate_samples(registry)
+    assert samples["rust_counter"] == [OutSample("", None, 3.0)]
+    assert samples["rust_gauge"] == [OutSample("", {"bob": "cat"}, 2.0)]
+
+    with pytest.raises(ValueError, match="only be incremented"):
+        counter.inc(-1.0)
+    with pytest.raises(ValueError, match="missing labels"):
+        gauge.set(1.0)
+    with pytest.raises(ValueError, match="unknown label"):
+        gauge.labels(other="cat")
+    with pytest.raises(ValueError, match="invalid metric name"):
+        RustCounter("1st", "desc", registry=registry)
+    with pytest.raises(ValueError, match="invalid label name"):
+        RustHistogram("bad", "desc", required_labels=["le"], registry=registry)
+
+
+def test_otel_exporter():
+    sdk = pytest.importorskip("opentelemetry.sdk.metrics")
+    from pytheus_backend_rs import OtelExporter
+
+    registry = CollectorRegistry()
+    exporter = OtelExporter(registry)
+    provider = sdk.MeterProvider(metric_readers=[exporter.reader(export_interval_millis=3600_000)])
+    meter = provider.get_meter("test")
+    requests = meter.create_counter("http.requests", description="desc")
+    in_flight = meter.create_up_down_counter("in_flight")
+    latency = meter.create_histogram("latency")
+
+    requests.add(2, {"method": "GET"})
+    in_flight.add(3)
+    latency.record(7)
+    provider.force_flush()
+    requests.add(1, {"method": "GET"})
+    in_flight.add(-1)
+    provider.force_flush()
+    assert exporter.force_flush()
+
+    samples = FakeRedisBackend._generate_samples(registry)
+    assert samples["http_requests"] == [OutSample("", {"method": "GET"}, 3.0)]
+    assert samples["in_flight"] == [OutSample("", None, 2.0)]
+    assert OutSample("_count", None, 1.0) in samples["latency"]
+    assert OutSample("_sum", None, 7.0) in samples["latency"]
+    assert OutSample("_bucket", {"le": "10.0"}, 1.0) in samples["latency"]
+    provider.shutdown()
+
+
+def test_prometheus_client_adapter(monkeypatch):
+    prometheus_client = pytest.importorskip("prometheus_client")
+    from prometheus_client import values
+    from pytheus_backend_rs import PrometheusClientCollector, install_prometheus_client
+
+    monkeypatch.setattr(values, "ValueClass", values.ValueClass)
+    install_prometheus_client()
+    registry = prometheus_client.CollectorRegistry()
+    requests = prometheus_client.Counter(
+        "client_requests", "desc", ["method"], registry=registry
+    )
+    latency = prometheus_client.Histogram(
+        "client_latency", "desc", buckets=[1, 5], registry=registry
+    )
+    requests.labels("GET").inc(2)
+    requests.labels("GET").inc()
+    latency.observe(3)
+    time.sleep(0.01)
+
+    exposition = prometheus_client.CollectorRegistry()
+    exposition.register(PrometheusClientCollector())
+    assert exposition.get_sample_value("client_requests_total", {"method": "GET"}) == 3
+    assert exposition.get_sample_value("client_latency_bucket", {"le": "5.0"}) == 1
+    assert exposition.get_sample_value("client_latency_count") == 1
+    assert exposition.get_sample_value("client_latency_sum") == 3
+    assert b"# TYPE client_requests counter" in prometheus_client.generate_latest(exposition)
+
+
+def test_labels_fast():
+    counter = Counter("fast", "desc", required_labels=["bob", "alice"])
+    child = labels_fast(counter, bob="cat", alice=1)
+    assert child._labels == {"bob": "cat", "alice": "1"}
+    assert labels_fast(counter, {"alice": "1"}, bob="cat") is child
+    assert labels_fast(counter.labels({"bob": "cat"}), alice="1") is child
+    child.inc(2.0)
+    time.sleep(0.01)
+    assert (
+        FakeRedisBackend.execute_command("HGET", "fast", '{"alice":"1","bob":"cat"}') == "2"
+    )
+
+    gauge = RustGauge("fast_rust", "desc", required_labels=["bob"])
+    assert labels_fast(gauge, bob="cat") is gauge.labels(bob="cat")
+
+
+def test_slow_operation_logging(monkeypatch, caplog):
+    monkeypatch.setenv("PYTHEUS_FAULT_INJECTION", "1")
+    load_backend(FakeRedisBackend, {"slow_operation_threshold": 0.01})
+    caplog.set_level(logging.WARNING)
+    registry = CollectorRegistry()
+    Counter("slow", "desc", registry=registry)
+    time.sleep(0.01)
+
+    inject_fault("latency", latency_ms=50)
+    FakeRedisBackend._generate_samples(registry)
+    assert "slow scrape pipeline" in caplog.text
+    assert "on 1 keys" in caplog.text
+
+
+def test_build_info():
+    info = build_info()
+    assert info["version"]
+    assert info["features"] == []
+    assert info["redis_rs_version"].startswith("0.23.")
+    assert info["pyo3_version"].startswith("0.19.")
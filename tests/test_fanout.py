@@ -0,0 +1,66 @@
+import time
+import pytest
+
+from pytheus.backends import load_backend
+from pytheus.metrics import Counter, Gauge
+from pytheus.registry import CollectorRegistry
+from pytheus_backend_rs import (
+    FakeRedisBackend,
+    FanOutBackend,
+    SingleProcessAtomicBackend,
+)
+from pytheus.exposition import generate_metrics
+
+
+class BrokenBackend:
+    def __init__(self, config, metric, histogram_bucket=None):
+        pass
+
+    def inc(self, value):
+        raise RuntimeError("unreachable")
+
+    def dec(self, value):
+        raise RuntimeError("unreachable")
+
+    def set(self, value):
+        raise RuntimeError("unreachable")
+
+    def get(self):
+        raise RuntimeError("unreachable")
+
+
+def load_fanout(*backends):
+    load_backend(FanOutBackend, {"backends": [{"backend": backend} for backend in backends]})
+    FakeRedisBackend.execute_command("FLUSHALL")
+
+
+def test_operations_reach_every_backend():
+    load_fanout(SingleProcessAtomicBackend, FakeRedisBackend)
+    registry = CollectorRegistry()
+    counter = Counter("counter", "desc", registry=registry)
+    counter.inc(2.5)
+    gauge = Gauge("gauge", "desc", required_labels=["bob"], registry=registry)
+    gauge.labels(bob="cat").set(3)
+
+    local, redis = counter._metric_value_backend.children
+    assert local.get() == 2.5
+    time.sleep(0.1)
+    assert redis.get() == 2.5
+    assert "counter 2.5\n" in generate_metrics(registry)
+    assert FanOutBackend.child_errors() == [0, 0]
+
+
+def test_errors_are_isolated():
+    load_fanout(BrokenBackend, SingleProcessAtomicBackend)
+    registry = CollectorRegistry()
+    counter = Counter("counter", "desc", registry=registry)
+    counter.inc(1)
+    counter.inc(1)
+
+    assert "counter 2.0\n" in generate_metrics(registry)
+    assert FanOutBackend.child_errors() == [3, 0]
+
+    FanOutBackend.clear_child_errors()
+    load_fanout(BrokenBackend)
+    with pytest.raises(RuntimeError, match="unreachable"):
+        Counter("broken", "desc", registry=CollectorRegistry()).inc(1)
@@ -0,0 +1,39 @@
+import pickle
+
+from pytheus_backend_rs import OutSample
+
+
+def test_equality_and_hash():
+    first = OutSample("_count", {"bob": "cat"}, 1.0)
+    second = OutSample("_count", {"bob": "cat"}, 1.0)
+    assert first == second
+    assert hash(first) == hash(second)
+    assert first != OutSample("_sum", {"bob": "cat"}, 1.0)
+    assert first != "_count"
+    assert len({first, second}) == 1
+
+
+def test_repr():
+    sample = OutSample("_bucket", {"le": "+Inf"}, 2.0)
+    assert repr(sample) == "OutSample(suffix='_bucket', labels={'le': '+Inf'}, value=2.0)"
+    assert repr(OutSample("")) == "OutSample(suffix='', labels=None, value=0.0)"
+
+
+def test_to_dict():
+    sample = OutSample("", None, 2.7)
+    assert sample.to_dict() == {"suffix": "", "labels": None, "value": 2.7}
+
+
+def test_pickle():
+    sample = OutSample("_sum", {"bob": "cat"}, 2.7)
+    assert pickle.loads(pickle.dumps(sample)) == sample
+
+
+def test_quantile():
+    sample = OutSample("", {"bob": "cat", "quantile": "0.50"}, 1.0)
+    assert sample.quantile == 0.5
+    assert sample.labels == {"bob": "cat", "quantile": "0.5"}
+    assert sample == OutSample("", {"quantile": "0.5", "bob": "cat"}, 1.0)
+    assert sample != OutSample("", {"bob": "cat", "quantile": "0.9"}, 1.0)
+    assert pickle.loads(pickle.dumps(sample)) == sample
+    assert OutSample("", {"bob": "cat"}).quantile is None
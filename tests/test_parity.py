@@ -0,0 +1,52 @@
+import time
+import pytest
+
+from pytheus.backends import load_backend
+from pytheus.metrics import Counter, Gauge
+from pytheus.registry import CollectorRegistry
+from pytheus_backend_rs import (
+    FakeRedisBackend,
+    ParityBackend,
+    SingleProcessBackend,
+    SingleProcessAtomicBackend,
+)
+from pytheus.exposition import generate_metrics
+
+
+@pytest.fixture(autouse=True)
+def clear_divergences():
+    ParityBackend.clear_divergences()
+
+
+def test_no_divergence():
+    load_backend(
+        ParityBackend,
+        {"primary": FakeRedisBackend, "secondary": SingleProcessAtomicBackend},
+    )
+    FakeRedisBackend.execute_command("FLUSHALL")
+    registry = CollectorRegistry()
+    counter = Counter("counter", "desc", registry=registry)
+    counter.inc(2.7)
+    gauge = Gauge("gauge", "desc", required_labels=["bob"], registry=registry)
+    gauge.labels(bob="cat").set(3)
+
+    time.sleep(0.1)
+    metrics_output = generate_metrics(registry)
+    assert 'counter 2.7\n' in metrics_output
+    assert ParityBackend.divergences() == []
+
+
+def test_divergence_is_reported():
+    load_backend(
+        ParityBackend,
+        {"primary": SingleProcessBackend, "secondary": SingleProcessAtomicBackend},
+    )
+    registry = CollectorRegistry()
+    counter = Counter("counter", "desc", registry=registry)
+    counter.inc(1)
+    counter._metric_value_backend.secondary.inc(1)
+
+    generate_metrics(registry)
+    assert ParityBackend.divergences() == [
+        {"metric": "counter", "suffix": "", "labels": {}, "primary": 1.0, "secondary": 2.0}
+    ]
@@ -0,0 +1,80 @@
+import pytest
+
+from pytheus.backends import load_backend as load_pytheus_backend
+from pytheus.metrics import Counter
+from pytheus.registry import CollectorRegistry
+from pytheus_backend_rs import (
+    FakeRedisBackend,
+    RedisBackend,
+    SingleProcessAtomicBackend,
+    backend_capabilities,
+    backend_class,
+    load_backend,
+    register_backend,
+    registered_backends,
+)
+
+
+class DictBackend:
+    values = {}
+
+    def __init__(self, config, metric, histogram_bucket=None):
+        self.key = (metric.name, histogram_bucket)
+
+    def inc(self, value):
+        self.values[self.key] = self.get() + value
+
+    def dec(self, value):
+        self.values[self.key] = self.get() - value
+
+    def set(self, value):
+        self.values[self.key] = value
+
+    def get(self):
+        return self.values.get(self.key, 0.0)
+
+
+@pytest.fixture(autouse=True)
+def restore_backend():
+    yield
+    load_pytheus_backend(SingleProcessAtomicBackend, {})
+
+
+def test_builtin_backends():
+    assert {"redis", "fake_redis", "single_process_atomic", "fanout"} <= set(
+        registered_backends()
+    )
+    assert backend_class("redis") is RedisBackend
+    assert backend_capabilities("redis") == {
+        "supports_multiprocess": True,
+        "supports_observe": True,
+    }
+    assert backend_capabilities("single_process_atomic") == {
+        "supports_multiprocess": False,
+        "supports_observe": False,
+    }
+    with pytest.raises(ValueError, match="unknown backend: missing"):
+        backend_class("missing")
+
+
+def test_register_backend():
+    register_backend("dict", DictBackend, supports_observe=False, replace=True)
+    assert backend_class("dict") is DictBackend
+    with pytest.raises(ValueError, match="already registered"):
+        register_backend("dict", SingleProcessAtomicBackend)
+
+    load_backend("dict")
+    counter = Counter("plugged", "desc", registry=CollectorRegistry())
+    counter.inc(2)
+    assert DictBackend.values[("plugged", None)] == 2
+
+
+def test_fanout_children_by_name():
+    register_backend("dict", DictBackend, replace=True)
+    load_backend("fanout", {"backends": [{"backend": "dict"}, {"backend": FakeRedisBackend}]})
+    counter = Counter("fanned", "desc", registry=CollectorRegistry())
+    counter.inc(3)
+
+    local, _ = counter._metric_value_backend.children
+    assert isinstance(local, DictBackend)
+    assert local.get() == 3
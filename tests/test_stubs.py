@@ -0,0 +1,132 @@
+import ast
+import inspect
+from pathlib import Path
+
+import pytheus_backend_rs
+
+STUB_PATH = Path(__file__).parent.parent / "pytheus_backend_rs.pyi"
+
+# dunders defined by pyo3 on every class, not part of the documented API
+IGNORED_DUNDERS = {
+    "__new__",
+    "__doc__",
+    "__module__",
+    "__reduce__",
+    "__repr__",
+    "__lt__",
+    "__le__",
+    "__gt__",
+    "__ge__",
+}
+
+
+def load_stub():
+    tree = ast.parse(STUB_PATH.read_text())
+    classes = {}
+    functions = {}
+    for node in tree.body:
+        if isinstance(node, ast.ClassDef):
+            classes[node.name] = node
+        elif isinstance(node, ast.FunctionDef):
+            functions[node.name] = node
+    return classes, functions
+
+
+def stub_members(node):
+    members = {}
+    for item in node.body:
+        if isinstance(item, ast.FunctionDef):
+            members[item.name] = item
+        elif isinstance(item, ast.AnnAssign):
+            members[item.target.id] = item
+    return members
+
+
+def stub_parameters(node):
+    arguments = [arg.arg for arg in node.args.args]
+    if node.args.vararg:
+        arguments.append("*" + node.args.vararg.arg)
+    if node.args.kwarg:
+        arguments.append("**" + node.args.kwarg.arg)
+    return [argument for argument in arguments if argument not in ("self", "cls")]
+
+
+def runtime_parameters(function):
+    try:
+        signature = inspect.signature(function)
+    except (ValueError, TypeError):
+        return None
+    parameters = []
+    for name, parameter in signature.parameters.items():
+        if name in ("self", "cls", "$self", "$cls"):
+            continue
+        if parameter.kind is inspect.Parameter.VAR_POSITIONAL:
+            name = "*" + name
+        elif parameter.kind is inspect.Parameter.VAR_KEYWORD:
+            name = "**" + name
+        parameters.append(name)
+    return parameters
+
+
+def runtime_classes():
+    return {
+        name: value
+        for name, value in vars(pytheus_backend_rs).items()
+        if inspect.isclass(value)
+    }
+
+
+def runtime_functions():
+    return {
+        name: value
+        for name, value in vars(pytheus_backend_rs).items()
+        if inspect.isbuiltin(value) and not name.startswith("_")
+    }
+
+
+def test_every_class_is_stubbed():
+    stub_classes, _ = load_stub()
+    assert set(runtime_classes()) <= set(stub_classes)
+
+
+def test_every_function_is_stubbed():
+    _, stub_functions = load_stub()
+    assert set(runtime_functions()) == set(stub_functions)
+
+
+def test_class_members_are_stubbed():
+    stub_classes, _ = load_stub()
+    for name, cls in runtime_classes().items():
+        members = stub_members(stub_classes[name])
+        runtime_members = {
+            member for member in vars(cls) if member not in IGNORED_DUNDERS
+        }
+        missing = runtime_members - set(members)
+        assert not missing, f"{name} members missing from the stub: {missing}"
+        extra = {
+            member
+            for member in set(members) - runtime_members
+            if member != "__init__" and not hasattr(cls, member)
+        }
+        assert not extra, f"{name} stub members not in the module: {extra}"
+
+
+def test_signatures_match():
+    stub_classes, stub_functions = load_stub()
+    for name, function in runtime_functions().items():
+        expected = runtime_parameters(function)
+        if expected is not None:
+            assert stub_parameters(stub_functions[name]) == expected, name
+
+    for name, cls in runtime_classes().items():
+        members = stub_members(stub_classes[name])
+        for member, node in members.items():
+            if not isinstance(node, ast.FunctionDef) or member.startswith("__"):
+                continue
+            expected = runtime_parameters(getattr(cls, member))
+            if expected is not None:
+                assert stub_parameters(node) == expected, f"{name}.{member}"
+        if "__init__" in members:
+            expected = runtime_parameters(cls)
+            if expected is not None:
+                assert stub_parameters(members["__init__"]) == expected, name